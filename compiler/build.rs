@@ -0,0 +1,53 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+//
+// Identify this build of the compiler by a hash of its sources and those of
+// the instruction set it targets, so compilation cache entries written by a
+// different compiler are never reused (CHROMA_BUILD_ID)
+//
+fn main() {
+    let mut files = Vec::new();
+
+    for directory in ["src", "../shared/src"] {
+        println!("cargo:rerun-if-changed={}", directory);
+        collect_files(Path::new(directory), &mut files);
+    }
+
+    for manifest in ["Cargo.toml", "../shared/Cargo.toml"] {
+        println!("cargo:rerun-if-changed={}", manifest);
+        files.push(PathBuf::from(manifest));
+    }
+
+    files.sort();
+
+    let mut hash: u64 = 0xcbf29ce484222325;
+
+    for file in files {
+        let contents = fs::read(&file).unwrap_or_default();
+        let name = file.to_string_lossy().into_owned().into_bytes();
+
+        for byte in name.into_iter().chain(std::iter::once(0)).chain(contents).chain(std::iter::once(0)) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    println!("cargo:rustc-env=CHROMA_BUILD_ID={:016x}", hash);
+}
+
+fn collect_files(directory: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        if path.is_dir() {
+            collect_files(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
@@ -1,3 +1,4 @@
 pub mod semantic;
 pub mod visitor;
-pub mod symbol;
\ No newline at end of file
+pub mod symbol;
+pub mod usage;
\ No newline at end of file
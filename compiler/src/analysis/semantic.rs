@@ -1,36 +1,34 @@
-use std::cell::RefCell;
+use std::collections::HashSet;
+
+use shared::intern::Atom;
 
 use crate::{parser::ast::ProgramNode, common::{logger::{Logger, LoggerError}, status::CompilationResult}};
 use super::symbol::{ScopeManager, SymbolEntry, SymbolType};
 
-pub struct SemanticAnalyser<'a> {    
-    syntax_tree: &'a mut ProgramNode,
-    analysed_tree: Option<RefCell<ProgramNode>>,
+pub struct SemanticAnalyser<'a, 'ast> {    
+    syntax_tree: &'a mut ProgramNode<'ast>,
     pub scope_manager: &'a mut ScopeManager,
     pub logger: &'a mut Logger<'a>,
     pub type_stack: Vec<SymbolType>,
+    // Arrays declared without initialiser that have not had an element written yet
+    pub uninitialised_arrays: HashSet<Atom>,
+    // Names of the functions whose bodies are being analysed (innermost last)
+    pub function_stack: Vec<Atom>,
     status: CompilationResult,
 }
 
-impl<'a> SemanticAnalyser<'a> {
-    pub fn new(syntax_tree: &'a mut ProgramNode, scope_manager: &'a mut ScopeManager, logger: &'a mut Logger<'a>) -> Self { 
+impl<'a, 'ast> SemanticAnalyser<'a, 'ast> {
+    pub fn new(syntax_tree: &'a mut ProgramNode<'ast>, scope_manager: &'a mut ScopeManager, logger: &'a mut Logger<'a>) -> Self { 
         SemanticAnalyser { 
             syntax_tree,
-            analysed_tree: None,
             scope_manager,
             logger,
             type_stack: Vec::<SymbolType>::new(),
+            uninitialised_arrays: HashSet::new(),
+            function_stack: Vec::new(),
             status: CompilationResult::Pending, } 
     }
 
-    pub fn get_analysed_tree(&self) -> Option<ProgramNode> {
-        if let Some(analysed_tree) = &self.analysed_tree {
-            Some(analysed_tree.borrow().clone())
-        } else {
-            None
-        }
-    }
-
     pub fn status_set(&mut self, status: CompilationResult) {
         self.status = status;
     }
@@ -39,17 +37,49 @@ impl<'a> SemanticAnalyser<'a> {
         self.status.clone()
     }
 
+    //
+    // Report a warning; this doesn't mask an earlier failure
+    //
+    pub fn warning(&mut self, message: &str, line_number: usize) {
+        self.logger.print_warning(message, line_number);
+
+        if self.status == CompilationResult::Success {
+            self.status_set(CompilationResult::Warning);
+        }
+    }
+
+    //
+    // A message about an undeclared name, with the closest visible name of
+    // the wanted kind (by edit distance) suggested if one is close enough to
+    // be a likely typo
+    //
+    pub fn with_suggestion(&self, message: &str, name: &str, wanted: fn(&SymbolType) -> bool) -> String {
+        // Allow a typo per three characters, at least one
+        let threshold = (name.chars().count() / 3).max(1);
+
+        let suggestion = self.scope_manager.visible_symbols().into_iter()
+            .filter(|symbol| wanted(&symbol.symbol_type) && symbol.name.as_str() != name)
+            .map(|symbol| (edit_distance(name, symbol.name.as_str()), symbol.name.as_str()))
+            .filter(|(distance, _)| *distance <= threshold)
+            .min();
+
+        match suggestion {
+            Some((_, candidate)) => format!("{}; did you mean '{}'?", message.trim_end_matches('.'), candidate),
+            None => message.to_string(),
+        }
+    }
+
     pub fn analyse(&mut self) {       
         // Set success flag (this will be cleared if any errors are encountered)
         self.status_set(CompilationResult::Success);
 
         // Start semantic analysis. This step assumes the syntax tree has already
-        // has already been generated.
-        let root_node = self.syntax_tree.clone();
+        // has already been generated. The tree is moved out while visiting (the
+        // visitor needs &mut self) and put back afterwards, rather than copied.
+        let root_node = std::mem::take(self.syntax_tree);
         root_node.accept(self);
 
-        // Save modified tree
-        self.analysed_tree = Some(RefCell::new(root_node));
+        *self.syntax_tree = root_node;
     }
 
     pub fn push_type(&mut self, symbol_type: SymbolType) {
@@ -60,7 +90,7 @@ impl<'a> SemanticAnalyser<'a> {
         self.type_stack.pop()
     }
 
-    pub fn add_variable_to_current_scope(&mut self, name: String, symbol: SymbolEntry) {
+    pub fn add_variable_to_current_scope(&mut self, name: Atom, symbol: SymbolEntry) {
         if let Some(scope) = self.scope_manager.current_mut() {
             scope.insert(name, symbol);
         }
@@ -218,4 +248,35 @@ impl<'a> SemanticAnalyser<'a> {
     pub fn exit_scope(&mut self) {        
         let _ = self.scope_manager.close();
     }
-}
\ No newline at end of file
+}
+
+//
+// Edit distance (optimal string alignment) between two names: the fewest
+// single-character insertions, deletions and substitutions turning one into
+// the other, a swap of adjacent characters (widht for width) counting as one
+//
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    // Distances from prefixes of a to the prefixes of b, two rows back
+    let mut previous2: Vec<usize> = vec![0; b.len() + 1];
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for i in 1..=a.len() {
+        let mut current = vec![i; b.len() + 1];
+
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            current[j] = (previous[j] + 1).min(current[j - 1] + 1).min(previous[j - 1] + cost);
+
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(previous2[j - 2] + 1);
+            }
+        }
+
+        previous2 = std::mem::replace(&mut previous, current);
+    }
+
+    previous[b.len()]
+}
@@ -3,6 +3,8 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 
 use regex::Regex;
 
+use shared::intern::{Atom, Interner};
+
 // use crate::lexer::lexer::Symbol;
 
 #[derive(Debug)]
@@ -10,6 +12,8 @@ pub struct ScopeManager {
     scope_array: Vec<SymbolTable>,
     scope_id: AtomicUsize,
     scope_current: Option<usize>,
+    // Interner the program's identifiers came from, for names made later
+    interner: Interner,
 }
 
 impl ScopeManager {
@@ -18,9 +22,18 @@ impl ScopeManager {
             scope_array: Vec::new(),
             scope_id: AtomicUsize::new(0),
             scope_current: None,
+            interner: Interner::new(),
         }
     }
 
+    pub fn interner(&self) -> Interner {
+        self.interner.clone()
+    }
+
+    pub fn interner_set(&mut self, interner: Interner) {
+        self.interner = interner;
+    }
+
     fn next_id(&mut self) -> usize {
         self.scope_id.fetch_add(1, Ordering::Relaxed)
     }
@@ -95,6 +108,19 @@ impl ScopeManager {
         self.find_symbol_from_scope(name, self.scope_current.clone().unwrap())
     }
 
+    // Symbols visible from the current scope, from it and each enclosing scope
+    pub fn visible_symbols(&self) -> Vec<&SymbolEntry> {
+        let mut symbols = Vec::new();
+        let mut scope_id = self.scope_current;
+
+        while let Some(scope) = scope_id.and_then(|id| self.scope_array.iter().find(|scope| scope.scope_id == id)) {
+            symbols.extend(scope.symbols.values());
+            scope_id = scope.parent_scope_id;
+        }
+
+        symbols
+    }
+
     pub fn open(&mut self, is_function: bool, return_type: Option<SymbolType>) -> Result<(), ()>
     {
         let parent_scope_id;
@@ -116,6 +142,11 @@ impl ScopeManager {
         parent_scope_id.map_or(Err(()), |id| self.activate(id))
     }
     
+    // Every scope opened so far, by id
+    pub fn scopes(&self) -> &[SymbolTable] {
+        &self.scope_array
+    }
+
     // returns the current scope
     pub fn current(&self) -> Option<&SymbolTable> {
         self.scope_current.map(|id| self.scope_array.get(id).unwrap())
@@ -141,7 +172,7 @@ impl ScopeManager {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct SymbolTable {
-    symbols: HashMap<String, SymbolEntry>,
+    symbols: HashMap<Atom, SymbolEntry>,
     scope_id: usize,
     parent_scope_id: Option<usize>,
     is_function: bool,
@@ -159,7 +190,7 @@ impl SymbolTable {
         }
     }
 
-    pub fn insert(&mut self, name: String, entry: SymbolEntry) {
+    pub fn insert(&mut self, name: Atom, entry: SymbolEntry) {
         let mut symbol_entry = entry.clone();
         symbol_entry.offset = Some(self.size());
         self.symbols.insert(name, symbol_entry);
@@ -173,7 +204,7 @@ impl SymbolTable {
         self.symbols.get(name)
     }
 
-    pub fn get_iter(&self) -> std::collections::hash_map::Iter<String, SymbolEntry> {
+    pub fn get_iter(&self) -> std::collections::hash_map::Iter<'_, Atom, SymbolEntry> {
         self.symbols.iter()
     }
 
@@ -295,7 +326,7 @@ impl SymbolType {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct SymbolEntry {
-    pub name: String,
+    pub name: Atom,
     pub symbol_type: SymbolType,
     pub params: Option<Vec<SymbolEntry>>,
     pub return_type: Option<SymbolType>,
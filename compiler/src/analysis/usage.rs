@@ -0,0 +1,81 @@
+use std::collections::HashSet;
+
+use shared::intern::Atom;
+
+use crate::parser::ast::{ExpressionNode, FactorNode, StatementNode};
+
+//
+// The variables a part of the program reads and writes, by name. Calls to
+// user functions and inline assembly can read or write any variable in
+// sight without naming it, so they only mark the usage opaque.
+//
+#[derive(Debug, Default, Clone)]
+pub struct SymbolUsage {
+    pub reads: HashSet<Atom>,
+    pub writes: HashSet<Atom>,
+    // Variables (and function parameters) declared inside
+    pub declarations: HashSet<Atom>,
+    pub opaque: bool,
+}
+
+impl SymbolUsage {
+    pub fn of_statement(node: &StatementNode) -> Self {
+        let mut usage = SymbolUsage::default();
+        usage.add_statement(node);
+        usage
+    }
+
+    pub fn of_expression(node: &ExpressionNode) -> Self {
+        let mut usage = SymbolUsage::default();
+        usage.add_expression(node);
+        usage
+    }
+
+    pub fn add_statement(&mut self, node: &StatementNode) {
+        let mut pending = vec![node];
+
+        while let Some(statement) = pending.pop() {
+            match statement {
+                StatementNode::VariableDeclaration(node) => { self.declarations.insert(node.identifier.clone()); },
+                StatementNode::ArrayDeclaration(node) => { self.declarations.insert(node.identifier.clone()); },
+                StatementNode::FunctionDeclaration(node) => self.declarations.extend(node.formal_parameters.iter().map(|parameter| parameter.identifier.clone())),
+                StatementNode::Assignment(node) => { self.writes.insert(node.identifier.clone()); },
+                StatementNode::Asm(_) => self.opaque = true,
+                _ => (),
+            }
+
+            let (statements, expressions) = statement.children();
+            pending.extend(statements);
+
+            for expression in expressions {
+                self.add_expression(expression);
+            }
+        }
+    }
+
+    pub fn add_expression(&mut self, node: &ExpressionNode) {
+        let mut pending = vec![node];
+
+        while let Some(expression) = pending.pop() {
+            match &expression.factor {
+                FactorNode::Identifier(name) => { self.reads.insert(name.clone()); },
+                FactorNode::ArrayAccess(access) => { self.reads.insert(access.identifier.clone()); },
+                FactorNode::FunctionCall(_) => self.opaque = true,
+                _ => (),
+            }
+
+            pending.extend(expression.children());
+        }
+    }
+
+    //
+    // True if nothing here can change what the given usage reads: none of
+    // its variables are written or declared (which may shadow them), and
+    // there are no calls or assembly unless it reads no variables at all
+    //
+    pub fn preserves(&self, usage: &SymbolUsage) -> bool {
+        !usage.opaque
+            && (usage.reads.is_empty() || !self.opaque)
+            && usage.reads.iter().all(|name| !self.writes.contains(name) && !self.declarations.contains(name))
+    }
+}
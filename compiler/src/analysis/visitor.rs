@@ -1,11 +1,14 @@
 use super::semantic::SemanticAnalyser;
 use super::symbol::SymbolEntry;
 use super::symbol::SymbolType;
+use crate::common::consteval;
 use crate::common::logger::LoggerError;
 use crate::common::status::CompilationResult;
 use crate::parser::ast::AbstractSyntaxTreeVisitor;
 
-impl AbstractSyntaxTreeVisitor for SemanticAnalyser<'_> {
+use shared::intern::Atom;
+
+impl AbstractSyntaxTreeVisitor for SemanticAnalyser<'_, '_> {
     fn visit_program(&mut self, node: &crate::parser::ast::ProgramNode) {
         self.enter_scope();
 
@@ -131,10 +134,37 @@ impl AbstractSyntaxTreeVisitor for SemanticAnalyser<'_> {
                     self.assert_array_type(&node.identifier, node.line);
                 }
             }
+
+            // Typecheck fill value
+            if let Some(fill) = &node.fill {
+                fill.accept(self);
+                self.assert_array_type(&node.identifier, node.line);
+            }
+
+            // Elements of arrays declared with '[]' are zero-filled; track them so
+            // reads before any write can be flagged
+            if node.initialiser.is_none() && node.fill.is_none() {
+                self.uninitialised_arrays.insert(node.identifier.clone());
+            } else {
+                self.uninitialised_arrays.remove(&node.identifier);
+            }
         }
     }
 
     fn visit_function_declaration(&mut self, node: &crate::parser::ast::FunctionDeclarationNode) {
+        // Nested functions would need static links to reach the enclosing
+        // function's frame (closures); the VM has no support for this.
+        if let Some(enclosing) = self.function_stack.last() {
+            let message = format!(
+                "Function '{}' cannot be declared inside function '{}'; nested functions are not supported.",
+                node.identifier,
+                enclosing
+            );
+
+            self.logger.print_error(LoggerError::Semantic, message.as_str(), node.line);
+            self.status_set(CompilationResult::Failure);
+        }
+
         // Create parameter array
         let mut parameters = Vec::<SymbolEntry>::new();
 
@@ -188,7 +218,9 @@ impl AbstractSyntaxTreeVisitor for SemanticAnalyser<'_> {
         }
 
         // Add parameters to function scope
+        self.function_stack.push(node.identifier.clone());
         node.body.accept(self);
+        self.function_stack.pop();
 
         self.exit_scope();
     }
@@ -209,11 +241,13 @@ impl AbstractSyntaxTreeVisitor for SemanticAnalyser<'_> {
     fn visit_assignment(&mut self, node: &crate::parser::ast::AssignmentNode) {
         // Make sure variable has been declared before assignment
         if !self.check_variable_exists(&node.identifier) {
-            self.logger.print_error(
-                LoggerError::Semantic,
-                format!("Variable '{}' used but not declared.", node.identifier).as_str(),
-                node.line,
+            let message = self.with_suggestion(
+                &format!("Variable '{}' used but not declared.", node.identifier),
+                &node.identifier,
+                |symbol_type| *symbol_type != SymbolType::Function,
             );
+
+            self.logger.print_error(LoggerError::Semantic, &message, node.line);
             self.status_set(CompilationResult::Failure);
         }
 
@@ -225,6 +259,8 @@ impl AbstractSyntaxTreeVisitor for SemanticAnalyser<'_> {
             node.expression.accept(self);
 
             self.assert_array_type(&node.identifier, node.line);
+
+            self.uninitialised_arrays.remove(&node.identifier);
         } else {
 
             // Evaluate expression
@@ -289,6 +325,22 @@ impl AbstractSyntaxTreeVisitor for SemanticAnalyser<'_> {
         // We're fine with printing any type
         let found_type = self.pop_type().unwrap();
         node.arg_type.replace(SymbolType::to_string(&found_type));
+
+        // ... except arrays when formatting; the decimal places are an int
+        if let Some(decimals) = &node.decimals {
+            if let SymbolType::Array(_, _) = found_type {
+                self.logger.print_error(
+                    LoggerError::Type,
+                    "__printf cannot print arrays; use __print.",
+                    node.line,
+                );
+
+                self.status_set(CompilationResult::Failure);
+            }
+
+            decimals.accept(self);
+            self.assert_type(SymbolType::Int, "__printf", decimals.line);
+        }
     }
 
     fn visit_delay(&mut self, node: &crate::parser::ast::ExpressionNode) {
@@ -297,12 +349,62 @@ impl AbstractSyntaxTreeVisitor for SemanticAnalyser<'_> {
         self.assert_type(SymbolType::Int, "__delay", node.line);
     }
 
+    fn visit_assert(&mut self, node: &crate::parser::ast::ExpressionNode) {
+        // Assert takes a boolean condition
+        node.accept(self);
+        self.assert_type(SymbolType::Bool, "__assert", node.line);
+    }
+
     fn visit_clear(&mut self, node: &crate::parser::ast::ExpressionNode) {
         // Clear takes a colour typed argument
         node.accept(self);
         self.assert_type(SymbolType::Colour, "__clear", node.line);
     }
 
+    fn visit_brightness(&mut self, node: &crate::parser::ast::ExpressionNode) {
+        // Brightness takes an integer level (0-255)
+        node.accept(self);
+        self.assert_type(SymbolType::Int, "__brightness", node.line);
+    }
+
+    fn visit_store(&mut self, _key: &str, node: &crate::parser::ast::ExpressionNode) {
+        // Only ints, floats and colours can be stored
+        node.accept(self);
+
+        match self.pop_type() {
+            Some(SymbolType::Int | SymbolType::Float | SymbolType::Colour) => {},
+            actual_type => {
+                self.logger.print_error(
+                    LoggerError::Type,
+                    &format!("Mismatching types in __store; expected int, float or colour, got {}.", actual_type.unwrap_or(SymbolType::Undefined).to_string()),
+                    node.line,
+                );
+
+                self.status_set(CompilationResult::Failure);
+            },
+        }
+    }
+
+    fn visit_asm(&mut self, node: &crate::parser::ast::AsmNode) {
+        // References must name variables (functions have no frame slot)
+        for (index, text) in node.lines.iter().enumerate() {
+            let resolved = crate::parser::ast::AsmNode::substitute(text, |name| match self.get_variable_type(name) {
+                Some(SymbolType::Function) | None => None,
+                Some(_) => Some((0, 0)),
+            });
+
+            if let Err(error) = resolved {
+                self.logger.print_error(
+                    LoggerError::Semantic,
+                    format!("Invalid inline assembly; {}.", error).as_str(),
+                    node.line + index,
+                );
+
+                self.status_set(CompilationResult::Failure);
+            }
+        }
+    }
+
     fn visit_write(&mut self, node: &[crate::parser::ast::ExpressionNode; 3]) {
         // first argument is x position (int)
         node[0].accept(self);
@@ -361,6 +463,36 @@ impl AbstractSyntaxTreeVisitor for SemanticAnalyser<'_> {
         self.assert_type(SymbolType::Colour, "__write_line", node[4].line);
     }
 
+    fn visit_write_box_gradient(&mut self, node: &[crate::parser::ast::ExpressionNode; 7]) {
+        // x, y, width, height (int), from and to colours (colour) and
+        // orientation (int: 0 = horizontal, 1 = vertical)
+        let types = [SymbolType::Int, SymbolType::Int, SymbolType::Int, SymbolType::Int, SymbolType::Colour, SymbolType::Colour, SymbolType::Int];
+
+        for (expression, expected) in node.iter().zip(types) {
+            expression.accept(self);
+            self.assert_type(expected, "__write_box_gradient", expression.line);
+        }
+    }
+
+    fn visit_write_box_pattern(&mut self, node: &[crate::parser::ast::ExpressionNode; 7]) {
+        // x, y, width, height (int), background and foreground colours
+        // (colour) and dither level (int: 0 = background .. 16 = foreground)
+        let types = [SymbolType::Int, SymbolType::Int, SymbolType::Int, SymbolType::Int, SymbolType::Colour, SymbolType::Colour, SymbolType::Int];
+
+        for (expression, expected) in node.iter().zip(types) {
+            expression.accept(self);
+            self.assert_type(expected, "__write_box_pattern", expression.line);
+        }
+    }
+
+    fn visit_write_triangle(&mut self, node: &[crate::parser::ast::ExpressionNode; 7]) {
+        // three vertices (int x, int y) followed by the colour
+        for (index, expression) in node.iter().enumerate() {
+            expression.accept(self);
+            self.assert_type(if index < 6 { SymbolType::Int } else { SymbolType::Colour }, "__write_triangle", expression.line);
+        }
+    }
+
     fn visit_return(&mut self, node: &crate::parser::ast::ExpressionNode) {
         // We're fine with returning any type
         node.accept(self);
@@ -441,13 +573,82 @@ impl AbstractSyntaxTreeVisitor for SemanticAnalyser<'_> {
         self.push_type(SymbolType::Int);
     }
 
-    fn visit_random_int(&mut self, node: &std::rc::Rc<crate::parser::ast::ExpressionNode>) {
+    fn visit_capabilities(&mut self) {
+        self.push_type(SymbolType::Int);
+    }
+
+    fn visit_random_int(&mut self, node: &crate::parser::ast::ExpressionNode) {
         node.accept(self);
         self.assert_type(SymbolType::Int, "random_int", node.line);
+
+        // A constant upper bound must leave a non-empty range [0, bound)
+        if let Ok(bound) = consteval::evaluate_integer(node) {
+            if bound <= 0 {
+                self.logger.print_error(
+                    LoggerError::Semantic,
+                    format!("Upper bound of __random_int must be positive, got {}.", bound).as_str(),
+                    node.line,
+                );
+
+                self.status_set(CompilationResult::Failure);
+            }
+        }
+
         self.push_type(SymbolType::Int);
     }
 
-    fn visit_read(&mut self, node: &[std::rc::Rc<crate::parser::ast::ExpressionNode>; 2]) {
+    fn visit_noise(&mut self, node: &[&crate::parser::ast::ExpressionNode; 3]) {
+        // x, y and time are floats, as is the result
+        for expression in node.iter() {
+            expression.accept(self);
+            self.assert_type(SymbolType::Float, "__noise", expression.line);
+        }
+
+        self.push_type(SymbolType::Float);
+    }
+
+    fn visit_random_float(&mut self) {
+        self.push_type(SymbolType::Float);
+    }
+
+    fn visit_random_range(&mut self, node: &[&crate::parser::ast::ExpressionNode; 2]) {
+        // Bounds are both ints or both floats; the result has the same type
+        node[0].accept(self);
+        let range_type = match self.pop_type() {
+            Some(SymbolType::Float) => SymbolType::Float,
+            Some(SymbolType::Int) => SymbolType::Int,
+            actual_type => {
+                self.logger.print_error(
+                    LoggerError::Type,
+                    &format!("Mismatching types in __random_range; expected int or float, got {}.", actual_type.unwrap_or(SymbolType::Undefined).to_string()),
+                    node[0].line,
+                );
+
+                self.status_set(CompilationResult::Failure);
+                SymbolType::Int
+            },
+        };
+
+        node[1].accept(self);
+        self.assert_type(range_type.clone(), "__random_range", node[1].line);
+
+        // Constant bounds must leave a non-empty range [lo, hi)
+        if let (Ok(low), Ok(high)) = (consteval::evaluate_integer(node[0]), consteval::evaluate_integer(node[1])) {
+            if high <= low {
+                self.logger.print_error(
+                    LoggerError::Semantic,
+                    format!("Range of __random_range is empty; {} must be above {}.", high, low).as_str(),
+                    node[0].line,
+                );
+
+                self.status_set(CompilationResult::Failure);
+            }
+        }
+
+        self.push_type(range_type);
+    }
+
+    fn visit_read(&mut self, node: &[&crate::parser::ast::ExpressionNode; 2]) {
         // first argument is x position (int)
         node[0].accept(self);
         self.assert_type(SymbolType::Int, "__read", node[0].line);
@@ -460,15 +661,110 @@ impl AbstractSyntaxTreeVisitor for SemanticAnalyser<'_> {
         self.push_type(SymbolType::Colour);
     }
 
-    fn visit_identifier(&mut self, value: String) {
+    fn visit_hit_test(&mut self, node: &[&crate::parser::ast::ExpressionNode; 5]) {
+        // x, y, width, height (int) and the colour mask (colour)
+        for (index, expression) in node.iter().enumerate() {
+            expression.accept(self);
+            self.assert_type(if index < 4 { SymbolType::Int } else { SymbolType::Colour }, "__hit_test", expression.line);
+        }
+
+        // Return type is bool
+        self.push_type(SymbolType::Bool);
+    }
+
+    fn visit_add_colour(&mut self, node: &[&crate::parser::ast::ExpressionNode; 2]) {
+        // Both arguments are colours, as is the result
+        for expression in node.iter() {
+            expression.accept(self);
+            self.assert_type(SymbolType::Colour, "__add_colour", expression.line);
+        }
+
+        self.push_type(SymbolType::Colour);
+    }
+
+    fn visit_subtract_colour(&mut self, node: &[&crate::parser::ast::ExpressionNode; 2]) {
+        // Both arguments are colours, as is the result
+        for expression in node.iter() {
+            expression.accept(self);
+            self.assert_type(SymbolType::Colour, "__sub_colour", expression.line);
+        }
+
+        self.push_type(SymbolType::Colour);
+    }
+
+    fn visit_hsv(&mut self, node: &[&crate::parser::ast::ExpressionNode; 3]) {
+        // hue (degrees), saturation and value (0-255) are ints
+        for expression in node.iter() {
+            expression.accept(self);
+            self.assert_type(SymbolType::Int, "__hsv", expression.line);
+        }
+
+        // Return type is colour
+        self.push_type(SymbolType::Colour);
+    }
+
+    fn visit_param(&mut self, _name: &str, default: Option<&crate::parser::ast::ExpressionNode>) {
+        // The parameter takes the type of its default (an int or a colour);
+        // without one it's an int defaulting to 0
+        let Some(default) = default else {
+            self.push_type(SymbolType::Int);
+            return;
+        };
+
+        default.accept(self);
+
+        match self.pop_type() {
+            Some(SymbolType::Int) => self.push_type(SymbolType::Int),
+            Some(SymbolType::Colour) => self.push_type(SymbolType::Colour),
+            actual_type => {
+                self.logger.print_error(
+                    LoggerError::Type,
+                    &format!("Mismatching types in __param; expected int or colour default, got {}.", actual_type.unwrap_or(SymbolType::Undefined).to_string()),
+                    default.line,
+                );
+
+                self.status_set(CompilationResult::Failure);
+                self.push_type(SymbolType::Int);
+            },
+        }
+    }
+
+    fn visit_load(&mut self, _key: &str, default: Option<&crate::parser::ast::ExpressionNode>) {
+        // As with __param, the value takes the type of its default
+        let Some(default) = default else {
+            self.push_type(SymbolType::Int);
+            return;
+        };
+
+        default.accept(self);
+
+        match self.pop_type() {
+            Some(symbol_type @ (SymbolType::Int | SymbolType::Float | SymbolType::Colour)) => self.push_type(symbol_type),
+            actual_type => {
+                self.logger.print_error(
+                    LoggerError::Type,
+                    &format!("Mismatching types in __load; expected int, float or colour default, got {}.", actual_type.unwrap_or(SymbolType::Undefined).to_string()),
+                    default.line,
+                );
+
+                self.status_set(CompilationResult::Failure);
+                self.push_type(SymbolType::Int);
+            },
+        }
+    }
+
+    fn visit_identifier(&mut self, value: Atom) {
         let symbol = self.get_variable_type(&value);
 
         if symbol.is_none() {
-            self.logger.print_short_error(
-                LoggerError::Semantic,
-                format!("Variable '{}' used but not declared.", value).as_str(),
+            let message = self.with_suggestion(
+                &format!("Variable '{}' used but not declared.", value),
+                &value,
+                |symbol_type| *symbol_type != SymbolType::Function,
             );
 
+            self.logger.print_short_error(LoggerError::Semantic, &message);
+
             self.status_set(CompilationResult::Failure);
 
             // Recover from error
@@ -481,11 +777,13 @@ impl AbstractSyntaxTreeVisitor for SemanticAnalyser<'_> {
     fn visit_function_call(&mut self, node: &crate::parser::ast::FunctionCallNode) {
         // Make sure function has been declared
         if self.check_variable_type(&node.identifier, SymbolType::Function) == false {
-            self.logger.print_error(
-                LoggerError::Semantic,
-                format!("Function '{}' has not been declared", node.identifier).as_str(),
-                node.line,
+            let message = self.with_suggestion(
+                &format!("Function '{}' has not been declared", node.identifier),
+                &node.identifier,
+                |symbol_type| *symbol_type == SymbolType::Function,
             );
+
+            self.logger.print_error(LoggerError::Semantic, &message, node.line);
             self.status_set(CompilationResult::Failure);
         }
 
@@ -535,15 +833,25 @@ impl AbstractSyntaxTreeVisitor for SemanticAnalyser<'_> {
         let variable_type = self.get_variable_type(&node.identifier).clone();
 
         if !matches!(variable_type, Some(SymbolType::Array(_, _))) {
-            self.logger.print_error(
-                LoggerError::Semantic,
-                format!("Array '{}' has not been declared", node.identifier).as_str(),
-                node.line,
+            let message = self.with_suggestion(
+                &format!("Array '{}' has not been declared", node.identifier),
+                &node.identifier,
+                |symbol_type| matches!(symbol_type, SymbolType::Array(_, _)),
             );
 
+            self.logger.print_error(LoggerError::Semantic, &message, node.line);
+
             self.status_set(CompilationResult::Failure);
         }
 
+        // Warn (once) if no element has been written before this read
+        if self.uninitialised_arrays.remove(&node.identifier) {
+            self.warning(
+                format!("Array '{}' may be read before any element is written; elements default to 0.", node.identifier).as_str(),
+                node.line,
+            );
+        }
+
         // Typecheck index
         node.index.accept(self);
 
@@ -558,11 +866,11 @@ impl AbstractSyntaxTreeVisitor for SemanticAnalyser<'_> {
         self.push_type(array_type.unwrap());
     }
 
-    fn visit_subexpression(&mut self, node: &std::rc::Rc<crate::parser::ast::ExpressionNode>) {
+    fn visit_subexpression(&mut self, node: &crate::parser::ast::ExpressionNode) {
         node.accept(self);
     }
 
-    fn visit_unary(&mut self, node: &std::rc::Rc<crate::parser::ast::ExpressionNode>) {
+    fn visit_unary(&mut self, node: &crate::parser::ast::ExpressionNode) {
         node.accept(self);
     }
 }
\ No newline at end of file
@@ -1,44 +1,73 @@
+use std::collections::{HashMap, HashSet};
+
+use shared::intern::Atom;
 use shared::pixardis::{
     PixardisInstruction, 
+    pixardis_immediate_integer,
+    pixardis_mangle_label,
     pixardis_print_code
 };
 
+use crate::common::consteval::{self, ConstValue};
 use crate::common::logger::Logger;
 use crate::common::status::CompilationResult;
-use crate::parser::ast::ProgramNode;
+use crate::parser::ast::{AbstractSyntaxTreeVisitor, ExpressionNode, FactorNode, ForNode, FunctionCallNode, ProgramNode, StatementNode};
 use crate::analysis::symbol::*;
+use crate::analysis::usage::SymbolUsage;
 
 #[allow(dead_code)]
-pub struct CodeGenerator<'a> {
-    syntax_tree: &'a mut ProgramNode,
+pub struct CodeGenerator<'a, 'ast> {
+    syntax_tree: &'a mut ProgramNode<'ast>,
     pub scope_manager: &'a mut ScopeManager,
     pub scope_index: usize,
     scope_stack: Vec<usize>,
     pub program_code: Vec<(usize, PixardisInstruction)>,
+    // Source line (1-based, 0 if unknown) of each instruction in program_code
+    pub line_table: Vec<usize>,
+    // Line of the expression or statement being generated
+    pub source_line: usize,
     pub instruction_index: usize,
     pass: usize,
     pub logger: &'a mut Logger<'a>,
     emit_debug: bool,
+    // Label of the function being generated and the index its body resumes at on a tail call
+    pub tail_call_target: Option<(Atom, Option<usize>)>,
+    // Number of fractional bits when lowering floats to fixed-point integers
+    fixed_point: Option<u32>,
+    // Instruction pushing the frame size of each scope opened so far, so
+    // variables declared during generation can grow the frame
+    frame_sizes: HashMap<usize, usize>,
+    temporary_count: usize,
+    // Loop-invariant expressions computed ahead of their loop, and the
+    // temporary holding each (see emit_loop_invariants)
+    hoisted: HashMap<*const (), Atom>,
     status: CompilationResult,
  }
 
-impl<'a> CodeGenerator<'a> {
-    pub fn new(syntax_tree: &'a mut ProgramNode, scope_manager: &'a mut ScopeManager, logger: &'a mut Logger<'a>) -> Self { 
+impl<'a, 'ast> CodeGenerator<'a, 'ast> {
+    pub fn new(syntax_tree: &'a mut ProgramNode<'ast>, scope_manager: &'a mut ScopeManager, logger: &'a mut Logger<'a>) -> Self { 
         CodeGenerator { 
             syntax_tree,
             scope_manager: scope_manager,
             scope_index: 0,
             scope_stack: Vec::<usize>::new(),
             program_code: Vec::<(usize, PixardisInstruction)>::new(),
+            line_table: Vec::new(),
+            source_line: 0,
             instruction_index: 0,
             pass: 0,
             logger,
             emit_debug: false, 
+            tail_call_target: None,
+            fixed_point: None,
+            frame_sizes: HashMap::new(),
+            temporary_count: 0,
+            hoisted: HashMap::new(),
             status: CompilationResult::Pending,
         } 
     }
 
-    fn status_set(&mut self, status: CompilationResult) {
+    pub fn status_set(&mut self, status: CompilationResult) {
         self.status = status;
     }
 
@@ -53,11 +82,14 @@ impl<'a> CodeGenerator<'a> {
         // Generate code for the syntax tree; note that this step assumes
         // the semantic analysis step has already been run and the 
         // code generator has been constructed with a valid scope manager.
-        let root_node = self.syntax_tree.clone();
+        // As in analysis, the tree is moved out while visiting, not copied.
+        let root_node = std::mem::take(self.syntax_tree);
         
         self.pass_set(0);
         root_node.accept(self);
 
+        *self.syntax_tree = root_node;
+
         // No need for a second pass (at the moment)
         // self.pass_set(1);
         // root_node.accept(self);
@@ -67,13 +99,90 @@ impl<'a> CodeGenerator<'a> {
         self.relocate_code();
     }
 
-    pub fn program_code(&self) -> Vec<(usize, PixardisInstruction)> {
-        self.program_code.clone()
+    //
+    // Lower float arithmetic to fixed-point integers with the given number of
+    // fractional bits (None keeps floats)
+    //
+    pub fn fixed_point_set(&mut self, bits: Option<u32>) {
+        self.fixed_point = bits;
+    }
+
+    pub fn fixed_point_scale(&self) -> Option<i64> {
+        self.fixed_point.map(|bits| 1i64 << bits)
+    }
+
+    pub fn fixed_point_bits(&self) -> Option<u32> {
+        self.fixed_point
+    }
+
+    pub fn take_program_code(&mut self) -> Vec<(usize, PixardisInstruction)> {
+        std::mem::take(&mut self.program_code)
+    }
+
+    pub fn take_line_table(&mut self) -> Vec<usize> {
+        std::mem::take(&mut self.line_table)
     }
 
     pub fn emit_code(&mut self, code: PixardisInstruction) {
         self.instruction_index += 1;
-        self.program_code.push((self.scope_id(), code.clone()));
+        self.program_code.push((self.scope_id(), code));
+        self.line_table.push(self.source_line);
+    }
+
+    //
+    // Push an integer immediate; small values share pooled text
+    //
+    pub fn emit_push_integer(&mut self, value: i64) {
+        self.emit_code(PixardisInstruction::PushImmediate(pixardis_immediate_integer(value)));
+    }
+
+    //
+    // Push the current scope's frame size (the total size of its symbols),
+    // remembering where for declare_temporary
+    //
+    pub fn emit_frame_size(&mut self) {
+        let count = self.symbol_table().unwrap().size();
+
+        self.frame_sizes.insert(self.scope_id(), self.current_instruction_index());
+        self.emit_push_integer(count as i64);
+    }
+
+    //
+    // Declare a variable the program doesn't name in the current scope,
+    // growing its frame to hold it; None if the frame's size wasn't pushed
+    //
+    pub fn declare_temporary(&mut self, symbol_type: SymbolType) -> Option<Atom> {
+        let frame_size_address = *self.frame_sizes.get(&self.scope_id())?;
+
+        // Source names can't start with $, so temporaries never shadow them
+        let name = self.scope_manager.interner().intern(&format!("$t{}", self.temporary_count));
+        self.temporary_count += 1;
+
+        let symbol_table = self.scope_manager.current_mut().unwrap();
+        symbol_table.insert(name.clone(), SymbolEntry {
+            name: name.clone(),
+            symbol_type,
+            params: None,
+            return_type: None,
+            offset: None,
+        });
+
+        let count = symbol_table.size();
+        self.emit_code_patch(PixardisInstruction::PushImmediate(pixardis_immediate_integer(count as i64)), frame_size_address);
+
+        Some(name)
+    }
+
+    //
+    // Store the value on top of the stack in a (scalar) variable
+    //
+    pub fn emit_store_variable(&mut self, name: &str) {
+        let (_, scope_distance, symbol) = self.scope_manager.find_symbol(name).unwrap();
+        let (offset, frame) = (symbol.offset.unwrap() as i64, scope_distance as i64);
+
+        self.emit_push_integer(offset);
+        self.emit_push_integer(frame);
+        self.emit_code(PixardisInstruction::Store);
     }
 
     pub fn emit_code_patch(&mut self, code: PixardisInstruction, index: usize) {
@@ -144,6 +253,385 @@ impl<'a> CodeGenerator<'a> {
         self.scope_manager.current().unwrap().parent_scope_id()
     }
 
+    //
+    // Close block frames until the enclosing function's frame is current
+    //
+    pub fn emit_function_frame_unwind(&mut self) {
+        while !self.is_function_declaration_scope() {
+            self.emit_code(PixardisInstruction::FrameClose);
+            self.previous_scope();
+        }
+    }
+
+    //
+    // Emit a self-call in return position as a jump back into the function
+    // body with the parameters overwritten in place, so recursion doesn't
+    // grow the address stack
+    //
+    pub fn emit_tail_call(&mut self, call: &FunctionCallNode, target: usize) {
+        let argument_header = self.get_function_argument_types(&call.identifier)
+            .unwrap()
+            .iter()
+            .fold(0, |acc, arg| acc + arg.symbol_type.size());
+
+        // Arguments are evaluated exactly as for a regular call
+        for argument in call.arguments.iter().rev() {
+            argument.accept(self);
+        }
+
+        self.push_scope();
+        self.emit_function_frame_unwind();
+
+        // Copy arguments into the parameter slots (in the same order as call)
+        for index in 0..argument_header {
+            self.emit_push_integer(index as i64);
+            self.emit_push_integer(0);
+            self.emit_code(PixardisInstruction::Store);
+        }
+
+        self.pop_scope();
+
+        let offset_target = target as i64 - self.current_instruction_index() as i64;
+        self.emit_code(PixardisInstruction::PushOffset(offset_target));
+        self.emit_code(PixardisInstruction::Jump);
+    }
+
+    //
+    // Strength reduction, for expressions the constant folder can see into: a
+    // constant expression becomes its value, an operation with an identity
+    // operand (x + 0, x - 0, x * 1, x / 1) leaves the other operand, and an
+    // int times zero is zero if the other operand can be dropped. Floats only
+    // lose exact identities (not x + 0.0, which makes -0.0 0.0). Casts aren't
+    // folded, as only fixed-point code lowers them to instructions. Returns
+    // false if the expression is left as it is.
+    //
+    pub fn emit_reduced_expression(&mut self, node: &ExpressionNode) -> bool {
+        let (Some(operator), Some(rhs)) = (node.operator.as_deref(), node.expression) else {
+            return false;
+        };
+
+        if !has_cast(node) {
+            match consteval::evaluate(node) {
+                Ok(ConstValue::Integer(value)) => {
+                    self.visit_integer_literal(value);
+                    return true;
+                },
+                Ok(ConstValue::Float(value)) => {
+                    self.visit_float_literal(value);
+                    return true;
+                },
+                _ => (),
+            }
+        }
+
+        let is_zero = |value: &Option<ConstValue>| matches!(value, Some(ConstValue::Integer(0)))
+            || matches!(value, Some(ConstValue::Float(value)) if *value == 0.0 && value.is_sign_positive());
+        let is_one = |value: &Option<ConstValue>| matches!(value, Some(ConstValue::Integer(1)))
+            || matches!(value, Some(ConstValue::Float(value)) if *value == 1.0);
+        let is_integer_zero = |value: &Option<ConstValue>| matches!(value, Some(ConstValue::Integer(0)));
+
+        let (left, right) = (consteval::evaluate_factor(&node.factor).ok(), consteval::evaluate(rhs).ok());
+
+        match operator {
+            "+" if is_integer_zero(&right) => node.factor.accept(self),
+            "+" if is_integer_zero(&left) => rhs.accept(self),
+            "-" if is_zero(&right) => node.factor.accept(self),
+            "*" | "/" if is_one(&right) => node.factor.accept(self),
+            "*" if is_one(&left) => rhs.accept(self),
+            "*" if (is_integer_zero(&right) && is_droppable_factor(&node.factor)) || (is_integer_zero(&left) && is_droppable(rhs)) =>
+                self.visit_integer_literal(0),
+            _ => return false,
+        }
+
+        true
+    }
+
+    //
+    // Loop-invariant code motion: expressions in a loop (its condition,
+    // increment and body) whose value can't change from one iteration to the
+    // next are computed once, into temporaries, where the loop is entered,
+    // and read back inside it. The loop may not evaluate them at all, so only
+    // expressions that can't fail (see is_droppable) are moved.
+    //
+    pub fn emit_loop_invariants(&mut self, expressions: &[&ExpressionNode], statements: &[&StatementNode]) {
+        let mut usage = SymbolUsage::default();
+        expressions.iter().for_each(|expression| usage.add_expression(expression));
+        statements.iter().for_each(|statement| usage.add_statement(statement));
+
+        let mut pending_expressions = expressions.to_vec();
+        let mut pending_statements = statements.to_vec();
+
+        while let Some(statement) = pending_statements.pop() {
+            // A function's body runs in its own frame, not the loop's
+            if let StatementNode::FunctionDeclaration(_) = statement {
+                continue;
+            }
+
+            let (statements, expressions) = statement.children();
+            pending_statements.extend(statements);
+            pending_expressions.extend(expressions);
+        }
+
+        // The largest invariant expressions move, rather than their parts
+        let mut invariants = Vec::new();
+
+        while let Some(expression) = pending_expressions.pop() {
+            if self.hoisted.contains_key(&expression_key(expression)) {
+                continue;
+            }
+
+            if self.is_loop_invariant(expression, &usage) {
+                invariants.push(expression);
+            } else {
+                pending_expressions.extend(expression.children());
+            }
+        }
+
+        for expression in invariants {
+            let symbol_type = self.expression_type(expression).unwrap_or(SymbolType::Int);
+
+            let Some(name) = self.declare_temporary(symbol_type) else {
+                return;
+            };
+
+            expression.accept(self);
+            self.emit_store_variable(&name);
+
+            self.hoisted.insert(expression_key(expression), name);
+        }
+    }
+
+    //
+    // True if an expression in a loop with the given usage is worth computing
+    // before it: it has an operation the constant folder can't do, reads no
+    // arrays, and the loop changes none of what it reads
+    //
+    fn is_loop_invariant(&mut self, node: &ExpressionNode, loop_usage: &SymbolUsage) -> bool {
+        if !matches!(node.operator.as_deref(), Some(operator) if operator != "as") || !is_droppable(node) {
+            return false;
+        }
+
+        if !has_cast(node) && consteval::evaluate(node).is_ok() {
+            return false;
+        }
+
+        let usage = SymbolUsage::of_expression(node);
+
+        loop_usage.preserves(&usage) && usage.reads.iter().all(|name| !matches!(
+            self.scope_manager.find_symbol(name),
+            Some((_, _, SymbolEntry { symbol_type: SymbolType::Array(..), .. }))
+        ))
+    }
+
+    //
+    // The temporary a loop-invariant expression was computed into, if it was
+    //
+    pub fn hoisted_expression(&self, node: &ExpressionNode) -> Option<Atom> {
+        self.hoisted.get(&expression_key(node)).cloned()
+    }
+
+    //
+    // A for loop, or two nested, doing nothing but write a pixel at their
+    // counters (one on each axis) in a colour that doesn't change, fills a
+    // box: it's drawn with a single write_box, of the loops' extents, when
+    // neither is empty. Bounds and colour must be safe to evaluate once (see
+    // is_droppable). The loops' scopes are skipped, as the loops aren't
+    // generated. Returns false if the loop isn't a fill.
+    //
+    pub fn emit_fill_loop(&mut self, node: &ForNode) -> bool {
+        let Some(outer) = counted_loop(node) else {
+            return false;
+        };
+
+        let mut loops = vec![outer];
+
+        if let StatementNode::For(inner) = single_statement(loops[0].body) {
+            loops.extend(counted_loop(inner));
+        }
+
+        let StatementNode::Write([x, y, colour]) = single_statement(loops[loops.len() - 1].body) else {
+            return false;
+        };
+
+        // Each loop counts along its own axis
+        let axis_loop = |axis: &ExpressionNode| loops.iter()
+            .position(|counted| axis.operator.is_none() && is_variable(&axis.factor, &counted.variable));
+        let (x_loop, y_loop) = (axis_loop(x), axis_loop(y));
+
+        if (0..loops.len()).any(|index| [x_loop, y_loop].iter().filter(|axis| **axis == Some(index)).count() != 1) {
+            return false;
+        }
+
+        // Everything else is the same on every iteration
+        let variables: HashSet<Atom> = loops.iter().map(|counted| counted.variable.clone()).collect();
+        let is_invariant = |operand: &ExpressionNode| is_droppable(operand)
+            && SymbolUsage::of_expression(operand).reads.is_disjoint(&variables);
+
+        let unbound_axes = [(x, x_loop), (y, y_loop)].into_iter().filter(|(_, bound)| bound.is_none()).map(|(axis, _)| axis);
+        let mut operands = unbound_axes.chain(Some(colour));
+
+        if !operands.all(is_invariant) || !loops.iter().all(|counted| is_invariant(counted.start) && is_invariant(counted.end)) {
+            return false;
+        }
+
+        if !loops.iter().all(|counted| self.expression_type(counted.start) == Some(SymbolType::Int) && self.expression_type(counted.end) == Some(SymbolType::Int)) {
+            return false;
+        }
+
+        let mut extents = Vec::new();
+
+        for _ in &loops {
+            match self.declare_temporary(SymbolType::Int) {
+                Some(temporary) => extents.push(temporary),
+                None => return false,
+            }
+        }
+
+        self.source_line = node.line + 1;
+
+        // Extent of each loop: max(end - start (+ 1 for <=), 0)
+        for (counted, extent) in loops.iter().zip(&extents) {
+            counted.start.accept(self);
+            counted.end.accept(self);
+
+            if counted.inclusive {
+                self.emit_code(PixardisInstruction::Increment);
+            }
+
+            self.emit_code(PixardisInstruction::Subtract);
+            self.emit_push_integer(0);
+            self.emit_code(PixardisInstruction::Maximum);
+            self.emit_store_variable(extent);
+        }
+
+        // Nothing is drawn unless every loop runs
+        self.emit_push_integer(0);
+
+        for (index, extent) in extents.iter().enumerate() {
+            self.visit_identifier(extent.clone());
+
+            if index > 0 {
+                self.emit_code(PixardisInstruction::Multiply);
+            }
+        }
+
+        self.emit_code(PixardisInstruction::GreaterThan);
+
+        let patch_box_address = self.current_instruction_index();
+        self.emit_code(PixardisInstruction::PushOffset(0));
+        self.emit_code(PixardisInstruction::ConditionalJump);
+
+        let patch_end_address = self.current_instruction_index();
+        self.emit_code(PixardisInstruction::PushOffset(0));
+        self.emit_code(PixardisInstruction::Jump);
+
+        let offset_box_address = (self.current_instruction_index() - patch_box_address) as i64;
+        self.emit_code_patch(PixardisInstruction::PushOffset(offset_box_address), patch_box_address);
+
+        // write_box x, y, w, h, colour; an axis no loop counts along is one pixel wide
+        colour.accept(self);
+
+        for bound in [y_loop, x_loop] {
+            match bound {
+                Some(index) => self.visit_identifier(extents[index].clone()),
+                None => self.emit_push_integer(1),
+            }
+        }
+
+        for (axis, bound) in [(y, y_loop), (x, x_loop)] {
+            match bound {
+                Some(index) => loops[index].start.accept(self),
+                None => axis.accept(self),
+            }
+        }
+
+        self.emit_code(PixardisInstruction::WriteBox);
+
+        let offset_end_address = (self.current_instruction_index() - patch_end_address) as i64;
+        self.emit_code_patch(PixardisInstruction::PushOffset(offset_end_address), patch_end_address);
+
+        self.scope_index += 1 + scope_count(node.body);
+
+        true
+    }
+
+    //
+    // In fixed-point mode, float products, quotients and int/float casts need
+    // rescaling; returns false if the expression needs no special handling
+    //
+    pub fn emit_fixed_point_expression(&mut self, node: &ExpressionNode, scale: i64) -> bool {
+        let push_scale = PixardisInstruction::PushImmediate(pixardis_immediate_integer(scale));
+        let is_float = self.factor_type(&node.factor) == Some(SymbolType::Float);
+
+        match (node.operator.as_deref(), node.expression.as_ref()) {
+            // (a * b) / scale
+            (Some("*"), Some(rhs)) if is_float => {
+                self.emit_code(push_scale);
+                rhs.accept(self);
+                node.factor.accept(self);
+                self.emit_code(PixardisInstruction::Multiply);
+                self.emit_code(PixardisInstruction::Divide);
+            },
+            // (a * scale) / b
+            (Some("/"), Some(rhs)) if is_float => {
+                rhs.accept(self);
+                self.emit_code(push_scale);
+                node.factor.accept(self);
+                self.emit_code(PixardisInstruction::Multiply);
+                self.emit_code(PixardisInstruction::Divide);
+            },
+            (Some("as"), _) => {
+                match (self.factor_type(&node.factor), node.type_name.as_deref()) {
+                    (Some(SymbolType::Int), Some("float")) => {
+                        self.emit_code(push_scale);
+                        node.factor.accept(self);
+                        self.emit_code(PixardisInstruction::Multiply);
+                    },
+                    (Some(SymbolType::Float), Some("int")) => {
+                        self.emit_code(push_scale);
+                        node.factor.accept(self);
+                        self.emit_code(PixardisInstruction::Divide);
+                    },
+                    _ => return false,
+                }
+            },
+            _ => return false,
+        }
+
+        true
+    }
+
+    pub fn expression_type(&mut self, node: &ExpressionNode) -> Option<SymbolType> {
+        match node.operator.as_deref() {
+            Some("as") => SymbolType::from_string(node.type_name.as_deref().unwrap_or_default()),
+            Some("<" | ">" | "<=" | ">=" | "==" | "!=" | "and" | "or" | "&&" | "||") => Some(SymbolType::Bool),
+            _ => self.factor_type(&node.factor),
+        }
+    }
+
+    pub fn factor_type(&mut self, factor: &FactorNode) -> Option<SymbolType> {
+        match factor {
+            FactorNode::BooleanLiteral(_) => Some(SymbolType::Bool),
+            FactorNode::IntegerLiteral(_) => Some(SymbolType::Int),
+            FactorNode::FloatLiteral(_) => Some(SymbolType::Float),
+            FactorNode::ColourLiteral(_) => Some(SymbolType::Colour),
+            FactorNode::Width | FactorNode::Height | FactorNode::Capabilities | FactorNode::RandomInt(_) => Some(SymbolType::Int),
+            FactorNode::Read(_) | FactorNode::AddColour(_) | FactorNode::SubtractColour(_) | FactorNode::Hsv(_) => Some(SymbolType::Colour),
+            FactorNode::HitTest(_) => Some(SymbolType::Bool),
+            FactorNode::RandomFloat | FactorNode::Noise(_) => Some(SymbolType::Float),
+            FactorNode::RandomRange(data) => self.expression_type(data[0]),
+            FactorNode::Param(_, Some(default)) | FactorNode::Load(_, Some(default)) => self.expression_type(default),
+            FactorNode::Param(_, None) | FactorNode::Load(_, None) => Some(SymbolType::Int),
+            FactorNode::Identifier(name) => self.scope_manager.find_symbol(name).map(|(_, _, symbol)| symbol.symbol_type.clone()),
+            FactorNode::ArrayAccess(node) => match self.scope_manager.find_symbol(&node.identifier) {
+                Some((_, _, SymbolEntry { symbol_type: SymbolType::Array(element_type, _), .. })) => Some(*element_type.clone()),
+                _ => None,
+            },
+            FactorNode::FunctionCall(node) => self.get_function_return_type(&node.identifier),
+            FactorNode::Subexpression(node) | FactorNode::Unary(node) => self.expression_type(node),
+        }
+    }
+
     pub fn is_function_declaration_scope(&mut self) -> bool {
         if let Some(current_scope) = self.scope_manager.current() {
             return current_scope.is_function();
@@ -174,5 +662,132 @@ impl<'a> CodeGenerator<'a> {
         } else {
             None
         }
-    }   
-}
\ No newline at end of file
+    }
+
+    // Label of the function the name resolves to from the current scope;
+    // every call to a function shares its label's text
+    pub fn get_function_label(&mut self, name: &str) -> Option<Atom> {
+        self.scope_manager.find_symbol(name)
+            .map(|(scope_id, _, _)| self.scope_manager.interner().intern(&pixardis_mangle_label(name, scope_id)))
+    }
+}
+
+//
+// True if an expression, or one inside it, is a cast
+//
+fn has_cast(node: &ExpressionNode) -> bool {
+    let mut pending = vec![node];
+
+    while let Some(node) = pending.pop() {
+        if node.operator.as_deref() == Some("as") {
+            return true;
+        }
+
+        pending.extend(node.children());
+    }
+
+    false
+}
+
+//
+// True if an expression can be left out without changing what the program
+// does: it only reads variables and constants, and can't fail (no array
+// access, call, or division by anything but a non-zero constant)
+//
+fn is_droppable(node: &ExpressionNode) -> bool {
+    let is_safe_divisor = || match node.expression.map(consteval::evaluate) {
+        Some(Ok(ConstValue::Integer(value))) => value != 0,
+        Some(Ok(ConstValue::Float(value))) => value != 0.0,
+        _ => false,
+    };
+
+    is_droppable_factor(&node.factor)
+        && (!matches!(node.operator.as_deref(), Some("/") | Some("%")) || is_safe_divisor())
+        && node.expression.is_none_or(is_droppable)
+}
+
+//
+// A for loop counting an int up by one:
+// for (let v:int = start; v < end (or v <= end); v = v + 1) body
+//
+struct CountedLoop<'n, 'ast> {
+    variable: Atom,
+    start: &'n ExpressionNode<'ast>,
+    end: &'n ExpressionNode<'ast>,
+    inclusive: bool,
+    body: &'n StatementNode<'ast>,
+}
+
+fn counted_loop<'n, 'ast>(node: &'n ForNode<'ast>) -> Option<CountedLoop<'n, 'ast>> {
+    let (StatementNode::VariableDeclaration(initialiser), Some(condition), Some(StatementNode::Assignment(increment))) =
+        (node.initialiser?, node.condition.as_ref(), node.increment) else {
+        return None;
+    };
+
+    let variable = &initialiser.identifier;
+
+    let inclusive = match condition.operator.as_deref() {
+        Some("<") => false,
+        Some("<=") => true,
+        _ => return None,
+    };
+
+    let is_one = |node: &ExpressionNode| matches!(consteval::evaluate(node), Ok(ConstValue::Integer(1)));
+    let step = &increment.expression;
+
+    let is_step = match (step.operator.as_deref(), step.expression) {
+        (Some("+"), Some(rhs)) =>
+            (is_variable(&step.factor, variable) && is_one(rhs))
+            || (matches!(consteval::evaluate_factor(&step.factor), Ok(ConstValue::Integer(1))) && rhs.operator.is_none() && is_variable(&rhs.factor, variable)),
+        _ => false,
+    };
+
+    if initialiser.type_name != "int" || !is_variable(&condition.factor, variable) || increment.identifier != *variable || increment.array_index.is_some() || !is_step {
+        return None;
+    }
+
+    Some(CountedLoop {
+        variable: variable.clone(),
+        start: &initialiser.expression,
+        end: condition.expression?,
+        inclusive,
+        body: node.body,
+    })
+}
+
+// True if a factor is just the variable (possibly parenthesised)
+fn is_variable(factor: &FactorNode, variable: &Atom) -> bool {
+    match factor {
+        FactorNode::Identifier(name) => name == variable,
+        FactorNode::Subexpression(node) => node.operator.is_none() && is_variable(&node.factor, variable),
+        _ => false,
+    }
+}
+
+// The statement a block of one statement wraps (or the statement itself)
+fn single_statement<'n, 'ast>(node: &'n StatementNode<'ast>) -> &'n StatementNode<'ast> {
+    match node {
+        StatementNode::Block(block) if block.statements.len() == 1 => single_statement(&block.statements[0]),
+        _ => node,
+    }
+}
+
+// Number of scopes a statement opens, itself included
+fn scope_count(node: &StatementNode) -> usize {
+    let opens_scope = matches!(node, StatementNode::Block(_) | StatementNode::For(_) | StatementNode::FunctionDeclaration(_));
+    opens_scope as usize + node.children().0.into_iter().map(scope_count).sum::<usize>()
+}
+
+// Identity of an expression node, while the tree is being generated
+fn expression_key(node: &ExpressionNode) -> *const () {
+    (node as *const ExpressionNode).cast()
+}
+
+fn is_droppable_factor(factor: &FactorNode) -> bool {
+    match factor {
+        FactorNode::BooleanLiteral(_) | FactorNode::IntegerLiteral(_) | FactorNode::FloatLiteral(_) | FactorNode::ColourLiteral(_) |
+        FactorNode::Identifier(_) | FactorNode::Width | FactorNode::Height | FactorNode::Capabilities => true,
+        FactorNode::Subexpression(node) | FactorNode::Unary(node) => is_droppable(node),
+        _ => false,
+    }
+}
@@ -0,0 +1,319 @@
+use shared::pixardis::{PixardisInstruction, pixardis_immediate_integer};
+
+//
+// Mid-level IR the optimiser works on: the generated code split into basic
+// blocks, with control flow between blocks made explicit. A block's jump
+// names the block it goes to rather than a PC-relative offset, so a pass
+// may add or remove instructions freely and the offsets are worked out
+// again when the program is lowered. Inside a block, values() gives an SSA
+// view of the stack code: each value is named by the instruction pushing
+// it, and each instruction by the values it pops and pushes, wherever on
+// the stack those are.
+//
+
+//
+// An instruction, with the scope and source line it was generated for
+//
+#[derive(Debug, Clone)]
+pub struct IrInstruction {
+    pub scope: usize,
+    pub line: usize,
+    pub instruction: PixardisInstruction,
+}
+
+//
+// The jump (or conditional jump) ending a block, to the start of block
+// target; a target one past the last block is the end of the program. The
+// push and jmp (or cjmp) pair is kept for its scope and line, and for
+// whether the push is PC-relative (push #PC+n) or absolute (push n, as the
+// entry stub has), the address being worked out when the program is
+// lowered.
+//
+#[derive(Debug, Clone)]
+pub struct IrExit {
+    pub target: usize,
+    pub code: [IrInstruction; 2],
+}
+
+//
+// Straight-line code entered only at its start: a block ends before a jump
+// target or label, and after a jump, return or halt. A block without an
+// exit, or whose exit is conditional, runs on into the next one unless it
+// returns or halts.
+//
+#[derive(Debug, Clone, Default)]
+pub struct IrBlock {
+    pub code: Vec<IrInstruction>,
+    pub exit: Option<IrExit>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct IrProgram {
+    pub blocks: Vec<IrBlock>,
+}
+
+//
+// A value on the stack, as seen from inside a block
+//
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IrValue {
+    // On the stack (this deep) when the block started, or after the
+    // instruction at the given index, whose stack effect isn't known
+    Incoming { after: Option<usize>, depth: usize },
+    // The slot'th value pushed by the instruction at the given index
+    Defined { instruction: usize, slot: usize },
+}
+
+//
+// The values an instruction pops (top first) and pushes (in push order).
+// An instruction whose stack effect can't be worked out (a call, or an
+// array operation with a computed count) pops everything on the stack it
+// knows of and pushes nothing, the values under it being Incoming again.
+//
+#[derive(Debug, Clone, Default)]
+pub struct IrStep {
+    pub pops: Vec<IrValue>,
+    pub pushes: Vec<IrValue>,
+}
+
+impl IrProgram {
+    //
+    // Split a program into basic blocks. Programs with jumps to computed
+    // addresses (other than push #PC+n or push n just before the jmp or
+    // cjmp), as inline assembly may write, can't be, as where they go isn't
+    // known; for those this gives None.
+    //
+    pub fn lift(code: &[(usize, PixardisInstruction)], lines: &[usize]) -> Option<IrProgram> {
+        use PixardisInstruction::*;
+
+        let is_jump = |index: usize| matches!(code.get(index), Some((_, Jump | ConditionalJump)));
+
+        // Where the jump pushed at an index goes, if it's a jump's address
+        let target = |index: usize| match &code[index].1 {
+            PushOffset(offset) => usize::try_from(index as i64 + offset).ok(),
+            PushImmediate(address) => address.parse().ok(),
+            _ => None,
+        };
+
+        // Where blocks start, with the end of the program as a last one
+        let mut leaders = vec![false; code.len() + 1];
+        leaders[0] = true;
+        leaders[code.len()] = true;
+
+        for (index, (_, instruction)) in code.iter().enumerate() {
+            match instruction {
+                PushOffset(_) if !is_jump(index + 1) => return None,
+                Jump | ConditionalJump => {
+                    let target = index.checked_sub(1).and_then(target).filter(|target| *target <= code.len())?;
+
+                    leaders[target] = true;
+                    leaders[index + 1] = true;
+                },
+                Label(_) => leaders[index] = true,
+                Return | ReturnArray | Halt => leaders[index + 1] = true,
+                _ => (),
+            }
+        }
+
+        // A jump landing between a push #PC+n and its jump splits the pair
+        if (0..code.len()).any(|index| leaders[index] && is_jump(index)) {
+            return None;
+        }
+
+        let starts: Vec<usize> = (0..=code.len()).filter(|index| leaders[*index]).collect();
+        let lift = |index: usize| IrInstruction {
+            scope: code[index].0,
+            line: lines.get(index).copied().unwrap_or(0),
+            instruction: code[index].1.clone(),
+        };
+
+        let blocks = starts.windows(2)
+            .map(|bounds| {
+                let (start, end) = (bounds[0], bounds[1]);
+                let mut block = IrBlock { code: (start..end).map(lift).collect(), exit: None };
+
+                if is_jump(end - 1) {
+                    let jump = block.code.pop()?;
+                    let push = block.code.pop()?;
+                    let target = starts.binary_search(&target(end - 2)?).ok()?;
+
+                    block.exit = Some(IrExit { target, code: [push, jump] });
+                }
+
+                Some(block)
+            })
+            .collect::<Option<Vec<IrBlock>>>()?;
+
+        Some(IrProgram { blocks })
+    }
+
+    //
+    // Join the blocks back into a program, with its line table, working
+    // out the offset of each block's jump
+    //
+    pub fn lower(self) -> (Vec<(usize, PixardisInstruction)>, Vec<usize>) {
+        let mut starts = Vec::with_capacity(self.blocks.len() + 1);
+        let mut size = 0;
+
+        for block in self.blocks.iter() {
+            starts.push(size);
+            size += block.code.len() + if block.exit.is_some() { 2 } else { 0 };
+        }
+
+        starts.push(size);
+
+        let mut code = Vec::with_capacity(size);
+        let mut lines = Vec::with_capacity(size);
+
+        for block in self.blocks {
+            for instruction in block.code {
+                code.push((instruction.scope, instruction.instruction));
+                lines.push(instruction.line);
+            }
+
+            if let Some(IrExit { target, code: [push, jump] }) = block.exit {
+                let address = match push.instruction {
+                    PixardisInstruction::PushImmediate(_) => PixardisInstruction::PushImmediate(pixardis_immediate_integer(starts[target] as i64)),
+                    _ => PixardisInstruction::PushOffset(starts[target] as i64 - code.len() as i64),
+                };
+
+                code.push((push.scope, address));
+                lines.push(push.line);
+                code.push((jump.scope, jump.instruction));
+                lines.push(jump.line);
+            }
+        }
+
+        (code, lines)
+    }
+}
+
+impl IrBlock {
+    //
+    // The SSA view of the block: what each instruction pops and pushes
+    //
+    pub fn values(&self) -> Vec<IrStep> {
+        let mut stack: Vec<IrValue> = Vec::new();
+        let mut incoming = (None, 0);
+        let mut steps = Vec::with_capacity(self.code.len());
+
+        for (index, instruction) in self.code.iter().enumerate() {
+            // An array count popped with the given number of values above it
+            let count = |depth: usize| stack.len().checked_sub(depth + 1)
+                .and_then(|position| self.integer(stack[position]))
+                .and_then(|count| usize::try_from(count).ok());
+
+            let step = match stack_effect(&instruction.instruction, count) {
+                Some((pops, pushes)) => {
+                    let pops = (0..pops)
+                        .map(|_| stack.pop().unwrap_or_else(|| {
+                            incoming.1 += 1;
+                            IrValue::Incoming { after: incoming.0, depth: incoming.1 - 1 }
+                        }))
+                        .collect();
+
+                    let pushes: Vec<IrValue> = (0..pushes).map(|slot| IrValue::Defined { instruction: index, slot }).collect();
+                    stack.extend(pushes.iter().copied());
+
+                    IrStep { pops, pushes }
+                },
+                None => {
+                    incoming = (Some(index), 0);
+                    IrStep { pops: stack.drain(..).rev().collect(), pushes: Vec::new() }
+                },
+            };
+
+            steps.push(step);
+        }
+
+        steps
+    }
+
+    //
+    // The index and instruction of what pushed a value, if it was pushed in
+    // this block
+    //
+    pub fn producer(&self, value: IrValue) -> Option<(usize, &PixardisInstruction)> {
+        match value {
+            IrValue::Defined { instruction, .. } => Some((instruction, &self.code[instruction].instruction)),
+            IrValue::Incoming { .. } => None,
+        }
+    }
+
+    //
+    // A value's integer, if it was pushed here as an integer immediate
+    //
+    pub fn integer(&self, value: IrValue) -> Option<i64> {
+        match self.producer(value)? {
+            (_, PixardisInstruction::PushImmediate(text)) => text.parse().ok(),
+            _ => None,
+        }
+    }
+
+    //
+    // Replace instructions by sequences (possibly empty) of others, where
+    // given; replacements take the scope and line of what they replace
+    //
+    pub fn replace(&mut self, replacements: Vec<Option<Vec<PixardisInstruction>>>) {
+        self.code = std::mem::take(&mut self.code).into_iter().zip(replacements)
+            .flat_map(|(replaced, replacement)| match replacement {
+                Some(instructions) => instructions.into_iter()
+                    .map(|instruction| IrInstruction { instruction, ..replaced.clone() })
+                    .collect(),
+                None => vec![replaced],
+            })
+            .collect();
+    }
+}
+
+// Largest array an operation may move for its values to be tracked one by
+// one; beyond it the stack effect is unknown, so a huge array costs no more
+// to optimise than a call
+const IR_ARRAY_LIMIT: usize = 4096;
+
+//
+// How many values an instruction pops and pushes, if that can be told; the
+// counts of array operations are read off the stack with count(depth)
+//
+fn stack_effect(instruction: &PixardisInstruction, count: impl Fn(usize) -> Option<usize>) -> Option<(usize, usize)> {
+    use PixardisInstruction::*;
+
+    let count = |depth: usize| count(depth).filter(|count| *count <= IR_ARRAY_LIMIT);
+
+    Some(match instruction {
+        Label(_) | Metadata(_, _) | Signature(_, _) | Nop | FrameClose | Halt => (0, 0),
+        PushImmediate(_) | PushColour(_) | PushLabel(_) | PushOffset(_) | PushIndexed(_) |
+        RandomFloat | Width | Height | Capabilities => (0, 1),
+        PushIndexedOffset(_) | Not | Increment | Decrement | RandomInt | Param(_) | LoadKV(_) => (1, 1),
+        Drop | FrameOpen | Allocate | Delay | Clear | SetBrightness | Print | Assert | Jump | StoreKV(_) => (1, 0),
+        Duplicate => (1, 2),
+        Add | Subtract | AddColour | SubtractColour | Multiply | Divide | Modulo | Maximum | Minimum |
+        RandomRange | LessThan | LessEqual | GreaterThan | GreaterEqual | Equal | Read => (2, 1),
+        ConditionalJump | PrintFormatted | PrintFixed => (2, 0),
+        Noise | HsvToRgb => (3, 1),
+        RgbToHsv => (1, 3),
+        Store | Write | PrintFormattedFixed => (3, 0),
+        HitTest => (5, 1),
+        WriteBox | WriteLine => (5, 0),
+        WriteBoxGradient | WriteBoxPattern | WriteTriangle => (7, 0),
+        PushArray(_) => (1, count(0).filter(|count| *count > 0)?),
+        PushArrayCount([_, _, count]) => (0, usize::try_from(*count).ok().filter(|count| (1..=IR_ARRAY_LIMIT).contains(count))?),
+        StoreArray => (3 + count(2)?, 0),
+        DuplicateArray => (2, count(0)? + 1),
+        PrintArray => (1 + count(0)?, 0),
+        // Calls and returns leave what the callee gives
+        _ => return None,
+    })
+}
+
+//
+// Rewrite each basic block of a program in turn, then lower it again. A
+// program that can't be lifted into blocks (see IrProgram::lift) is left
+// as it is.
+//
+pub fn rewrite_blocks(code: &mut Vec<(usize, PixardisInstruction)>, lines: &mut Vec<usize>, rewrite: impl FnMut(&mut IrBlock)) {
+    if let Some(mut program) = IrProgram::lift(code, lines) {
+        program.blocks.iter_mut().for_each(rewrite);
+        (*code, *lines) = program.lower();
+    }
+}
@@ -1,3 +1,4 @@
+pub mod ir;
 pub mod optimiser;
 pub mod generator;
 pub mod visitor;
\ No newline at end of file
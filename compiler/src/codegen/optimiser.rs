@@ -1,5 +1,649 @@
-use shared::pixardis::PixardisInstruction;
+use std::collections::{BTreeMap, HashMap};
 
-pub fn optimise_code_pixardis(code: &mut Vec<(usize, PixardisInstruction)>) -> Vec<(usize, PixardisInstruction)> {
-    code.clone()
-}
\ No newline at end of file
+use shared::pixardis::{PixardisInstruction, pixardis_immediate_integer, pixardis_instruction_to_string};
+
+use crate::codegen::ir::{IrBlock, IrStep, IrValue, rewrite_blocks};
+use crate::common::{
+    logger::{Logger, LoggerMessage},
+    status::CompilationResult,
+};
+
+//
+// An optimisation pass rewrites the program in place. It may change anything
+// but the side-effecting instructions (see is_barrier), which must all stay
+// and in the same order; fixing up PC-relative offsets, and keeping the line
+// table in step, is up to the pass (see replace_instructions).
+//
+pub struct OptimisationPass {
+    pub name: &'static str,
+    pub run: fn(&mut Vec<(usize, PixardisInstruction)>, &mut Vec<usize>),
+}
+
+// Passes in the order they run; min-max comes first, as its pattern
+// includes the pushes the others rewrite, and constants are folded before
+// inc-dec takes the additions of one
+pub const OPTIMISATION_PASSES: &[OptimisationPass] = &[
+    OptimisationPass { name: "min-max", run: min_max },
+    OptimisationPass { name: "const-prop", run: constant_propagation },
+    OptimisationPass { name: "dead-store", run: dead_store_elimination },
+    OptimisationPass { name: "inc-dec", run: increment_decrement },
+    OptimisationPass { name: "dup", run: duplicate },
+];
+
+pub fn optimise_code_pixardis(code: &mut Vec<(usize, PixardisInstruction)>, lines: &mut Vec<usize>, logger: &Logger, debug_passes: bool) -> (Vec<(usize, PixardisInstruction)>, CompilationResult) {
+    run_passes(OPTIMISATION_PASSES, code, lines, logger, debug_passes)
+}
+
+//
+// Instructions whose effects can be seen outside the VM, or that end the
+// program: passes must neither drop nor reorder them
+//
+pub fn is_barrier(instruction: &PixardisInstruction) -> bool {
+    matches!(instruction,
+        PixardisInstruction::Print | PixardisInstruction::PrintArray | PixardisInstruction::PrintFormatted |
+        PixardisInstruction::PrintFixed | PixardisInstruction::PrintFormattedFixed |
+        PixardisInstruction::Delay | PixardisInstruction::Clear | PixardisInstruction::SetBrightness |
+        PixardisInstruction::Write | PixardisInstruction::WriteBox | PixardisInstruction::WriteLine |
+        PixardisInstruction::WriteBoxGradient | PixardisInstruction::WriteBoxPattern | PixardisInstruction::WriteTriangle |
+        PixardisInstruction::StoreKV(_) | PixardisInstruction::Assert | PixardisInstruction::Halt)
+}
+
+//
+// Run passes over a program in turn. A pass that drops, adds or reorders
+// barriers is reported and its changes (to the line table too) discarded,
+// as, in debug builds, is one that leaves a PC-relative jump landing
+// somewhere it can't (see offset_problem); with debug_passes, each pass's
+// changes are logged.
+//
+pub fn run_passes(passes: &[OptimisationPass], code: &mut Vec<(usize, PixardisInstruction)>, lines: &mut Vec<usize>, logger: &Logger, debug_passes: bool) -> (Vec<(usize, PixardisInstruction)>, CompilationResult) {
+    let mut program = std::mem::take(code);
+    let mut status = CompilationResult::Success;
+
+    for pass in passes {
+        let mut candidate = program.clone();
+        let mut candidate_lines = lines.clone();
+        (pass.run)(&mut candidate, &mut candidate_lines);
+
+        if let Some(change) = barrier_change(&program, &candidate) {
+            logger.print_message(LoggerMessage::Warning,
+                format!("Optimisation pass '{}' {}; its changes were discarded.", pass.name, change).as_str());
+            status = CompilationResult::Warning;
+            continue;
+        }
+
+        // Only checked where the offsets were sound to begin with, as inline
+        // assembly may jump anywhere it likes
+        if cfg!(debug_assertions) && offset_problem(&program).is_none() {
+            if let Some(problem) = offset_problem(&candidate) {
+                logger.print_message(LoggerMessage::Warning,
+                    format!("Optimisation pass '{}' {}; its changes were discarded.", pass.name, problem).as_str());
+                status = CompilationResult::Warning;
+                continue;
+            }
+        }
+
+        let (start, removed, added) = changed_range(&program, &candidate);
+
+        if debug_passes {
+            report_pass(logger, pass.name, &program, &candidate, start, removed, added);
+        }
+
+        program = candidate;
+        *lines = candidate_lines;
+    }
+
+    (program, status)
+}
+
+//
+// How a pass changed the program's barriers, if it did: the barrier kinds
+// whose count changed (e.g. "print 3 -> 2"), or that they were reordered
+//
+fn barrier_change(before: &[(usize, PixardisInstruction)], after: &[(usize, PixardisInstruction)]) -> Option<String> {
+    let barriers = |program: &[(usize, PixardisInstruction)]| -> Vec<PixardisInstruction> {
+        program.iter()
+            .filter(|(_, instruction)| is_barrier(instruction))
+            .map(|(_, instruction)| instruction.clone())
+            .collect()
+    };
+
+    let (before, after) = (barriers(before), barriers(after));
+
+    if before == after {
+        return None;
+    }
+
+    let mut counts: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+
+    for instruction in before.iter() {
+        counts.entry(pixardis_instruction_to_string(instruction.clone())).or_default().0 += 1;
+    }
+
+    for instruction in after.iter() {
+        counts.entry(pixardis_instruction_to_string(instruction.clone())).or_default().1 += 1;
+    }
+
+    let changed: Vec<String> = counts.iter()
+        .filter(|(_, (before, after))| before != after)
+        .map(|(mnemonic, (before, after))| format!("{} {} -> {}", mnemonic, before, after))
+        .collect();
+
+    match changed.is_empty() {
+        true => Some(String::from("reordered side-effecting instructions")),
+        false => Some(format!("changed the number of side-effecting instructions ({})", changed.join(", "))),
+    }
+}
+
+//
+// The first PC-relative jump in a program that lands outside it, or
+// between a jump and the push of its address, if any
+//
+fn offset_problem(program: &[(usize, PixardisInstruction)]) -> Option<String> {
+    use PixardisInstruction::*;
+
+    program.iter().enumerate().find_map(|(index, (_, instruction))| {
+        let PushOffset(offset) = instruction else {
+            return None;
+        };
+
+        let target = index as i64 + offset;
+
+        match usize::try_from(target).ok().filter(|target| *target <= program.len()) {
+            None => Some(format!("left the jump pushed at [{}] landing outside the program (at {})", index, target)),
+            Some(target) if matches!(program.get(target), Some((_, Jump | ConditionalJump)))
+                && matches!(target.checked_sub(1).map(|push| &program[push].1), Some(PushOffset(_) | PushImmediate(_))) =>
+                Some(format!("left the jump pushed at [{}] landing between a jump and its address (at {})", index, target)),
+            Some(_) => None,
+        }
+    })
+}
+
+//
+// The part of a program a pass changed, as (start, instructions removed,
+// instructions added), found by trimming the common prefix and suffix
+//
+fn changed_range(before: &[(usize, PixardisInstruction)], after: &[(usize, PixardisInstruction)]) -> (usize, usize, usize) {
+    let prefix = before.iter().zip(after.iter())
+        .take_while(|((_, before), (_, after))| before == after)
+        .count();
+
+    let suffix = before[prefix..].iter().rev().zip(after[prefix..].iter().rev())
+        .take_while(|((_, before), (_, after))| before == after)
+        .count();
+
+    (prefix, before.len() - prefix - suffix, after.len() - prefix - suffix)
+}
+
+fn report_pass(logger: &Logger, name: &str, before: &[(usize, PixardisInstruction)], after: &[(usize, PixardisInstruction)], start: usize, removed: usize, added: usize) {
+    if removed == 0 && added == 0 {
+        logger.print_message(LoggerMessage::Info, format!("Pass '{}': no changes.", name).as_str());
+        return;
+    }
+
+    logger.print_message(LoggerMessage::Info,
+        format!("Pass '{}': {} instruction(s) at [{}] replaced by {} ({} -> {} in total).",
+            name, removed, start, added, before.len(), after.len()).as_str());
+
+    for (_, instruction) in &before[start..start + removed] {
+        logger.print_message(LoggerMessage::Info, format!("  - {}", pixardis_instruction_to_string(instruction.clone())).as_str());
+    }
+
+    for (_, instruction) in &after[start..start + added] {
+        logger.print_message(LoggerMessage::Info, format!("  + {}", pixardis_instruction_to_string(instruction.clone())).as_str());
+    }
+}
+
+//
+// Remove program output (print instructions) and, optionally, delays, so a
+// finished program runs at full speed. Their operands are still evaluated
+// (they may call functions) and then dropped; PC-relative offsets are fixed
+// up for the change in code size. The line table is kept in step, with any
+// replacement instructions taking the line of the instruction they replace.
+//
+pub fn strip_debug_io(code: &mut Vec<(usize, PixardisInstruction)>, lines: &mut Vec<usize>, strip_delays: bool) {
+    let mut replacements: Vec<Option<Vec<PixardisInstruction>>> = vec![None; code.len()];
+
+    for index in 0..code.len() {
+        let drops = match &code[index].1 {
+            PixardisInstruction::Print => 1,
+            PixardisInstruction::PrintFormatted | PixardisInstruction::PrintFixed => 2,
+            PixardisInstruction::PrintFormattedFixed => 3,
+            PixardisInstruction::Delay if strip_delays => 1,
+            // Array prints pop a count pushed just before them, then the elements
+            PixardisInstruction::PrintArray => match index.checked_sub(1).map(|previous| &code[previous].1) {
+                Some(PixardisInstruction::PushImmediate(count)) => match count.parse::<usize>() {
+                    Ok(count) => {
+                        replacements[index - 1] = Some(Vec::new());
+                        count
+                    },
+                    Err(_) => continue,
+                },
+                _ => continue,
+            },
+            _ => continue,
+        };
+
+        replacements[index] = Some(vec![PixardisInstruction::Drop; drops]);
+    }
+
+    replace_instructions(code, lines, replacements);
+}
+
+//
+// Replace instructions by sequences (possibly empty) of others, where given.
+// PC-relative offsets that are kept are fixed up for the change in code
+// size, a jump to a replaced instruction landing on the start of its
+// replacement. The line table is kept in step, with replacement
+// instructions taking the line of the instruction they replace.
+//
+pub fn replace_instructions(code: &mut Vec<(usize, PixardisInstruction)>, lines: &mut Vec<usize>, replacements: Vec<Option<Vec<PixardisInstruction>>>) {
+    // New index of each instruction (and of the end of the program), and
+    // the old and new indices of PC-relative pushes
+    let mut relocation = Vec::with_capacity(code.len() + 1);
+    let mut offsets = Vec::new();
+    let mut stripped = Vec::with_capacity(code.len());
+    let mut stripped_lines = Vec::with_capacity(code.len());
+
+    for (index, ((scope, instruction), replacement)) in std::mem::take(code).into_iter().zip(replacements).enumerate() {
+        relocation.push(stripped.len());
+
+        let line = lines.get(index).copied().unwrap_or(0);
+
+        match replacement {
+            Some(instructions) => {
+                stripped_lines.extend(std::iter::repeat_n(line, instructions.len()));
+                stripped.extend(instructions.into_iter().map(|instruction| (scope, instruction)));
+            },
+            None => {
+                if let PixardisInstruction::PushOffset(_) = instruction {
+                    offsets.push((index, stripped.len()));
+                }
+
+                stripped.push((scope, instruction));
+                stripped_lines.push(line);
+            },
+        }
+    }
+
+    relocation.push(stripped.len());
+
+    // Offsets are relative to the pushing instruction
+    for (index, relocated) in offsets {
+        if let PixardisInstruction::PushOffset(offset) = &mut stripped[relocated].1 {
+            let target = (index as i64 + *offset).clamp(0, relocation.len() as i64 - 1) as usize;
+            *offset = relocation[target] as i64 - relocated as i64;
+        }
+    }
+
+    *code = stripped;
+    *lines = stripped_lines;
+}
+
+// How many instructions from the start of a window to replace, and with what
+type PeepholeRule = fn(&[PixardisInstruction]) -> Option<(usize, Vec<PixardisInstruction>)>;
+
+//
+// Rewrite a program a window at a time: at each instruction, rewrite gives
+// how many instructions from there it replaces, and with what (which must
+// not include PC-relative pushes), or None to leave it be. A window that a
+// jump from outside it lands inside of is left alone.
+//
+fn peephole(code: &mut Vec<(usize, PixardisInstruction)>, lines: &mut Vec<usize>, rewrite: PeepholeRule) {
+    let instructions: Vec<PixardisInstruction> = code.iter().map(|(_, instruction)| instruction.clone()).collect();
+
+    // Where each PC-relative jump lands, by the instruction that pushes it
+    let jumps: Vec<(usize, usize)> = instructions.iter().enumerate()
+        .filter_map(|(index, instruction)| match instruction {
+            PixardisInstruction::PushOffset(offset) => usize::try_from(index as i64 + offset).ok().map(|target| (index, target)),
+            _ => None,
+        })
+        .collect();
+
+    let mut replacements: Vec<Option<Vec<PixardisInstruction>>> = vec![None; instructions.len()];
+    let mut index = 0;
+
+    while index < instructions.len() {
+        let window = rewrite(&instructions[index..]).filter(|(length, _)| {
+            let end = index + length;
+            !jumps.iter().any(|&(source, target)| (source < index || source >= end) && target > index && target < end)
+        });
+
+        match window {
+            Some((length, replacement)) => {
+                replacements[index] = Some(replacement);
+
+                for replaced in replacements[index + 1..index + length].iter_mut() {
+                    *replaced = Some(Vec::new());
+                }
+
+                index += length;
+            },
+            None => index += 1,
+        }
+    }
+
+    replace_instructions(code, lines, replacements);
+}
+
+//
+// A push of a single value that reads nothing off the stack, and so can be
+// moved past or repeated in place of another
+//
+fn is_simple_push(instruction: &PixardisInstruction) -> bool {
+    matches!(instruction,
+        PixardisInstruction::PushImmediate(_) | PixardisInstruction::PushColour(_) | PixardisInstruction::PushIndexed(_))
+}
+
+fn is_one(instruction: &PixardisInstruction) -> bool {
+    matches!(instruction, PixardisInstruction::PushImmediate(value) if value == "1")
+}
+
+fn is_constant_push(instruction: &PixardisInstruction) -> bool {
+    matches!(instruction, PixardisInstruction::PushImmediate(_) | PixardisInstruction::PushColour(_))
+}
+
+//
+// Constant propagation within basic blocks: once a variable is stored a
+// constant, reading it (push [offset:frame]) pushes the constant instead,
+// until the block ends or the variable may be written again. Constants are
+// folded as they meet (see fold_constants), which may make more stores
+// constant, until nothing more changes.
+//
+fn constant_propagation(code: &mut Vec<(usize, PixardisInstruction)>, lines: &mut Vec<usize>) {
+    rewrite_blocks(code, lines, |block| while propagate_constants(block) | fold_constants(block) {});
+}
+
+//
+// The [offset, frame] a store writes to, if both are integer immediates
+//
+fn store_address(block: &IrBlock, step: &IrStep) -> Option<[i64; 2]> {
+    match step.pops[..] {
+        [frame, offset, _] => Some([block.integer(offset)?, block.integer(frame)?]),
+        _ => None,
+    }
+}
+
+//
+// One round of constant propagation; true if any read was replaced. Array
+// stores and stores to computed addresses forget every variable, and
+// frames opening or closing move what's known a frame further out or in.
+//
+fn propagate_constants(block: &mut IrBlock) -> bool {
+    use PixardisInstruction::*;
+
+    let steps = block.values();
+
+    // Constant push giving each variable's value, by [offset, frame]
+    let mut known: HashMap<[i64; 2], PixardisInstruction> = HashMap::new();
+    let mut changed = false;
+
+    for (index, step) in steps.iter().enumerate() {
+        match &block.code[index].instruction {
+            PushIndexed(address) => {
+                if let Some(constant) = known.get(address) {
+                    block.code[index].instruction = constant.clone();
+                    changed = true;
+                }
+            },
+            Store => {
+                let value = step.pops.get(2)
+                    .and_then(|value| block.producer(*value))
+                    .map(|(_, instruction)| instruction.clone())
+                    .filter(is_constant_push);
+
+                match (store_address(block, step), value) {
+                    (Some(address), Some(value)) => { known.insert(address, value); },
+                    (Some(address), None) => { known.remove(&address); },
+                    (None, _) => known.clear(),
+                }
+            },
+            FrameOpen => {
+                known = known.drain().map(|([offset, frame], value)| ([offset, frame + 1], value)).collect();
+            },
+            FrameClose => {
+                known = known.drain()
+                    .filter(|([_, frame], _)| *frame > 0)
+                    .map(|([offset, frame], value)| ([offset, frame - 1], value))
+                    .collect();
+            },
+            StoreArray | Allocate | Call | Return | ReturnArray | Halt => known.clear(),
+            _ => (),
+        }
+    }
+
+    changed
+}
+
+//
+// Dead store elimination within basic blocks: a store to a variable that is
+// stored again, or whose frame is closed (or returned from), before any
+// read of it is dropped, along with its value if that was a plain push
+// (else the value is computed and dropped). Anything that may read
+// variables the pass can't see counts as reading all of them: array reads
+// (their offsets are computed), calls (callees reach outer frames), and
+// the end of the block.
+//
+fn dead_store_elimination(code: &mut Vec<(usize, PixardisInstruction)>, lines: &mut Vec<usize>) {
+    rewrite_blocks(code, lines, |block| {
+        use PixardisInstruction::*;
+
+        let steps = block.values();
+
+        // Stores not yet read, by [offset, frame], and those found dead
+        let mut pending: HashMap<[i64; 2], usize> = HashMap::new();
+        let mut dead = Vec::new();
+
+        for (index, step) in steps.iter().enumerate() {
+            match &block.code[index].instruction {
+                PushIndexed(address) => {
+                    pending.remove(address);
+                },
+                Store => {
+                    if let Some(address) = store_address(block, step) {
+                        dead.extend(pending.insert(address, index));
+                    }
+                },
+                FrameOpen => {
+                    pending = pending.drain().map(|([offset, frame], store)| ([offset, frame + 1], store)).collect();
+                },
+                FrameClose | Return | ReturnArray => {
+                    dead.extend(pending.iter().filter(|([_, frame], _)| *frame == 0).map(|(_, store)| *store));
+
+                    pending = match block.code[index].instruction {
+                        FrameClose => pending.drain()
+                            .filter(|([_, frame], _)| *frame > 0)
+                            .map(|([offset, frame], store)| ([offset, frame - 1], store))
+                            .collect(),
+                        _ => HashMap::new(),
+                    };
+                },
+                PushIndexedOffset(_) | PushArray(_) | PushArrayCount(_) | Call | Halt => pending.clear(),
+                _ => (),
+            }
+        }
+
+        let mut replacements: Vec<Option<Vec<PixardisInstruction>>> = vec![None; block.code.len()];
+
+        for store in dead {
+            let [frame, offset, value] = steps[store].pops[..] else {
+                continue;
+            };
+
+            // The address is pushed as immediates (see store_address)
+            for address in [frame, offset] {
+                if let Some((push, _)) = block.producer(address) {
+                    replacements[push] = Some(Vec::new());
+                }
+            }
+
+            match block.producer(value).filter(|(_, instruction)| is_simple_push(instruction)) {
+                Some((push, _)) => {
+                    replacements[push] = Some(Vec::new());
+                    replacements[store] = Some(Vec::new());
+                },
+                None => replacements[store] = Some(vec![Drop]),
+            }
+        }
+
+        block.replace(replacements);
+    });
+}
+
+//
+// Integer arithmetic on constants, as constant propagation leaves it, is
+// done here: add, sub, mul, max and min of two integer immediates, and inc
+// and dec of one, become a push of the result, the immediates going.
+// Division is left to the VM, to report division by zero, as is anything
+// that overflows. Repeats until nothing more folds, so chains of
+// operations fold through; true if anything did.
+//
+fn fold_constants(block: &mut IrBlock) -> bool {
+    use PixardisInstruction::*;
+
+    let mut changed = false;
+
+    loop {
+        let steps = block.values();
+        let mut replacements: Vec<Option<Vec<PixardisInstruction>>> = vec![None; block.code.len()];
+        let mut folded = false;
+
+        for (index, step) in steps.iter().enumerate() {
+            let operands: Option<Vec<i64>> = step.pops.iter().map(|value| block.integer(*value)).collect();
+
+            let value = match (&block.code[index].instruction, operands.as_deref()) {
+                (Increment, Some(&[a])) => a.checked_add(1),
+                (Decrement, Some(&[a])) => a.checked_sub(1),
+                (Add, Some(&[a, b])) => a.checked_add(b),
+                (Subtract, Some(&[a, b])) => a.checked_sub(b),
+                (Multiply, Some(&[a, b])) => a.checked_mul(b),
+                (Maximum, Some(&[a, b])) => Some(a.max(b)),
+                (Minimum, Some(&[a, b])) => Some(a.min(b)),
+                _ => None,
+            };
+
+            if let Some(value) = value {
+                for (push, _) in step.pops.iter().filter_map(|value| block.producer(*value)) {
+                    replacements[push] = Some(Vec::new());
+                }
+
+                replacements[index] = Some(vec![PushImmediate(pixardis_immediate_integer(value))]);
+                folded = true;
+            }
+        }
+
+        if !folded {
+            return changed;
+        }
+
+        block.replace(replacements);
+        changed = true;
+    }
+}
+
+//
+// Adding or subtracting one: x + 1 (push 1, push x, add) and 1 + x become
+// push x, inc, and x - 1 becomes push x, dec, wherever x is computed
+//
+fn increment_decrement(code: &mut Vec<(usize, PixardisInstruction)>, lines: &mut Vec<usize>) {
+    rewrite_blocks(code, lines, |block| {
+        use PixardisInstruction::*;
+
+        let steps = block.values();
+        let mut replacements: Vec<Option<Vec<PixardisInstruction>>> = vec![None; block.code.len()];
+
+        // The push of a one among the values given, if pushed here
+        let one = |values: &[IrValue]| values.iter()
+            .filter_map(|value| block.producer(*value))
+            .find(|(_, instruction)| is_one(instruction))
+            .map(|(push, _)| push);
+
+        for (index, step) in steps.iter().enumerate() {
+            let rewrite = match (&block.code[index].instruction, &step.pops[..]) {
+                // b first, as 1 + x is pushed 1 first
+                (Add, [a, b]) => one(&[*b, *a]).map(|push| (push, Increment)),
+                (Subtract, [_, b]) => one(&[*b]).map(|push| (push, Decrement)),
+                _ => None,
+            };
+
+            if let Some((push, instruction)) = rewrite {
+                replacements[push] = Some(Vec::new());
+                replacements[index] = Some(vec![instruction]);
+            }
+        }
+
+        block.replace(replacements);
+    });
+}
+
+//
+// A variable read twice running (as in x * x) is read once and duplicated
+//
+fn duplicate(code: &mut Vec<(usize, PixardisInstruction)>, lines: &mut Vec<usize>) {
+    peephole(code, lines, |window| match window {
+        [first @ PixardisInstruction::PushIndexed(a), PixardisInstruction::PushIndexed(b), ..] if a == b =>
+            Some((2, vec![first.clone(), PixardisInstruction::Duplicate])),
+        _ => None,
+    })
+}
+
+//
+// An if/else assigning one of the two values it compares to the same
+// variable, as generated for
+//
+//     if (a > b) { m = a; } else { m = b; }
+//
+// becomes max (or min) of the values and a single store. Each branch is a
+// block of its own, with no locals, so inside it the values and the
+// variable are a frame further out.
+//
+fn min_max(code: &mut Vec<(usize, PixardisInstruction)>, lines: &mut Vec<usize>) {
+    use PixardisInstruction::*;
+
+    // The value a push inside a branch block gives, as pushed outside it
+    fn unnest(push: &PixardisInstruction) -> Option<PixardisInstruction> {
+        match push {
+            PushIndexed([offset, frame]) if *frame >= 1 => Some(PushIndexed([*offset, frame - 1])),
+            PushImmediate(_) | PushColour(_) => Some(push.clone()),
+            _ => None,
+        }
+    }
+
+    peephole(code, lines, |window| {
+        let [b, a, comparison, PushOffset(4), ConditionalJump, PushOffset(11), Jump,
+             PushImmediate(then_size), FrameOpen, then_value, PushImmediate(then_offset), PushImmediate(then_frame), Store, FrameClose,
+             PushOffset(9), Jump,
+             PushImmediate(else_size), FrameOpen, else_value, PushImmediate(else_offset), PushImmediate(else_frame), Store, FrameClose, ..] = window else {
+            return None;
+        };
+
+        if !is_simple_push(a) || !is_simple_push(b) || then_size != "0" || else_size != "0"
+            || then_offset != else_offset || then_frame != else_frame {
+            return None;
+        }
+
+        let frame = then_frame.parse::<i64>().ok().filter(|frame| *frame >= 1)?;
+        let (then_value, else_value) = (unnest(then_value)?, unnest(else_value)?);
+
+        // The branch taken when a compares greater (or less) than b
+        let greater = match comparison {
+            GreaterThan | GreaterEqual => true,
+            LessThan | LessEqual => false,
+            _ => return None,
+        };
+
+        let larger = match (&then_value, &else_value) {
+            (then_value, else_value) if then_value == a && else_value == b => greater,
+            (then_value, else_value) if then_value == b && else_value == a => !greater,
+            _ => return None,
+        };
+
+        Some((23, vec![
+            b.clone(),
+            a.clone(),
+            if larger { Maximum } else { Minimum },
+            PushImmediate(then_offset.clone()),
+            PushImmediate(pixardis_immediate_integer(frame - 1)),
+            Store,
+        ]))
+    })
+}
@@ -1,32 +1,37 @@
 use crate::{
     analysis::symbol::SymbolType, 
+    common::{logger::LoggerError, status::CompilationResult},
     parser::ast::*
 };
 use super::generator::CodeGenerator;
-use shared::pixardis::PixardisInstruction;
+use shared::{intern::Atom, pixardis::{PixardisInstruction, PIXARDIS_ENTRY_LABEL, pixardis_instruction_from_string}};
 
-impl AbstractSyntaxTreeVisitor for CodeGenerator<'_> {
+impl AbstractSyntaxTreeVisitor for CodeGenerator<'_, '_> {
     fn visit_program(&mut self, node: &ProgramNode) {
         // We assume scope with id 0 is the global scope
         self.reset_scope();
 
+        // Metadata directives go first, so loaders find them without
+        // scanning the whole program
+        for (key, value) in &node.metadata {
+            self.emit_code(PixardisInstruction::Metadata(key.clone(), value.clone()));
+        }
+
         // No need to emit the entry point label since the
         // code organisation step adds it when global scope
         // instructions are grouped together.
-        self.emit_code(PixardisInstruction::Label("main".to_string()));
+        self.emit_code(PixardisInstruction::Label(self.scope_manager.interner().intern(PIXARDIS_ENTRY_LABEL)));
 
         // This stub enables us to bypass the halt check in the VM
         // initialiser. It is required because the VM expects the
         // program to end with a halt instruction before it runs
         // into a label.
-        self.emit_code(PixardisInstruction::PushImmediate("4".to_string()));
+        let target = self.current_instruction_index() + 3;
+        self.emit_push_integer(target as i64);
         self.emit_code(PixardisInstruction::Jump);
         self.emit_code(PixardisInstruction::Halt);
 
-        let symbol_table = self.symbol_table().unwrap();
-        let count = symbol_table.size();
-        
-        self.emit_code(PixardisInstruction::PushImmediate(count.to_string()));
+        self.emit_frame_size();
         self.emit_code(PixardisInstruction::FrameOpen);
 
         for statement in &node.statements {            
@@ -40,10 +45,7 @@ impl AbstractSyntaxTreeVisitor for CodeGenerator<'_> {
     fn visit_block(&mut self, node: &BlockNode) {
         self.next_scope();
 
-        let symbol_table = self.symbol_table().unwrap();
-        let count = symbol_table.size();
-
-        self.emit_code(PixardisInstruction::PushImmediate(count.to_string()));
+        self.emit_frame_size();
         self.emit_code(PixardisInstruction::FrameOpen);
 
         for statement in &node.statements {
@@ -56,14 +58,17 @@ impl AbstractSyntaxTreeVisitor for CodeGenerator<'_> {
 
     fn visit_unscoped_block(&mut self, node: &UnscopedBlockNode) {
         // We need to use alloc to extend the stack frame
-        let symbol_table = self.symbol_table().unwrap();
-        let count = symbol_table.size();
 
         // TODO:
         // Need to subtract parameter count from count
-        self.emit_code(PixardisInstruction::PushImmediate(count.to_string()));
+        self.emit_frame_size();
         self.emit_code(PixardisInstruction::Allocate);
 
+        // Tail calls jump here, after the function frame has been extended
+        if let Some((_, target @ None)) = &mut self.tail_call_target {
+            *target = Some(self.instruction_index);
+        }
+
         for statement in &node.statements {
             statement.accept(self);
         }
@@ -81,12 +86,14 @@ impl AbstractSyntaxTreeVisitor for CodeGenerator<'_> {
         let symbol_table = self.symbol_table().unwrap();
         let symbol = symbol_table.get(&node.identifier).unwrap();
 
-        self.emit_code(PixardisInstruction::PushImmediate(symbol.offset.unwrap().to_string()));
-        self.emit_code(PixardisInstruction::PushImmediate("0".to_string()));
+        self.emit_push_integer(symbol.offset.unwrap() as i64);
+        self.emit_push_integer(0);
         self.emit_code(PixardisInstruction::Store);
     }
 
     fn visit_array_declaration(&mut self, node: &ArrayDeclarationNode) {
+        self.source_line = node.line + 1;
+
         let symbol_table = self.symbol_table().unwrap();
         let symbol = symbol_table.get(&node.identifier).unwrap();
         let symbol_index = symbol.offset.unwrap();        
@@ -97,27 +104,38 @@ impl AbstractSyntaxTreeVisitor for CodeGenerator<'_> {
             for expression in initialiser.iter().rev() {
                 expression.accept(self);
             }
+        } else if let Some(fill) = &node.fill {
+            // Evaluate fill value once and replicate it
+            fill.accept(self);
+
+            if node.size - 1 > 0 {
+                self.emit_push_integer(node.size - 1);
+                self.emit_code(PixardisInstruction::DuplicateArray);
+            }
         } else {
-            self.emit_code(PixardisInstruction::PushImmediate("0".to_string()));
+            // No initialiser; elements are explicitly zero-filled
+            self.emit_push_integer(0);
             
             if node.size - 1 > 0 {
-                self.emit_code(PixardisInstruction::PushImmediate((node.size - 1).to_string()));
+                self.emit_push_integer(node.size - 1);
                 self.emit_code(PixardisInstruction::DuplicateArray);
             }
 
             // for _ in 0..node.size {
             //     // Push zero as initialiser
-            //     self.emit_code(PixardisInstruction::PushImmediate("0".to_string()));
+            //     self.emit_push_integer(0);
             // }
         }
 
-        self.emit_code(PixardisInstruction::PushImmediate(symbol_size.to_string()));
-        self.emit_code(PixardisInstruction::PushImmediate(symbol_index.to_string()));
-        self.emit_code(PixardisInstruction::PushImmediate("0".to_string()));
+        self.emit_push_integer(symbol_size as i64);
+        self.emit_push_integer(symbol_index as i64);
+        self.emit_push_integer(0);
         self.emit_code(PixardisInstruction::StoreArray);
     }
 
     fn visit_function_declaration(&mut self, node: &FunctionDeclarationNode) {                
+        self.source_line = node.line + 1;
+
         // Functions are encapsulated with jumps 
         // to prevent execution of function code 
         // without explicit calls
@@ -132,14 +150,24 @@ impl AbstractSyntaxTreeVisitor for CodeGenerator<'_> {
             parameter.accept(self);
         }
 
+        // Declare how many argument values calls pass, so the VM can check them
+        let argument_size = self.get_function_argument_types(&node.identifier)
+            .unwrap()
+            .iter()
+            .fold(0, |acc, arg| acc + arg.symbol_type.size());
+        let label = self.get_function_label(&node.identifier).unwrap();
+        self.emit_code(PixardisInstruction::Signature(label.clone(), argument_size));
+
         // Emit label for function entry point
-        self.emit_code(PixardisInstruction::Label(node.identifier.clone()));
+        self.emit_code(PixardisInstruction::Label(label.clone()));
 
         // Enter function scope (VM does this automatically)
         self.next_scope();
 
-        // Process function body
+        // Process function body (the body records where tail calls resume)
+        let enclosing_target = self.tail_call_target.replace((label, None));
         node.body.accept(self);
+        self.tail_call_target = enclosing_target;
 
         // Exit function scope
         self.previous_scope();
@@ -161,33 +189,53 @@ impl AbstractSyntaxTreeVisitor for CodeGenerator<'_> {
         let (_, scope_distance, symbol) = self.scope_manager.find_symbol(&node.identifier.as_str()).unwrap();
 
         // Get frame, offset and size
-        let frame = scope_distance.clone().to_string();
-        let offset = symbol.offset.clone().unwrap().to_string();
+        let frame = scope_distance as i64;
+        let offset = symbol.offset.unwrap() as i64;
         let symbol_type = symbol.symbol_type.clone();
 
         // Is this an array?
         if let SymbolType::Array(_, s) = symbol_type {
             // Is array indexed?
             if let Some(array_index) = node.array_index.as_ref() {
-                self.emit_code(PixardisInstruction::PushImmediate(offset.clone()));
+                self.emit_push_integer(offset);
                 array_index.accept(self);
                 self.emit_code(PixardisInstruction::Add);
-                self.emit_code(PixardisInstruction::PushImmediate(frame));
+                self.emit_push_integer(frame);
                 self.emit_code(PixardisInstruction::Store);
             } else {
-                self.emit_code(PixardisInstruction::PushImmediate(s.to_string()));
-                self.emit_code(PixardisInstruction::PushImmediate(offset));
-                self.emit_code(PixardisInstruction::PushImmediate(frame));
+                self.emit_push_integer(s);
+                self.emit_push_integer(offset);
+                self.emit_push_integer(frame);
                 self.emit_code(PixardisInstruction::StoreArray);
             }
         } else {
-            self.emit_code(PixardisInstruction::PushImmediate(offset.clone()));
-            self.emit_code(PixardisInstruction::PushImmediate(frame));
+            self.emit_push_integer(offset);
+            self.emit_push_integer(frame);
             self.emit_code(PixardisInstruction::Store);
     }
     }
 
     fn visit_expression(&mut self, node: &ExpressionNode) {
+        self.source_line = node.line + 1;
+
+        // Loop-invariant expressions were computed before their loop
+        if let Some(temporary) = self.hoisted_expression(node) {
+            self.visit_identifier(temporary);
+            return;
+        }
+
+        // Constant operands may make the operation itself unnecessary
+        if self.emit_reduced_expression(node) {
+            return;
+        }
+
+        // Fixed-point floats need rescaling after multiplication, division and casts
+        if let Some(scale) = self.fixed_point_scale() {
+            if self.emit_fixed_point_expression(node, scale) {
+                return;
+            }
+        }
+
         // Expression rhs and lhs are traversed in reverse order due to the way 
         // the VM stack works w.r.t. the order of operands
         
@@ -210,7 +258,7 @@ impl AbstractSyntaxTreeVisitor for CodeGenerator<'_> {
                 "==" => self.emit_code(PixardisInstruction::Equal),
                 "!=" => { 
                     self.emit_code(PixardisInstruction::Equal);
-                    self.emit_code(PixardisInstruction::PushImmediate("1".to_string()));
+                    self.emit_push_integer(1);
                     self.emit_code(PixardisInstruction::Subtract);
                 },
                 "<" => self.emit_code(PixardisInstruction::LessThan),
@@ -223,12 +271,58 @@ impl AbstractSyntaxTreeVisitor for CodeGenerator<'_> {
     }
     
     fn visit_print(&mut self, node: &PrintNode) {
-        node.arg_expr.accept(self);
-
         let arg_type = SymbolType::from_string(node.arg_type.borrow().as_str());
 
+        // Fixed-point floats print as the reals they stand for
+        let fixed_point = match &arg_type {
+            Some(SymbolType::Float) => self.fixed_point_bits(),
+            _ => None,
+        };
+
+        if let Some(decimals) = &node.decimals {
+            decimals.accept(self);
+
+            match fixed_point {
+                Some(bits) => {
+                    self.emit_push_integer(bits as i64);
+                    node.arg_expr.accept(self);
+                    self.emit_code(PixardisInstruction::PrintFormattedFixed);
+                },
+                None => {
+                    node.arg_expr.accept(self);
+                    self.emit_code(PixardisInstruction::PrintFormatted);
+                },
+            }
+
+            return;
+        }
+
+        if let Some(bits) = fixed_point {
+            self.emit_push_integer(bits as i64);
+            node.arg_expr.accept(self);
+            self.emit_code(PixardisInstruction::PrintFixed);
+            return;
+        }
+
+        // The elements of an array print as they're stored, and fixed-point
+        // ones are stored as integers
+        if let (Some(SymbolType::Array(element, _)), Some(_)) = (&arg_type, self.fixed_point_bits()) {
+            if **element == SymbolType::Float {
+                self.logger.print_error(
+                    LoggerError::Semantic,
+                    "__print can't print a float array in fixed point; print its elements.",
+                    node.line,
+                );
+
+                self.status_set(CompilationResult::Failure);
+                return;
+            }
+        }
+
+        node.arg_expr.accept(self);
+
         if let Some(SymbolType::Array(_, s)) = arg_type {
-            self.emit_code(PixardisInstruction::PushImmediate(s.to_string()));
+            self.emit_push_integer(s);
             self.emit_code(PixardisInstruction::PrintArray);
         } else {
             self.emit_code(PixardisInstruction::Print);
@@ -240,11 +334,46 @@ impl AbstractSyntaxTreeVisitor for CodeGenerator<'_> {
         self.emit_code(PixardisInstruction::Delay);
     }
 
+    fn visit_assert(&mut self, node: &ExpressionNode) {
+        node.accept(self);
+
+        // A failed assertion is reported at the line the condition starts on
+        self.source_line = node.line + 1;
+        self.emit_code(PixardisInstruction::Assert);
+    }
+
     fn visit_clear(&mut self, node: &ExpressionNode) {
         node.accept(self);
         self.emit_code(PixardisInstruction::Clear);
     }
 
+    fn visit_brightness(&mut self, node: &ExpressionNode) {
+        node.accept(self);
+        self.emit_code(PixardisInstruction::SetBrightness);
+    }
+
+    fn visit_store(&mut self, key: &str, node: &ExpressionNode) {
+        node.accept(self);
+        self.emit_code(PixardisInstruction::StoreKV(key.to_string()));
+    }
+
+    fn visit_asm(&mut self, node: &AsmNode) {
+        for (index, text) in node.lines.iter().enumerate() {
+            // Semantic analysis has checked every reference names a variable
+            let code = AsmNode::substitute(text, |name| {
+                let (_, scope_distance, symbol) = self.scope_manager.find_symbol(name)?;
+                Some((symbol.offset? as i64, scope_distance as i64))
+            }).unwrap();
+
+            if code.is_empty() {
+                continue;
+            }
+
+            self.source_line = node.line + index + 1;
+            self.emit_code(pixardis_instruction_from_string(code));
+        }
+    }
+
     fn visit_write(&mut self, node: &[ExpressionNode; 3]) {
         node[2].accept(self);
         node[1].accept(self);
@@ -270,16 +399,44 @@ impl AbstractSyntaxTreeVisitor for CodeGenerator<'_> {
         self.emit_code(PixardisInstruction::WriteLine);
     }
 
+    fn visit_write_box_gradient(&mut self, node: &[ExpressionNode; 7]) {
+        for expression in node.iter().rev() {
+            expression.accept(self);
+        }
+        self.emit_code(PixardisInstruction::WriteBoxGradient);
+    }
+
+    fn visit_write_box_pattern(&mut self, node: &[ExpressionNode; 7]) {
+        for expression in node.iter().rev() {
+            expression.accept(self);
+        }
+        self.emit_code(PixardisInstruction::WriteBoxPattern);
+    }
+
+    fn visit_write_triangle(&mut self, node: &[ExpressionNode; 7]) {
+        for expression in node.iter().rev() {
+            expression.accept(self);
+        }
+        self.emit_code(PixardisInstruction::WriteTriangle);
+    }
+
     fn visit_return(&mut self, node: &ExpressionNode) {
+        // Self-calls in return position reuse the current frame
+        if let (FactorNode::FunctionCall(call), None) = (&node.factor, &node.operator) {
+            if let Some((function, Some(target))) = self.tail_call_target.clone() {
+                if self.get_function_label(&call.identifier) == Some(function) {
+                    self.emit_tail_call(call, target);
+                    return;
+                }
+            }
+        }
+
         node.accept(self);
 
         self.push_scope();
 
         // Pop operands till we reach function frame
-        while self.is_function_declaration_scope() == false {
-            self.emit_code(PixardisInstruction::FrameClose);
-            self.previous_scope();
-        }
+        self.emit_function_frame_unwind();
 
         // Get return type before popping the function scope
         let return_type = self.get_current_scope_return_type();
@@ -289,7 +446,7 @@ impl AbstractSyntaxTreeVisitor for CodeGenerator<'_> {
 
         // Return array or scalar
         if let Some(SymbolType::Array(_, s)) = return_type {
-            self.emit_code(PixardisInstruction::PushImmediate(s.to_string()));
+            self.emit_push_integer(s);
             self.emit_code(PixardisInstruction::ReturnArray);
         } else {
             self.emit_code(PixardisInstruction::Return);
@@ -322,8 +479,11 @@ impl AbstractSyntaxTreeVisitor for CodeGenerator<'_> {
         // Else block address start
         let mut offset_else_block_address = (self.current_instruction_index() - patch_else_block_address) as i64;
 
-        // ... and else block if it exists
+        // ... and else block if it exists; the jump over it and its frame
+        // belong to the if statement's line rather than the body's last
         if let Some(else_body) = &node.else_body.as_ref() {
+            self.source_line = node.line + 1;
+
             let patch_block_end_address = self.current_instruction_index();
             self.emit_code(PixardisInstruction::PushOffset(self.current_instruction_index() as i64));
             self.emit_code(PixardisInstruction::Jump);
@@ -341,6 +501,9 @@ impl AbstractSyntaxTreeVisitor for CodeGenerator<'_> {
     }
 
     fn visit_while(&mut self, node: &WhileNode) {
+        // Values the loop doesn't change are computed once, before it
+        self.emit_loop_invariants(&[&node.condition], &[node.body]);
+
         // Each loop iteration will run the condition expression
         let patch_condition_address = self.current_instruction_index();
 
@@ -370,6 +533,7 @@ impl AbstractSyntaxTreeVisitor for CodeGenerator<'_> {
         let offset_condition_address = patch_condition_address as i64 - self.current_instruction_index() as i64;
 
         // End of while block (jumps to condition)
+        self.source_line = node.line + 1;
         self.emit_code(PixardisInstruction::PushOffset(offset_condition_address as i64));
         self.emit_code(PixardisInstruction::Jump);
 
@@ -380,14 +544,17 @@ impl AbstractSyntaxTreeVisitor for CodeGenerator<'_> {
     }
 
     fn visit_for(&mut self, node: &ForNode) {
+        self.source_line = node.line + 1;
+
+        // Loops that only fill a box draw it in one go
+        if self.emit_fill_loop(node) {
+            return;
+        }
 
         // Create a new scope
         self.next_scope();
         
-        let symbol_table = self.symbol_table().unwrap();
-        let count = symbol_table.size();
-
-        self.emit_code(PixardisInstruction::PushImmediate(count.to_string()));
+        self.emit_frame_size();
         self.emit_code(PixardisInstruction::FrameOpen);
 
         // Initialser
@@ -395,6 +562,11 @@ impl AbstractSyntaxTreeVisitor for CodeGenerator<'_> {
             initialiser.accept(self);
         }
 
+        // Values the loop doesn't change are computed once, before it
+        let statements: Vec<_> = node.increment.into_iter().chain(Some(node.body)).collect();
+        self.emit_loop_invariants(&node.condition.iter().collect::<Vec<_>>(), &statements);
+        self.source_line = node.line + 1;
+
         // Each loop iteration will run the condition expression
         let patch_condition_address = self.current_instruction_index();
 
@@ -431,6 +603,7 @@ impl AbstractSyntaxTreeVisitor for CodeGenerator<'_> {
         let offset_condition_address = patch_condition_address as i64 - self.current_instruction_index() as i64;
 
         // End of while block (jumps to condition)
+        self.source_line = node.line + 1;
         self.emit_code(PixardisInstruction::PushOffset(offset_condition_address as i64));
         self.emit_code(PixardisInstruction::Jump);
 
@@ -449,21 +622,31 @@ impl AbstractSyntaxTreeVisitor for CodeGenerator<'_> {
 
     fn visit_boolean_literal(&mut self, value: bool) {
         match value {
-            true => self.emit_code(PixardisInstruction::PushImmediate("1".to_string())),
-            false => self.emit_code(PixardisInstruction::PushImmediate("0".to_string())),
+            true => self.emit_push_integer(1),
+            false => self.emit_push_integer(0),
         }
     }
 
     fn visit_integer_literal(&mut self, value: i64) {
-        self.emit_code(PixardisInstruction::PushImmediate(value.to_string()));
+        self.emit_push_integer(value);
     }
 
     fn visit_float_literal(&mut self, value: f64) {
-        self.emit_code(PixardisInstruction::PushImmediate(value.to_string()));
+        match self.fixed_point_scale() {
+            Some(scale) => self.emit_push_integer((value * scale as f64).round() as i64),
+            None => {
+                // Keep the decimal point on whole values so they stay reals
+                let text = value.to_string();
+                let text = if text.contains('.') { text } else { format!("{}.0", text) };
+                self.emit_code(PixardisInstruction::PushImmediate(text.into()));
+            },
+        }
     }
 
     fn visit_colour_literal(&mut self, value: String) {
-        self.emit_code(PixardisInstruction::PushImmediate(value));
+        // The parser only accepts well-formed (#rrggbb) colour literals
+        let colour = u32::from_str_radix(&value[1..], 16).unwrap();
+        self.emit_code(PixardisInstruction::PushColour(colour));
     }
 
     fn visit_width(&mut self) {
@@ -474,27 +657,108 @@ impl AbstractSyntaxTreeVisitor for CodeGenerator<'_> {
         self.emit_code(PixardisInstruction::Height);
     }
 
-    fn visit_random_int(&mut self, node: &std::rc::Rc<ExpressionNode>) {
+    fn visit_capabilities(&mut self) {
+        self.emit_code(PixardisInstruction::Capabilities);
+    }
+
+    fn visit_random_int(&mut self, node: &ExpressionNode) {
         node.accept(self);
         self.emit_code(PixardisInstruction::RandomInt);
     }
 
-    fn visit_read(&mut self, node: &[std::rc::Rc<ExpressionNode>; 2]) {       
+    fn visit_noise(&mut self, node: &[&ExpressionNode; 3]) {
+        // Noise is computed in floating point by the VM
+        if self.fixed_point_scale().is_some() {
+            self.logger.print_error(
+                LoggerError::Semantic,
+                "__noise needs float support and can't be lowered to fixed point.",
+                node[0].line,
+            );
+
+            self.status_set(CompilationResult::Failure);
+            return;
+        }
+
+        for expression in node.iter().rev() {
+            expression.accept(self);
+        }
+        self.emit_code(PixardisInstruction::Noise);
+    }
+
+    fn visit_random_float(&mut self) {
+        // In fixed point, a random fraction is a random int below the scale
+        match self.fixed_point_scale() {
+            Some(scale) => {
+                self.emit_push_integer(scale);
+                self.emit_code(PixardisInstruction::RandomInt);
+            },
+            None => self.emit_code(PixardisInstruction::RandomFloat),
+        }
+    }
+
+    fn visit_random_range(&mut self, node: &[&ExpressionNode; 2]) {
+        node[1].accept(self);
+        node[0].accept(self);
+        self.emit_code(PixardisInstruction::RandomRange);
+    }
+
+    fn visit_read(&mut self, node: &[&ExpressionNode; 2]) {       
         node[1].accept(self);
         node[0].accept(self);
         self.emit_code(PixardisInstruction::Read);
     }
 
-    fn visit_identifier(&mut self, value: String) {
-        let (_, scope_distance, symbol) = self.scope_manager.find_symbol(value.as_str()).unwrap();
+    fn visit_hit_test(&mut self, node: &[&ExpressionNode; 5]) {
+        for expression in node.iter().rev() {
+            expression.accept(self);
+        }
+        self.emit_code(PixardisInstruction::HitTest);
+    }
+
+    fn visit_add_colour(&mut self, node: &[&ExpressionNode; 2]) {
+        node[1].accept(self);
+        node[0].accept(self);
+        self.emit_code(PixardisInstruction::AddColour);
+    }
+
+    fn visit_subtract_colour(&mut self, node: &[&ExpressionNode; 2]) {
+        node[1].accept(self);
+        node[0].accept(self);
+        self.emit_code(PixardisInstruction::SubtractColour);
+    }
+
+    fn visit_hsv(&mut self, node: &[&ExpressionNode; 3]) {
+        for expression in node.iter().rev() {
+            expression.accept(self);
+        }
+        self.emit_code(PixardisInstruction::HsvToRgb);
+    }
+
+    fn visit_param(&mut self, name: &str, default: Option<&ExpressionNode>) {
+        match default {
+            Some(default) => default.accept(self),
+            None => self.emit_push_integer(0),
+        }
+        self.emit_code(PixardisInstruction::Param(name.to_string()));
+    }
+
+    fn visit_load(&mut self, key: &str, default: Option<&ExpressionNode>) {
+        match default {
+            Some(default) => default.accept(self),
+            None => self.emit_push_integer(0),
+        }
+        self.emit_code(PixardisInstruction::LoadKV(key.to_string()));
+    }
+
+    fn visit_identifier(&mut self, value: Atom) {
+        let (_, scope_distance, symbol) = self.scope_manager.find_symbol(&value).unwrap();
 
         // Get frame, offset and size
         let frame = scope_distance.clone() as i64;
         let offset = symbol.offset.clone().unwrap() as i64;
 
         if let SymbolType::Array(_, s) = symbol.symbol_type {
-            self.emit_code(PixardisInstruction::PushImmediate(s.to_string()));
-            self.emit_code(PixardisInstruction::PushArray([offset, frame]));
+            self.emit_code(PixardisInstruction::PushArrayCount([offset, frame, s]));
         } else {
             self.emit_code(PixardisInstruction::PushIndexed([offset, frame]));
         }
@@ -514,8 +778,9 @@ impl AbstractSyntaxTreeVisitor for CodeGenerator<'_> {
             arg.accept(self);
         });
 
-        self.emit_code(PixardisInstruction::PushImmediate(argument_header.to_string()));
-        self.emit_code(PixardisInstruction::PushLabel(node.identifier.clone()));
+        self.emit_push_integer(argument_header as i64);
+        let label = self.get_function_label(&node.identifier).unwrap();
+        self.emit_code(PixardisInstruction::PushLabel(label));
         self.emit_code(PixardisInstruction::Call);
     }
 
@@ -530,16 +795,20 @@ impl AbstractSyntaxTreeVisitor for CodeGenerator<'_> {
         self.emit_code(PixardisInstruction::PushIndexedOffset([offset, frame]));
     }
 
-    fn visit_subexpression(&mut self, node: &std::rc::Rc<ExpressionNode>) {
+    fn visit_subexpression(&mut self, node: &ExpressionNode) {
         node.accept(self);
     }
 
-    fn visit_unary(&mut self, node: &std::rc::Rc<ExpressionNode>) {
+    fn visit_unary(&mut self, node: &ExpressionNode) {
         node.accept(self);
 
-        self.emit_code(PixardisInstruction::PushImmediate("0".to_string()));
-        self.emit_code(PixardisInstruction::Subtract);
-        
-        // self.emit_code(PixardisInstruction::Not);
+        // 'not' on a boolean, '-' on a number
+        if let Some(SymbolType::Bool) = self.expression_type(node) {
+            self.emit_push_integer(0);
+            self.emit_code(PixardisInstruction::Equal);
+        } else {
+            self.emit_push_integer(0);
+            self.emit_code(PixardisInstruction::Subtract);
+        }
     }
 }
\ No newline at end of file
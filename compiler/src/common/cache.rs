@@ -0,0 +1,95 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use shared::pixardis::{
+    PixardisInstruction,
+    PIXARDIS_CAPABILITIES,
+    PIXARDIS_ENTRY_LABEL,
+    pixardis_instruction_from_string,
+    pixardis_instruction_to_string,
+};
+
+//
+// On-disk cache of compilation results, keyed by a hash of the source text,
+// the compiler build, the instruction set it targets and the options that
+// affect compilation. Only
+// clean compilations (no warnings) are stored, so a cache hit never hides
+// diagnostics the user would otherwise have seen.
+//
+// Each entry is a text file of "scope<TAB>line<TAB>instruction" lines, line
+// being the instruction's source line (0 if unknown).
+//
+pub struct CompilationCache {
+    directory: PathBuf,
+}
+
+// Bumped whenever the entry layout changes, so old entries become misses
+const CACHE_FORMAT: &str = "2";
+
+// A cached program (scope and instruction) and its line table
+type CachedCompilation = (Vec<(usize, PixardisInstruction)>, Vec<usize>);
+
+impl CompilationCache {
+    pub fn new(directory: &str) -> CompilationCache {
+        CompilationCache {
+            directory: PathBuf::from(directory),
+        }
+    }
+
+    //
+    // Cache key for a source file compiled with the given options; the build
+    // id (see build.rs) changes with any change to the compiler's sources
+    //
+    pub fn key(source: &str, options: &str) -> String {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let target = format!("capabilities={} entry={}", PIXARDIS_CAPABILITIES, PIXARDIS_ENTRY_LABEL);
+
+        for part in [env!("CHROMA_BUILD_ID"), CACHE_FORMAT, &target, options, source] {
+            for byte in part.bytes().chain(std::iter::once(0)) {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+
+        format!("{:016x}", hash)
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.directory.join(format!("{}.pxc", key))
+    }
+
+    //
+    // Load a cached program and its line table; any missing or unreadable
+    // entry is a miss
+    //
+    pub fn load(&self, key: &str) -> Option<CachedCompilation> {
+        let contents = fs::read_to_string(self.entry_path(key)).ok()?;
+
+        contents.lines()
+            .map(|line| {
+                let mut fields = line.splitn(3, '\t');
+                let scope: usize = fields.next()?.parse().ok()?;
+                let source_line: usize = fields.next()?.parse().ok()?;
+                let instruction = pixardis_instruction_from_string(fields.next()?.to_string());
+                Some(((scope, instruction), source_line))
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(|entries| entries.into_iter().unzip())
+    }
+
+    pub fn store(&self, key: &str, program: &[(usize, PixardisInstruction)], lines: &[usize]) -> Result<(), io::Error> {
+        fs::create_dir_all(&self.directory)?;
+
+        let contents: String = program.iter().zip(lines)
+            .map(|((scope, instruction), line)| format!("{}\t{}\t{}\n", scope, line, pixardis_instruction_to_string(instruction.clone())))
+            .collect();
+
+        // Write then rename, so a concurrent reader never sees a partial entry
+        let path = self.entry_path(key);
+        let temporary = path.with_extension(format!("tmp{}", std::process::id()));
+
+        fs::write(&temporary, contents)?;
+        fs::rename(&temporary, &path)
+    }
+}
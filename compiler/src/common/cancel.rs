@@ -0,0 +1,45 @@
+use std::{
+    rc::Rc,
+    sync::{Arc, atomic::{AtomicBool, Ordering}},
+};
+
+//
+// Lets whoever started a compilation stop it; the pipeline checks the token
+// between phases and gives up with "Compilation cancelled" once it's
+// cancelled. Clones share the cancellation. A host that can't call cancel()
+// while the compiler runs (a web worker, whose event loop is blocked) gives
+// a poll function instead, asked whenever the token is checked.
+//
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    poll: Option<Rc<dyn Fn() -> bool>>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_poll(poll: impl Fn() -> bool + 'static) -> Self {
+        Self { cancelled: Arc::default(), poll: Some(Rc::new(poll)) }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return true;
+        }
+
+        // A cancellation seen once stays seen
+        if self.poll.as_ref().is_some_and(|poll| poll()) {
+            self.cancel();
+            return true;
+        }
+
+        false
+    }
+}
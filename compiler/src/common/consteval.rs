@@ -0,0 +1,152 @@
+use crate::parser::ast::{ExpressionNode, FactorNode};
+
+//
+// Compile-time evaluation of constant expressions (literals combined with
+// arithmetic, relational and logical operators). Used wherever the language
+// requires a value known at compile time, e.g. array sizes.
+//
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConstValue {
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConstEvalError {
+    NonConstant(String),
+    DivisionByZero,
+    Overflow,
+    InvalidOperands(String),
+}
+
+impl ConstEvalError {
+    pub fn message(&self) -> String {
+        match self {
+            ConstEvalError::NonConstant(what) => format!("{} is not a compile-time constant", what),
+            ConstEvalError::DivisionByZero => String::from("division by zero in constant expression"),
+            ConstEvalError::Overflow => String::from("integer overflow in constant expression"),
+            ConstEvalError::InvalidOperands(operator) => format!("invalid operands for '{}' in constant expression", operator),
+        }
+    }
+}
+
+//
+// Evaluate an expression that must produce an integer
+//
+pub fn evaluate_integer(expression: &ExpressionNode) -> Result<i64, ConstEvalError> {
+    match evaluate(expression)? {
+        ConstValue::Integer(value) => Ok(value),
+        _ => Err(ConstEvalError::InvalidOperands(String::from("integer"))),
+    }
+}
+
+pub fn evaluate(expression: &ExpressionNode) -> Result<ConstValue, ConstEvalError> {
+    let left = evaluate_factor(&expression.factor)?;
+
+    match (expression.operator.as_deref(), expression.expression.as_ref()) {
+        (None, _) => Ok(left),
+        (Some("as"), _) => evaluate_cast(left, expression.type_name.as_deref().unwrap_or_default()),
+        (Some(operator), Some(right)) => evaluate_binary(operator, left, evaluate(right)?),
+        (Some(operator), None) => Err(ConstEvalError::InvalidOperands(operator.to_string())),
+    }
+}
+
+pub fn evaluate_factor(factor: &FactorNode) -> Result<ConstValue, ConstEvalError> {
+    match factor {
+        FactorNode::BooleanLiteral(value) => Ok(ConstValue::Boolean(*value)),
+        FactorNode::IntegerLiteral(value) => Ok(ConstValue::Integer(*value)),
+        FactorNode::FloatLiteral(value) => Ok(ConstValue::Float(*value)),
+        FactorNode::Subexpression(expression) => evaluate(expression),
+        FactorNode::Unary(expression) => match evaluate(expression)? {
+            ConstValue::Integer(value) => value.checked_neg().map(ConstValue::Integer).ok_or(ConstEvalError::Overflow),
+            ConstValue::Float(value) => Ok(ConstValue::Float(-value)),
+            ConstValue::Boolean(value) => Ok(ConstValue::Boolean(!value)),
+        },
+        FactorNode::ColourLiteral(_) => Err(ConstEvalError::NonConstant(String::from("Colour literal"))),
+        FactorNode::Width => Err(ConstEvalError::NonConstant(String::from("__width"))),
+        FactorNode::Height => Err(ConstEvalError::NonConstant(String::from("__height"))),
+        FactorNode::Capabilities => Err(ConstEvalError::NonConstant(String::from("__capabilities"))),
+        FactorNode::RandomInt(_) => Err(ConstEvalError::NonConstant(String::from("__random_int"))),
+        FactorNode::RandomFloat => Err(ConstEvalError::NonConstant(String::from("__random_float"))),
+        FactorNode::RandomRange(_) => Err(ConstEvalError::NonConstant(String::from("__random_range"))),
+        FactorNode::Read(_) => Err(ConstEvalError::NonConstant(String::from("__read"))),
+        FactorNode::HitTest(_) => Err(ConstEvalError::NonConstant(String::from("__hit_test"))),
+        FactorNode::AddColour(_) => Err(ConstEvalError::NonConstant(String::from("__add_colour"))),
+        FactorNode::SubtractColour(_) => Err(ConstEvalError::NonConstant(String::from("__sub_colour"))),
+        FactorNode::Hsv(_) => Err(ConstEvalError::NonConstant(String::from("__hsv"))),
+        FactorNode::Noise(_) => Err(ConstEvalError::NonConstant(String::from("__noise"))),
+        FactorNode::Param(_, _) => Err(ConstEvalError::NonConstant(String::from("__param"))),
+        FactorNode::Load(_, _) => Err(ConstEvalError::NonConstant(String::from("__load"))),
+        FactorNode::Identifier(name) => Err(ConstEvalError::NonConstant(format!("Variable '{}'", name))),
+        FactorNode::FunctionCall(call) => Err(ConstEvalError::NonConstant(format!("Call to '{}'", call.identifier))),
+        FactorNode::ArrayAccess(access) => Err(ConstEvalError::NonConstant(format!("Array element '{}[]'", access.identifier))),
+    }
+}
+
+fn evaluate_cast(value: ConstValue, type_name: &str) -> Result<ConstValue, ConstEvalError> {
+    match (type_name, value) {
+        ("int", ConstValue::Integer(value)) => Ok(ConstValue::Integer(value)),
+        ("int", ConstValue::Float(value)) => Ok(ConstValue::Integer(value as i64)),
+        ("float", ConstValue::Integer(value)) => Ok(ConstValue::Float(value as f64)),
+        ("float", ConstValue::Float(value)) => Ok(ConstValue::Float(value)),
+        ("bool", ConstValue::Boolean(value)) => Ok(ConstValue::Boolean(value)),
+        _ => Err(ConstEvalError::InvalidOperands(String::from("as"))),
+    }
+}
+
+fn evaluate_binary(operator: &str, left: ConstValue, right: ConstValue) -> Result<ConstValue, ConstEvalError> {
+    match (left, right) {
+        (ConstValue::Integer(a), ConstValue::Integer(b)) => {
+            let result = match operator {
+                "+" => a.checked_add(b),
+                "-" => a.checked_sub(b),
+                "*" => a.checked_mul(b),
+                "/" | "%" if b == 0 => return Err(ConstEvalError::DivisionByZero),
+                "/" => a.checked_div(b),
+                "%" => a.checked_rem(b),
+                _ => return evaluate_comparison(operator, a.cmp(&b)),
+            };
+
+            result.map(ConstValue::Integer).ok_or(ConstEvalError::Overflow)
+        },
+        (ConstValue::Float(a), ConstValue::Float(b)) => {
+            match operator {
+                "+" => Ok(ConstValue::Float(a + b)),
+                "-" => Ok(ConstValue::Float(a - b)),
+                "*" => Ok(ConstValue::Float(a * b)),
+                "/" if b == 0.0 => Err(ConstEvalError::DivisionByZero),
+                "/" => Ok(ConstValue::Float(a / b)),
+                _ => match a.partial_cmp(&b) {
+                    Some(ordering) => evaluate_comparison(operator, ordering),
+                    None => Err(ConstEvalError::InvalidOperands(operator.to_string())),
+                },
+            }
+        },
+        (ConstValue::Boolean(a), ConstValue::Boolean(b)) => {
+            match operator {
+                "and" | "&&" | "&" => Ok(ConstValue::Boolean(a && b)),
+                "or" | "||" | "|" => Ok(ConstValue::Boolean(a || b)),
+                "==" => Ok(ConstValue::Boolean(a == b)),
+                "!=" => Ok(ConstValue::Boolean(a != b)),
+                _ => Err(ConstEvalError::InvalidOperands(operator.to_string())),
+            }
+        },
+        _ => Err(ConstEvalError::InvalidOperands(operator.to_string())),
+    }
+}
+
+fn evaluate_comparison(operator: &str, ordering: std::cmp::Ordering) -> Result<ConstValue, ConstEvalError> {
+    let result = match operator {
+        "<" => ordering.is_lt(),
+        "<=" => ordering.is_le(),
+        ">" => ordering.is_gt(),
+        ">=" => ordering.is_ge(),
+        "==" => ordering.is_eq(),
+        "!=" => ordering.is_ne(),
+        _ => return Err(ConstEvalError::InvalidOperands(operator.to_string())),
+    };
+
+    Ok(ConstValue::Boolean(result))
+}
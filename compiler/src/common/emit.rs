@@ -0,0 +1,381 @@
+use std::fs;
+use std::io::{self, Write};
+
+use shared::pixardis::{
+    PIXARDIS_ENTRY_LABEL,
+    PixardisInstruction,
+    pixardis_hash,
+    pixardis_program_hash,
+    pixardis_demangle_label,
+    pixardis_encode_program,
+    pixardis_instruction_to_string,
+    pixardis_save_code,
+};
+
+//
+// Kinds of artifact a compilation can be written as
+//
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EmitKind {
+    // Assembly text (as written by -o)
+    Asm,
+    // Binary program (see pixardis_encode_program)
+    Bin,
+    // Debug information (JSON): instruction scopes and source lines, label
+    // addresses and source names of function labels
+    Dbg,
+    // The code generator's output and the program side by side (see
+    // assembly_diff)
+    AsmDiff,
+    // Build manifest (JSON): what was compiled, how, and into what (see
+    // build_manifest)
+    Manifest,
+}
+
+impl EmitKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EmitKind::Asm => "asm",
+            EmitKind::Bin => "bin",
+            EmitKind::Dbg => "dbg",
+            EmitKind::AsmDiff => "asm-diff",
+            EmitKind::Manifest => "manifest",
+        }
+    }
+}
+
+//
+// A compilation, and what it was compiled from, to write artifacts of
+//
+pub struct Compilation<'a> {
+    pub program: &'a Vec<(usize, PixardisInstruction)>,
+    // The code generator's output, if kept (the diff needs it)
+    pub unoptimised: Option<&'a [(usize, PixardisInstruction)]>,
+    pub lines: &'a [usize],
+    pub source_path: &'a str,
+    pub source: &'a str,
+    // Options the program was compiled with, as (name, value), None for an
+    // option not given
+    pub options: &'a [(&'a str, Option<String>)],
+    // Every artifact written of the compilation (the -o output as asm), as
+    // (kind, path)
+    pub outputs: &'a [(EmitKind, &'a str)],
+    // Prefixes for assembly
+    pub show_line_numbers: bool,
+    pub show_scope: bool,
+}
+
+//
+// An artifact to write, given on the command line as kind:path; a path of
+// - (or none, for asm-diff) is stdout
+//
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmitTarget {
+    pub kind: EmitKind,
+    pub path: String,
+}
+
+impl EmitTarget {
+    pub fn parse(target: &str) -> Result<EmitTarget, String> {
+        // The diff is meant for reading, so goes to stdout by default
+        let (kind, path) = match target {
+            "asm-diff" => ("asm-diff", "-"),
+            _ => target.split_once(':').ok_or("expected KIND:PATH")?,
+        };
+
+        let kind = match kind {
+            "asm" => EmitKind::Asm,
+            "bin" => EmitKind::Bin,
+            "dbg" => EmitKind::Dbg,
+            "asm-diff" => EmitKind::AsmDiff,
+            "manifest" => EmitKind::Manifest,
+            _ => return Err(format!("unknown output kind '{}' (expected asm, bin, dbg, asm-diff or manifest)", kind)),
+        };
+
+        if path.is_empty() {
+            return Err(String::from("missing output path"));
+        }
+
+        Ok(EmitTarget { kind, path: path.to_string() })
+    }
+
+    //
+    // Write the compilation as this target's kind of artifact
+    //
+    pub fn write(&self, compilation: &Compilation) -> Result<(), io::Error> {
+        let program = compilation.program;
+
+        match self.kind {
+            EmitKind::Asm => pixardis_save_code(program, &self.path, compilation.show_line_numbers, compilation.show_scope),
+            EmitKind::Bin => {
+                let instructions: Vec<PixardisInstruction> = program.iter()
+                    .map(|(_, instruction)| instruction.clone())
+                    .collect();
+
+                self.write_bytes(&pixardis_encode_program(&instructions))
+            },
+            EmitKind::Dbg => self.write_bytes(debug_info(program, compilation.lines, compilation.source_path).as_bytes()),
+            EmitKind::AsmDiff => {
+                let unoptimised = compilation.unoptimised.ok_or_else(|| io::Error::other("unoptimised program not kept"))?;
+                self.write_bytes(assembly_diff(unoptimised, program).as_bytes())
+            },
+            EmitKind::Manifest => self.write_bytes(build_manifest(compilation).as_bytes()),
+        }
+    }
+
+    fn write_bytes(&self, bytes: &[u8]) -> Result<(), io::Error> {
+        match self.path.as_str() {
+            "-" => io::stdout().write_all(bytes),
+            path => fs::write(path, bytes),
+        }
+    }
+}
+
+//
+// Build manifest, as JSON: the source's path and hash, the compiler and the
+// options it ran with, the program's size, hash (see pixardis_program_hash)
+// and entry point (the address of .main), what was written and where the
+// debug information went. Nothing in it depends on when or where the build
+// ran, so rebuilding the same source the same way writes the same manifest;
+// VMs given it warn when the program or its source has changed since.
+//
+pub fn build_manifest(compilation: &Compilation) -> String {
+    let instructions: Vec<PixardisInstruction> = compilation.program.iter()
+        .map(|(_, instruction)| instruction.clone())
+        .collect();
+
+    let entry = instructions.iter()
+        .position(|instruction| matches!(instruction, PixardisInstruction::Label(label) if *label == PIXARDIS_ENTRY_LABEL));
+
+    let options: Vec<String> = compilation.options.iter()
+        .map(|(name, value)| format!("    {}: {}", json_string(name), value.as_deref().map_or(String::from("null"), json_string)))
+        .collect();
+
+    let outputs: Vec<String> = compilation.outputs.iter()
+        .map(|(kind, path)| format!("    {{ \"kind\": {}, \"path\": {} }}", json_string(kind.name()), json_string(path)))
+        .collect();
+
+    let debug_info = compilation.outputs.iter()
+        .find(|(kind, _)| *kind == EmitKind::Dbg)
+        .map_or(String::from("null"), |(_, path)| json_string(path));
+
+    format!("{{\n  \"compiler\": {{ \"name\": \"chroma\", \"version\": {} }},\n  \"source\": {{ \"path\": {}, \"hash\": {} }},\n  \"options\": {{\n{}\n  }},\n  \"program\": {{ \"instructions\": {}, \"hash\": {}, \"entry\": {} }},\n  \"outputs\": [\n{}\n  ],\n  \"debug_info\": {}\n}}\n",
+        json_string(env!("CARGO_PKG_VERSION")),
+        json_string(compilation.source_path), json_string(&pixardis_hash(compilation.source.as_bytes())),
+        options.join(",\n"),
+        instructions.len(), json_string(&pixardis_program_hash(&instructions)), entry.map_or(String::from("null"), |entry| entry.to_string()),
+        outputs.join(",\n"),
+        debug_info)
+}
+
+//
+// How an instruction of one program lines up with the other's
+//
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DiffLine {
+    Same(usize, usize),
+    Removed(usize),
+    Added(usize),
+    Changed(usize, usize),
+}
+
+//
+// The unoptimised and optimised programs side by side, aligned on the
+// instructions they share (a shortest edit script; Myers' algorithm). The
+// marker between the columns is < for an instruction the optimiser
+// removed, > for one it added and | for one it changed (a removal and an
+// addition in the same place), as in sdiff; a summary line follows.
+//
+pub fn assembly_diff(unoptimised: &[(usize, PixardisInstruction)], optimised: &[(usize, PixardisInstruction)]) -> String {
+    let text = |program: &[(usize, PixardisInstruction)]| -> Vec<String> {
+        program.iter().map(|(_, instruction)| pixardis_instruction_to_string(instruction.clone())).collect()
+    };
+
+    let (before, after) = (text(unoptimised), text(optimised));
+    let alignment = diff_align(&before, &after);
+
+    let width = before.iter().map(String::len).max().unwrap_or(0).max("unoptimised".len());
+    let mut diff = format!("{:>5}  {:<width$}     {:>5}  {}\n", "", "unoptimised", "", "optimised");
+    let (mut removed, mut added, mut changed) = (0, 0, 0);
+
+    for line in alignment.iter() {
+        let (left, marker, right) = match *line {
+            DiffLine::Same(a, b) => (Some(a), ' ', Some(b)),
+            DiffLine::Removed(a) => { removed += 1; (Some(a), '<', None) },
+            DiffLine::Added(b) => { added += 1; (None, '>', Some(b)) },
+            DiffLine::Changed(a, b) => { changed += 1; (Some(a), '|', Some(b)) },
+        };
+
+        let left = left.map_or((String::new(), ""), |a| (a.to_string(), before[a].as_str()));
+        let right = right.map_or((String::new(), ""), |b| (b.to_string(), after[b].as_str()));
+
+        let row = format!("{:>5}  {:<width$}  {}  {:>5}  {}", left.0, left.1, marker, right.0, right.1);
+        diff.push_str(row.trim_end());
+        diff.push('\n');
+    }
+
+    diff.push_str(&format!("{} instructions unoptimised, {} optimised: {} removed, {} added, {} changed\n",
+        before.len(), after.len(), removed, added, changed));
+
+    diff
+}
+
+//
+// Align two instruction listings; runs of removals and additions between
+// shared instructions are paired up as changes
+//
+fn diff_align(before: &[String], after: &[String]) -> Vec<DiffLine> {
+    let mut lines: Vec<DiffLine> = Vec::new();
+    let (mut removed, mut added): (Vec<usize>, Vec<usize>) = (Vec::new(), Vec::new());
+
+    let flush = |lines: &mut Vec<DiffLine>, removed: &mut Vec<usize>, added: &mut Vec<usize>| {
+        let paired = removed.len().min(added.len());
+
+        lines.extend(removed.iter().zip(added.iter()).map(|(&a, &b)| DiffLine::Changed(a, b)));
+        lines.extend(removed[paired..].iter().map(|&a| DiffLine::Removed(a)));
+        lines.extend(added[paired..].iter().map(|&b| DiffLine::Added(b)));
+
+        removed.clear();
+        added.clear();
+    };
+
+    for line in edit_script(before, after) {
+        match line {
+            DiffLine::Removed(a) => removed.push(a),
+            DiffLine::Added(b) => added.push(b),
+            line => {
+                flush(&mut lines, &mut removed, &mut added);
+                lines.push(line);
+            },
+        }
+    }
+
+    flush(&mut lines, &mut removed, &mut added);
+    lines
+}
+
+//
+// Shortest edit script turning before into after, as Same, Removed and
+// Added lines in order (Myers, "An O(ND) Difference Algorithm"). The
+// furthest points reached on the diagonals each edit count could start from
+// are kept, for walking back from the end.
+//
+fn edit_script(before: &[String], after: &[String]) -> Vec<DiffLine> {
+    let (n, m) = (before.len() as isize, after.len() as isize);
+    let max = n + m;
+
+    // Furthest x on diagonal k (x - y) is furthest[k + offset]
+    let offset = max + 1;
+    let mut furthest = vec![0isize; 2 * offset as usize + 1];
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    'search: for d in 0..=max {
+        // Diagonals -d - 1 to d + 1, as trace[d][k + d + 1]
+        trace.push(furthest[(offset - d - 1) as usize..=(offset + d + 1) as usize].to_vec());
+
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && furthest[(k - 1 + offset) as usize] < furthest[(k + 1 + offset) as usize]) {
+                furthest[(k + 1 + offset) as usize]
+            } else {
+                furthest[(k - 1 + offset) as usize] + 1
+            };
+            let mut y = x - k;
+
+            while x < n && y < m && before[x as usize] == after[y as usize] {
+                x += 1;
+                y += 1;
+            }
+
+            furthest[(k + offset) as usize] = x;
+
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut script: Vec<DiffLine> = Vec::new();
+    let (mut x, mut y) = (n, m);
+
+    for (d, furthest) in trace.iter().enumerate().rev() {
+        let (d, k) = (d as isize, x - y);
+        let at = |k: isize| furthest[(k + d + 1) as usize];
+
+        let previous_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let previous_x = if d == 0 { 0 } else { at(previous_k) };
+        let previous_y = if d == 0 { 0 } else { previous_x - previous_k };
+
+        while x > previous_x && y > previous_y {
+            x -= 1;
+            y -= 1;
+            script.push(DiffLine::Same(x as usize, y as usize));
+        }
+
+        if d > 0 {
+            if x == previous_x {
+                script.push(DiffLine::Added(previous_y as usize));
+            } else {
+                script.push(DiffLine::Removed(previous_x as usize));
+            }
+        }
+
+        (x, y) = (previous_x, previous_y);
+    }
+
+    script.reverse();
+    script
+}
+
+//
+// Debug information for a compiled program, as JSON: the source file, each
+// instruction with its scope and source line (0 if unknown), the address of
+// each label, and the source name and declaring scope behind each function
+// label
+//
+pub fn debug_info(program: &[(usize, PixardisInstruction)], lines: &[usize], source_path: &str) -> String {
+    let instructions: Vec<String> = program.iter().enumerate()
+        .map(|(address, (scope, instruction))| format!(
+            "    {{ \"address\": {}, \"scope\": {}, \"line\": {}, \"code\": {} }}",
+            address, scope, lines.get(address).copied().unwrap_or(0), json_string(&pixardis_instruction_to_string(instruction.clone()))))
+        .collect();
+
+    let labels: Vec<String> = program.iter().enumerate()
+        .filter_map(|(address, (_, instruction))| match instruction {
+            PixardisInstruction::Label(label) => Some(format!("    {}: {}", json_string(label), address)),
+            _ => None,
+        })
+        .collect();
+
+    let functions: Vec<String> = program.iter()
+        .filter_map(|(_, instruction)| match instruction {
+            PixardisInstruction::Label(label) => pixardis_demangle_label(label).map(|(name, scope)| format!(
+                "    {}: {{ \"name\": {}, \"scope\": {} }}", json_string(label), json_string(name), scope)),
+            _ => None,
+        })
+        .collect();
+
+    format!("{{\n  \"source\": {},\n  \"instructions\": [\n{}\n  ],\n  \"labels\": {{\n{}\n  }},\n  \"functions\": {{\n{}\n  }}\n}}\n",
+        json_string(source_path), instructions.join(",\n"), labels.join(",\n"), functions.join(",\n"))
+}
+
+fn json_string(value: &str) -> String {
+    let mut quoted = String::from("\"");
+
+    for character in value.chars() {
+        match character {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            character if character.is_control() => quoted.push_str(&format!("\\u{:04x}", character as u32)),
+            character => quoted.push(character),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
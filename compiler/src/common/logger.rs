@@ -1,41 +1,4 @@
-#[cfg(target_arch = "wasm32")]
-use std::sync::Mutex;
-
-// Global error collector for WASM builds
-#[cfg(target_arch = "wasm32")]
-static ERROR_COLLECTOR: Mutex<Vec<String>> = Mutex::new(Vec::new());
-
-// Conditional eprintln! macro
-#[cfg(target_arch = "wasm32")]
-macro_rules! eprintln {
-    ($($arg:tt)*) => {
-        {
-            let message = format!($($arg)*);
-            if let Ok(mut errors) = ERROR_COLLECTOR.lock() {
-                errors.push(message);
-            }
-        }
-    };
-}
-
-// For non-WASM builds, use regular eprintln!
-#[cfg(not(target_arch = "wasm32"))]
-macro_rules! eprintln {
-    ($($arg:tt)*) => {
-        std::eprintln!($($arg)*)
-    };
-}
-
-// Function to get captured errors (WASM only)
-#[cfg(target_arch = "wasm32")]
-pub fn get_captured_errors() -> Vec<String> {
-    ERROR_COLLECTOR.lock().unwrap().drain(..).collect()
-}
-
-#[cfg(target_arch = "wasm32")]
-pub fn clear_captured_errors() {
-    ERROR_COLLECTOR.lock().unwrap().clear();
-}
+use std::sync::{Arc, Mutex};
 
 #[derive(Debug, Clone, Copy)]
 pub enum LoggerError {
@@ -53,29 +16,105 @@ pub enum LoggerMessage {
     Error,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "lowercase"))]
+pub enum DiagnosticSeverity {
+    Warning,
+    Error,
+}
+
+//
+// A warning or error as a capturing logger collects it, for tools that show
+// diagnostics next to the source rather than as text
+//
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Diagnostic {
+    pub severity: DiagnosticSeverity,
+    // 1-based source line, if the diagnostic is about one
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+#[derive(Debug, Default)]
+struct Captured {
+    messages: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+//
+// Messages are written to stderr unless the logger is capturing, in which case
+// they are collected per logger instance (clones share the same collection),
+// so concurrent compilations don't see each other's errors.
+//
 #[derive(Debug, Clone)]
 pub struct Logger<'a> {
     source: &'a str,
-    newlines: Vec<usize>,        
+    // Shared by clones, so a logger per pipeline stage doesn't copy the index
+    newlines: Arc<[usize]>,
+    captured: Option<Arc<Mutex<Captured>>>,
 }
 
 impl<'a> Logger<'a> {
     pub fn new(source: &'a str) -> Logger<'a> {
-        let mut logger = Logger {
+        Logger {
             source: source,
-            newlines: Vec::new(),
-        };
+            newlines: Self::enumerate_newlines(source).into(),
+            captured: None,
+        }
+    }
 
-        logger.enumerate_newlines();
+    #[allow(dead_code)]
+    pub fn new_capturing(source: &'a str) -> Logger<'a> {
+        let mut logger = Logger::new(source);
+        logger.captured = Some(Arc::new(Mutex::new(Captured::default())));
         logger
     }
 
-    fn enumerate_newlines(&mut self) {
-        for (line_end, _) in self.source.match_indices("\n") {
-            self.newlines.push(line_end);
+    //
+    // Take the messages collected so far (capturing loggers only)
+    //
+    #[allow(dead_code)]
+    pub fn captured_messages(&self) -> Vec<String> {
+        match &self.captured {
+            Some(captured) => captured.lock().map(|mut captured| std::mem::take(&mut captured.messages)).unwrap_or_default(),
+            None => Vec::new(),
         }
     }
 
+    //
+    // Take the warnings and errors collected so far, with their source
+    // lines where known (capturing loggers only)
+    //
+    #[allow(dead_code)]
+    pub fn captured_diagnostics(&self) -> Vec<Diagnostic> {
+        match &self.captured {
+            Some(captured) => captured.lock().map(|mut captured| std::mem::take(&mut captured.diagnostics)).unwrap_or_default(),
+            None => Vec::new(),
+        }
+    }
+
+    fn emit(&self, message: String) {
+        match &self.captured {
+            Some(captured) => {
+                if let Ok(mut captured) = captured.lock() {
+                    captured.messages.push(message);
+                }
+            },
+            None => eprintln!("{}", message),
+        }
+    }
+
+    fn record(&self, severity: DiagnosticSeverity, line: Option<usize>, message: &str) {
+        if let Some(Ok(mut captured)) = self.captured.as_ref().map(|captured| captured.lock()) {
+            captured.diagnostics.push(Diagnostic { severity, line, message: message.trim_end().to_string() });
+        }
+    }
+
+    fn enumerate_newlines(source: &str) -> Vec<usize> {
+        source.match_indices("\n").map(|(line_end, _)| line_end).collect()
+    }
+
     fn get_source_line(&self, line_number: usize) -> &str {
         if line_number == 0 {
             let line_start = 0;
@@ -90,25 +129,46 @@ impl<'a> Logger<'a> {
 
     pub fn print_message(&self, category: LoggerMessage, message: &str) {
         match category {
-            LoggerMessage::Silent => eprintln!("{}", message),
-            LoggerMessage::Info => eprintln!("Info: {}", message),
-            LoggerMessage::Warning => eprintln!("Warning: {}", message),
-            LoggerMessage::Error => eprintln!("Error: {}", message),
+            LoggerMessage::Silent => self.emit(message.to_string()),
+            LoggerMessage::Info => self.emit(format!("Info: {}", message)),
+            LoggerMessage::Warning => {
+                self.record(DiagnosticSeverity::Warning, None, message);
+                self.emit(format!("Warning: {}", message))
+            },
+            LoggerMessage::Error => {
+                self.record(DiagnosticSeverity::Error, None, message);
+                self.emit(format!("Error: {}", message))
+            },
         }
     }
 
+    //
+    // A warning about a line of the source (0-based line_number)
+    //
+    pub fn print_warning(&self, message: &str, line_number: usize) {
+        self.record(DiagnosticSeverity::Warning, Some(line_number + 1), message);
+        self.emit(format!("Warning: In Line {}: {}", line_number + 1, message));
+    }
+
     pub fn print_short_error(&self, category: LoggerError, message: &str) {
-        match category {
-            LoggerError::Lexical => eprintln!("Lexical Error: {}\n", message),
-            LoggerError::Syntax => eprintln!("Syntax Error: {}\n", message),
-            LoggerError::Semantic => eprintln!("Semantic Error: {}\n", message),
-            LoggerError::Type => eprintln!("Type Error: {}\n", message),
-            LoggerError::NameResolution => eprintln!("Name Resolution Error: {}\n", message),
-        }
+        self.short_error(category, message, None);
     }
 
     pub fn print_error(&self, category: LoggerError, message: &str, line_number: usize) {
-        eprintln!("In Line {}: {}", line_number + 1, self.get_source_line(line_number));        
-        self.print_short_error(category, message);
+        self.emit(format!("In Line {}: {}", line_number + 1, self.get_source_line(line_number)));        
+        self.short_error(category, message, Some(line_number + 1));
+    }
+
+    fn short_error(&self, category: LoggerError, message: &str, line: Option<usize>) {
+        let message = match category {
+            LoggerError::Lexical => format!("Lexical Error: {}\n", message),
+            LoggerError::Syntax => format!("Syntax Error: {}\n", message),
+            LoggerError::Semantic => format!("Semantic Error: {}\n", message),
+            LoggerError::Type => format!("Type Error: {}\n", message),
+            LoggerError::NameResolution => format!("Name Resolution Error: {}\n", message),
+        };
+
+        self.record(DiagnosticSeverity::Error, line, &message);
+        self.emit(message);
     }
 }
\ No newline at end of file
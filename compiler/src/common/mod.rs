@@ -1,2 +1,8 @@
+pub mod cache;
+pub mod cancel;
+pub mod consteval;
+pub mod emit;
 pub mod logger;
-pub mod status;
\ No newline at end of file
+pub mod report;
+pub mod status;
+pub mod timings;
\ No newline at end of file
@@ -0,0 +1,85 @@
+use shared::pixardis::{
+    PixardisInstruction,
+    pixardis_demangle_label,
+};
+
+//
+// Code size of one function (or of the main program, the code outside every
+// function) and a static estimate of the cycles one pass through it costs
+//
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionSize {
+    pub name: String,
+    pub label: Option<String>,
+    pub instructions: usize,
+    pub cycles: usize,
+}
+
+//
+// Size of each function in a compiled program, largest estimate first.
+// Functions are emitted as a jump over their body (push #PC+n, jmp) followed
+// by .sig and the function label, so each spans from its .sig to the jump's
+// target; a function without that prologue runs to the next label.
+//
+pub fn size_report(program: &[(usize, PixardisInstruction)], width: usize, height: usize) -> Vec<FunctionSize> {
+    let mut owner: Vec<Option<usize>> = vec![None; program.len()];
+    let mut functions: Vec<FunctionSize> = Vec::new();
+
+    for (address, (_, instruction)) in program.iter().enumerate() {
+        let PixardisInstruction::Label(label) = instruction else { continue };
+        let Some((name, _)) = pixardis_demangle_label(label) else { continue };
+
+        let (start, end) = match address.checked_sub(3).map(|prologue| (prologue, &program[prologue..address])) {
+            Some((prologue, [(_, PixardisInstruction::PushOffset(offset)), (_, PixardisInstruction::Jump), (_, PixardisInstruction::Signature(_, _))])) =>
+                (prologue + 2, (prologue as i64 + offset).clamp(address as i64, program.len() as i64) as usize),
+            _ => {
+                let next = program[address + 1..].iter()
+                    .position(|(_, instruction)| matches!(instruction, PixardisInstruction::Label(_)))
+                    .map_or(program.len(), |offset| address + 1 + offset);
+                (address, next)
+            },
+        };
+
+        // Functions declared inside others take their code from the outer one
+        for slot in owner[start..end].iter_mut() {
+            *slot = Some(functions.len());
+        }
+
+        functions.push(FunctionSize { name: name.to_string(), label: Some(label.to_string()), instructions: 0, cycles: 0 });
+    }
+
+    let mut main = FunctionSize { name: String::from("main"), label: None, instructions: 0, cycles: 0 };
+
+    for ((_, instruction), owner) in program.iter().zip(owner) {
+        let size = match owner {
+            Some(function) => &mut functions[function],
+            None => &mut main,
+        };
+
+        size.instructions += 1;
+        size.cycles += cycle_estimate(instruction, width, height);
+    }
+
+    functions.push(main);
+    functions.sort_by(|a, b| b.cycles.cmp(&a.cycles).then_with(|| a.name.cmp(&b.name)));
+    functions
+}
+
+//
+// Cycles an instruction costs the VM: one, except for drawing (and hit
+// tests), which cost a cycle per pixel; boxes, triangles and clears are taken
+// to cover the whole display and lines to span it, since their sizes are only
+// known at run time
+//
+fn cycle_estimate(instruction: &PixardisInstruction, width: usize, height: usize) -> usize {
+    match instruction {
+        PixardisInstruction::WriteBox |
+        PixardisInstruction::WriteBoxGradient |
+        PixardisInstruction::WriteBoxPattern |
+        PixardisInstruction::WriteTriangle |
+        PixardisInstruction::HitTest |
+        PixardisInstruction::Clear => width * height,
+        PixardisInstruction::WriteLine => width.max(height),
+        _ => 1,
+    }
+}
@@ -0,0 +1,80 @@
+use std::time::{Duration, Instant};
+
+//
+// Wall time one phase of a compilation took, and the size of what it worked
+// on or produced; counts that don't apply to a phase are None
+//
+#[derive(Debug, Clone, PartialEq)]
+pub struct PhaseStatistics {
+    pub phase: String,
+    pub elapsed: Duration,
+    pub tokens: Option<usize>,
+    pub nodes: Option<usize>,
+    pub instructions: Option<usize>,
+}
+
+//
+// Statistics for each phase of a compilation, in the order they ran
+//
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompilationStatistics {
+    pub phases: Vec<PhaseStatistics>,
+}
+
+impl CompilationStatistics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn total(&self) -> Duration {
+        self.phases.iter().map(|phase| phase.elapsed).sum()
+    }
+
+    // The statistics as a table, a phase per row and a total
+    pub fn to_table(&self) -> String {
+        let count = |count: Option<usize>| count.map_or(String::from("-"), |count| count.to_string());
+        let milliseconds = |elapsed: Duration| format!("{:.3}", elapsed.as_secs_f64() * 1000.0);
+
+        let name_width = self.phases.iter().map(|phase| phase.phase.len()).max().unwrap_or(0).max("phase".len());
+        let mut table = format!("{:<name_width$}  {:>10}  {:>8}  {:>8}  {:>12}\n", "phase", "time (ms)", "tokens", "nodes", "instructions");
+
+        for phase in self.phases.iter() {
+            table.push_str(&format!("{:<name_width$}  {:>10}  {:>8}  {:>8}  {:>12}\n",
+                phase.phase, milliseconds(phase.elapsed), count(phase.tokens), count(phase.nodes), count(phase.instructions)));
+        }
+
+        table.push_str(&format!("{:<name_width$}  {:>10}\n", "total", milliseconds(self.total())));
+        table
+    }
+}
+
+//
+// Times consecutive phases into statistics, if there are any to keep: each
+// phase runs from the previous record (or start) to its own. Without
+// statistics the clock is never read, so pipelines can keep a timer on
+// targets without one (wasm32-unknown-unknown).
+//
+pub struct PhaseTimer<'s> {
+    statistics: Option<&'s mut CompilationStatistics>,
+    started: Option<Instant>,
+}
+
+impl<'s> PhaseTimer<'s> {
+    pub fn start(statistics: Option<&'s mut CompilationStatistics>) -> Self {
+        let started = statistics.as_ref().map(|_| Instant::now());
+        PhaseTimer { statistics, started }
+    }
+
+    // True if statistics are being kept (counts need only be taken then)
+    pub fn is_active(&self) -> bool {
+        self.statistics.is_some()
+    }
+
+    pub fn record(&mut self, phase: &str, tokens: Option<usize>, nodes: Option<usize>, instructions: Option<usize>) {
+        let (Some(statistics), Some(started)) = (self.statistics.as_deref_mut(), self.started) else { return };
+
+        let now = Instant::now();
+        statistics.phases.push(PhaseStatistics { phase: phase.to_string(), elapsed: now - started, tokens, nodes, instructions });
+        self.started = Some(now);
+    }
+}
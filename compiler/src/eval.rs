@@ -0,0 +1,222 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+use shared::pixardis::{PixardisInstruction, pixardis_instruction_to_string};
+use vm::machine::architecture::{Operand, VirtualMachineError};
+use vm::machine::observer::Observer;
+use vm::pixardis::pixardis::{PixardisVirtualMachine, PixardisPrintFormat, param_from_string};
+use vm::pixardis::storage::{PixardisMemoryStorage, PixardisStorage};
+
+use crate::common::logger::Logger;
+use crate::compile_with_line_table;
+
+///
+/// Settings for a headless evaluation run
+///
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct EvalConfig {
+    pub width: usize,
+    pub height: usize,
+    // Maximum number of instructions to execute
+    pub cycles: usize,
+    // Seed for the VM's random number generator
+    pub seed: u64,
+    // Print bare values, without type prefixes, for comparing output
+    pub print_raw: bool,
+    // Parameters read by __param: numbers, or colour strings (#rrggbb)
+    pub params: BTreeMap<String, serde_json::Value>,
+    // Values already in storage (kvld) when the run starts, as for params
+    pub storage: BTreeMap<String, serde_json::Value>,
+}
+
+impl Default for EvalConfig {
+    fn default() -> EvalConfig {
+        EvalConfig {
+            width: 64,
+            height: 48,
+            cycles: 1_000_000,
+            seed: 0,
+            print_raw: false,
+            params: BTreeMap::new(),
+            storage: BTreeMap::new(),
+        }
+    }
+}
+
+///
+/// Outcome of compiling and running a program headless
+///
+#[derive(Debug, Clone, Serialize)]
+pub struct EvalReport {
+    pub compiled: bool,
+    pub halted: bool,
+    pub cycles: usize,
+    pub frames: usize,
+    pub width: usize,
+    pub height: usize,
+    // FNV-1a hash of the final framebuffer, as hex
+    pub framebuffer_hash: String,
+    pub print_output: Vec<String>,
+    // Executed instruction counts, keyed by mnemonic
+    pub instruction_counts: BTreeMap<String, usize>,
+    pub errors: Vec<String>,
+}
+
+//
+// Counts executed instructions by mnemonic and collects printed lines
+//
+struct EvalObserver {
+    counts: Rc<RefCell<BTreeMap<String, usize>>>,
+    prints: Rc<RefCell<Vec<String>>>,
+}
+
+impl Observer for EvalObserver {
+    fn on_instruction(&mut self, _address: usize, instruction: &PixardisInstruction) {
+        let text = pixardis_instruction_to_string(instruction.clone());
+        let mnemonic = text.split_whitespace().next().unwrap_or_default().to_string();
+
+        *self.counts.borrow_mut().entry(mnemonic).or_insert(0) += 1;
+    }
+
+    fn on_print(&mut self, text: &str) {
+        self.prints.borrow_mut().push(text.to_string());
+    }
+}
+
+fn framebuffer_hash(framebuffer: &[u64]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+
+    for colour in framebuffer {
+        for byte in colour.to_le_bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    format!("{:016x}", hash)
+}
+
+//
+// Parameter or stored value given as a JSON number or colour string
+//
+fn operand_from_json(value: &serde_json::Value) -> Option<Operand> {
+    match value {
+        serde_json::Value::Number(number) => number.as_i64().map(Operand::Integer)
+            .or_else(|| number.as_f64().map(Operand::Real)),
+        serde_json::Value::String(text) => param_from_string(text),
+        _ => None,
+    }
+}
+
+//
+// Compile a program and run it headless for (at most) the configured number
+// of cycles with a fixed seed. Delays don't wait on wall-clock time, so the
+// same submission always produces the same report.
+//
+pub fn evaluate(source: &str, config: &EvalConfig) -> EvalReport {
+    let mut report = EvalReport {
+        compiled: false,
+        halted: false,
+        cycles: 0,
+        frames: 0,
+        width: config.width,
+        height: config.height,
+        framebuffer_hash: String::new(),
+        print_output: Vec::new(),
+        instruction_counts: BTreeMap::new(),
+        errors: Vec::new(),
+    };
+
+    let mut vm = PixardisVirtualMachine::new(config.width, config.height);
+    vm.random_seed_set(config.seed);
+
+    // On console targets, runtime errors and prints go in the report rather
+    // than ending the process and going to stdout
+    #[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+    {
+        vm.exit_on_error_set(false);
+        vm.console_print_set(false);
+    }
+
+    if config.print_raw {
+        vm.print_format_set(PixardisPrintFormat::Raw);
+    }
+
+    for (name, value) in &config.params {
+        match operand_from_json(value) {
+            Some(operand) => vm.param_set(name, operand),
+            None => report.errors.push(format!("Invalid value for parameter '{}'.", name)),
+        }
+    }
+
+    // Each run gets its own storage, so stored values never leak between runs
+    let mut storage = PixardisMemoryStorage::default();
+
+    for (key, value) in &config.storage {
+        match operand_from_json(value) {
+            Some(operand) => storage.store(key, &operand),
+            None => report.errors.push(format!("Invalid value for storage key '{}'.", key)),
+        }
+    }
+
+    vm.storage_set(Some(Box::new(storage)));
+
+    let logger = Logger::new_capturing(source);
+    match compile_with_line_table(source, &logger, None) {
+        Ok((program, lines)) => {
+            report.compiled = true;
+            vm.load_program(program.into_iter().map(|(_, instruction)| instruction).collect());
+            vm.source_lines_set(lines);
+
+            if let Some(rejection) = &vm.load_report().rejection {
+                report.errors.push(rejection.clone());
+            }
+        },
+        Err(error) => {
+            let mut errors = logger.captured_messages();
+            if errors.is_empty() {
+                errors.push(error);
+            }
+            report.errors = errors;
+        }
+    }
+
+    if report.compiled && vm.load_report().rejection.is_none() {
+        let counts = Rc::new(RefCell::new(BTreeMap::new()));
+        let prints = Rc::new(RefCell::new(Vec::new()));
+        let handle = vm.observer_add(Box::new(EvalObserver { counts: counts.clone(), prints: prints.clone() }));
+
+        while report.cycles < config.cycles {
+            match vm.run_frame(config.cycles - report.cycles) {
+                Ok(cycles) => {
+                    report.cycles += cycles;
+                    if vm.delay_requested().is_none() {
+                        break;
+                    }
+                    report.frames += 1;
+                },
+                Err(VirtualMachineError::TrapHalt) => {
+                    report.halted = true;
+                    break;
+                },
+                Err(error) => {
+                    report.errors.push(format!("{:?}", error));
+                    break;
+                }
+            }
+        }
+
+        vm.observer_remove(handle);
+        report.instruction_counts = counts.take();
+        report.cycles = report.instruction_counts.values().sum();
+        report.print_output = prints.take();
+    }
+
+    let (_, _, framebuffer) = vm.framebuffer();
+    report.framebuffer_hash = framebuffer_hash(framebuffer);
+
+    report
+}
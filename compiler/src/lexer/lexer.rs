@@ -6,12 +6,15 @@ use crate::common::{
     status::CompilationResult
 };
 
-use super::token::{Token, classify_token};
+use shared::intern::Interner;
+
+use super::token::{Token, TokenKind, classify_token};
 
 ///
 /// Span structure to keep track of the start and end of a token
 /// 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Span {
     pub start: usize,
     pub end: usize,
@@ -39,6 +42,7 @@ pub enum Symbol {
     Semicolon,
     Underscore,
     Pound,
+    Quote,
     LAngle,
     RAngle,
     LParen,
@@ -79,6 +83,7 @@ pub fn classify_symbol(symbol: char) -> Symbol {
         '>' => Symbol::RAngle,
         '=' => Symbol::Equals,
         '#' => Symbol::Pound,
+        '"' => Symbol::Quote,
         '(' => Symbol::LParen,
         ')' => Symbol::RParen,
         '{' => Symbol::LBrace,
@@ -103,6 +108,8 @@ pub struct Lexer<'a> {
     newlines: Vec<usize>,
     logger: &'a mut Logger<'a>,
     status: CompilationResult,
+    // Identifiers are interned here; later stages share the table
+    interner: Interner,
 }
 
 ///
@@ -118,6 +125,7 @@ impl<'a> Lexer<'a> {
             newlines: vec![],
             logger,
             status: CompilationResult::Pending,
+            interner: Interner::new(),
         };
 
         lexer.enumerate_newlines();
@@ -133,6 +141,10 @@ impl<'a> Lexer<'a> {
         self.status.clone()
     }
 
+    pub fn interner(&self) -> Interner {
+        self.interner.clone()
+    }
+
     fn enumerate_newlines(&mut self) {
         for (line_end, _) in self.input.match_indices("\n") {
             self.newlines.push(line_end);
@@ -208,11 +220,34 @@ impl<'a> Lexer<'a> {
         {
             Symbol::Digit(value) => Some(value),
             Symbol::Period => Some('.'),
+            Symbol::Underscore => Some('_'),
             _ => None
         }
     }
 
     fn scan_number(&mut self) -> String {
+        // Hex (0x) and binary (0b) literals run to the end of the word, so a
+        // stray digit is part of the literal (and an error) rather than the
+        // start of an identifier
+        let prefixed = self.input[self.position..].get(..2)
+            .is_some_and(|prefix| matches!(prefix, "0x" | "0X" | "0b" | "0B"));
+
+        if prefixed {
+            let mut number = String::from(&self.input[self.position..self.position + 2]);
+            self.position += 2;
+
+            let mut symbol = self.peek();
+            while let Some(ch) = self.get_identifier_char(symbol) {
+                number.push(ch);
+
+                self.next();
+
+                symbol = self.peek();
+            }
+
+            return number;
+        }
+
         let mut number = String::new();
         let mut symbol = self.peek();
         let mut period = false;
@@ -268,6 +303,76 @@ impl<'a> Lexer<'a> {
         return colour;
     }
 
+    //
+    // Scan a pragma line (#pragma key value); the value runs to the end of
+    // the line and may be quoted
+    //
+    fn scan_pragma(&mut self) -> TokenKind {
+        let line_end = self.input[self.position..].find('\n').map_or(self.input.len(), |offset| self.position + offset);
+        let directive = self.input[self.position + "#pragma".len()..line_end].trim();
+
+        self.position = line_end;
+
+        let (key, value) = directive.split_once(char::is_whitespace).unwrap_or((directive, ""));
+        let value = value.trim();
+        let value = value.strip_prefix('"').and_then(|value| value.strip_suffix('"')).unwrap_or(value);
+
+        TokenKind::Pragma(key.to_string(), value.to_string())
+    }
+
+    //
+    // Scan the body of an inline assembly block (asm { ... }), from the
+    // opening brace to the one that closes it; braces of {name} references
+    // nest, and those in comments don't count. None if the block isn't
+    // closed.
+    //
+    fn scan_asm(&mut self) -> Option<String> {
+        let open = self.position + self.input[self.position..].find('{')?;
+        let mut depth = 0;
+        let mut comment = false;
+
+        for (offset, ch) in self.input[open..].char_indices() {
+            match ch {
+                '\n' => comment = false,
+                '/' if self.input[open + offset..].starts_with("//") => comment = true,
+                '{' if !comment => depth += 1,
+                '}' if !comment => {
+                    depth -= 1;
+
+                    if depth == 0 {
+                        self.position = open + offset + 1;
+                        return Some(self.input[open + 1..open + offset].to_string());
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        self.position = self.input.len();
+        None
+    }
+
+    //
+    // Scan a quoted string literal; strings can't span lines, so None if the
+    // closing quote is missing
+    //
+    fn scan_string(&mut self) -> Option<String> {
+        let line_end = self.input[self.position..].find('\n').map_or(self.input.len(), |offset| self.position + offset);
+        let closing = self.input[self.position + 1..line_end].find('"');
+
+        match closing {
+            Some(length) => {
+                let value = self.input[self.position + 1..self.position + 1 + length].to_string();
+                self.position += length + 2;
+                Some(value)
+            },
+            None => {
+                self.position = line_end;
+                None
+            },
+        }
+    }
+
     fn scan_character(&mut self) -> String {
         let mut result = String::new();
         
@@ -447,7 +552,7 @@ impl<'a> Lexer<'a> {
                         },
                         _ => {
                             let token_input: String = String::from("/");
-                            let token = classify_token(&token_input);
+                            let token = classify_token(&token_input, &self.interner);
                             let line_number = self.token_position_to_line_number(symbol_position); 
                             self.tokens.push(Token { 
                                 kind: token,
@@ -464,7 +569,38 @@ impl<'a> Lexer<'a> {
                 // identifier
                 Symbol::Underscore | Symbol::Alpha(_) => {
                     let token_input = self.scan_identifier();
-                    let token = classify_token(&token_input);
+
+                    // asm is only a keyword before a block, so it's still a
+                    // valid name; the block's lines count from its brace
+                    if token_input == "asm" && self.input[self.position..].trim_start().starts_with('{') {
+                        let brace = self.position + self.input[self.position..].find('{').unwrap();
+                        let line_number = self.token_position_to_line_number(brace);
+
+                        match self.scan_asm() {
+                            Some(body) => {
+                                self.tokens.push(Token { 
+                                    kind: TokenKind::Asm(body),
+                                    span: Span { 
+                                        start: symbol_position, 
+                                        end: self.position,
+                                    },
+                                    line: line_number,
+                                });
+                            },
+                            None => {
+                                self.logger.print_error(
+                                    LoggerError::Lexical, 
+                                    "Unterminated asm block.",
+                                    line_number);
+
+                                self.status_set(CompilationResult::Failure);
+                            },
+                        }
+
+                        continue;
+                    }
+
+                    let token = classify_token(&token_input, &self.interner);
                     let line_number = self.token_position_to_line_number(symbol_position); 
                     self.tokens.push(Token { 
                         kind: token,
@@ -479,8 +615,18 @@ impl<'a> Lexer<'a> {
                 // integer or float literal
                 Symbol::Digit(_) => {
                     let token_input = self.scan_number();
-                    let token = classify_token(&token_input);
+                    let token = classify_token(&token_input, &self.interner);
                     let line_number = self.token_position_to_line_number(symbol_position); 
+
+                    if let TokenKind::Identifier(_) = token {
+                        self.logger.print_error(
+                            LoggerError::Lexical, 
+                            format!("Malformed numeric literal '{}'.", token_input).as_str(),
+                            line_number);
+
+                        self.status_set(CompilationResult::Failure);
+                    }
+
                     self.tokens.push(Token { 
                         kind: token,
                         span: Span { 
@@ -491,10 +637,16 @@ impl<'a> Lexer<'a> {
                     });
                 },
 
-                // colour literal
+                // pragma or colour literal
                 Symbol::Pound => {
-                    let token_input = self.scan_colour();
-                    let token = classify_token(&token_input);
+                    let is_pragma = self.input[self.position..].strip_prefix("#pragma")
+                        .is_some_and(|rest| rest.starts_with(char::is_whitespace));
+
+                    let token = if is_pragma {
+                        self.scan_pragma()
+                    } else {
+                        classify_token(&self.scan_colour(), &self.interner)
+                    };
                     let line_number = self.token_position_to_line_number(symbol_position); 
                     self.tokens.push(Token { 
                         kind: token,
@@ -506,10 +658,36 @@ impl<'a> Lexer<'a> {
                     });
                 },
 
+                // string literal
+                Symbol::Quote => {
+                    let line_number = self.token_position_to_line_number(symbol_position);
+
+                    match self.scan_string() {
+                        Some(value) => {
+                            self.tokens.push(Token { 
+                                kind: TokenKind::StringLiteral(value),
+                                span: Span { 
+                                    start: symbol_position, 
+                                    end: self.position,
+                                },
+                                line: line_number,
+                            });
+                        },
+                        None => {
+                            self.logger.print_error(
+                                LoggerError::Lexical, 
+                                "Unterminated string literal.",
+                                line_number);
+
+                            self.status_set(CompilationResult::Failure);
+                        },
+                    }
+                },
+
                 // delimiters and punctuation
                 Symbol::LBracket | Symbol::RBracket | Symbol::LParen | Symbol::RParen | Symbol::LBrace | Symbol::RBrace | Symbol::Comma | Symbol::Colon | Symbol::Semicolon => {
                     let token_input: String = self.scan_character();
-                    let token = classify_token(&token_input);
+                    let token = classify_token(&token_input, &self.interner);
                     let line_number = self.token_position_to_line_number(symbol_position); 
                     self.tokens.push(Token { 
                         kind: token,
@@ -524,7 +702,7 @@ impl<'a> Lexer<'a> {
                 // operators
                 Symbol::Equals | Symbol::Bang | Symbol::LAngle | Symbol::RAngle | Symbol::Percent | Symbol::Asterisk | Symbol::Plus | Symbol::Minus | Symbol::Ampersand | Symbol::Pipe => {
                     let token_input: String = self.scan_operator();
-                    let token = classify_token(&token_input);
+                    let token = classify_token(&token_input, &self.interner);
                     let line_number = self.token_position_to_line_number(symbol_position); 
                     self.tokens.push(Token { 
                         kind: token,
@@ -582,6 +760,16 @@ impl<'a> Lexer<'a> {
         return token;        
     }
 
+    // Source text a token was scanned from
+    pub fn token_text(&self, token: &Token) -> &str {
+        &self.input[token.span.start..token.span.end]
+    }
+
+    // Tokens scanned, in source order
+    pub fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
     pub fn print_tokens(&self) {
         for token in &self.tokens {
             println!("{:?}", token);
@@ -1,6 +1,9 @@
+use shared::intern::{Atom, Interner};
+
 use super::lexer::Span;
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Token {
     pub kind: TokenKind,
     pub span: Span,
@@ -8,28 +11,52 @@ pub struct Token {
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum TokenKind {
-    Identifier(String),
+    Identifier(Atom),
     Type(String),
     ArrayType(String, isize),
     BooleanLiteral(bool),
     IntegerLiteral(i64),
     FloatLiteral(f64),
     ColourLiteral(String),
+    StringLiteral(String),
+    // #pragma key value (program metadata)
+    Pragma(String, String),
+    // asm { ... }: the text between the braces
+    Asm(String),
     RandomInt,
+    RandomFloat,
+    RandomRange,
     Width,
     Height,
+    Capabilities,
     Read,
+    HitTest,
+    AddColour,
+    SubtractColour,
+    Hsv,
+    Noise,
+    Param,
+    Load,
     UnaryOp,
     MultiplicativeOp(String),
     AdditiveOp(String),
     RelationalOp(String),
+    LogicalOp(String),
     Equals,
     Let,
     Print,
+    PrintFormatted,
     Clear,
+    Brightness,
+    Store,
+    Assert,
     Delay,
     WriteLine,
+    WriteBoxGradient,
+    WriteBoxPattern,
+    WriteTriangle,
     WriteBox,
     Write,
     Return,
@@ -51,20 +78,37 @@ pub enum TokenKind {
     Arrow,
 }
 
-pub fn classify_token(s: &str) -> TokenKind {
+pub fn classify_token(s: &str, interner: &Interner) -> TokenKind {
     match s {
         "float" | "int" | "bool" | "colour" => TokenKind::Type(s.to_string()),
         "true" => TokenKind::BooleanLiteral(true),
         "false" => TokenKind::BooleanLiteral(false),
         "__width" => TokenKind::Width,
         "__height" => TokenKind::Height,
+        "__capabilities" => TokenKind::Capabilities,
         "__read" => TokenKind::Read,
+        "__hit_test" => TokenKind::HitTest,
+        "__add_colour" | "__addc" => TokenKind::AddColour,
+        "__sub_colour" | "__subc" => TokenKind::SubtractColour,
+        "__hsv" => TokenKind::Hsv,
+        "__noise" => TokenKind::Noise,
+        "__param" => TokenKind::Param,
+        "__load" => TokenKind::Load,
         "__random_int" | "__randi" => TokenKind::RandomInt,
+        "__random_float" | "__randf" => TokenKind::RandomFloat,
+        "__random_range" | "__randr" => TokenKind::RandomRange,
         "__print" => TokenKind::Print,
+        "__printf" => TokenKind::PrintFormatted,
         "__clear" => TokenKind::Clear,
+        "__brightness" => TokenKind::Brightness,
+        "__store" => TokenKind::Store,
+        "__assert" => TokenKind::Assert,
         "__delay" => TokenKind::Delay,
         "__write_box" | "__pixelr" => TokenKind::WriteBox,
         "__write_line" | "__pixell" => TokenKind::WriteLine,
+        "__write_box_gradient" => TokenKind::WriteBoxGradient,
+        "__write_box_pattern" => TokenKind::WriteBoxPattern,
+        "__write_triangle" => TokenKind::WriteTriangle,
         "__write" | "__pixel" => TokenKind::Write,
         "return" => TokenKind::Return,
         "if" => TokenKind::If,
@@ -88,24 +132,76 @@ pub fn classify_token(s: &str) -> TokenKind {
         "not" => TokenKind::UnaryOp,
         "+" | "-" | "|" => TokenKind::AdditiveOp(s.to_string()),
         "*" | "/" | "&" | "%" => TokenKind::MultiplicativeOp(s.to_string()),
-        "==" | "<" | ">" | ">=" | "<=" | "!=" | "&&" | "and" | "||" | "or" => TokenKind::RelationalOp(s.to_string()),
+        "==" | "<" | ">" | ">=" | "<=" | "!=" => TokenKind::RelationalOp(s.to_string()),
+        "&&" | "and" | "||" | "or" => TokenKind::LogicalOp(s.to_string()),
         _ => { // identifier or literal
             // literal type (int, float, colour)
             match s.chars().next().unwrap() {
                 '0'..='9' => { 
-                    match s.parse::<i64>() {
-                        Ok(i) => TokenKind::IntegerLiteral(i),
-                        Err(_) => {
-                            match s.parse::<f64>() {
-                                Ok(f) => TokenKind::FloatLiteral(f),
-                                Err(_) => TokenKind::Identifier(s.to_string()),
+                    match parse_integer_literal(s) {
+                        Some(i) => TokenKind::IntegerLiteral(i),
+                        None => {
+                            match parse_float_literal(s) {
+                                Some(f) => TokenKind::FloatLiteral(f),
+                                None => TokenKind::Identifier(interner.intern(s)),
                             }
                         }
                     }
                 },
                 '#' => TokenKind::ColourLiteral(s.to_string()),
-                _ => TokenKind::Identifier(s.to_string()),
+                _ => TokenKind::Identifier(interner.intern(s)),
             }
         }
     }
-}
\ No newline at end of file
+}
+
+//
+// Value of an integer literal: decimal, hex (0xFF00FF) or binary (0b1010),
+// with underscores allowed between digits (1_000_000, 0xFF_00_FF); None if
+// it's malformed or doesn't fit
+//
+pub fn parse_integer_literal(s: &str) -> Option<i64> {
+    i64::try_from(literal_magnitude(s)?).ok()
+}
+
+//
+// Value of an integer literal with a minus folded into it, which reaches one
+// further than a positive literal (-9223372036854775808); None if it's
+// malformed or doesn't fit
+//
+pub fn parse_negated_integer_literal(s: &str) -> Option<i64> {
+    0i64.checked_sub_unsigned(literal_magnitude(s)?)
+}
+
+// The unsigned value an integer literal spells, in any of its radixes
+fn literal_magnitude(s: &str) -> Option<u64> {
+    let (digits, radix) = match s.get(..2) {
+        Some("0x" | "0X") => (&s[2..], 16),
+        Some("0b" | "0B") => (&s[2..], 2),
+        _ => (s, 10),
+    };
+
+    u64::from_str_radix(&literal_digits(digits)?, radix).ok()
+}
+
+//
+// Value of a float literal, with underscores allowed between digits
+// (1_000.5)
+//
+pub fn parse_float_literal(s: &str) -> Option<f64> {
+    literal_digits(s)?.parse::<f64>().ok()
+}
+
+// The digits of a literal without its underscores, which may only separate
+// digits
+fn literal_digits(digits: &str) -> Option<String> {
+    let bytes = digits.as_bytes();
+    let separates = |index: usize| index > 0 && index + 1 < bytes.len()
+        && bytes[index - 1].is_ascii_alphanumeric() && bytes[index + 1].is_ascii_alphanumeric();
+
+    if digits.is_empty() || digits.char_indices().any(|(index, ch)| ch == '_' && !separates(index)) {
+        return None;
+    }
+
+    Some(digits.replace('_', ""))
+}
@@ -0,0 +1,204 @@
+//! chroma compiler library
+//!
+//! The compiler's stages, and the pipeline that runs them, for the chroma
+//! binary and for crates embedding the compiler (the web and Python
+//! bindings).
+
+pub mod common;
+pub mod lexer;
+pub mod parser;
+pub mod analysis;
+pub mod codegen;
+#[cfg(feature = "serde")]
+pub mod stages;
+#[cfg(feature = "eval")]
+pub mod eval;
+
+use common::{cancel::CancellationToken, logger::Logger, status::CompilationResult, timings::{CompilationStatistics, PhaseTimer}};
+use lexer::lexer::Lexer;
+use parser::{arena::AstArena, parser::{Parser, PARSER_MAX_NESTING}, ast::ProgramNode};
+use analysis::{semantic::SemanticAnalyser, symbol::ScopeManager};
+use codegen::generator::CodeGenerator;
+use codegen::optimiser::*;
+use shared::pixardis::PixardisInstruction;
+
+/// A program as code generation and optimisation produce it: each
+/// instruction with the id of the scope it was generated in
+pub type ScopedProgram = Vec<(usize, PixardisInstruction)>;
+
+///
+/// Lexical Analysis
+/// 
+pub fn lexical_analysis<'a>(source: &'a str, logger: &'a mut Logger<'a>) -> (Lexer<'a>, CompilationResult) {
+    let mut lexer = Lexer::new(source, logger);
+
+    lexer.scan();
+
+    let status = lexer.status().clone();
+
+    (lexer, status)
+}
+
+///
+/// Parsing; the syntax tree's nodes are allocated in the arena, and
+/// expressions nesting deeper than max_nesting are rejected
+/// 
+pub fn parse<'a>(lexer: Lexer<'a>, logger: &'a mut Logger<'a>, arena: &'a AstArena<'a>, max_nesting: usize) -> (Parser<'a>, CompilationResult) {
+    let mut parser = Parser::new(lexer, logger, arena);
+    parser.max_nesting_set(max_nesting);
+
+    parser.parse();
+
+    let status = parser.status().clone();
+
+    (parser, status)
+}
+
+///
+/// Semantic Analysis
+/// 
+pub fn semantic_analysis<'a>(syntax_tree: &'a mut ProgramNode<'_>, scope_manager: &'a mut ScopeManager, logger: &'a mut Logger<'a>) -> CompilationResult {
+    let mut semantic_analyser = SemanticAnalyser::new(syntax_tree, scope_manager, logger);
+    semantic_analyser.analyse();
+
+    semantic_analyser.status()
+}
+
+///
+/// Code Generation
+/// 
+pub fn code_generation<'a>(syntax_tree: &'a mut ProgramNode<'_>, scope_manager: &'a mut ScopeManager, logger: &'a mut Logger<'a>, fixed_point: Option<u32>) -> (ScopedProgram, Vec<usize>, CompilationResult) {
+    let mut code_generator = CodeGenerator::new(syntax_tree, scope_manager, logger);
+    code_generator.fixed_point_set(fixed_point);
+    code_generator.generate();
+
+    (code_generator.take_program_code(), code_generator.take_line_table(), code_generator.status())
+}
+
+///
+/// Code Optimisation; the line table is kept in step with the code, and
+/// debug_passes logs what each pass changed
+/// 
+pub fn code_optimisation(code: &mut Vec::<(usize, PixardisInstruction)>, lines: &mut Vec<usize>, logger: &Logger, debug_passes: bool) -> (Vec<(usize, PixardisInstruction)>, CompilationResult) {
+    optimise_code_pixardis(code, lines, logger, debug_passes)
+}
+
+///
+/// Parse a source and return its syntax tree (see parser::ast) as JSON, for
+/// tools that show the tree next to the source; statements and expressions
+/// carry their source lines (0-based). Diagnostics go to the logger.
+/// 
+#[cfg(feature = "serde")]
+pub fn parse_to_json<'a>(source: &'a str, logger: &Logger<'a>) -> Result<serde_json::Value, String> {
+    let artifacts = stages::compile_to_stage(source, logger, stages::PipelineStage::Parse);
+
+    match artifacts.failed {
+        Some(error) => Err(error),
+        None => artifacts.ast.ok_or_else(|| String::from("Failed to serialise syntax tree")),
+    }
+}
+
+///
+/// Run the whole pipeline on a source, returning the optimised program or
+/// the stage that failed; diagnostics go to the logger. Unlike chroma, a
+/// failing stage doesn't exit the process.
+/// 
+pub fn compile_with_logger<'a>(source: &'a str, logger: &Logger<'a>, fixed_point: Option<u32>) -> Result<Vec<(usize, PixardisInstruction)>, String> {
+    compile_with_line_table(source, logger, fixed_point).map(|(program, _)| program)
+}
+
+///
+/// As compile_with_logger, also returning the source line (1-based, 0 if
+/// unknown) of each instruction, for runtime errors and debug information.
+/// 
+pub fn compile_with_line_table<'a>(source: &'a str, logger: &Logger<'a>, fixed_point: Option<u32>) -> Result<(ScopedProgram, Vec<usize>), String> {
+    compile_pipeline(source, logger, fixed_point, None, None)
+}
+
+///
+/// As compile_with_line_table, giving up between phases (with "Compilation
+/// cancelled") once the token is cancelled.
+/// 
+pub fn compile_cancellable<'a>(source: &'a str, logger: &Logger<'a>, fixed_point: Option<u32>, cancel: &CancellationToken) -> Result<(ScopedProgram, Vec<usize>), String> {
+    compile_pipeline(source, logger, fixed_point, None, Some(cancel))
+}
+
+///
+/// As compile_with_line_table, also returning the wall time and token, node
+/// and instruction counts of each phase. Reads the system clock, which
+/// wasm32-unknown-unknown doesn't have.
+/// 
+pub fn compile_with_statistics<'a>(source: &'a str, logger: &Logger<'a>, fixed_point: Option<u32>) -> Result<(ScopedProgram, Vec<usize>, CompilationStatistics), String> {
+    let mut statistics = CompilationStatistics::new();
+    let (program, lines) = compile_pipeline(source, logger, fixed_point, Some(&mut statistics), None)?;
+
+    Ok((program, lines, statistics))
+}
+
+fn compile_pipeline<'a>(source: &'a str, logger: &Logger<'a>, fixed_point: Option<u32>, statistics: Option<&mut CompilationStatistics>, cancel: Option<&CancellationToken>) -> Result<(ScopedProgram, Vec<usize>), String> {
+    let cancelled = || match cancel {
+        Some(cancel) if cancel.is_cancelled() => Err(String::from("Compilation cancelled")),
+        _ => Ok(()),
+    };
+
+    cancelled()?;
+
+    let mut timer = PhaseTimer::start(statistics);
+    let mut scope_manager = ScopeManager::new();
+    let arena = AstArena::new();
+
+    let mut lexer_logger = logger.clone();
+    let (lexer, status) = lexical_analysis(source, &mut lexer_logger);
+
+    let tokens = lexer.tokens().len();
+    timer.record("Lexical Analysis", Some(tokens), None, None);
+
+    if matches!(status, CompilationResult::Failure) {
+        return Err("Lexical analysis failed".to_string());
+    }
+
+    cancelled()?;
+
+    let mut parser_logger = logger.clone();
+    let (mut parser, status) = parse(lexer, &mut parser_logger, &arena, PARSER_MAX_NESTING);
+
+    if matches!(status, CompilationResult::Failure) {
+        return Err("Parsing failed".to_string());
+    }
+
+    // Analysis and code generation work on the parser's tree in place, so
+    // only one copy of the AST is ever held; labels they make are interned
+    // with the source's identifiers
+    let mut syntax_tree = parser.take_syntax_tree()
+        .ok_or("Failed to get syntax tree")?;
+    let nodes = timer.is_active().then(|| syntax_tree.node_count());
+    timer.record("Parsing", Some(tokens), nodes, None);
+
+    cancelled()?;
+
+    scope_manager.interner_set(parser.interner());
+    let mut analysis_logger = logger.clone();
+    let status = semantic_analysis(&mut syntax_tree, &mut scope_manager, &mut analysis_logger);
+    timer.record("Semantic Analysis", None, nodes, None);
+
+    if matches!(status, CompilationResult::Failure) {
+        return Err("Semantic analysis failed".to_string());
+    }
+
+    cancelled()?;
+
+    let mut codegen_logger = logger.clone();
+    let (mut program, mut lines, status) = code_generation(&mut syntax_tree, &mut scope_manager, &mut codegen_logger, fixed_point);
+    timer.record("Code Generation", None, nodes, Some(program.len()));
+
+    if matches!(status, CompilationResult::Failure) {
+        return Err("Code generation failed".to_string());
+    }
+
+    cancelled()?;
+
+    let (optimised_program, _status) = code_optimisation(&mut program, &mut lines, logger, false);
+    timer.record("Code Optimisation", None, None, Some(optimised_program.len()));
+
+    Ok((optimised_program, lines))
+}
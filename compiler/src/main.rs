@@ -5,47 +5,49 @@
 //! TODO: [FIXES]
 //! - Need to handle the unary operator properly (for non-integer values)
 //! - Need to handle empty blocks (i.e. {})
-//! - Separate logical and relational operators
 //! 
 //! TODO: [FEATURES]
 //! - Add structs to the language
 //! - Add proper variable scope determination (i.e. global, local, function)
 //! - Add support for global variables
 
-pub mod common;
-pub mod lexer;
-pub mod parser;
-pub mod analysis;
-pub mod codegen;
-
-use common::{
-    logger::{
-        Logger, 
-        LoggerMessage,
-    }, 
-    status::CompilationResult
+mod test_runner;
+
+use compiler::{
+    ScopedProgram,
+    code_generation,
+    code_optimisation,
+    lexical_analysis,
+    parse,
+    semantic_analysis,
+    analysis::symbol::ScopeManager,
+    codegen::optimiser::strip_debug_io,
+    parser::{arena::AstArena, parser::PARSER_MAX_NESTING},
+    common::{
+        cache::CompilationCache,
+        emit::{Compilation, EmitKind, EmitTarget},
+        report::size_report,
+        timings::{CompilationStatistics, PhaseTimer},
+        logger::{
+            Logger, 
+            LoggerMessage,
+        }, 
+        status::CompilationResult
+    },
 };
 
-use lexer::lexer::Lexer;
-
-use parser::{
-    parser::Parser, 
-    ast::ProgramNode
-};
-
-use analysis::{
-    semantic::SemanticAnalyser, 
-    symbol::ScopeManager
-};
-
-use codegen::generator::CodeGenerator;
-use codegen::optimiser::*;
 use shared::pixardis::{
     PixardisInstruction, 
+    pixardis_instruction_to_string,
     pixardis_print_code, 
     pixardis_save_code
 };
 
+use vm::pixardis::{
+    differential::pixardis_differential_run,
+    pixardis::PixardisMetadata,
+};
+
 use std::io;
 use std::process;
 
@@ -53,9 +55,16 @@ fn main() -> Result<(), io::Error> {
 
     // Parse command line arguments; place the results in a context object.
     let context = process_cmd_args();
+
+    // chroma test runs the program's tests instead of writing code
+    if let Some(Command::Test { input, cycles, coverage, fixed_point }) = &context.command {
+        let passed = test_runner::run_tests(input, *cycles, coverage.as_deref(), *fixed_point)?;
+        process::exit(if passed { 0 } else { 1 });
+    }
     
-    // Get the file path from the context object.
-    let file_path = context.input.as_str();
+    // Get the file path from the context object (clap requires it without a
+    // subcommand).
+    let file_path = context.input.as_deref().unwrap_or_default();
     
     // Read source file
     let source = shared::io::read_file_to_string(&file_path)?;
@@ -65,132 +74,251 @@ fn main() -> Result<(), io::Error> {
     //
     let logger = Logger::new(source.as_str());
 
+    //
+    // Reuse the cached result when the source and options are unchanged
+    //
+    let cache = context.cache.as_deref().map(CompilationCache::new);
+    let cache_key = CompilationCache::key(&source, &format!("fixed_point={:?} strip_debug_io={:?} check_optimiser={:?} max_nesting={}",
+        context.fixed_point, context.strip_debug_io, context.check_optimiser, context.max_nesting));
+
+    // The cache only holds the optimised program, so a diff against the
+    // unoptimised one always compiles, as does timing the compilation or
+    // logging its optimisation passes
+    let diff = context.emit.iter().any(|target| target.kind == EmitKind::AsmDiff);
+    let mut statistics = context.timings.then(CompilationStatistics::new);
+
+    let (optimised_program, lines, unoptimised_program) = match cache.as_ref().filter(|_| !diff && !context.timings && !context.debug_passes).and_then(|cache| cache.load(&cache_key)) {
+        Some((program, lines)) => {
+            logger.print_message(LoggerMessage::Info, "Using cached compilation.");
+            (program, lines, None)
+        },
+        None => {
+            let (program, lines, unoptimised_program, clean) = compile_source(&source, &logger, &context, diff, statistics.as_mut());
+
+            if let (Some(cache), true) = (cache.as_ref(), clean) {
+                if let Err(error) = cache.store(&cache_key, &program, &lines) {
+                    logger.print_message(LoggerMessage::Warning,
+                        format!("Failed writing to compilation cache, error '{}'", error).as_str());
+                }
+            }
+
+            (program, lines, unoptimised_program)
+        }
+    };
+
+    //
+    // Write generated code to stdout or file
+    //
+    let show_line_number = context.line_prefix.is_some_and(|show| show);
+    let show_scope = context.scope_prefix.is_some_and(|show| show);
+
+    if let Some(output) = &context.output {
+        if let Err(error) = pixardis_save_code(&optimised_program, output, show_line_number, show_scope) {
+            logger.print_message(LoggerMessage::Error, 
+                format!("Failed writing to '{}', error '{}'", output, error).as_str());    
+        }
+    } else if context.emit.is_empty() {
+        pixardis_print_code(&optimised_program, show_line_number, show_scope);
+    }
+
+    //
+    // Show how long each phase took (to stderr, as stdout may carry the
+    // program)
+    //
+    if let Some(statistics) = statistics {
+        eprint!("{}", statistics.to_table());
+    }
+
+    //
+    // Show which functions take up the most code and time
+    //
+    if context.report_size {
+        print_size_report(&optimised_program);
+    }
+
+    //
+    // Write any additional artifacts from the same compilation
+    //
+    let options = [
+        ("fixed_point", context.fixed_point.map(|bits| bits.to_string())),
+        ("strip_debug_io", context.strip_debug_io.map(|strip| format!("{:?}", strip).to_lowercase())),
+        ("max_nesting", Some(context.max_nesting.to_string())),
+    ];
+
+    let outputs: Vec<(EmitKind, &str)> = context.output.iter().map(|output| (EmitKind::Asm, output.as_str()))
+        .chain(context.emit.iter().map(|target| (target.kind, target.path.as_str())))
+        .collect();
+
+    let compilation = Compilation {
+        program: &optimised_program,
+        unoptimised: unoptimised_program.as_deref(),
+        lines: &lines,
+        source_path: file_path,
+        source: &source,
+        options: &options,
+        outputs: &outputs,
+        show_line_numbers: show_line_number,
+        show_scope,
+    };
+
+    for target in context.emit.iter() {
+        if let Err(error) = target.write(&compilation) {
+            logger.print_message(LoggerMessage::Error, 
+                format!("Failed writing to '{}', error '{}'", target.path, error).as_str());    
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Run the compilation pipeline with the options given on the command line;
+/// exits on failure. Also returns the line table, the code generator's output
+/// if keep_unoptimised is set, and whether every stage completed without
+/// warnings; each stage is timed into statistics if given.
+///
+fn compile_source<'a>(source: &'a str, logger: &Logger<'a>, options: &Args, keep_unoptimised: bool, statistics: Option<&mut CompilationStatistics>) -> (ScopedProgram, Vec<usize>, Option<ScopedProgram>, bool) {
+    let (fixed_point, check_optimiser) = (options.fixed_point, options.check_optimiser);
+
+    let mut clean = true;
+    let mut timer = PhaseTimer::start(statistics);
+
     //
     // Create scope manager
     //
     let mut scope_manager = ScopeManager::new();
+    let arena = AstArena::new();
 
     //
     // Perform lexical analysis    
     //
     let mut lexer_logger = logger.clone();
-    let (lexer, status) = lexical_analysis(&source, &mut lexer_logger).unwrap();
-    assert_stage(&logger, status, "Lexical Analysis");
+    let (lexer, status) = lexical_analysis(source, &mut lexer_logger);
+    let tokens = lexer.tokens().len();
+    timer.record("Lexical Analysis", Some(tokens), None, None);
+    clean &= assert_stage(logger, status, "Lexical Analysis");
 
     //
     // Perform parsing and build the syntax tree
     //
     let mut parser_logger = logger.clone();
-    let (parser, status) = parse(lexer, &mut parser_logger).unwrap();
-    assert_stage(&logger, status, "Parsing");
+    let (mut parser, status) = parse(lexer, &mut parser_logger, &arena, options.max_nesting);
+    clean &= assert_stage(logger, status, "Parsing");
 
 
     //
-    // Perform semantic analysis
+    // Perform semantic analysis; this and code generation share the one tree
     //
+    let mut syntax_tree = parser.take_syntax_tree().unwrap();
+    let nodes = timer.is_active().then(|| syntax_tree.node_count());
+    timer.record("Parsing", Some(tokens), nodes, None);
+
+    scope_manager.interner_set(parser.interner());
     let mut analysis_logger = logger.clone();
-    let mut analysis_syntax_tree = parser.get_syntax_tree().unwrap();
-    let (semantic_analyser, status) = semantic_analysis(&mut analysis_syntax_tree, &mut scope_manager, &mut analysis_logger).unwrap();
-    assert_stage(&logger, status, "Semantic Analysis");
+    let status = semantic_analysis(&mut syntax_tree, &mut scope_manager, &mut analysis_logger);
+    timer.record("Semantic Analysis", None, nodes, None);
+    clean &= assert_stage(logger, status, "Semantic Analysis");
 
 
     //
     // Perform code generation
     //
     let mut codegen_logger = logger.clone();
-    let mut codegen_syntax_tree = semantic_analyser.get_analysed_tree().unwrap(); //parser.get_syntax_tree().unwrap();
-    let (program, status) = code_generation(&mut codegen_syntax_tree, &mut scope_manager, &mut codegen_logger).unwrap();
-    assert_stage(&logger, status, "Code Generation");
+    let (mut program, mut lines, status) = code_generation(&mut syntax_tree, &mut scope_manager, &mut codegen_logger, fixed_point);
+    timer.record("Code Generation", None, nodes, Some(program.len()));
+    clean &= assert_stage(logger, status, "Code Generation");
 
     //
     // Perform code optimisation
     //
-    let (optimised_program,status) = code_optimisation(&mut program.clone()).unwrap();
-    assert_stage(&logger, status, "Code Optimisation");
-    
+    let unoptimised_program = (check_optimiser.is_some() || keep_unoptimised).then(|| program.clone());
+
+    let (mut optimised_program,status) = code_optimisation(&mut program, &mut lines, logger, options.debug_passes);
+    timer.record("Code Optimisation", None, None, Some(optimised_program.len()));
+    clean &= assert_stage(logger, status, "Code Optimisation");
+
     //
-    // Write generated code to stdout or file
+    // Check the optimised code does what the unoptimised code does
     //
-    let show_line_number = context.line_prefix.map_or(false, |show| show);
-    let show_scope = context.scope_prefix.map_or(false, |show| show);
+    if let (Some(unoptimised_program), Some(cycles)) = (unoptimised_program.as_ref(), check_optimiser) {
+        check_optimisation(logger, unoptimised_program, &optimised_program, cycles, fixed_point);
+        timer.record("Optimiser Check", None, None, Some(optimised_program.len()));
+    }
 
-    if let Some(output) = context.output {
-        if let Err(error) = pixardis_save_code(&optimised_program, &output, show_line_number, show_scope) {
-            logger.print_message(LoggerMessage::Error, 
-                format!("Failed writing to '{}', error '{}'", output, error).as_str());    
-        }
-    } else {
-        pixardis_print_code(&optimised_program, show_line_number, show_scope);
+    //
+    // Strip prints (and delays) for benchmark builds
+    //
+    if let Some(strip) = options.strip_debug_io {
+        strip_debug_io(&mut optimised_program, &mut lines, strip == StripArg::All);
+        timer.record("Debug I/O Stripping", None, None, Some(optimised_program.len()));
     }
 
-    Ok(())
+    (optimised_program, lines, unoptimised_program.filter(|_| keep_unoptimised), clean)
 }
 
 ///
-/// Lexical Analysis
-/// 
-pub fn lexical_analysis<'a>(source: &'a str, logger: &'a mut Logger<'a>) -> Result<(Lexer<'a>, CompilationResult),()> {
-    let mut lexer = Lexer::new(source, logger);
-
-    lexer.scan();
-
-    let status = lexer.status().clone();
-
-    Ok((lexer, status))
-}
-
+/// Run the unoptimised and optimised programs side by side and report the
+/// first thing the optimised one does differently; exits on a divergence.
 ///
-/// Parsing
-/// 
-pub fn parse<'a>(lexer: Lexer<'a>, logger: &'a mut Logger<'a>) -> Result<(Parser<'a>, CompilationResult),()> {
-    let mut parser = Parser::new(lexer, logger);
-
-    parser.parse();
-
-    let status = parser.status().clone();
-
-    Ok((parser, status))
+fn check_optimisation(logger: &Logger, unoptimised: &[(usize, PixardisInstruction)], optimised: &[(usize, PixardisInstruction)], cycles: usize, fixed_point: Option<u32>) {
+    let instructions = |program: &[(usize, PixardisInstruction)]| -> Vec<PixardisInstruction> {
+        program.iter().map(|(_, instruction)| instruction.clone()).collect()
+    };
+
+    let (reference, candidate) = (instructions(unoptimised), instructions(optimised));
+
+    let metadata = PixardisMetadata::from_instructions(&reference);
+    let report = pixardis_differential_run(reference, candidate, metadata.width.unwrap_or(64), metadata.height.unwrap_or(48), cycles,
+        |vm| vm.float_support_set(fixed_point.is_none()));
+
+    match report.divergence {
+        None => logger.print_message(LoggerMessage::Info,
+            format!("Optimiser Check Complete ({} effects compared).", report.compared).as_str()),
+        Some(divergence) => {
+            logger.print_message(LoggerMessage::Error,
+                format!("Optimised code diverges after {} matching effects: unoptimised [{}] {} gives {:?}, optimised [{}] {} gives {:?}.",
+                    divergence.index,
+                    divergence.reference.address, pixardis_instruction_to_string(divergence.reference.instruction), divergence.reference.effect,
+                    divergence.candidate.address, pixardis_instruction_to_string(divergence.candidate.instruction), divergence.candidate.effect).as_str());
+            process::exit(1);
+        },
+    }
 }
 
 ///
-/// Semantic Analysis
-/// 
-pub fn semantic_analysis<'a>(syntax_tree: &'a mut ProgramNode, scope_manager: &'a mut ScopeManager, logger: &'a mut Logger<'a>) -> Result<(SemanticAnalyser<'a>, CompilationResult),()> {
-    let mut semantic_analyser = SemanticAnalyser::new(syntax_tree, scope_manager, logger);
-    semantic_analyser.analyse();
-
-    let status = semantic_analyser.status().clone();
+/// Print the size of each function (to stderr, as stdout may carry the
+/// program), for the display size the program asks for
+///
+fn print_size_report(program: &[(usize, PixardisInstruction)]) {
+    let instructions: Vec<PixardisInstruction> = program.iter().map(|(_, instruction)| instruction.clone()).collect();
+    let metadata = PixardisMetadata::from_instructions(&instructions);
+    let (width, height) = (metadata.width.unwrap_or(64), metadata.height.unwrap_or(48));
 
-    Ok((semantic_analyser, status))
-}
+    let report = size_report(program, width, height);
+    let name_width = report.iter().map(|function| function.name.len()).max().unwrap_or(0).max("function".len());
 
-///
-/// Code Generation
-/// 
-pub fn code_generation<'a>(syntax_tree: &'a mut ProgramNode, scope_manager: &'a mut ScopeManager, logger: &'a mut Logger<'a>) -> Result<(Vec<(usize, PixardisInstruction)>, CompilationResult), ()>{
-    let mut code_generator = CodeGenerator::new(syntax_tree, scope_manager, logger);
-    code_generator.generate();
+    eprintln!("{:<name_width$}  {:>12}  {:>15}", "function", "instructions", "cycles (est.)");
 
-    Ok((code_generator.program_code(), code_generator.status()))
-}
+    for function in report.iter() {
+        eprintln!("{:<name_width$}  {:>12}  {:>15}", function.name, function.instructions, function.cycles);
+    }
 
-///
-/// Code Optimisation
-/// 
-pub fn code_optimisation(code: &mut Vec::<(usize, PixardisInstruction)>) -> Result<(Vec<(usize, PixardisInstruction)>, CompilationResult), ()> {
-    Ok((optimise_code_pixardis(code), CompilationResult::Success))
+    eprintln!("Cycle estimates count each instruction once (loops aren't unrolled) and take drawing to cover the {}x{} display.", width, height);
 }
 
 ///
-/// Helper function to assert stage completed successfully
+/// Helper function to assert stage completed successfully; returns true if it
+/// completed without warnings
 /// 
-pub fn assert_stage(logger: &Logger, status: CompilationResult, stage: &str) {
+pub fn assert_stage(logger: &Logger, status: CompilationResult, stage: &str) -> bool {
     match status {
         CompilationResult::Success => { 
             logger.print_message(LoggerMessage::Info, format!("{} Complete.", stage).as_str());
+            true
         },
         CompilationResult::Warning => {
             logger.print_message(LoggerMessage::Warning, format!("{} Complete with Warnings.", stage).as_str());
+            false
         },
         CompilationResult::Failure => {
             logger.print_message(LoggerMessage::Error, format!("{} Failed.", stage).as_str());
@@ -198,6 +326,7 @@ pub fn assert_stage(logger: &Logger, status: CompilationResult, stage: &str) {
         },
         CompilationResult::Pending => {
             logger.print_message(LoggerMessage::Warning, format!("{} Pending.", stage).as_str());
+            false
         },
     }
 }
@@ -223,23 +352,81 @@ use clap::Parser as ClapParser;
                                                         
                         Compiler
 ------------------------------------------------------------")]
+#[command(subcommand_negates_reqs = true)]
 struct Args {
-    #[arg(short, long, value_name = "FILE")]
-    input: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(short, long, value_name = "FILE", required = true)]
+    input: Option<String>,
 
     #[arg(short, long, value_name = "FILE")]
     output: Option<String>,
 
+    #[arg(long, value_name = "KIND:FILE", value_parser = EmitTarget::parse, help = "Also write the program as KIND (asm, bin or dbg) to FILE, or - for stdout; asm-diff (to stdout unless given a FILE) lines the unoptimised code up with the optimised; manifest records the source's hash, the options and the outputs, for VMs to check against (--manifest). May be repeated.")]
+    emit: Vec<EmitTarget>,
+
     #[arg(short, long, help = "Prefixes instructions with line numbers.")]
     line_prefix: Option<bool>,
 
     #[arg(short, long, help = "Prefixes instructions with scope id.")]
     scope_prefix: Option<bool>,
 
+    #[arg(long, value_name = "BITS", value_parser = clap::value_parser!(u32).range(1..=30), help = "Lower float arithmetic to fixed-point integers with BITS fractional bits.")]
+    fixed_point: Option<u32>,
+
+    #[arg(long, value_enum, value_name = "WHAT", num_args = 0..=1, default_missing_value = "prints", help = "Remove print instructions, or prints and delays (all), so the program runs at full speed.")]
+    strip_debug_io: Option<StripArg>,
+
+    #[arg(long, value_name = "DIR", num_args = 0..=1, default_missing_value = ".chroma-cache", help = "Reuse compilation results for unchanged sources, stored in DIR [default = .chroma-cache].")]
+    cache: Option<String>,
+
+    #[arg(long, help = "Print each function's instruction count and estimated cycles per call.")]
+    report_size: bool,
+
+    #[arg(long, help = "Print each phase's wall time and its token, syntax tree node and instruction counts.")]
+    timings: bool,
+
+    #[arg(long, help = "Log the instructions each optimisation pass changes.")]
+    debug_passes: bool,
+
+    #[arg(long, value_name = "CYCLES", num_args = 0..=1, default_missing_value = "1000000", help = "Run the unoptimised and optimised code for up to CYCLES instructions each and fail if they behave differently [default = 1000000].")]
+    check_optimiser: Option<usize>,
+
+    #[arg(long, value_name = "LEVELS", default_value_t = PARSER_MAX_NESTING, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..), help = "Reject expressions nesting deeper than LEVELS; the compiler recurses once per level, so high limits need a large stack.")]
+    max_nesting: usize,
+
     //#[arg(short, long, help = "Generate debug information.")]
     //debug: Option<bool>,
 }
 
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Run the program's test_* functions, each on a fresh VM; a test fails
+    /// on a failed __assert or any other runtime error
+    Test {
+        #[arg(short, long, value_name = "FILE")]
+        input: String,
+
+        #[arg(short, long, value_name = "CYCLES", help = "Most instructions a test may execute.", default_value = "1000000")]
+        cycles: usize,
+
+        #[arg(long, value_name = "FILE", help = "Write the source lines the tests executed to FILE (JSON) and list those they didn't.")]
+        coverage: Option<String>,
+
+        #[arg(long, value_name = "BITS", value_parser = clap::value_parser!(u32).range(1..=30), help = "Also run each test with floats lowered to fixed point with BITS fractional bits; it fails unless it prints the same.")]
+        fixed_point: Option<u32>,
+    },
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum StripArg {
+    // Print instructions only
+    Prints,
+    // Print and delay instructions
+    All,
+}
+
 //
 // Process compiler command line arguments
 //
@@ -0,0 +1,94 @@
+use std::cell::RefCell;
+
+use super::ast::{ExpressionNode, StatementNode};
+
+//
+// Append-only storage handing out references that live as long as the arena.
+// Values are kept in chunks that are never grown past their capacity, so a
+// value never moves once allocated; a full chunk is followed by one twice its
+// size. Everything is dropped with the arena.
+//
+pub struct Arena<T> {
+    chunks: RefCell<Vec<Vec<T>>>,
+}
+
+const ARENA_FIRST_CHUNK: usize = 64;
+
+impl<T> Arena<T> {
+    pub fn new() -> Self {
+        Arena { chunks: RefCell::new(Vec::new()) }
+    }
+
+    pub fn alloc(&self, value: T) -> &T {
+        let mut chunks = self.chunks.borrow_mut();
+
+        let capacity = match chunks.last() {
+            Some(chunk) if chunk.len() < chunk.capacity() => None,
+            Some(chunk) => Some(chunk.capacity() * 2),
+            None => Some(ARENA_FIRST_CHUNK),
+        };
+
+        if let Some(capacity) = capacity {
+            chunks.push(Vec::with_capacity(capacity));
+        }
+
+        let chunk = chunks.last_mut().unwrap();
+        chunk.push(value);
+
+        // SAFETY: the push above was within the chunk's capacity, so the
+        // chunk's buffer (and every value already in it) stays where it is;
+        // buffers are only freed when the arena is dropped, which the returned
+        // reference's lifetime rules out
+        unsafe { &*(chunk.last().unwrap() as *const T) }
+    }
+
+    // Number of values allocated
+    pub fn len(&self) -> usize {
+        self.chunks.borrow().iter().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Default for Arena<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//
+// Storage for the nodes of one syntax tree. Nodes refer to their children by
+// reference into the arena, so the tree is built without reference counting
+// and shared by the later stages without being copied; it lives as long as
+// the arena does.
+//
+#[derive(Default)]
+pub struct AstArena<'ast> {
+    expressions: Arena<ExpressionNode<'ast>>,
+    statements: Arena<StatementNode<'ast>>,
+}
+
+impl<'ast> AstArena<'ast> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn expression(&'ast self, node: ExpressionNode<'ast>) -> &'ast ExpressionNode<'ast> {
+        self.expressions.alloc(node)
+    }
+
+    pub fn statement(&'ast self, node: StatementNode<'ast>) -> &'ast StatementNode<'ast> {
+        self.statements.alloc(node)
+    }
+
+    // Number of nodes allocated
+    pub fn len(&self) -> usize {
+        self.expressions.len() + self.statements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -1,4 +1,6 @@
-use std::{cell::RefCell, rc::Rc};
+use std::cell::RefCell;
+
+use shared::intern::Atom;
 
 pub trait AbstractSyntaxTreeVisitor
 {
@@ -15,9 +17,16 @@ pub trait AbstractSyntaxTreeVisitor
     fn visit_print(&mut self, node: &PrintNode);
     fn visit_delay(&mut self, node: &ExpressionNode);
     fn visit_clear(&mut self, node: &ExpressionNode);
+    fn visit_brightness(&mut self, node: &ExpressionNode);
+    fn visit_store(&mut self, key: &str, node: &ExpressionNode);
+    fn visit_asm(&mut self, node: &AsmNode);
+    fn visit_assert(&mut self, node: &ExpressionNode);
     fn visit_write(&mut self, node: &[ExpressionNode; 3]);
     fn visit_write_box(&mut self, node: &[ExpressionNode; 5]);
     fn visit_write_line(&mut self, node: &[ExpressionNode; 5]);
+    fn visit_write_box_gradient(&mut self, node: &[ExpressionNode; 7]);
+    fn visit_write_box_pattern(&mut self, node: &[ExpressionNode; 7]);
+    fn visit_write_triangle(&mut self, node: &[ExpressionNode; 7]);
     fn visit_return(&mut self, node: &ExpressionNode);
     fn visit_if(&mut self, node: &IfNode);
     fn visit_while(&mut self, node: &WhileNode);
@@ -29,34 +38,73 @@ pub trait AbstractSyntaxTreeVisitor
     fn visit_colour_literal(&mut self, value: String);
     fn visit_width(&mut self);
     fn visit_height(&mut self);
-    fn visit_random_int(&mut self, node: &Rc<ExpressionNode>);
-    fn visit_read(&mut self, data: &[Rc<ExpressionNode>; 2]);
-    fn visit_identifier(&mut self, value: String);
+    fn visit_capabilities(&mut self);
+    fn visit_random_int(&mut self, node: &ExpressionNode);
+    fn visit_random_float(&mut self);
+    fn visit_random_range(&mut self, data: &[&ExpressionNode; 2]);
+    fn visit_read(&mut self, data: &[&ExpressionNode; 2]);
+    fn visit_hit_test(&mut self, data: &[&ExpressionNode; 5]);
+    fn visit_add_colour(&mut self, data: &[&ExpressionNode; 2]);
+    fn visit_subtract_colour(&mut self, data: &[&ExpressionNode; 2]);
+    fn visit_hsv(&mut self, data: &[&ExpressionNode; 3]);
+    fn visit_noise(&mut self, data: &[&ExpressionNode; 3]);
+    fn visit_param(&mut self, name: &str, default: Option<&ExpressionNode>);
+    fn visit_load(&mut self, key: &str, default: Option<&ExpressionNode>);
+    fn visit_identifier(&mut self, value: Atom);
     fn visit_function_call(&mut self, node: &FunctionCallNode);
     fn visit_array_access(&mut self, node: &ArrayAccessNode);
-    fn visit_subexpression(&mut self, node: &Rc<ExpressionNode>);
-    fn visit_unary(&mut self, node: &Rc<ExpressionNode>);
+    fn visit_subexpression(&mut self, node: &ExpressionNode);
+    fn visit_unary(&mut self, node: &ExpressionNode);
 }
 
 // Program Node : this is the root node of the AST
-#[derive(Debug, PartialEq, Clone)]
-pub struct ProgramNode {
-    pub statements: Vec<StatementNode>,
+#[derive(Debug, PartialEq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ProgramNode<'ast> {
+    // Program metadata from #pragma lines (key, value), in source order
+    pub metadata: Vec<(String, String)>,
+    pub statements: Vec<StatementNode<'ast>>,
 }
 
-impl ProgramNode {
+impl<'ast> ProgramNode<'ast> {
     pub fn accept(&self, visitor: &mut dyn AbstractSyntaxTreeVisitor) {
         visitor.visit_program(self);
     }
+
+    //
+    // Statement and expression nodes in the tree (factors count as part of
+    // their expression), counted with work stacks like ExpressionNode::depth
+    //
+    pub fn node_count(&self) -> usize {
+        let mut count = 0;
+        let mut statements: Vec<&StatementNode> = self.statements.iter().collect();
+        let mut expressions: Vec<&ExpressionNode> = Vec::new();
+
+        while let Some(statement) = statements.pop() {
+            count += 1;
+
+            let (child_statements, child_expressions) = statement.children();
+            statements.extend(child_statements);
+            expressions.extend(child_expressions);
+        }
+
+        while let Some(expression) = expressions.pop() {
+            count += 1;
+            expressions.extend(expression.children());
+        }
+
+        count
+    }
 }
 
 // Block Node : this is a block of statements
 #[derive(Debug, PartialEq, Clone)]
-pub struct BlockNode {
-    pub statements: Vec<StatementNode>,
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BlockNode<'ast> {
+    pub statements: Vec<StatementNode<'ast>>,
 }
 
-impl BlockNode {
+impl<'ast> BlockNode<'ast> {
     pub fn accept(&self, visitor: &mut dyn AbstractSyntaxTreeVisitor) {
         visitor.visit_block(self);
     }
@@ -64,11 +112,12 @@ impl BlockNode {
 
 // Block Node : this is a block of statements
 #[derive(Debug, PartialEq, Clone)]
-pub struct UnscopedBlockNode {
-    pub statements: Vec<StatementNode>,
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct UnscopedBlockNode<'ast> {
+    pub statements: Vec<StatementNode<'ast>>,
 }
 
-impl UnscopedBlockNode {
+impl<'ast> UnscopedBlockNode<'ast> {
     pub fn accept(&self, visitor: &mut dyn AbstractSyntaxTreeVisitor) {
         visitor.visit_unscoped_block(self);
     }
@@ -76,26 +125,37 @@ impl UnscopedBlockNode {
 
 // Statement Node : enumerates all possible statements types in the language
 #[derive(Debug, PartialEq, Clone)]
-pub enum StatementNode {
-    VariableDeclaration(VariableDeclarationNode),
-    ArrayDeclaration(ArrayDeclarationNode),
-    FunctionDeclaration(FunctionDeclarationNode),
-    Assignment(AssignmentNode),
-    Print(PrintNode),
-    Delay(ExpressionNode),
-    Write([ExpressionNode; 3]),
-    WriteBox([ExpressionNode; 5]),
-    WriteLine([ExpressionNode; 5]),
-    Return(ExpressionNode),
-    Block(BlockNode),
-    UnscopedBlock(UnscopedBlockNode),
-    If(IfNode),
-    While(WhileNode),
-    For(ForNode),
-    Clear(ExpressionNode),
-}
-
-impl StatementNode {
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum StatementNode<'ast> {
+    VariableDeclaration(VariableDeclarationNode<'ast>),
+    ArrayDeclaration(ArrayDeclarationNode<'ast>),
+    FunctionDeclaration(FunctionDeclarationNode<'ast>),
+    Assignment(AssignmentNode<'ast>),
+    Print(PrintNode<'ast>),
+    Delay(ExpressionNode<'ast>),
+    Write([ExpressionNode<'ast>; 3]),
+    WriteBox([ExpressionNode<'ast>; 5]),
+    WriteLine([ExpressionNode<'ast>; 5]),
+    WriteBoxGradient([ExpressionNode<'ast>; 7]),
+    WriteBoxPattern([ExpressionNode<'ast>; 7]),
+    WriteTriangle([ExpressionNode<'ast>; 7]),
+    Return(ExpressionNode<'ast>),
+    Block(BlockNode<'ast>),
+    UnscopedBlock(UnscopedBlockNode<'ast>),
+    If(IfNode<'ast>),
+    While(WhileNode<'ast>),
+    For(ForNode<'ast>),
+    Clear(ExpressionNode<'ast>),
+    Brightness(ExpressionNode<'ast>),
+    // Value kept in persistent storage under a key
+    Store(String, ExpressionNode<'ast>),
+    // Stops the program with an error unless the condition holds
+    Assert(ExpressionNode<'ast>),
+    // Assembly passed through to the program
+    Asm(AsmNode),
+}
+
+impl<'ast> StatementNode<'ast> {
     pub fn accept(&self, visitor: &mut dyn AbstractSyntaxTreeVisitor) {
         match self {
             StatementNode::VariableDeclaration(node) => visitor.visit_variable_declaration(node),
@@ -104,9 +164,13 @@ impl StatementNode {
             StatementNode::Assignment(node) => visitor.visit_assignment(node),
             StatementNode::Print(node) => visitor.visit_print(node),
             StatementNode::Delay(node) => visitor.visit_delay(node),
+            StatementNode::Assert(node) => visitor.visit_assert(node),
             StatementNode::Write(node) => visitor.visit_write(node),
             StatementNode::WriteBox(node) => visitor.visit_write_box(node),
             StatementNode::WriteLine(node) => visitor.visit_write_line(node),
+            StatementNode::WriteBoxGradient(node) => visitor.visit_write_box_gradient(node),
+            StatementNode::WriteBoxPattern(node) => visitor.visit_write_box_pattern(node),
+            StatementNode::WriteTriangle(node) => visitor.visit_write_triangle(node),
             StatementNode::Return(node) => visitor.visit_return(node),
             StatementNode::Block(node) => visitor.visit_block(node),
             StatementNode::UnscopedBlock(node) => visitor.visit_unscoped_block(node),
@@ -114,20 +178,111 @@ impl StatementNode {
             StatementNode::While(node) => visitor.visit_while(node),
             StatementNode::For(node) => visitor.visit_for(node),
             StatementNode::Clear(node) => visitor.visit_clear(node),
+            StatementNode::Brightness(node) => visitor.visit_brightness(node),
+            StatementNode::Store(key, node) => visitor.visit_store(key, node),
+            StatementNode::Asm(node) => visitor.visit_asm(node),
+        }
+    }
+
+    // Statements and expressions directly inside this statement
+    pub fn children(&self) -> (Vec<&StatementNode<'ast>>, Vec<&ExpressionNode<'ast>>) {
+        match self {
+            StatementNode::VariableDeclaration(node) => (vec![], vec![&node.expression]),
+            StatementNode::ArrayDeclaration(node) => (vec![], node.initialiser.iter().flatten().chain(node.fill.as_ref()).collect()),
+            StatementNode::FunctionDeclaration(node) => (vec![node.body], vec![]),
+            StatementNode::Assignment(node) => (vec![], node.array_index.iter().chain(Some(&node.expression)).collect()),
+            StatementNode::Print(node) => (vec![], Some(&node.arg_expr).into_iter().chain(node.decimals.as_ref()).collect()),
+            StatementNode::Delay(node) |
+            StatementNode::Return(node) |
+            StatementNode::Clear(node) |
+            StatementNode::Brightness(node) |
+            StatementNode::Assert(node) |
+            StatementNode::Store(_, node) => (vec![], vec![node]),
+            StatementNode::Write(nodes) => (vec![], nodes.iter().collect()),
+            StatementNode::WriteBox(nodes) |
+            StatementNode::WriteLine(nodes) => (vec![], nodes.iter().collect()),
+            StatementNode::WriteBoxGradient(nodes) |
+            StatementNode::WriteBoxPattern(nodes) |
+            StatementNode::WriteTriangle(nodes) => (vec![], nodes.iter().collect()),
+            StatementNode::Block(node) => (node.statements.iter().collect(), vec![]),
+            StatementNode::UnscopedBlock(node) => (node.statements.iter().collect(), vec![]),
+            StatementNode::If(node) => (Some(node.body).into_iter().chain(node.else_body).collect(), vec![&node.condition]),
+            StatementNode::While(node) => (vec![node.body], vec![&node.condition]),
+            StatementNode::For(node) => (
+                node.initialiser.into_iter().chain(node.increment).chain(Some(node.body)).collect(),
+                node.condition.iter().collect(),
+            ),
+            StatementNode::Asm(_) => (vec![], vec![]),
+        }
+    }
+}
+
+// Asm Node : lines of Pixardis assembly, emitted as written but for
+// references to variables: {name} becomes the variable's offset:frame (as
+// in push [{name}]), and {name.offset} or {name.frame} either on its own
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct AsmNode {
+    // Lines between the braces; the first is on the line of the opening brace
+    pub lines: Vec<String>,
+    pub line: usize,
+}
+
+impl AsmNode {
+    //
+    // A line's instruction (without its comment) with each reference replaced
+    // by what locate gives for the variable (its offset and frame); the error
+    // says which reference is malformed or names no variable
+    //
+    pub fn substitute(text: &str, mut locate: impl FnMut(&str) -> Option<(i64, i64)>) -> Result<String, String> {
+        let mut code = text.split("//").next().unwrap_or_default().trim();
+        let mut result = String::new();
+
+        while let Some(open) = code.find('{') {
+            let close = code[open..].find('}').map(|close| open + close)
+                .ok_or_else(|| format!("unterminated reference '{}'", &code[open..]))?;
+
+            let reference = &code[open + 1..close];
+            let (name, field) = match reference.split_once('.') {
+                Some((name, field)) => (name, Some(field)),
+                None => (reference, None),
+            };
+
+            let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+            if !valid || !matches!(field, None | Some("offset") | Some("frame")) {
+                return Err(format!("malformed reference '{{{}}}' (expected {{name}}, {{name.offset}} or {{name.frame}})", reference));
+            }
+
+            let (offset, frame) = locate(name).ok_or_else(|| format!("'{}' is not a declared variable", name))?;
+
+            result.push_str(&code[..open]);
+            result.push_str(&match field {
+                Some("offset") => offset.to_string(),
+                Some(_) => frame.to_string(),
+                None => format!("{}:{}", offset, frame),
+            });
+
+            code = &code[close + 1..];
         }
+
+        result.push_str(code);
+        Ok(result)
     }
 }
 
 // If Node : this is an if statement
 #[derive(Debug, PartialEq, Clone)]
-pub struct IfNode {
-    pub condition: ExpressionNode,
-    pub body: Rc<StatementNode>,
-    pub else_body: Rc<Option<StatementNode>>,
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct IfNode<'ast> {
+    pub condition: ExpressionNode<'ast>,
+    pub body: &'ast StatementNode<'ast>,
+    pub else_body: Option<&'ast StatementNode<'ast>>,
     pub line: usize,
 }
 
-impl IfNode {
+impl<'ast> IfNode<'ast> {
     pub fn accept(&self, visitor: &mut dyn AbstractSyntaxTreeVisitor) {
         visitor.visit_if(self);
     }
@@ -135,13 +290,14 @@ impl IfNode {
 
 // While Node : this is a while statement
 #[derive(Debug, PartialEq, Clone)]
-pub struct WhileNode {
-    pub condition: ExpressionNode,
-    pub body: Rc<StatementNode>,
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct WhileNode<'ast> {
+    pub condition: ExpressionNode<'ast>,
+    pub body: &'ast StatementNode<'ast>,
     pub line: usize,
 }
 
-impl WhileNode {
+impl<'ast> WhileNode<'ast> {
     pub fn accept(&self, visitor: &mut dyn AbstractSyntaxTreeVisitor) {
         visitor.visit_while(self);
     }
@@ -149,15 +305,16 @@ impl WhileNode {
 
 // For Node : this is a for statement
 #[derive(Debug, PartialEq, Clone)]
-pub struct ForNode {
-    pub initialiser: Rc<Option<StatementNode>>,
-    pub condition: Option<ExpressionNode>,
-    pub increment: Rc<Option<StatementNode>>,
-    pub body: Rc<StatementNode>,
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ForNode<'ast> {
+    pub initialiser: Option<&'ast StatementNode<'ast>>,
+    pub condition: Option<ExpressionNode<'ast>>,
+    pub increment: Option<&'ast StatementNode<'ast>>,
+    pub body: &'ast StatementNode<'ast>,
     pub line: usize,
 }
 
-impl ForNode {
+impl<'ast> ForNode<'ast> {
     pub fn accept(&self, visitor: &mut dyn AbstractSyntaxTreeVisitor) {
         visitor.visit_for(self);
     }
@@ -165,15 +322,17 @@ impl ForNode {
 
 // Array Declaration Node : this is an array declaration
 #[derive(Debug, PartialEq, Clone)]
-pub struct ArrayDeclarationNode {
-    pub identifier: String,
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ArrayDeclarationNode<'ast> {
+    pub identifier: Atom,
     pub type_name: String,
     pub size: i64,
-    pub initialiser: Option<Vec<ExpressionNode>>,
+    pub initialiser: Option<Vec<ExpressionNode<'ast>>>,
+    pub fill: Option<ExpressionNode<'ast>>,
     pub line: usize,
 }
 
-impl ArrayDeclarationNode {
+impl<'ast> ArrayDeclarationNode<'ast> {
     pub fn accept(&self, visitor: &mut dyn AbstractSyntaxTreeVisitor) {
         visitor.visit_array_declaration(self);
     }
@@ -181,13 +340,14 @@ impl ArrayDeclarationNode {
 
 // Array Access Node : this is an array access through indexing
 #[derive(Debug, PartialEq, Clone)]
-pub struct ArrayAccessNode{
-    pub identifier: String,
-    pub index: Rc<ExpressionNode>,
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ArrayAccessNode<'ast>{
+    pub identifier: Atom,
+    pub index: &'ast ExpressionNode<'ast>,
     pub line: usize,
 }
 
-impl ArrayAccessNode {
+impl<'ast> ArrayAccessNode<'ast> {
     pub fn accept(&self, visitor: &mut dyn AbstractSyntaxTreeVisitor) {
         visitor.visit_array_access(self);
     }
@@ -195,14 +355,15 @@ impl ArrayAccessNode {
 
 // Variable Declaration Node : this is a variable declaration
 #[derive(Debug, PartialEq, Clone)]
-pub struct VariableDeclarationNode {
-    pub identifier: String,
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct VariableDeclarationNode<'ast> {
+    pub identifier: Atom,
     pub type_name: String,
-    pub expression: ExpressionNode,
+    pub expression: ExpressionNode<'ast>,
     pub line: usize,
 }
 
-impl VariableDeclarationNode {
+impl<'ast> VariableDeclarationNode<'ast> {
     pub fn accept(&self, visitor: &mut dyn AbstractSyntaxTreeVisitor) {
         visitor.visit_variable_declaration(self);
     }
@@ -210,14 +371,15 @@ impl VariableDeclarationNode {
 
 // Assignment Node : this is an assignment
 #[derive(Debug, PartialEq, Clone)]
-pub struct AssignmentNode {
-    pub identifier: String,
-    pub array_index: Option<ExpressionNode>,
-    pub expression: ExpressionNode,
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct AssignmentNode<'ast> {
+    pub identifier: Atom,
+    pub array_index: Option<ExpressionNode<'ast>>,
+    pub expression: ExpressionNode<'ast>,
     pub line: usize,
 }
 
-impl AssignmentNode {
+impl<'ast> AssignmentNode<'ast> {
     pub fn accept(&self, visitor: &mut dyn AbstractSyntaxTreeVisitor) {
         visitor.visit_assignment(self);
     }
@@ -225,8 +387,9 @@ impl AssignmentNode {
 
 // Formal Parameter Node : this is a formal parameter declaration (x: type)
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct FormalParameterNode {
-    pub identifier: String,
+    pub identifier: Atom,
     pub type_name: String,
     pub size: i64,
     pub line: usize,
@@ -240,16 +403,17 @@ impl FormalParameterNode {
 
 // Function Declaration Node : this is a function declaration
 #[derive(Debug, PartialEq, Clone)]
-pub struct FunctionDeclarationNode {
-    pub identifier: String,
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FunctionDeclarationNode<'ast> {
+    pub identifier: Atom,
     pub formal_parameters: Vec<FormalParameterNode>,
     pub return_type: String,
     pub return_size: i64,
-    pub body: Rc<StatementNode>,
+    pub body: &'ast StatementNode<'ast>,
     pub line: usize,
 }
 
-impl FunctionDeclarationNode {
+impl<'ast> FunctionDeclarationNode<'ast> {
     pub fn accept(&self, visitor: &mut dyn AbstractSyntaxTreeVisitor) {
         visitor.visit_function_declaration(self);
     }
@@ -257,37 +421,42 @@ impl FunctionDeclarationNode {
 
 // Function Call Node : this is a function call
 #[derive(Debug, PartialEq, Clone)]
-pub struct FunctionCallNode{
-    pub identifier: String,
-    pub arguments: Vec<ExpressionNode>,
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FunctionCallNode<'ast>{
+    pub identifier: Atom,
+    pub arguments: Vec<ExpressionNode<'ast>>,
     pub line: usize,
 }
 
-impl FunctionCallNode {
+impl<'ast> FunctionCallNode<'ast> {
     pub fn accept(&self, visitor: &mut dyn AbstractSyntaxTreeVisitor) {
         visitor.visit_function_call(self);
     }
 }
 
-// Print Node : this is the print function
+// Print Node : this is the print function (__printf also gives the number
+// of decimal places to print reals with)
 #[derive(Debug, PartialEq)]
-pub struct PrintNode{
-    pub arg_expr: ExpressionNode,
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PrintNode<'ast>{
+    pub arg_expr: ExpressionNode<'ast>,
     pub arg_type: RefCell<String>,
+    pub decimals: Option<ExpressionNode<'ast>>,
     pub line: usize,
 }
 
-impl Clone for PrintNode {
-    fn clone(&self) -> PrintNode {       
+impl<'ast> Clone for PrintNode<'ast> {
+    fn clone(&self) -> PrintNode<'ast> {       
         PrintNode {
             arg_expr: self.arg_expr.clone(),
             arg_type: RefCell::new(self.arg_type.borrow().clone()),
+            decimals: self.decimals.clone(),
             line: self.line,
         }
     }
 }
 
-impl PrintNode {
+impl<'ast> PrintNode<'ast> {
     pub fn accept(&self, visitor: &mut dyn AbstractSyntaxTreeVisitor) {
         visitor.visit_print(self);
     }
@@ -295,39 +464,99 @@ impl PrintNode {
 
 // Expression Node : factor <operator> <expression>
 #[derive(Debug, PartialEq, Clone)]
-pub struct ExpressionNode {
-    pub factor: FactorNode,
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ExpressionNode<'ast> {
+    pub factor: FactorNode<'ast>,
     pub operator: Option<String>,
-    pub expression: Rc<Option<ExpressionNode>>,
+    pub expression: Option<&'ast ExpressionNode<'ast>>,
     pub type_name: Option<String>,
     pub line: usize,
 }
 
-impl ExpressionNode {
+impl<'ast> ExpressionNode<'ast> {
     pub fn accept(&self, visitor: &mut dyn AbstractSyntaxTreeVisitor) {
         visitor.visit_expression(self);
     }
+
+    //
+    // Levels of expressions in this one (1 if it has no subexpressions),
+    // measured with a work stack so trees too deep to visit are safe to check
+    //
+    pub fn depth(&self) -> usize {
+        let mut deepest = 0;
+        let mut pending: Vec<(&ExpressionNode, usize)> = vec![(self, 1)];
+
+        while let Some((node, depth)) = pending.pop() {
+            deepest = deepest.max(depth);
+            pending.extend(node.children().into_iter().map(|child| (child, depth + 1)));
+        }
+
+        deepest
+    }
+
+    // Expressions directly inside this one: its factor's operands, function
+    // call arguments and array index, and its right operand
+    pub fn children(&self) -> Vec<&ExpressionNode<'ast>> {
+        let mut children: Vec<&ExpressionNode<'ast>> = match &self.factor {
+            FactorNode::RandomInt(child) |
+            FactorNode::Subexpression(child) |
+            FactorNode::Unary(child) |
+            FactorNode::Param(_, Some(child)) |
+            FactorNode::Load(_, Some(child)) => vec![*child],
+            FactorNode::RandomRange(children) |
+            FactorNode::Read(children) |
+            FactorNode::AddColour(children) |
+            FactorNode::SubtractColour(children) => children.to_vec(),
+            FactorNode::HitTest(children) => children.to_vec(),
+            FactorNode::Hsv(children) |
+            FactorNode::Noise(children) => children.to_vec(),
+            FactorNode::ArrayAccess(access) => vec![access.index],
+            FactorNode::FunctionCall(call) => call.arguments.iter().collect(),
+            _ => vec![],
+        };
+
+        children.extend(self.expression);
+        children
+    }
 }
 
 // Factor Node : this is a factor (literal, identifier, function call, subexpression)
 #[derive(Debug, PartialEq, Clone)]
-pub enum FactorNode {
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum FactorNode<'ast> {
     BooleanLiteral(bool),
     IntegerLiteral(i64),
     FloatLiteral(f64),
     ColourLiteral(String),
     Width,
     Height,
-    RandomInt(Rc<ExpressionNode>),
-    Read([Rc<ExpressionNode>; 2]),
-    Identifier(String),
-    FunctionCall(FunctionCallNode),
-    ArrayAccess(ArrayAccessNode),
-    Subexpression(Rc<ExpressionNode>),
-    Unary(Rc<ExpressionNode>),
-}
-
-impl FactorNode {
+    // Instruction set extensions the VM supports (PIXARDIS_CAPABILITY_* bits)
+    Capabilities,
+    RandomInt(&'ast ExpressionNode<'ast>),
+    // Random float in [0, 1), and random int or float in [lo, hi)
+    RandomFloat,
+    RandomRange([&'ast ExpressionNode<'ast>; 2]),
+    Read([&'ast ExpressionNode<'ast>; 2]),
+    HitTest([&'ast ExpressionNode<'ast>; 5]),
+    // Per-channel saturating colour sum and difference
+    AddColour([&'ast ExpressionNode<'ast>; 2]),
+    SubtractColour([&'ast ExpressionNode<'ast>; 2]),
+    // Colour from hue, saturation and value
+    Hsv([&'ast ExpressionNode<'ast>; 3]),
+    // Smooth noise in [0, 1] at x, y and time
+    Noise([&'ast ExpressionNode<'ast>; 3]),
+    // Host parameter by name, with an optional default (int or colour)
+    Param(String, Option<&'ast ExpressionNode<'ast>>),
+    // Value from persistent storage by key, with an optional default (int, float or colour)
+    Load(String, Option<&'ast ExpressionNode<'ast>>),
+    Identifier(Atom),
+    FunctionCall(FunctionCallNode<'ast>),
+    ArrayAccess(ArrayAccessNode<'ast>),
+    Subexpression(&'ast ExpressionNode<'ast>),
+    Unary(&'ast ExpressionNode<'ast>),
+}
+
+impl<'ast> FactorNode<'ast> {
     pub fn accept(&self, visitor: &mut dyn AbstractSyntaxTreeVisitor) {
         match self {
             FactorNode::BooleanLiteral(value) => visitor.visit_boolean_literal(*value),
@@ -336,8 +565,18 @@ impl FactorNode {
             FactorNode::ColourLiteral(value) => visitor.visit_colour_literal(value.clone()),
             FactorNode::Width => visitor.visit_width(),
             FactorNode::Height => visitor.visit_height(),
+            FactorNode::Capabilities => visitor.visit_capabilities(),
             FactorNode::RandomInt(node) => visitor.visit_random_int(node),
+            FactorNode::RandomFloat => visitor.visit_random_float(),
+            FactorNode::RandomRange(data) => visitor.visit_random_range(data),
             FactorNode::Read(data) => visitor.visit_read(data),
+            FactorNode::HitTest(data) => visitor.visit_hit_test(data),
+            FactorNode::AddColour(data) => visitor.visit_add_colour(data),
+            FactorNode::SubtractColour(data) => visitor.visit_subtract_colour(data),
+            FactorNode::Hsv(data) => visitor.visit_hsv(data),
+            FactorNode::Noise(data) => visitor.visit_noise(data),
+            FactorNode::Param(name, default) => visitor.visit_param(name, *default),
+            FactorNode::Load(key, default) => visitor.visit_load(key, *default),
             FactorNode::Identifier(value) => visitor.visit_identifier(value.clone()),
             FactorNode::FunctionCall(node) => visitor.visit_function_call(node),
             FactorNode::ArrayAccess(node) => visitor.visit_array_access(node),
@@ -1,2 +1,3 @@
+pub mod arena;
 pub mod parser;
 pub mod ast;
\ No newline at end of file
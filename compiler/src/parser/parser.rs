@@ -1,4 +1,5 @@
 use crate::{analysis::symbol::SymbolType, common::{
+    consteval,
     logger::{
         Logger, 
         LoggerError
@@ -7,30 +8,117 @@ use crate::{analysis::symbol::SymbolType, common::{
 
 use crate::lexer::{
     lexer::Lexer,
-    token::TokenKind
+    token::{TokenKind, parse_negated_integer_literal}
 };
 
-use super::ast::*;
+use super::{arena::AstArena, ast::*};
 
-use std::{cell::RefCell, rc::Rc};
+use std::cell::RefCell;
+
+use shared::intern::Interner;
+use shared::pixardis::{PixardisInstruction, pixardis_colour_from_string, pixardis_instruction_from_string};
+
+// Deepest an expression may nest (parentheses, operator chains) unless the
+// parser is told otherwise; the parser and later stages recurse once per level
+pub const PARSER_MAX_NESTING: usize = 256;
+
+// How a chain of operators from the same level groups
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Associativity {
+    // a - b - c is (a - b) - c
+    Left,
+    // a < b < c is an error; one of the pair must be parenthesised
+    NonAssociative,
+}
+
+// Binary operators, from the loosest binding level to the tightest
+pub const PARSER_OPERATOR_TABLE: &[(&[&str], Associativity)] = &[
+    (&["or", "||"], Associativity::Left),
+    (&["and", "&&"], Associativity::Left),
+    (&["==", "!=", "<", ">", "<=", ">="], Associativity::NonAssociative),
+    (&["+", "-", "|"], Associativity::Left),
+    (&["*", "/", "&", "%"], Associativity::Left),
+];
+
+// How a builtin is written, for diagnostics about its misuse: statements
+// have no value, the others are expressions
+#[derive(Debug, Clone, Copy)]
+struct BuiltinForm {
+    name: &'static str,
+    statement: bool,
+    form: &'static str,
+}
+
+fn builtin_form(kind: &TokenKind) -> Option<BuiltinForm> {
+    let (name, statement, form) = match kind {
+        TokenKind::Print => ("__print", true, "__print value"),
+        TokenKind::PrintFormatted => ("__printf", true, "__printf value, decimals"),
+        TokenKind::Delay => ("__delay", true, "__delay milliseconds"),
+        TokenKind::Assert => ("__assert", true, "__assert condition"),
+        TokenKind::Clear => ("__clear", true, "__clear colour"),
+        TokenKind::Brightness => ("__brightness", true, "__brightness level"),
+        TokenKind::Store => ("__store", true, "__store \"key\", value"),
+        TokenKind::Write => ("__write", true, "__write x, y, colour"),
+        TokenKind::WriteBox => ("__write_box", true, "__write_box x, y, width, height, colour"),
+        TokenKind::WriteBoxGradient => ("__write_box_gradient", true, "__write_box_gradient x, y, width, height, colour, colour, mode"),
+        TokenKind::WriteBoxPattern => ("__write_box_pattern", true, "__write_box_pattern x, y, width, height, colour, colour, mode"),
+        TokenKind::WriteTriangle => ("__write_triangle", true, "__write_triangle x0, y0, x1, y1, x2, y2, colour"),
+        TokenKind::Width => ("__width", false, "__width"),
+        TokenKind::Height => ("__height", false, "__height"),
+        TokenKind::Capabilities => ("__capabilities", false, "__capabilities"),
+        TokenKind::RandomFloat => ("__random_float", false, "__random_float"),
+        TokenKind::RandomInt => ("__random_int", false, "__random_int max"),
+        TokenKind::RandomRange => ("__random_range", false, "__random_range low, high"),
+        TokenKind::Read => ("__read", false, "__read x, y"),
+        TokenKind::HitTest => ("__hit_test", false, "__hit_test x, y, width, height, colour"),
+        TokenKind::AddColour => ("__add_colour", false, "__add_colour colour, colour"),
+        TokenKind::SubtractColour => ("__sub_colour", false, "__sub_colour colour, colour"),
+        TokenKind::Hsv => ("__hsv", false, "__hsv hue, saturation, value"),
+        TokenKind::Noise => ("__noise", false, "__noise x, y, time"),
+        TokenKind::Param => ("__param", false, "__param \"name\" or __param \"name\", default"),
+        TokenKind::Load => ("__load", false, "__load \"key\" or __load \"key\", default"),
+        _ => return None,
+    };
+
+    Some(BuiltinForm { name, statement, form })
+}
+
+// Tokens that end a builtin's arguments
+fn ends_arguments(kind: &TokenKind) -> bool {
+    matches!(kind, TokenKind::SemiColon | TokenKind::CloseParen | TokenKind::CloseBrace | TokenKind::CloseBracket)
+}
 
 pub struct Parser<'a> {
     lexer: Lexer <'a>,
-    syntax_tree: Option<ProgramNode>,
+    // Nodes are allocated here; the tree lives as long as the arena
+    arena: &'a AstArena<'a>,
+    syntax_tree: Option<ProgramNode<'a>>,
     logger: &'a mut Logger<'a>,
     status: CompilationResult,
+    // Expressions being parsed, outermost first, and how many may be open
+    nesting: usize,
+    max_nesting: usize,
+    nesting_reported: bool,
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(lexer: Lexer<'a>, logger: &'a mut Logger<'a>) -> Self { 
+    pub fn new(lexer: Lexer<'a>, logger: &'a mut Logger<'a>, arena: &'a AstArena<'a>) -> Self { 
         Parser { 
             lexer: lexer, 
+            arena,
             syntax_tree: None,
             logger: logger,
             status: CompilationResult::Pending,
+            nesting: 0,
+            max_nesting: PARSER_MAX_NESTING,
+            nesting_reported: false,
         } 
     }
 
+    pub fn max_nesting_set(&mut self, max_nesting: usize) {
+        self.max_nesting = max_nesting;
+    }
+
     fn status_set(&mut self, status: CompilationResult) {
         self.status = status;
     }
@@ -39,38 +127,101 @@ impl<'a> Parser<'a> {
         self.status.clone()
     }
 
+    // The lexer's interner, which the program's identifiers are atoms of
+    pub fn interner(&self) -> Interner {
+        self.lexer.interner()
+    }
+
     pub fn get_line_number(&self) -> usize {
         self.lexer.peek_token().unwrap().line.clone()
     }
 
-    pub fn get_syntax_tree(&self) -> Option<ProgramNode> {
-        self.syntax_tree.clone()
+    //
+    // Move the syntax tree out of the parser; later stages work on this single
+    // tree by reference rather than on copies
+    //
+    pub fn take_syntax_tree(&mut self) -> Option<ProgramNode<'a>> {
+        self.syntax_tree.take()
     }
 
-    // Get next additive operator
-    pub fn peek_additive_operator(&mut self) -> Option<String> {
-        match &self.lexer.peek_token().unwrap().kind {
-            TokenKind::AdditiveOp(s) => Some(s.clone()),
-            _ => None,
+    //
+    // Open one more level of expression nesting; false if that's deeper than
+    // the limit (the level is not opened)
+    //
+    fn nesting_enter(&mut self) -> bool {
+        if self.nesting >= self.max_nesting {
+            return false;
         }
+
+        self.nesting += 1;
+        true
     }
 
-    // Get next multiplicative operator
-    pub fn peek_multiplicative_operator(&mut self) -> Option<String> {
-        match &self.lexer.peek_token().unwrap().kind {
-            TokenKind::MultiplicativeOp(s) => Some(s.clone()),
-            _ => None,
-        }
+    fn nesting_leave(&mut self) {
+        self.nesting -= 1;
     }
 
-    // Get next relational operator
-    pub fn peek_relational_operator(&mut self) -> Option<String> {
-        match &self.lexer.peek_token().unwrap().kind {
-            TokenKind::RelationalOp(s) => Some(s.clone()),
-            _ => None,
+    //
+    // Report an expression nested too deeply and stand in for it, so the
+    // expressions around it parse without errors of their own; when skip is
+    // set the expression hasn't been read yet and its tokens are skipped
+    //
+    fn nesting_exceeded(&mut self, skip: bool) -> ExpressionNode<'a> {
+        let line = self.get_line_number();
+
+        if skip {
+            let mut depth = 0usize;
+
+            while let Some(token) = self.lexer.peek_token() {
+                match token.kind {
+                    TokenKind::OpenParen | TokenKind::OpenBracket => depth += 1,
+                    TokenKind::CloseParen | TokenKind::CloseBracket if depth > 0 => depth -= 1,
+                    TokenKind::CloseParen | TokenKind::CloseBracket | TokenKind::Comma |
+                    TokenKind::SemiColon | TokenKind::OpenBrace => break,
+                    _ => { },
+                }
+
+                self.lexer.next_token();
+            }
+        }
+
+        // Every enclosing expression is too deep as well; report the
+        // outermost expression once
+        if !self.nesting_reported {
+            self.logger.print_error(
+                LoggerError::Syntax,
+                format!("Expression nests more than {} levels deep; split it into smaller expressions.", self.max_nesting).as_str(),
+                line
+            );
+            self.nesting_reported = true;
+        }
+
+        self.status_set(CompilationResult::Failure);
+
+        ExpressionNode {
+            factor: FactorNode::IntegerLiteral(0),
+            operator: None,
+            expression: None,
+            type_name: None,
+            line,
         }
     }
 
+    // Get next binary operator and its binding level in the operator table
+    pub fn peek_binary_operator(&mut self) -> Option<(String, usize)> {
+        let operator = match &self.lexer.peek_token().unwrap().kind {
+            TokenKind::LogicalOp(s) |
+            TokenKind::RelationalOp(s) |
+            TokenKind::AdditiveOp(s) |
+            TokenKind::MultiplicativeOp(s) => s,
+            _ => return None,
+        };
+
+        PARSER_OPERATOR_TABLE.iter()
+            .position(|(operators, _)| operators.contains(&operator.as_str()))
+            .map(|level| (operator.clone(), level))
+    }
+
     // parse lexer tokens into AST
     pub fn parse(&mut self) {
         // Set success flag (this will be cleared if any errors are encountered)
@@ -105,14 +256,42 @@ impl<'a> Parser<'a> {
     }
 
     // parse entire program
-    pub fn parse_program(&mut self) -> Option<ProgramNode> {
+    pub fn parse_program(&mut self) -> Option<ProgramNode<'a>> {
+        let mut metadata = Vec::new();
         let mut statements = Vec::new();
+
+        // Metadata pragmas form a header before the first statement
+        while let Some(TokenKind::Pragma(key, value)) = self.lexer.peek_token().map(|token| token.kind.clone()) {
+            self.parse_pragma(&key, &value);
+            metadata.push((key, value));
+            self.lexer.next_token();
+        }
     
         while let Some(statement) = self.parse_statement() {
             statements.push(statement);
         }
     
-        Some(ProgramNode { statements })
+        Some(ProgramNode { metadata, statements })
+    }
+
+    //
+    // Check a metadata pragma: title and author take any text, width and
+    // height a positive integer
+    //
+    pub fn parse_pragma(&mut self, key: &str, value: &str) {
+        let message = match key {
+            "title" | "author" => None,
+            "width" | "height" => match value.parse::<usize>() {
+                Ok(size) if size > 0 => None,
+                _ => Some(format!("Pragma '{}' expects a positive integer, found '{}'.", key, value)),
+            },
+            _ => Some(format!("Unknown pragma '{}'; expected title, author, width or height.", key)),
+        };
+
+        if let Some(message) = message {
+            self.logger.print_error(LoggerError::Syntax, message.as_str(), self.get_line_number());
+            self.status_set(CompilationResult::Failure);
+        }
     }
 
     // parse the expected token
@@ -138,7 +317,7 @@ impl<'a> Parser<'a> {
     }
 
     // parse a series of statements enclosed in braces
-    pub fn parse_statement_block(&mut self, is_unscoped_block: bool) -> Option<StatementNode> {
+    pub fn parse_statement_block(&mut self, is_unscoped_block: bool) -> Option<StatementNode<'a>> {
         self.parse_token(TokenKind::OpenBrace).ok()?;
         
         let mut statements = Vec::new();
@@ -160,7 +339,7 @@ impl<'a> Parser<'a> {
     }
 
     // parse a statement
-    pub fn parse_statement(&mut self) -> Option<StatementNode>{
+    pub fn parse_statement(&mut self) -> Option<StatementNode<'a>>{
         let token = self.lexer.peek_token();
         let kind = &token?.kind;
         let mut semicolon = true;
@@ -170,6 +349,12 @@ impl<'a> Parser<'a> {
             TokenKind::Clear => {
                 self.parse_clear()
             },
+            TokenKind::Brightness => {
+                self.parse_brightness()
+            },
+            TokenKind::Store => {
+                self.parse_store()
+            },
             TokenKind::Let => { 
                 self.parse_variable_declaration()
             },
@@ -180,12 +365,15 @@ impl<'a> Parser<'a> {
                 semicolon = false;
                 self.parse_function_declaration()
             },
-            TokenKind::Print => {
+            TokenKind::Print | TokenKind::PrintFormatted => {
                 self.parse_print()
             },
             TokenKind::Delay => {
                 self.parse_delay()
             },
+            TokenKind::Assert => {
+                self.parse_assert()
+            },
             TokenKind::Return => {
                 self.parse_return()
             },
@@ -195,6 +383,15 @@ impl<'a> Parser<'a> {
             TokenKind::WriteBox => {
                 self.parse_write_box()
             },
+            TokenKind::WriteBoxGradient => {
+                self.parse_write_box_fill(TokenKind::WriteBoxGradient)
+            },
+            TokenKind::WriteBoxPattern => {
+                self.parse_write_box_fill(TokenKind::WriteBoxPattern)
+            },
+            TokenKind::WriteTriangle => {
+                self.parse_write_triangle()
+            },
             TokenKind::OpenBrace => {
                 semicolon = false;
                 self.parse_statement_block(false)
@@ -211,21 +408,42 @@ impl<'a> Parser<'a> {
                 semicolon = false;
                 self.parse_for()
             },
-            _ => { 
+            TokenKind::Asm(_) => {
+                semicolon = false;
+                self.parse_asm()
+            },
+            TokenKind::Pragma(_, _) => {
                 self.logger.print_error(
                     LoggerError::Syntax, 
-                    "Invalid statement found.",
+                    "#pragma directives must appear before the first statement.",
                     self.get_line_number()
                 );
 
                 self.status_set(CompilationResult::Failure);
 
+                return None;
+            },
+            _ => { 
+                match builtin_form(kind) {
+                    Some(builtin) => self.builtin_error(&builtin, &format!("{} gives a value, so can't be a statement on its own", builtin.name)),
+                    None => {
+                        self.logger.print_error(
+                            LoggerError::Syntax, 
+                            "Invalid statement found.",
+                            self.get_line_number()
+                        );
+
+                        self.status_set(CompilationResult::Failure);
+                    },
+                }
+
                 None 
             },
         };
 
-        // some statements end in a semicolon
-        if semicolon {
+        // some statements end in a semicolon; one that failed to parse has
+        // been reported already, wherever it stopped
+        if semicolon && result.is_some() {
             self.parse_token(TokenKind::SemiColon).ok()?;
         }
 
@@ -233,7 +451,7 @@ impl<'a> Parser<'a> {
     }
 
     // parse a factor (literal, identifier, subexpression)
-    pub fn parse_factor(&mut self) -> Option<FactorNode> {
+    pub fn parse_factor(&mut self) -> Option<FactorNode<'a>> {
         let mut advance_token = true;
         let token = self.lexer.peek_token();
         let kind = &token?.kind;
@@ -242,34 +460,96 @@ impl<'a> Parser<'a> {
             TokenKind::BooleanLiteral(b) => FactorNode::BooleanLiteral(b.clone()),
             TokenKind::IntegerLiteral(i) => FactorNode::IntegerLiteral(i.clone()),
             TokenKind::FloatLiteral(f) => FactorNode::FloatLiteral(f.clone()),
-            TokenKind::ColourLiteral(c) => FactorNode::ColourLiteral(c.clone()),
+            TokenKind::ColourLiteral(c) => {
+                // Short and opaque 8-digit forms are normalised to #rrggbb
+                match pixardis_colour_from_string(c) {
+                    Ok(colour) => FactorNode::ColourLiteral(format!("#{:06x}", colour)),
+                    Err(reason) => {
+                        self.logger.print_error(
+                            LoggerError::Syntax,
+                            format!("Invalid colour literal '{}'; {}.", c, reason).as_str(),
+                            self.get_line_number()
+                        );
+
+                        self.status_set(CompilationResult::Failure);
+
+                        return None;
+                    },
+                }
+            },
             TokenKind::Width => FactorNode::Width,
             TokenKind::Height => FactorNode::Height,        
+            TokenKind::Capabilities => FactorNode::Capabilities,
             TokenKind::Identifier(i) => FactorNode::Identifier(i.clone()),
             TokenKind::RandomInt => { 
                 advance_token = false;
                 self.lexer.next_token();
 
-                match self.parse_expression() {
-                    Some(expression) => FactorNode::RandomInt(Rc::new(expression)),
-                    _ => return None,
-                }
+                let [expression] = self.parse_arguments::<1>(&TokenKind::RandomInt)?;
+                FactorNode::RandomInt(self.arena.expression(expression))
+            },
+            TokenKind::RandomFloat => FactorNode::RandomFloat,
+            TokenKind::RandomRange => {
+                advance_token = false;
+                self.lexer.next_token();
+
+                FactorNode::RandomRange(self.parse_arguments::<2>(&TokenKind::RandomRange)?.map(|expression| self.arena.expression(expression)))
             },
             TokenKind::Read => { 
                 advance_token = false;
                 self.lexer.next_token();
 
-                let expression_x = self.parse_expression()?;
-                let _ = self.parse_token(TokenKind::Comma).ok()?;
-                let expression_y = self.parse_expression()?;
+                FactorNode::Read(self.parse_arguments::<2>(&TokenKind::Read)?.map(|expression| self.arena.expression(expression)))
+            },
+            TokenKind::HitTest => {
+                advance_token = false;
+                self.lexer.next_token();
+
+                FactorNode::HitTest(self.parse_arguments::<5>(&TokenKind::HitTest)?.map(|expression| self.arena.expression(expression)))
+            },
+            TokenKind::AddColour => {
+                advance_token = false;
+                self.lexer.next_token();
+
+                FactorNode::AddColour(self.parse_arguments::<2>(&TokenKind::AddColour)?.map(|expression| self.arena.expression(expression)))
+            },
+            TokenKind::SubtractColour => {
+                advance_token = false;
+                self.lexer.next_token();
+
+                FactorNode::SubtractColour(self.parse_arguments::<2>(&TokenKind::SubtractColour)?.map(|expression| self.arena.expression(expression)))
+            },
+            TokenKind::Hsv => {
+                advance_token = false;
+                self.lexer.next_token();
+
+                FactorNode::Hsv(self.parse_arguments::<3>(&TokenKind::Hsv)?.map(|expression| self.arena.expression(expression)))
+            },
+            TokenKind::Noise => {
+                advance_token = false;
+                self.lexer.next_token();
+
+                FactorNode::Noise(self.parse_arguments::<3>(&TokenKind::Noise)?.map(|expression| self.arena.expression(expression)))
+            },
+            TokenKind::Param => {
+                advance_token = false;
+                self.lexer.next_token();
+
+                let (name, default) = self.parse_keyed_builtin("__param")?;
+                FactorNode::Param(name, default)
+            },
+            TokenKind::Load => {
+                advance_token = false;
+                self.lexer.next_token();
 
-                FactorNode::Read([Rc::new(expression_x), Rc::new(expression_y)])
+                let (key, default) = self.parse_keyed_builtin("__load")?;
+                FactorNode::Load(key, default)
             },
             TokenKind::OpenParen => {
                 advance_token = false;
 
                 match self.parse_subexpression() {
-                    Some(expression) => FactorNode::Subexpression(Rc::new(expression)),
+                    Some(expression) => FactorNode::Subexpression(self.arena.expression(expression)),
                     _ => return None,
                 }
             },
@@ -277,8 +557,25 @@ impl<'a> Parser<'a> {
                 advance_token = false;
                 self.lexer.next_token();
 
-                match self.parse_expression() {
-                    Some(expression) => FactorNode::Unary(Rc::new(expression)),
+                // A minus on a literal is folded into it, so -5 + 3 is (-5) + 3;
+                // 9223372036854775808 lexes as a float, as it's too large for
+                // an integer, but negated it's the smallest one
+                let literal = self.lexer.peek_token().and_then(|token| match token.kind {
+                    TokenKind::IntegerLiteral(i) => Some(FactorNode::IntegerLiteral(-i)),
+                    TokenKind::FloatLiteral(f) => Some(match parse_negated_integer_literal(self.lexer.token_text(token)) {
+                        Some(i) => FactorNode::IntegerLiteral(i),
+                        None => FactorNode::FloatLiteral(-f),
+                    }),
+                    _ => None,
+                });
+
+                if let Some(literal) = literal {
+                    self.lexer.next_token();
+                    return Some(literal);
+                }
+
+                match self.parse_unary_operand() {
+                    Some(expression) => FactorNode::Unary(self.arena.expression(expression)),
                     _ => return None,
                 }
             }
@@ -286,19 +583,24 @@ impl<'a> Parser<'a> {
                 advance_token = false;
                 self.lexer.next_token();
 
-                match self.parse_expression() {
-                    Some(expression) => FactorNode::Unary(Rc::new(expression)),
+                match self.parse_unary_operand() {
+                    Some(expression) => FactorNode::Unary(self.arena.expression(expression)),
                     _ => return None,
                 }
             }
             _ => { 
-                self.logger.print_error(
-                    LoggerError::Syntax, 
-                    "Expected expression.",
-                    self.get_line_number(),
-                );
-
-                self.status_set(CompilationResult::Failure);
+                match builtin_form(kind) {
+                    Some(builtin) => self.builtin_error(&builtin, &format!("{} is a statement and has no value", builtin.name)),
+                    None => {
+                        self.logger.print_error(
+                            LoggerError::Syntax, 
+                            "Expected expression.",
+                            self.get_line_number(),
+                        );
+
+                        self.status_set(CompilationResult::Failure);
+                    },
+                }
 
                 return None
             },
@@ -322,6 +624,14 @@ impl<'a> Parser<'a> {
                     }
                 }
             }
+            // These take no arguments, so can't be called
+            FactorNode::Width | FactorNode::Height | FactorNode::Capabilities | FactorNode::RandomFloat
+                if self.lexer.peek_k_tokens(1).map(|token| &token.kind) == Some(&TokenKind::OpenParen) => {
+                let builtin = builtin_form(&self.lexer.peek_token()?.kind)?;
+                self.builtin_error(&builtin, &format!("{} takes no arguments", builtin.name));
+
+                return None;
+            }
             _ => {}
         }
 
@@ -333,54 +643,63 @@ impl<'a> Parser<'a> {
         Some(result)
     }
 
-    // parse relational expression
-    pub fn parse_relational_expression(&mut self) -> Option<ExpressionNode> { 
-        let mut left_expr = self.parse_additive_expression()?;
-    
-        while let Some(operator) = self.peek_relational_operator() {
-            self.lexer.next_token(); // Consume the operator
-            let right_expr = self.parse_additive_expression()?;
-            left_expr = ExpressionNode {
-                factor: FactorNode::Subexpression(Rc::new(left_expr)),
-                operator: Some(operator),
-                expression: Rc::new(Some(right_expr)),
-                type_name: None,
-                line: self.get_line_number(),
-            };
-        }
-        
-        Some(left_expr)
-    }
+    //
+    // parse binary expression (precedence climbing): an operand followed by
+    // operators binding at level or tighter; each right operand only takes
+    // operators binding tighter than its own, so chains associate left
+    //
+    pub fn parse_binary_expression(&mut self, level: usize) -> Option<ExpressionNode<'a>> {
+        let mut left_expr = self.parse_operand()?;
+
+        // Last operator applied at this level of the chain, if any
+        let mut previous: Option<(String, usize)> = None;
+
+        while let Some((operator, operator_level)) = self.peek_binary_operator() {
+            if operator_level < level {
+                break;
+            }
+
+            // The chain is parsed as if it grouped left, so the rest of the
+            // expression is still checked
+            if let Some((previous, previous_level)) = &previous {
+                if *previous_level == operator_level && PARSER_OPERATOR_TABLE[operator_level].1 == Associativity::NonAssociative {
+                    self.logger.print_error(
+                        LoggerError::Syntax,
+                        format!(
+                            "Comparisons can't be chained (a {} b {} c); join them with '&&' (a {} b && b {} c), or parenthesise the first to compare its result.",
+                            previous, operator, previous, operator
+                        ).as_str(),
+                        self.get_line_number()
+                    );
+                    self.status_set(CompilationResult::Failure);
+                }
+            }
 
-    // parse additive expression
-    pub fn parse_additive_expression(&mut self) -> Option<ExpressionNode> { 
-        let mut left_expr = self.parse_multiplicative_expression()?;
-    
-        while let Some(operator) = self.peek_additive_operator() {
             self.lexer.next_token(); // Consume the operator
-            let right_expr = self.parse_multiplicative_expression()?;
+            previous = Some((operator.clone(), operator_level));
+            let right_expr = self.parse_binary_expression(operator_level + 1)?;
             left_expr = ExpressionNode {
-                factor: FactorNode::Subexpression(Rc::new(left_expr)),
+                factor: FactorNode::Subexpression(self.arena.expression(left_expr)),
                 operator: Some(operator),
-                expression: Rc::new(Some(right_expr)),
+                expression: Some(self.arena.expression(right_expr)),
                 type_name: None,
                 line: self.get_line_number(),
             };
         }
-        
+
         Some(left_expr)
     }
 
-    // parse multiplicative expression
-    pub fn parse_multiplicative_expression(&mut self) -> Option<ExpressionNode> {         
+    // parse operand (factor, with an optional typecast)
+    pub fn parse_operand(&mut self) -> Option<ExpressionNode<'a>> {
         // Parse factor
         let factor = self.parse_factor()?;
 
-        // Build LHS expression node
-        let mut left_expr = ExpressionNode {
+        // Build operand expression node
+        let mut operand = ExpressionNode {
             factor: factor,
             operator: None,
-            expression: Rc::new(None),
+            expression: None,
             type_name: None,
             line: self.get_line_number(),
         };
@@ -401,35 +720,63 @@ impl<'a> Parser<'a> {
                 },
             };
 
-            // Turn LHS expression node into a typecast expression node
-            left_expr.operator = Some(String::from("as"));
-            left_expr.type_name = Some(type_name);
-
-            return Some(left_expr);
+            // Turn operand expression node into a typecast expression node
+            operand.operator = Some(String::from("as"));
+            operand.type_name = Some(type_name);
         }
 
-        while let Some(operator) = self.peek_multiplicative_operator() {
-            self.lexer.next_token();
-            let right_expr = self.parse_multiplicative_expression()?;
-            left_expr = ExpressionNode {
-                factor: FactorNode::Subexpression(Rc::new(left_expr)),
-                operator: Some(operator),
-                expression: Rc::new(Some(right_expr)),
-                type_name: None,
-                line: self.get_line_number(),
-            };
+        Some(operand)
+    }
+
+    //
+    // Parse the operand of a unary operator; unary operators bind tighter
+    // than every binary operator, so -a + b is (-a) + b
+    //
+    fn parse_unary_operand(&mut self) -> Option<ExpressionNode<'a>> {
+        if !self.nesting_enter() {
+            return Some(self.nesting_exceeded(true));
         }
-        
-        Some(left_expr)
+
+        let factor = self.parse_factor();
+        self.nesting_leave();
+
+        Some(ExpressionNode {
+            factor: factor?,
+            operator: None,
+            expression: None,
+            type_name: None,
+            line: self.get_line_number(),
+        })
     }
 
     // parse expression
-    pub fn parse_expression(&mut self) -> Option<ExpressionNode> {
-        self.parse_relational_expression()
+    pub fn parse_expression(&mut self) -> Option<ExpressionNode<'a>> {
+        if !self.nesting_enter() {
+            return Some(self.nesting_exceeded(true));
+        }
+
+        let expression = self.parse_binary_expression(0);
+        self.nesting_leave();
+
+        if self.nesting > 0 {
+            return expression;
+        }
+
+        // Left-nested operator chains (a + b + c) are parsed in a loop, so
+        // only the finished tree shows how deep they go
+        let expression = match expression {
+            Some(expression) if expression.depth() > self.max_nesting => Some(self.nesting_exceeded(false)),
+            expression => expression,
+        };
+
+        // The next expression gets its own diagnostic
+        self.nesting_reported = false;
+
+        expression
     }
 
     // parse subexpression '(' + expression + ')'
-    pub fn parse_subexpression(&mut self) -> Option<ExpressionNode> {
+    pub fn parse_subexpression(&mut self) -> Option<ExpressionNode<'a>> {
         let _ = self.parse_token(TokenKind::OpenParen).ok()?;
         
         let expression = match self.parse_expression() {
@@ -525,7 +872,7 @@ impl<'a> Parser<'a> {
     }
 
     // parse function declaration
-    pub fn parse_function_declaration(&mut self) -> Option<StatementNode> {
+    pub fn parse_function_declaration(&mut self) -> Option<StatementNode<'a>> {
         let line_number = self.get_line_number();
         
         self.parse_token(TokenKind::Fun).ok()?;
@@ -599,7 +946,7 @@ impl<'a> Parser<'a> {
         };
         
         let body = match self.parse_statement_block(true) {
-            Some(body) => Rc::new(body),
+            Some(body) => self.arena.statement(body),
             _ => return None,
         };
 
@@ -616,7 +963,7 @@ impl<'a> Parser<'a> {
     }
 
     // parse a call to a function with arguments
-    pub fn parse_function_call(&mut self) -> Option<FunctionCallNode> {
+    pub fn parse_function_call(&mut self) -> Option<FunctionCallNode<'a>> {
         let line_number = self.get_line_number();
         
         let identifier = match &self.lexer.next_token().unwrap().kind {
@@ -670,7 +1017,7 @@ impl<'a> Parser<'a> {
     }
 
     // parse a reference to an array element
-    pub fn parse_array_access(&mut self) -> Option<ArrayAccessNode> {
+    pub fn parse_array_access(&mut self) -> Option<ArrayAccessNode<'a>> {
         let line_number = self.get_line_number();
         
         let identifier = match &self.lexer.next_token().unwrap().kind {
@@ -695,7 +1042,7 @@ impl<'a> Parser<'a> {
             self.parse_token(TokenKind::CloseBracket).ok()?;
             return Some(ArrayAccessNode {
                 identifier,
-                index: Rc::new(index),
+                index: self.arena.expression(index),
                 line: line_number,
             });
         }
@@ -710,7 +1057,7 @@ impl<'a> Parser<'a> {
     }
 
     // parse while statement
-    pub fn parse_while(&mut self) -> Option<StatementNode> {
+    pub fn parse_while(&mut self) -> Option<StatementNode<'a>> {
         let line_number = self.get_line_number();
         
         let _ = self.parse_token(TokenKind::While).ok()?;
@@ -731,7 +1078,7 @@ impl<'a> Parser<'a> {
         };
 
         let body = match self.parse_statement_block(false) {
-            Some(body) => Rc::new(body),
+            Some(body) => self.arena.statement(body),
             _ => return None,
         };
 
@@ -743,16 +1090,16 @@ impl<'a> Parser<'a> {
     }
 
     // parse for loop
-    pub fn parse_for(&mut self) -> Option<StatementNode> {
+    pub fn parse_for(&mut self) -> Option<StatementNode<'a>> {
         let line_number = self.get_line_number();
         
         let _ = self.parse_token(TokenKind::For).ok()?;
         let _ = self.parse_token(TokenKind::OpenParen).ok()?;
 
         let initialiser = match self.lexer.peek_token().unwrap().kind {
-            TokenKind::SemiColon => Rc::new(None),
-            TokenKind::Identifier(_) => Rc::new(self.parse_assignment()),
-            TokenKind::Let => Rc::new(self.parse_variable_declaration()),
+            TokenKind::SemiColon => None,
+            TokenKind::Identifier(_) => self.parse_assignment().map(|statement| self.arena.statement(statement)),
+            TokenKind::Let => self.parse_variable_declaration().map(|statement| self.arena.statement(statement)),
             _ => { 
                 self.logger.print_error(
                     LoggerError::Syntax, 
@@ -776,14 +1123,14 @@ impl<'a> Parser<'a> {
         let _ = self.parse_token(TokenKind::SemiColon).ok()?;
 
         let increment = match self.lexer.peek_token().unwrap().kind {
-            TokenKind::CloseParen => Rc::new(None),
-            _ => Rc::new(self.parse_assignment()),
+            TokenKind::CloseParen => None,
+            _ => self.parse_assignment().map(|statement| self.arena.statement(statement)),
         };
 
         let _ = self.parse_token(TokenKind::CloseParen).ok()?;
 
         let body = match self.parse_statement_block(false) {
-            Some(body) => Rc::new(body),
+            Some(body) => self.arena.statement(body),
             _ => return None,
         };
 
@@ -797,7 +1144,7 @@ impl<'a> Parser<'a> {
     }
 
     // parse if-else statement
-    pub fn parse_if_else(&mut self) -> Option<StatementNode> {
+    pub fn parse_if_else(&mut self) -> Option<StatementNode<'a>> {
         let line_number = self.get_line_number();
         
         let _ = self.parse_token(TokenKind::If).ok()?;
@@ -818,7 +1165,7 @@ impl<'a> Parser<'a> {
         };
 
         let if_block = match self.parse_statement_block(false) {
-            Some(if_block) => Rc::new(if_block),
+            Some(if_block) => self.arena.statement(if_block),
             _ => { 
                 self.logger.print_error(
                     LoggerError::Syntax, 
@@ -835,12 +1182,12 @@ impl<'a> Parser<'a> {
                 match token.kind {
                     TokenKind::Else => {
                         self.lexer.next_token(); // Consume the 'else' token
-                        Rc::new(self.parse_statement_block(false))
+                        self.parse_statement_block(false).map(|statement| self.arena.statement(statement))
                     },
-                    _ => Rc::new(None),
+                    _ => None,
                 }
             },
-            None => Rc::new(None), // Handle EOF case
+            None => None, // Handle EOF case
         };
 
         Some(StatementNode::If(IfNode {
@@ -852,11 +1199,12 @@ impl<'a> Parser<'a> {
         ))
     }
 
-    // parse print statement
-    pub fn parse_print(&mut self) -> Option<StatementNode>{
+    // parse print statement, or formatted print: __printf value, decimals
+    pub fn parse_print(&mut self) -> Option<StatementNode<'a>>{
         let line_number = self.get_line_number();
 
-        let _ = self.parse_token(TokenKind::Print).ok()?;
+        let formatted = self.lexer.peek_token()?.kind == TokenKind::PrintFormatted;
+        self.parse_token(if formatted { TokenKind::PrintFormatted } else { TokenKind::Print }).ok()?;
 
         let expression = match self.parse_expression() {
             Some(expression) => expression,
@@ -873,15 +1221,37 @@ impl<'a> Parser<'a> {
             },
         };
 
+        let decimals = if formatted {
+            self.parse_token(TokenKind::Comma).ok()?;
+
+            match self.parse_expression() {
+                Some(expression) => Some(expression),
+                _ => {
+                    self.logger.print_error(
+                        LoggerError::Syntax,
+                        "Invalid __printf statement declaration. Number of decimal places expected.",
+                        line_number
+                    );
+
+                    self.status_set(CompilationResult::Failure);
+
+                    return None
+                },
+            }
+        } else {
+            None
+        };
+
         Some(StatementNode::Print(PrintNode {
             arg_expr: expression,
             arg_type: RefCell::new(SymbolType::to_string(&SymbolType::Undefined)),
+            decimals,
             line: line_number,
         }))
     }
 
     // parse delay statement
-    pub fn parse_delay(&mut self) -> Option<StatementNode>{
+    pub fn parse_delay(&mut self) -> Option<StatementNode<'a>>{
         let _ = self.parse_token(TokenKind::Delay).ok()?;
 
         let expression = match self.parse_expression() {
@@ -902,8 +1272,30 @@ impl<'a> Parser<'a> {
         Some(StatementNode::Delay(expression))
     }
 
+    // parse assert statement: __assert condition
+    pub fn parse_assert(&mut self) -> Option<StatementNode<'a>>{
+        self.parse_token(TokenKind::Assert).ok()?;
+
+        let expression = match self.parse_expression() {
+            Some(expression) => expression,
+            _ => { 
+                self.logger.print_error(
+                    LoggerError::Syntax, 
+                    "Invalid __assert statement declaration. Expression expected.",
+                    self.get_line_number()
+                );
+
+                self.status_set(CompilationResult::Failure);
+
+                return None
+            },
+        };
+
+        Some(StatementNode::Assert(expression))
+    }
+
     // parse clear statement
-    pub fn parse_clear(&mut self) -> Option<StatementNode>{
+    pub fn parse_clear(&mut self) -> Option<StatementNode<'a>>{
         let _ = self.parse_token(TokenKind::Clear).ok()?;
 
         let expression = match self.parse_expression() {
@@ -924,8 +1316,94 @@ impl<'a> Parser<'a> {
         Some(StatementNode::Clear(expression))
     }
 
+    // parse brightness statement
+    pub fn parse_brightness(&mut self) -> Option<StatementNode<'a>>{
+        self.parse_token(TokenKind::Brightness).ok()?;
+
+        let expression = match self.parse_expression() {
+            Some(expression) => expression,
+            _ => { 
+                self.logger.print_error(
+                    LoggerError::Syntax, 
+                    "Invalid __brightness statement declaration. Expression expected.",
+                    self.get_line_number()
+                );
+
+                self.status_set(CompilationResult::Failure);
+
+                return None
+            },
+        };
+
+        Some(StatementNode::Brightness(expression))
+    }
+
+    // parse inline assembly: asm { ... }, an instruction per line
+    pub fn parse_asm(&mut self) -> Option<StatementNode<'a>>{
+        let (body, line) = match self.lexer.next_token() {
+            Some(token) => match &token.kind {
+                TokenKind::Asm(body) => (body.clone(), token.line),
+                _ => return None,
+            },
+            None => return None,
+        };
+
+        let lines: Vec<String> = body.split('\n').map(|text| text.trim_end_matches('\r').to_string()).collect();
+        let mut valid = true;
+
+        // Variables are looked up in semantic analysis; only the instructions
+        // are checked here, with every reference standing in for 0:0
+        for (index, text) in lines.iter().enumerate() {
+            let error = match AsmNode::substitute(text, |_| Some((0, 0))) {
+                Ok(code) if code.is_empty() || code.eq_ignore_ascii_case("nop") => None,
+                Ok(code) => match pixardis_instruction_from_string(code.clone()) {
+                    PixardisInstruction::Nop => Some(format!("unrecognised instruction '{}'", code)),
+                    _ => None,
+                },
+                Err(error) => Some(error),
+            };
+
+            if let Some(error) = error {
+                self.logger.print_error(
+                    LoggerError::Syntax,
+                    format!("Invalid inline assembly; {}.", error).as_str(),
+                    line + index
+                );
+
+                valid = false;
+            }
+        }
+
+        if !valid {
+            self.status_set(CompilationResult::Failure);
+            return None;
+        }
+
+        Some(StatementNode::Asm(AsmNode { lines, line }))
+    }
+
+    // parse store statement: __store "key", value
+    pub fn parse_store(&mut self) -> Option<StatementNode<'a>>{
+        self.parse_token(TokenKind::Store).ok()?;
+
+        match self.parse_keyed_builtin("__store")? {
+            (key, Some(expression)) => Some(StatementNode::Store(key, expression.clone())),
+            (_, None) => {
+                self.logger.print_error(
+                    LoggerError::Syntax,
+                    "Invalid __store statement declaration. Value expected after the key.",
+                    self.get_line_number()
+                );
+
+                self.status_set(CompilationResult::Failure);
+
+                None
+            },
+        }
+    }
+
     // parse return statement
-    pub fn parse_return(&mut self) -> Option<StatementNode>{
+    pub fn parse_return(&mut self) -> Option<StatementNode<'a>>{
         let _ = self.parse_token(TokenKind::Return).ok()?;
 
         let expression = match self.parse_expression() {
@@ -947,47 +1425,21 @@ impl<'a> Parser<'a> {
     }
 
     // parse write statement
-    pub fn parse_write(&mut self) -> Option<StatementNode>{
+    pub fn parse_write(&mut self) -> Option<StatementNode<'a>>{
         let _ = self.parse_token(TokenKind::Write).ok()?;
 
-        let expression_x = self.parse_expression()?;
-        let _ = self.parse_token(TokenKind::Comma).ok()?;
-        let expression_y = self.parse_expression()?;
-        let _ = self.parse_token(TokenKind::Comma).ok()?;
-        let expression_color = self.parse_expression()?;
-
-        Some (StatementNode::Write([
-            expression_x, 
-            expression_y, 
-            expression_color
-        ]))
+        Some(StatementNode::Write(self.parse_arguments::<3>(&TokenKind::Write)?))
     }
 
     // parse write_box statement
-    pub fn parse_write_box(&mut self) -> Option<StatementNode>{
+    pub fn parse_write_box(&mut self) -> Option<StatementNode<'a>>{
         let _ = self.parse_token(TokenKind::WriteBox).ok()?;
 
-        let expression_x = self.parse_expression()?;
-        let _ = self.parse_token(TokenKind::Comma).ok()?;
-        let expression_y = self.parse_expression()?;
-        let _ = self.parse_token(TokenKind::Comma).ok()?;
-        let expression_w = self.parse_expression()?;
-        let _ = self.parse_token(TokenKind::Comma).ok()?;
-        let expression_h = self.parse_expression()?;
-        let _ = self.parse_token(TokenKind::Comma).ok()?;
-        let expression_color = self.parse_expression()?;
-
-        Some (StatementNode::WriteBox([
-            expression_x, 
-            expression_y, 
-            expression_w, 
-            expression_h, 
-            expression_color
-        ]))
+        Some(StatementNode::WriteBox(self.parse_arguments::<5>(&TokenKind::WriteBox)?))
     }
 
     // parse write_line statement
-    pub fn parse_write_line(&mut self) -> Option<StatementNode>{
+    pub fn parse_write_line(&mut self) -> Option<StatementNode<'a>>{
         let _ = self.parse_token(TokenKind::WriteLine).ok()?;
 
         let expression_x0 = self.parse_expression()?;
@@ -1009,8 +1461,203 @@ impl<'a> Parser<'a> {
         ]))
     }
 
+    // parse the arguments of a builtin keyed by name (__param, __load and
+    // __store): "name" with an optional value, either bare or in parentheses
+    // (__param "speed", 3 or __param("speed", 3))
+    pub fn parse_keyed_builtin(&mut self, builtin: &str) -> Option<(String, Option<&'a ExpressionNode<'a>>)> {
+        let parenthesised = self.lexer.peek_token()?.kind == TokenKind::OpenParen;
+        if parenthesised {
+            self.lexer.next_token();
+        }
+
+        let name = match self.lexer.peek_token()?.kind.clone() {
+            TokenKind::StringLiteral(name) => name,
+            _ => {
+                self.logger.print_error(
+                    LoggerError::Syntax,
+                    &format!("Invalid {} expression. Name (string) expected.", builtin),
+                    self.get_line_number()
+                );
+
+                self.status_set(CompilationResult::Failure);
+
+                return None
+            },
+        };
+
+        // Names end up in the assembly as the instruction's operand
+        let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        if !valid {
+            self.logger.print_error(
+                LoggerError::Syntax,
+                &format!("Invalid {} name \"{}\"; names are made of letters, digits and underscores.", builtin, name),
+                self.get_line_number()
+            );
+
+            self.status_set(CompilationResult::Failure);
+
+            return None
+        }
+
+        self.lexer.next_token();
+
+        let default = if self.lexer.peek_token()?.kind == TokenKind::Comma {
+            self.lexer.next_token();
+            Some(self.arena.expression(self.parse_expression()?))
+        } else {
+            None
+        };
+
+        if parenthesised {
+            self.parse_token(TokenKind::CloseParen).ok()?;
+        }
+
+        Some((name, default))
+    }
+
+    // parse a comma-separated list of exactly N arguments of a builtin,
+    // naming it and its form if they're miswritten
+    pub fn parse_arguments<const N: usize>(&mut self, builtin: &TokenKind) -> Option<[ExpressionNode<'a>; N]> {
+        let builtin = builtin_form(builtin)?;
+
+        if self.parenthesised_arguments() {
+            self.builtin_error(&builtin, &format!("{} takes its arguments without parentheses", builtin.name));
+            return None;
+        }
+
+        let mut expressions = Vec::with_capacity(N);
+        for index in 0..N {
+            let kind = self.lexer.peek_token()?.kind.clone();
+
+            if ends_arguments(&kind) {
+                self.builtin_error(&builtin, &format!("{} expects {} argument(s), {} provided", builtin.name, N, index));
+                return None;
+            }
+
+            if index > 0 {
+                if kind != TokenKind::Comma {
+                    self.builtin_error(&builtin, &format!("Missing comma after argument {} of {}", index, builtin.name));
+                    return None;
+                }
+
+                self.lexer.next_token();
+            }
+
+            expressions.push(self.parse_expression()?);
+        }
+
+        // A statement's arguments end it; an expression's may be followed by
+        // a comma of whatever it's an argument of
+        if builtin.statement && self.lexer.peek_token()?.kind == TokenKind::Comma {
+            self.builtin_error(&builtin, &format!("{} expects {} argument(s), more provided", builtin.name, N));
+            return None;
+        }
+
+        expressions.try_into().ok()
+    }
+
+    // True if a builtin's arguments are written as a call, __write(x, y,
+    // colour), which would otherwise parse as a parenthesised expression up
+    // to its first comma
+    fn parenthesised_arguments(&self) -> bool {
+        let mut depth = 0;
+        let mut k = 0;
+
+        while let Some(token) = self.lexer.peek_k_tokens(k) {
+            match token.kind {
+                TokenKind::OpenParen | TokenKind::OpenBracket => depth += 1,
+                TokenKind::CloseParen | TokenKind::CloseBracket if depth > 1 => depth -= 1,
+                TokenKind::Comma if depth == 1 => return true,
+                _ if depth == 0 => return false,
+                TokenKind::CloseParen | TokenKind::CloseBracket | TokenKind::SemiColon | TokenKind::OpenBrace | TokenKind::CloseBrace => return false,
+                _ => (),
+            }
+
+            k += 1;
+        }
+
+        false
+    }
+
+    // report a misused builtin, with how it's written
+    fn builtin_error(&mut self, builtin: &BuiltinForm, problem: &str) {
+        self.logger.print_error(
+            LoggerError::Syntax,
+            &format!("{}; it's written {}.", problem, builtin.form),
+            self.get_line_number()
+        );
+
+        self.status_set(CompilationResult::Failure);
+    }
+
+    // parse write_box_gradient and write_box_pattern statements, which share
+    // the form: x, y, w, h, colour, colour, mode
+    pub fn parse_write_box_fill(&mut self, token: TokenKind) -> Option<StatementNode<'a>>{
+        self.parse_token(token.clone()).ok()?;
+
+        let expressions = self.parse_arguments::<7>(&token)?;
+
+        match token {
+            TokenKind::WriteBoxGradient => Some(StatementNode::WriteBoxGradient(expressions)),
+            _ => Some(StatementNode::WriteBoxPattern(expressions)),
+        }
+    }
+
+    // parse write_triangle statement: x0, y0, x1, y1, x2, y2, colour
+    pub fn parse_write_triangle(&mut self) -> Option<StatementNode<'a>>{
+        self.parse_token(TokenKind::WriteTriangle).ok()?;
+
+        Some(StatementNode::WriteTriangle(self.parse_arguments::<7>(&TokenKind::WriteTriangle)?))
+    }
+
+    // parse variable declaration
+    // parse a constant expression giving a (positive) array size or count
+    pub fn parse_constant_size(&mut self, what: &str) -> Option<i64> {
+        let expression = match self.parse_expression() {
+            Some(expression) => expression,
+            _ => {
+                self.logger.print_error(
+                    LoggerError::Syntax, 
+                    format!("Invalid variable declaration. Expected {}.", what.to_lowercase()).as_str(),
+                    self.get_line_number()
+                );
+
+                return None;
+            },
+        };
+
+        match consteval::evaluate_integer(&expression) {
+            Ok(size) if size > 0 => Some(size),
+            Ok(size) => {
+                self.logger.print_error(
+                    LoggerError::Syntax, 
+                    format!("Invalid variable declaration. {} must be positive, got {}.", what, size).as_str(),
+                    self.get_line_number()
+                );
+
+                self.status_set(CompilationResult::Failure);
+
+                None
+            },
+            Err(error) => {
+                self.logger.print_error(
+                    LoggerError::Syntax, 
+                    format!("Invalid variable declaration. {} must be a constant integer expression; {}.", what, error.message()).as_str(),
+                    self.get_line_number()
+                );
+
+                self.status_set(CompilationResult::Failure);
+
+                None
+            },
+        }
+    }
+
     // parse variable declaration
-    pub fn parse_variable_declaration(&mut self) -> Option<StatementNode>{
+
+    pub fn parse_variable_declaration(&mut self) -> Option<StatementNode<'a>>{
         let line_number = self.get_line_number();        
         let _ = self.parse_token(TokenKind::Let).ok()?;
         
@@ -1076,28 +1723,17 @@ impl<'a> Parser<'a> {
         // If we don't have an equals sign, we're initialising an array type
         let _ = self.parse_token(TokenKind::OpenBracket).ok()?;
 
-        let mut advance_token = true;
-
-        let size = match &self.lexer.next_token().unwrap().kind {
-            TokenKind::IntegerLiteral(i) => i.clone(),
-            TokenKind::CloseBracket => { 
-                advance_token = false;
-                0
-            },
-            _ => {
-                self.logger.print_error(
-                    LoggerError::Syntax, 
-                    "Invalid variable declaration. Expected array size.",
-                    self.get_line_number()
-                );
-                
-                return None;
-            },
-        };
+        // Array size is a constant expression (or omitted, if given by the initialiser)
+        let size = if self.lexer.peek_token().unwrap().kind == TokenKind::CloseBracket {
+            self.lexer.next_token();
+            0
+        } else {
+            let size = self.parse_constant_size("Array size")?;
 
-        if advance_token {
             let _ = self.parse_token(TokenKind::CloseBracket).ok()?;
-        }
+
+            size
+        };
 
         // Parse assignment
         let _ = self.parse_token(TokenKind::Equals).ok()?;
@@ -1125,6 +1761,7 @@ impl<'a> Parser<'a> {
                 type_name,
                 size,
                 initialiser: None,
+                fill: None,
                 line: line_number,
             };
 
@@ -1136,6 +1773,38 @@ impl<'a> Parser<'a> {
 
         while let Some(expression) = self.parse_expression() 
         {
+            // A fill initialiser ([value; count]) replicates a single value
+            if arguments.is_empty() && self.lexer.peek_token().unwrap().kind == TokenKind::SemiColon {
+                self.lexer.next_token();
+
+                let count = self.parse_constant_size("Fill count")?;
+
+                if size != 0 && size != count {
+                    self.logger.print_error(
+                        LoggerError::Syntax, 
+                        format!("Invalid variable declaration. Array has size {}, but fill initialiser provides {} elements.", size, count).as_str(),
+                        self.get_line_number()
+                    );
+
+                    self.status_set(CompilationResult::Failure);
+
+                    return None;
+                }
+
+                self.parse_token(TokenKind::CloseBracket).ok()?;
+
+                let array_declaration_node = ArrayDeclarationNode {
+                    identifier,
+                    type_name,
+                    size: count,
+                    initialiser: None,
+                    fill: Some(expression),
+                    line: line_number,
+                };
+
+                return Some(StatementNode::ArrayDeclaration(array_declaration_node));
+            }
+
             arguments.push(expression);
 
             if self.lexer.peek_token().unwrap().kind != TokenKind::Comma {
@@ -1152,6 +1821,7 @@ impl<'a> Parser<'a> {
                 type_name,
                 size,
                 initialiser: Some(arguments),
+                fill: None,
                 line: line_number,
             };
                 
@@ -1159,7 +1829,7 @@ impl<'a> Parser<'a> {
     }
 
     // parse assignment
-    pub fn parse_assignment(&mut self) -> Option<StatementNode>{
+    pub fn parse_assignment(&mut self) -> Option<StatementNode<'a>>{
         let line_number = self.get_line_number();
         
         let identifier = match &self.lexer.next_token().unwrap().kind {
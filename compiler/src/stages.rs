@@ -0,0 +1,186 @@
+//! Stage-by-stage compilation
+//!
+//! Runs the pipeline up to a given stage and keeps what every stage on the
+//! way produced, for views of how the compiler sees a program (the
+//! playground's pipeline explorer).
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use shared::pixardis::{PixardisInstruction, pixardis_instruction_to_string};
+
+use crate::{
+    analysis::symbol::{ScopeManager, SymbolEntry, SymbolTable},
+    code_generation,
+    code_optimisation,
+    common::{logger::Logger, status::CompilationResult},
+    lexical_analysis,
+    parse,
+    parser::{arena::AstArena, parser::PARSER_MAX_NESTING},
+    semantic_analysis,
+};
+
+//
+// Stages of the pipeline, in the order they run
+//
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PipelineStage {
+    Lex,
+    Parse,
+    Analyse,
+    Generate,
+    Optimise,
+}
+
+impl PipelineStage {
+    pub fn from_string(name: &str) -> Option<PipelineStage> {
+        match name {
+            "lex" => Some(PipelineStage::Lex),
+            "parse" => Some(PipelineStage::Parse),
+            "analyse" => Some(PipelineStage::Analyse),
+            "generate" => Some(PipelineStage::Generate),
+            "optimise" => Some(PipelineStage::Optimise),
+            _ => None,
+        }
+    }
+}
+
+//
+// What each stage produced; a stage that didn't run (it comes after the
+// one asked for, or after the one that failed) leaves its artifact empty
+//
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PipelineArtifacts {
+    // Tokens, with their spans and (0-based) lines
+    pub tokens: Option<Value>,
+    // Syntax tree (see parse_to_json)
+    pub ast: Option<Value>,
+    // Scopes and the symbols declared in each (see symbols_to_json)
+    pub symbols: Option<Value>,
+    // Assembly as code generation and optimisation leave it, an instruction
+    // per line
+    pub unoptimised: Option<Vec<String>>,
+    pub optimised: Option<Vec<String>>,
+    // The stage that failed, if one did
+    pub failed: Option<String>,
+}
+
+impl PipelineArtifacts {
+    fn fail(mut self, message: &str) -> PipelineArtifacts {
+        self.failed = Some(message.to_string());
+        self
+    }
+}
+
+//
+// Run the pipeline on a source up to and including the given stage;
+// diagnostics go to the logger
+//
+pub fn compile_to_stage<'a>(source: &'a str, logger: &Logger<'a>, stage: PipelineStage) -> PipelineArtifacts {
+    let mut artifacts = PipelineArtifacts::default();
+
+    let mut lexer_logger = logger.clone();
+    let (lexer, status) = lexical_analysis(source, &mut lexer_logger);
+
+    artifacts.tokens = serde_json::to_value(lexer.tokens()).ok();
+
+    if matches!(status, CompilationResult::Failure) {
+        return artifacts.fail("Lexical analysis failed");
+    }
+
+    if stage == PipelineStage::Lex {
+        return artifacts;
+    }
+
+    let arena = AstArena::new();
+    let mut parser_logger = logger.clone();
+    let (mut parser, status) = parse(lexer, &mut parser_logger, &arena, PARSER_MAX_NESTING);
+
+    if matches!(status, CompilationResult::Failure) {
+        return artifacts.fail("Parsing failed");
+    }
+
+    let mut syntax_tree = match parser.take_syntax_tree() {
+        Some(syntax_tree) => syntax_tree,
+        None => return artifacts.fail("Failed to get syntax tree"),
+    };
+
+    artifacts.ast = serde_json::to_value(&syntax_tree).ok();
+
+    if stage == PipelineStage::Parse {
+        return artifacts;
+    }
+
+    let mut scope_manager = ScopeManager::new();
+    scope_manager.interner_set(parser.interner());
+
+    let mut analysis_logger = logger.clone();
+    let status = semantic_analysis(&mut syntax_tree, &mut scope_manager, &mut analysis_logger);
+
+    // Symbols declared before an error are still worth showing
+    artifacts.symbols = Some(symbols_to_json(&scope_manager));
+
+    if matches!(status, CompilationResult::Failure) {
+        return artifacts.fail("Semantic analysis failed");
+    }
+
+    if stage == PipelineStage::Analyse {
+        return artifacts;
+    }
+
+    let mut codegen_logger = logger.clone();
+    let (mut program, mut lines, status) = code_generation(&mut syntax_tree, &mut scope_manager, &mut codegen_logger, None);
+
+    if matches!(status, CompilationResult::Failure) {
+        return artifacts.fail("Code generation failed");
+    }
+
+    artifacts.unoptimised = Some(assembly_lines(&program));
+
+    if stage == PipelineStage::Generate {
+        return artifacts;
+    }
+
+    let (program, _) = code_optimisation(&mut program, &mut lines, logger, false);
+    artifacts.optimised = Some(assembly_lines(&program));
+
+    artifacts
+}
+
+//
+// Scopes as JSON: an array of { scope, parent, function, return_type,
+// symbols }, each symbol a { name, type, offset, params, return_type } in
+// frame order
+//
+pub fn symbols_to_json(scope_manager: &ScopeManager) -> Value {
+    Value::Array(scope_manager.scopes().iter().map(scope_to_json).collect())
+}
+
+fn scope_to_json(scope: &SymbolTable) -> Value {
+    let mut symbols: Vec<&SymbolEntry> = scope.get_iter().map(|(_, entry)| entry).collect();
+    symbols.sort_by(|a, b| a.offset.cmp(&b.offset).then_with(|| a.name.cmp(&b.name)));
+
+    json!({
+        "scope": scope.scope_id(),
+        "parent": scope.parent_scope_id(),
+        "function": scope.is_function(),
+        "return_type": scope.return_type().map(|symbol_type| symbol_type.to_string()),
+        "symbols": symbols.into_iter().map(symbol_to_json).collect::<Vec<_>>(),
+    })
+}
+
+fn symbol_to_json(entry: &SymbolEntry) -> Value {
+    json!({
+        "name": entry.name.as_str(),
+        "type": entry.symbol_type.to_string(),
+        "offset": entry.offset,
+        "params": entry.params.as_ref().map(|params| params.iter().map(symbol_to_json).collect::<Vec<_>>()),
+        "return_type": entry.return_type.as_ref().map(|symbol_type| symbol_type.to_string()),
+    })
+}
+
+fn assembly_lines(program: &[(usize, PixardisInstruction)]) -> Vec<String> {
+    program.iter()
+        .map(|(_, instruction)| pixardis_instruction_to_string(instruction.clone()))
+        .collect()
+}
@@ -0,0 +1,273 @@
+//! chroma test
+//!
+//! Runs a program's test functions (top-level functions named test_*, taking
+//! no arguments), each on a fresh VM. A test passes if it returns without a
+//! runtime error; __assert is the usual way to fail one. With --fixed-point,
+//! a test also has to print the same with floats lowered to fixed point.
+
+use compiler::{common::logger::Logger, compile_with_line_table};
+
+use shared::intern::Atom;
+use shared::pixardis::{PixardisInstruction, pixardis_demangle_label, pixardis_immediate_integer};
+
+use vm::machine::architecture::VirtualMachineError;
+use vm::machine::observer::Observer;
+use vm::pixardis::coverage::{pixardis_coverage_to_json, pixardis_line_coverage, pixardis_uncovered_lines};
+use vm::pixardis::pixardis::{PixardisMetadata, PixardisVirtualMachine};
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io;
+use std::rc::Rc;
+
+const TEST_PREFIX: &str = "test_";
+
+// Label of the stub that calls the test function (not a mangled name, so it
+// can't clash with the program's own labels)
+const TEST_ENTRY_LABEL: &str = "__test";
+
+// Scope user functions are declared in at the top level of a program
+const TEST_SCOPE: usize = 0;
+
+enum TestOutcome {
+    Passed,
+    Failed(String),
+    Skipped(String),
+}
+
+// Collects the lines a test prints
+struct PrintCapture(Rc<RefCell<Vec<String>>>);
+
+impl Observer for PrintCapture {
+    fn on_print(&mut self, text: &str) {
+        self.0.borrow_mut().push(text.to_string());
+    }
+}
+
+//
+// Compile the program at path and run its tests for at most cycles
+// instructions each, writing the lines they executed to the coverage file if
+// given; with fixed_point, each test that passes runs again compiled with
+// that many fractional bits on a float-less VM, and fails unless it passes
+// and prints the same there. Returns whether every test passed.
+//
+pub fn run_tests(path: &str, cycles: usize, coverage: Option<&str>, fixed_point: Option<u32>) -> Result<bool, io::Error> {
+    let source = shared::io::read_file_to_string(path)?;
+    let (program, lines) = compile(&source, None)?;
+
+    let fixed = match fixed_point {
+        Some(bits) => Some((bits, compile(&source, Some(bits))?)),
+        None => None,
+    };
+
+    let tests = find_tests(&program);
+
+    if tests.is_empty() {
+        println!("No test functions (named {}*) found in {}.", TEST_PREFIX, path);
+        return Ok(true);
+    }
+
+    println!("Running {} test(s) from {}", tests.len(), path);
+
+    let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+
+    // Instructions executed by any test
+    let mut executed = vec![false; program.len()];
+
+    for (name, label, arguments) in tests.iter() {
+        let outcome = match (arguments, &fixed) {
+            (0, None) => run_test(&program, &lines, label, cycles, &mut executed, false, None),
+            (0, Some((bits, (fixed_program, fixed_lines)))) => {
+                let mut prints = Vec::new();
+
+                match run_test(&program, &lines, label, cycles, &mut executed, false, Some(&mut prints)) {
+                    TestOutcome::Passed => compare_fixed_point(*bits, fixed_program, fixed_lines, label, cycles, &prints),
+                    outcome => outcome,
+                }
+            },
+            _ => TestOutcome::Skipped(format!("takes {} argument value(s)", arguments)),
+        };
+
+        match outcome {
+            TestOutcome::Passed => {
+                passed += 1;
+                println!("test {} ... ok", name);
+            },
+            TestOutcome::Failed(reason) => {
+                failed += 1;
+                println!("test {} ... FAILED ({})", name, reason);
+            },
+            TestOutcome::Skipped(reason) => {
+                skipped += 1;
+                println!("test {} ... skipped ({})", name, reason);
+            },
+        }
+    }
+
+    println!();
+    println!("test result: {}. {} passed; {} failed; {} skipped",
+        if failed == 0 { "ok" } else { "FAILED" }, passed, failed, skipped);
+
+    if let Some(coverage_path) = coverage {
+        let line_coverage = pixardis_line_coverage(&executed, &lines);
+        let covered = line_coverage.iter().filter(|line| line.executed > 0).count();
+
+        println!("coverage: {} of {} lines executed", covered, line_coverage.len());
+
+        if covered < line_coverage.len() {
+            println!("not executed: {}", pixardis_uncovered_lines(&line_coverage));
+        }
+
+        std::fs::write(coverage_path, pixardis_coverage_to_json(&line_coverage))?;
+    }
+
+    Ok(failed == 0)
+}
+
+//
+// Compile a source to its instructions and line table, showing the
+// compiler's messages only if it fails
+//
+fn compile(source: &str, fixed_point: Option<u32>) -> Result<(Vec<PixardisInstruction>, Vec<usize>), io::Error> {
+    let logger = Logger::new_capturing(source);
+
+    match compile_with_line_table(source, &logger, fixed_point) {
+        Ok((program, lines)) => Ok((program.into_iter().map(|(_, instruction)| instruction).collect(), lines)),
+        Err(error) => {
+            for message in logger.captured_messages() {
+                eprintln!("{}", message);
+            }
+
+            Err(io::Error::new(io::ErrorKind::InvalidData, error))
+        },
+    }
+}
+
+//
+// Run a test that passed again from its fixed-point build, which has to pass
+// and print what the test printed with floats
+//
+fn compare_fixed_point(bits: u32, program: &[PixardisInstruction], lines: &[usize], label: &Atom, cycles: usize, expected: &[String]) -> TestOutcome {
+    let mut prints = Vec::new();
+    let mut executed = vec![false; program.len()];
+
+    match run_test(program, lines, label, cycles, &mut executed, true, Some(&mut prints)) {
+        TestOutcome::Passed => { },
+        TestOutcome::Failed(reason) => return TestOutcome::Failed(format!("with --fixed-point {}, {}", bits, reason)),
+        outcome => return outcome,
+    }
+
+    let describe = |text: Option<&String>| text.map_or(String::from("nothing"), |text| format!("'{}'", text));
+
+    match (0..expected.len().max(prints.len())).find(|&index| expected.get(index) != prints.get(index)) {
+        Some(index) => TestOutcome::Failed(format!("with --fixed-point {}, print {} gave {} instead of {}",
+            bits, index + 1, describe(prints.get(index)), describe(expected.get(index)))),
+        None => TestOutcome::Passed,
+    }
+}
+
+//
+// Test functions as (name, label, argument size), in the order they're
+// defined; the argument size comes from the function's .sig directive
+//
+fn find_tests(program: &[PixardisInstruction]) -> Vec<(String, Atom, usize)> {
+    let signatures: BTreeMap<&str, usize> = program.iter()
+        .filter_map(|instruction| match instruction {
+            PixardisInstruction::Signature(name, size) => Some((name.as_str(), *size)),
+            _ => None,
+        })
+        .collect();
+
+    program.iter()
+        .filter_map(|instruction| match instruction {
+            PixardisInstruction::Label(label) => match pixardis_demangle_label(label) {
+                Some((name, TEST_SCOPE)) if name.starts_with(TEST_PREFIX) => {
+                    let arguments = signatures.get(label.as_str()).copied().unwrap_or(0);
+                    Some((name.to_string(), label.clone(), arguments))
+                },
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+//
+// Run one test on a fresh VM: a stub appended to the program calls the test
+// function and halts, and the VM starts at the stub instead of .main. The
+// line table lets failed assertions name their source line; the instructions
+// the test executes are marked in executed. A fixed-point build runs without
+// float support and quietly; prints collects what the test printed.
+//
+fn run_test(program: &[PixardisInstruction], lines: &[usize], label: &Atom, cycles: usize, executed: &mut [bool], fixed_point: bool, prints: Option<&mut Vec<String>>) -> TestOutcome {
+    let mut code = program.to_vec();
+
+    code.extend([
+        PixardisInstruction::Label(Atom::new(TEST_ENTRY_LABEL)),
+        PixardisInstruction::PushImmediate(pixardis_immediate_integer(0)),
+        PixardisInstruction::PushLabel(label.clone()),
+        PixardisInstruction::Call,
+        PixardisInstruction::Halt,
+    ]);
+
+    let metadata = PixardisMetadata::from_instructions(&code);
+    let mut vm = PixardisVirtualMachine::new(metadata.width.unwrap_or(64), metadata.height.unwrap_or(48));
+
+    // A failing test mustn't end the run, and tests are repeatable: the same
+    // seed every run
+    vm.exit_on_error_set(false);
+    vm.random_seed_set(0);
+    vm.float_support_set(!fixed_point);
+    vm.console_print_set(!fixed_point);
+
+    let captured = Rc::new(RefCell::new(Vec::new()));
+
+    if prints.is_some() {
+        vm.observer_add(Box::new(PrintCapture(captured.clone())));
+    }
+
+    if vm.load_program_with_entry(code, TEST_ENTRY_LABEL).is_err() {
+        return TestOutcome::Failed(String::from("couldn't load the test stub"));
+    }
+
+    vm.source_lines_set(lines.to_vec());
+    vm.coverage_set(true);
+
+    let outcome = run_until_done(&mut vm, cycles);
+
+    if let Some(prints) = prints {
+        prints.append(&mut captured.borrow_mut());
+    }
+
+    // The stub's instructions follow the program's, so addresses line up
+    for (executed, &ran) in executed.iter_mut().zip(vm.coverage().unwrap_or_default()) {
+        *executed |= ran;
+    }
+
+    outcome
+}
+
+//
+// Run a loaded test until it halts, fails or uses up its cycles; delays end
+// a frame rather than waiting
+//
+fn run_until_done(vm: &mut PixardisVirtualMachine, cycles: usize) -> TestOutcome {
+    let mut executed = 0;
+
+    while executed < cycles {
+        match vm.run_frame(cycles - executed) {
+            Ok(count) => {
+                executed += count;
+                if vm.delay_requested().is_none() {
+                    break;
+                }
+            },
+            Err(VirtualMachineError::TrapHalt) => return TestOutcome::Passed,
+            Err(VirtualMachineError::AssertionFailed(Some(line))) => return TestOutcome::Failed(format!("assertion failed at line {}", line)),
+            Err(VirtualMachineError::AssertionFailed(None)) => return TestOutcome::Failed(String::from("assertion failed")),
+            Err(error) => return TestOutcome::Failed(format!("{:?}", error)),
+        }
+    }
+
+    TestOutcome::Failed(format!("didn't finish within {} instructions", cycles))
+}
@@ -0,0 +1,138 @@
+#ifndef PIXARDIS_H
+#define PIXARDIS_H
+
+/* Mirrors ffi/src/lib.rs; regenerate with cbindgen (see ffi/cbindgen.toml) after API changes. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// The call succeeded; the VM ran out of cycles without reaching a delay
+#define PIXARDIS_OK 0
+
+// The program issued a delay; this is the natural frame boundary
+#define PIXARDIS_DELAYED 1
+
+// The program executed a halt instruction
+#define PIXARDIS_HALTED 2
+
+// A null pointer, invalid UTF-8 or out-of-range argument was passed
+#define PIXARDIS_INVALID_ARGUMENT -1
+
+// The program raised a runtime error; the VM is stopped
+#define PIXARDIS_RUNTIME_ERROR -2
+
+// Opaque handle to a virtual machine instance
+typedef struct PixardisVm PixardisVm;
+
+// A pixel the host wrote with pixardis_vm_write_pixel, and the number of
+// instructions the program had executed when it did
+typedef struct PixardisInputEvent {
+  uint64_t cycle;
+  uintptr_t x;
+  uintptr_t y;
+  uint64_t colour;
+} PixardisInputEvent;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Create a VM with a display of the given size. Returns null if either
+// dimension is zero. Release with pixardis_vm_destroy.
+PixardisVm *pixardis_vm_create(uintptr_t width, uintptr_t height);
+
+// Destroy a VM created by pixardis_vm_create. Passing null is a no-op.
+//
+// # Safety
+// `vm` must be null or a live handle; it must not be used afterwards.
+void pixardis_vm_destroy(PixardisVm *vm);
+
+// Load a program from NUL-terminated Pixardis assembly text.
+//
+// # Safety
+// `vm` must be null or a live handle; `source` must be null or point to a
+// NUL-terminated string.
+int32_t pixardis_vm_load_program(PixardisVm *vm, const char *source);
+
+// Seed the VM's random number generator, for reproducible runs.
+//
+// # Safety
+// `vm` must be null or a live handle.
+int32_t pixardis_vm_seed(PixardisVm *vm, uint64_t seed);
+
+// Run one program frame: execute up to (and including) the next delay, or
+// at most `max_cycles` instructions. A pending delay is skipped, so the host
+// controls timing. The number of instructions executed is written to
+// `cycles` when it isn't null.
+//
+// # Safety
+// `vm` must be null or a live handle; `cycles` must be null or writable.
+int32_t pixardis_vm_step(PixardisVm *vm, uintptr_t max_cycles, uintptr_t *cycles);
+
+// Delay (in milliseconds) requested by the last delay instruction, or a
+// negative value if the VM isn't delayed.
+//
+// # Safety
+// `vm` must be null or a live handle.
+double pixardis_vm_delay_ms(const PixardisVm *vm);
+
+// Pointer to the framebuffer: width * height colours (0xRRGGBB), row-major.
+// The dimensions are written to `width` and `height` when they aren't null.
+// The pointer stays valid until the next call that mutates the VM.
+//
+// # Safety
+// `vm` must be null or a live handle; `width` and `height` must be null or
+// writable.
+const uint64_t *pixardis_vm_framebuffer(const PixardisVm *vm, uintptr_t *width, uintptr_t *height);
+
+// Latch input per frame: pixels written while a frame is in progress (after
+// a step ran out of cycles before reaching a delay) take effect at the
+// program's next delay, so a frame never sees its input change. Off by default.
+//
+// # Safety
+// `vm` must be null or a live handle.
+int32_t pixardis_vm_set_input_latch(PixardisVm *vm, bool enabled);
+
+// Inject input by writing a pixel; programs read it back with __read.
+//
+// # Safety
+// `vm` must be null or a live handle.
+int32_t pixardis_vm_write_pixel(PixardisVm *vm, uintptr_t x, uintptr_t y, uint64_t colour);
+
+// Record the pixels written with pixardis_vm_write_pixel and the cycle each
+// was written at, for pixardis_vm_replay_input to reproduce the run (with
+// the same seed). Enabling starts an empty recording, as does loading a
+// program. Off by default.
+//
+// # Safety
+// `vm` must be null or a live handle.
+int32_t pixardis_vm_record_input(PixardisVm *vm, bool enabled);
+
+// Copy up to `capacity` recorded input events into `events` and set `count`
+// to the number recorded, which may be more; call with a capacity of zero
+// to size the buffer.
+//
+// # Safety
+// `vm` must be null or a live handle; `events` must be null or point to
+// `capacity` writable events; `count` must be null or writable.
+int32_t pixardis_vm_recorded_input(const PixardisVm *vm,
+                                   PixardisInputEvent *events,
+                                   uintptr_t capacity,
+                                   uintptr_t *count);
+
+// Replay recorded input: each pixel is written, as pixardis_vm_write_pixel
+// would, when the program reaches its cycle. Call after loading the
+// program; the events must be in cycle order.
+//
+// # Safety
+// `vm` must be null or a live handle; `events` must be null or point to
+// `count` events.
+int32_t pixardis_vm_replay_input(PixardisVm *vm, const PixardisInputEvent *events, uintptr_t count);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif // PIXARDIS_H
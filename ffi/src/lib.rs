@@ -0,0 +1,282 @@
+// lib.rs
+//
+// C-compatible interface to the Pixardis virtual machine, for hosts that
+// aren't written in Rust (visualisers, firmware simulators, test rigs).
+// The matching header is include/pixardis.h (see cbindgen.toml).
+//
+// All functions taking a PixardisVm pointer expect one returned by
+// pixardis_vm_create that hasn't been destroyed yet; null is rejected with
+// PIXARDIS_INVALID_ARGUMENT rather than dereferenced.
+//
+
+use std::ffi::{c_char, CStr};
+
+use vm::machine::architecture::VirtualMachineError;
+use vm::pixardis::input::PixardisInputEvent as InputEvent;
+use vm::pixardis::pixardis::{PixardisLogLevel, PixardisVirtualMachine};
+
+/// The call succeeded; the VM ran out of cycles without reaching a delay
+pub const PIXARDIS_OK: i32 = 0;
+/// The program issued a delay; this is the natural frame boundary
+pub const PIXARDIS_DELAYED: i32 = 1;
+/// The program executed a halt instruction
+pub const PIXARDIS_HALTED: i32 = 2;
+/// A null pointer, invalid UTF-8 or out-of-range argument was passed
+pub const PIXARDIS_INVALID_ARGUMENT: i32 = -1;
+/// The program raised a runtime error; the VM is stopped
+pub const PIXARDIS_RUNTIME_ERROR: i32 = -2;
+
+/// Opaque handle to a virtual machine instance
+pub struct PixardisVm {
+    machine: PixardisVirtualMachine,
+}
+
+/// A pixel the host wrote with pixardis_vm_write_pixel, and the number of
+/// instructions the program had executed when it did
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PixardisInputEvent {
+    pub cycle: u64,
+    pub x: usize,
+    pub y: usize,
+    pub colour: u64,
+}
+
+/// Create a VM with a display of the given size. Returns null if either
+/// dimension is zero. Release with pixardis_vm_destroy.
+#[no_mangle]
+pub extern "C" fn pixardis_vm_create(width: usize, height: usize) -> *mut PixardisVm {
+    if width == 0 || height == 0 {
+        return std::ptr::null_mut();
+    }
+
+    let mut machine = PixardisVirtualMachine::new(width, height);
+    machine.log_level_set(PixardisLogLevel::None);
+
+    // Errors (and halt) must come back to the host, not end its process
+    machine.exit_on_error_set(false);
+
+    Box::into_raw(Box::new(PixardisVm { machine }))
+}
+
+/// Destroy a VM created by pixardis_vm_create. Passing null is a no-op.
+///
+/// # Safety
+/// `vm` must be null or a live handle; it must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn pixardis_vm_destroy(vm: *mut PixardisVm) {
+    if !vm.is_null() {
+        drop(Box::from_raw(vm));
+    }
+}
+
+/// Load a program from NUL-terminated Pixardis assembly text.
+///
+/// # Safety
+/// `vm` must be null or a live handle; `source` must be null or point to a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn pixardis_vm_load_program(vm: *mut PixardisVm, source: *const c_char) -> i32 {
+    let (Some(vm), false) = (vm.as_mut(), source.is_null()) else {
+        return PIXARDIS_INVALID_ARGUMENT;
+    };
+
+    match CStr::from_ptr(source).to_str() {
+        Ok(source) => {
+            vm.machine.load_program_from_source(source);
+            PIXARDIS_OK
+        },
+        Err(_) => PIXARDIS_INVALID_ARGUMENT,
+    }
+}
+
+/// Seed the VM's random number generator, for reproducible runs.
+///
+/// # Safety
+/// `vm` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn pixardis_vm_seed(vm: *mut PixardisVm, seed: u64) -> i32 {
+    match vm.as_mut() {
+        Some(vm) => {
+            vm.machine.random_seed_set(seed);
+            PIXARDIS_OK
+        },
+        None => PIXARDIS_INVALID_ARGUMENT,
+    }
+}
+
+/// Run one program frame: execute up to (and including) the next delay, or
+/// at most `max_cycles` instructions. A pending delay is skipped, so the host
+/// controls timing. The number of instructions executed is written to
+/// `cycles` when it isn't null.
+///
+/// # Safety
+/// `vm` must be null or a live handle; `cycles` must be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn pixardis_vm_step(vm: *mut PixardisVm, max_cycles: usize, cycles: *mut usize) -> i32 {
+    let Some(vm) = vm.as_mut() else {
+        return PIXARDIS_INVALID_ARGUMENT;
+    };
+
+    let (executed, status) = match vm.machine.run_frame(max_cycles) {
+        Ok(executed) if vm.machine.delay_requested().is_some() => (executed, PIXARDIS_DELAYED),
+        Ok(executed) => (executed, PIXARDIS_OK),
+        Err(VirtualMachineError::TrapHalt) => (0, PIXARDIS_HALTED),
+        Err(_) => (0, PIXARDIS_RUNTIME_ERROR),
+    };
+
+    if let Some(cycles) = cycles.as_mut() {
+        *cycles = executed;
+    }
+
+    status
+}
+
+/// Delay (in milliseconds) requested by the last delay instruction, or a
+/// negative value if the VM isn't delayed.
+///
+/// # Safety
+/// `vm` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn pixardis_vm_delay_ms(vm: *const PixardisVm) -> f64 {
+    vm.as_ref()
+        .and_then(|vm| vm.machine.delay_requested())
+        .unwrap_or(-1.0)
+}
+
+/// Pointer to the framebuffer: width * height colours (0xRRGGBB), row-major.
+/// The dimensions are written to `width` and `height` when they aren't null.
+/// The pointer stays valid until the next call that mutates the VM.
+///
+/// # Safety
+/// `vm` must be null or a live handle; `width` and `height` must be null or
+/// writable.
+#[no_mangle]
+pub unsafe extern "C" fn pixardis_vm_framebuffer(vm: *const PixardisVm, width: *mut usize, height: *mut usize) -> *const u64 {
+    let Some(vm) = vm.as_ref() else {
+        return std::ptr::null();
+    };
+
+    let (w, h, framebuffer) = vm.machine.framebuffer();
+
+    if let Some(width) = width.as_mut() {
+        *width = w;
+    }
+
+    if let Some(height) = height.as_mut() {
+        *height = h;
+    }
+
+    framebuffer.as_ptr()
+}
+
+/// Latch input per frame: pixels written while a frame is in progress (after
+/// a step ran out of cycles before reaching a delay) take effect at the
+/// program's next delay, so a frame never sees its input change. Off by default.
+///
+/// # Safety
+/// `vm` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn pixardis_vm_set_input_latch(vm: *mut PixardisVm, enabled: bool) -> i32 {
+    match vm.as_mut() {
+        Some(vm) => {
+            vm.machine.input_latch_set(enabled);
+            PIXARDIS_OK
+        },
+        None => PIXARDIS_INVALID_ARGUMENT,
+    }
+}
+
+/// Inject input by writing a pixel; programs read it back with __read.
+///
+/// # Safety
+/// `vm` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn pixardis_vm_write_pixel(vm: *mut PixardisVm, x: usize, y: usize, colour: u64) -> i32 {
+    let Some(vm) = vm.as_mut() else {
+        return PIXARDIS_INVALID_ARGUMENT;
+    };
+
+    match vm.machine.framebuffer_write(x, y, colour) {
+        Ok(()) => PIXARDIS_OK,
+        Err(_) => PIXARDIS_INVALID_ARGUMENT,
+    }
+}
+
+/// Record the pixels written with pixardis_vm_write_pixel and the cycle each
+/// was written at, for pixardis_vm_replay_input to reproduce the run (with
+/// the same seed). Enabling starts an empty recording, as does loading a
+/// program. Off by default.
+///
+/// # Safety
+/// `vm` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn pixardis_vm_record_input(vm: *mut PixardisVm, enabled: bool) -> i32 {
+    match vm.as_mut() {
+        Some(vm) => {
+            vm.machine.input_record_set(enabled);
+            PIXARDIS_OK
+        },
+        None => PIXARDIS_INVALID_ARGUMENT,
+    }
+}
+
+/// Copy up to `capacity` recorded input events into `events` and set `count`
+/// to the number recorded, which may be more; call with a capacity of zero
+/// to size the buffer.
+///
+/// # Safety
+/// `vm` must be null or a live handle; `events` must be null or point to
+/// `capacity` writable events; `count` must be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn pixardis_vm_recorded_input(vm: *const PixardisVm, events: *mut PixardisInputEvent, capacity: usize, count: *mut usize) -> i32 {
+    let Some(vm) = vm.as_ref() else {
+        return PIXARDIS_INVALID_ARGUMENT;
+    };
+
+    if events.is_null() && capacity > 0 {
+        return PIXARDIS_INVALID_ARGUMENT;
+    }
+
+    let recording = vm.machine.input_recording();
+
+    for (index, event) in recording.iter().take(capacity).enumerate() {
+        *events.add(index) = PixardisInputEvent { cycle: event.cycle, x: event.x, y: event.y, colour: event.colour };
+    }
+
+    if let Some(count) = count.as_mut() {
+        *count = recording.len();
+    }
+
+    PIXARDIS_OK
+}
+
+/// Replay recorded input: each pixel is written, as pixardis_vm_write_pixel
+/// would, when the program reaches its cycle. Call after loading the
+/// program; the events must be in cycle order.
+///
+/// # Safety
+/// `vm` must be null or a live handle; `events` must be null or point to
+/// `count` events.
+#[no_mangle]
+pub unsafe extern "C" fn pixardis_vm_replay_input(vm: *mut PixardisVm, events: *const PixardisInputEvent, count: usize) -> i32 {
+    let Some(vm) = vm.as_mut() else {
+        return PIXARDIS_INVALID_ARGUMENT;
+    };
+
+    let events = match (events.is_null(), count) {
+        (_, 0) => &[][..],
+        (true, _) => return PIXARDIS_INVALID_ARGUMENT,
+        (false, _) => std::slice::from_raw_parts(events, count),
+    };
+
+    if events.windows(2).any(|pair| pair[0].cycle > pair[1].cycle) {
+        return PIXARDIS_INVALID_ARGUMENT;
+    }
+
+    vm.machine.input_replay_set(events.iter()
+        .map(|event| InputEvent { cycle: event.cycle, x: event.x, y: event.y, colour: event.colour })
+        .collect());
+
+    PIXARDIS_OK
+}
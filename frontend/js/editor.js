@@ -11,7 +11,7 @@ export async function initializeEditor() {
                 // Define syntax highlighting
                 monaco.languages.setMonarchTokensProvider('pixardis', {
                     keywords: [
-                        'fun', 'let', 'if', 'else', 'while', 'for', 'return', 'as',
+                        'fun', 'let', 'if', 'else', 'while', 'for', 'return', 'as', 'asm',
                         'true', 'false'
                     ],
                     
@@ -20,8 +20,9 @@ export async function initializeEditor() {
                     ],
 
                     builtinFunctions: [
-                        '__clear', '__read', '__write', '__write_box', '__write_line', '__random_int', 
-                        '__width', '__height', '__print', '__pixel', '__delay'
+                        '__clear', '__read', '__write', '__write_box', '__write_line', '__write_box_gradient',
+                        '__write_box_pattern', '__write_triangle', '__hit_test', '__add_colour', '__sub_colour', '__hsv', '__noise', '__param', '__load', '__random_int', '__random_float', '__random_range', 
+                        '__width', '__height', '__capabilities', '__print', '__printf', '__pixel', '__delay', '__brightness', '__store', '__assert'
                     ],
                     
                     operators: [
@@ -3,6 +3,8 @@ import { getEditor } from './editor.js';  // Import to access Monaco editor
 export function showCompilerErrors(errorText) {
     const errorPanel = document.getElementById('error-panel');
     const errorContent = document.getElementById('error-content');
+
+    setPanelTitle('❌ Compilation Errors');
     
     // Parse the error text and create formatted error messages
     const errors = parseCompilerErrors(errorText);
@@ -67,6 +69,46 @@ function jumpToLine(lineNumber) {
     }
 }
 
+// Warnings from a successful compile, as the compile result's warnings
+// array of { severity, line, message } (line 1-based, or null)
+export function showCompilerWarnings(warnings) {
+    const errorPanel = document.getElementById('error-panel');
+    const errorContent = document.getElementById('error-content');
+
+    setPanelTitle('⚠️ Compilation Warnings');
+    errorContent.innerHTML = '';
+
+    warnings.forEach(warning => {
+        // serde_wasm_bindgen gives objects as Maps
+        const field = (name) => warning.get ? warning.get(name) : warning[name];
+        const line = field('line');
+
+        const warningDiv = document.createElement('div');
+        warningDiv.className = 'error-message warning-message';
+        warningDiv.innerHTML = `
+            <div class="error-location">${line ? `Line ${line}` : 'General warning'}</div>
+            <div class="error-description">${field('message')}</div>
+        `;
+
+        if (line) {
+            warningDiv.classList.add('clickable');
+            warningDiv.addEventListener('click', () => jumpToLine(line));
+            warningDiv.style.cursor = 'pointer';
+        }
+
+        errorContent.appendChild(warningDiv);
+    });
+
+    errorPanel.style.display = 'flex';
+}
+
+function setPanelTitle(title) {
+    const header = document.querySelector('#error-panel .error-header span');
+    if (header) {
+        header.textContent = title;
+    }
+}
+
 export function hideCompilerErrors() {
     const errorPanel = document.getElementById('error-panel');
     errorPanel.style.display = 'none';
@@ -9,6 +9,7 @@ import {
     pauseVM,
     resumeVM,
     stepVM,
+    stepBackVM,
     setCyclesPerFrame,
     getVMRunningState
 } from './vm.js';
@@ -80,6 +81,7 @@ export function setupEventHandlers() {
     });
 
     document.getElementById('step-btn')?.addEventListener('click', stepVM);
+    document.getElementById('step-back-btn')?.addEventListener('click', stepBackVM);
 
     document.getElementById('cycles-slider')?.addEventListener('input', (e) => {
         setCyclesPerFrame(parseInt(e.target.value));
@@ -14,7 +14,7 @@ import {
     load_vm_program 
 } from '../pkg/web.js';
 
-import { showCompilerErrors, hideCompilerErrors } from './errors.js'; 
+import { showCompilerErrors, showCompilerWarnings, hideCompilerErrors } from './errors.js'; 
 
 let vm = null;
 let animationId = null;
@@ -25,6 +25,9 @@ let ctx = null;
 
 let isRunning = false;
 let cyclesPerFrame = 1000;
+let resumeAt = 0; // performance.now() timestamp at which a delayed VM wakes up
+let watchdogCycles = 50_000_000; // cycles without visible output before warning
+let historyInterval = 1000; // instructions between step-back checkpoints
 let performanceStats = {
     lastTime: 0,
     frameCount: 0,
@@ -33,6 +36,21 @@ let performanceStats = {
     totalCycles: 0
 };
 
+// Values programs keep with __store live in localStorage under a prefix, so
+// high scores survive reloads without clashing with the editor's own keys
+const STORAGE_PREFIX = 'pixardis_kv_';
+const programStorage = {
+    load: (key) => localStorage.getItem(STORAGE_PREFIX + key) ?? undefined,
+    store: (key, value) => {
+        try {
+            localStorage.setItem(STORAGE_PREFIX + key, value);
+        } catch (error) {
+            console.warn("Couldn't save program storage:", error);
+        }
+    },
+    count: () => Object.keys(localStorage).filter((key) => key.startsWith(STORAGE_PREFIX)).length,
+};
+
 // Add this helper function
 export function getVMRunningState() {
     return isRunning;
@@ -41,6 +59,9 @@ export function getVMRunningState() {
 export async function initializeVM() {
     // Initialize VM with default size
     vm = create_vm(vmWidth, vmHeight);
+    vm.set_watchdog?.(watchdogCycles);
+    vm.enable_history?.(historyInterval);
+    vm.set_storage?.(programStorage);
     
     // Get canvas elements
     canvas = document.getElementById('vm-canvas');
@@ -76,6 +97,7 @@ export function compileAndRun(sourceCode) {
         const success = result.get('success');
         const assembly = result.get('assembly');
         const errors = result.get('errors');
+        const warnings = result.get('warnings') ?? [];
         
         console.log("Success:", success);
         console.log("Assembly:", assembly);
@@ -92,10 +114,41 @@ export function compileAndRun(sourceCode) {
             const assemblyString = typeof assembly === 'string' ? assembly : 
                                  (assembly && assembly.get ? assembly.get('assembly') || String(assembly) : String(assembly));
             
-            load_vm_program(vm, assemblyString);
+            // The VM refuses programs over its load limits
+            if (load_vm_program(vm, assemblyString) === false) {
+                const report = vm.get_load_report?.();
+                const rejection = report?.get ? report.get('rejection') : report?.rejection;
+                statusBar.textContent = `❌ Program not loaded: ${rejection}`;
+                statusBar.className = "status-bar status-error";
+                return;
+            }
+
+            // Resize the display if the program declares its own size
+            // (#pragma width/height), then reload into the resized VM
+            let metadata = vm.get_metadata?.();
+            const field = (name) => metadata?.get ? metadata.get(name) : metadata?.[name];
+
+            const preferredWidth = field('width') ?? vmWidth;
+            const preferredHeight = field('height') ?? vmHeight;
+
+            if (preferredWidth !== vmWidth || preferredHeight !== vmHeight) {
+                resizeVM(preferredWidth, preferredHeight);
+                load_vm_program(vm, assemblyString);
+                metadata = vm.get_metadata?.();
+            }
+
+            // Show the program's title (#pragma title) if it declares one
+            const title = field('title');
+            const label = title ? `"${title}" ` : '';
             
-            statusBar.textContent = `✅ Compiled successfully! Running...`;
+            const warned = warnings.length ? ` with ${warnings.length} warning(s)` : '';
+            statusBar.textContent = `✅ Compiled ${label}successfully${warned}! Running...`;
             statusBar.className = "status-bar status-success";
+
+            // Non-fatal issues are shown, but don't stop the program running
+            if (warnings.length) {
+                showCompilerWarnings(warnings);
+            }
             
             startVMLoop();
         } else {
@@ -144,6 +197,12 @@ function startVMLoop() {
         
         try {
             const currentTime = performance.now();
+
+            // The VM is sleeping on a delay; skip stepping until it wakes up
+            if (currentTime < resumeAt) {
+                animationId = requestAnimationFrame(render);
+                return;
+            }
             
              // Execute VM cycles and check for errors
             const vmResult = step_vm(vm, cyclesPerFrame);
@@ -153,17 +212,31 @@ function startVMLoop() {
 
             const success = vmResult.get('success');
             const error = vmResult.get('error');
+            const waitMs = vmResult.get('wait_ms');
+
+            resumeAt = waitMs ? currentTime + waitMs : 0;
             
             // Check if VM encountered an error
             if (!success) {
-                console.error("VM Runtime Error:", error);
+                // Call chain, innermost first
+                const backtrace = (vmResult.get('backtrace') || [])
+                    .map(frame => `${frame.get('function') ?? '?'} @ [${frame.get('address')}]`)
+                    .join(' ← ');
+                console.error("VM Runtime Error:", error, backtrace);
                 const statusBar = document.getElementById('status-bar');
-                statusBar.textContent = `❌ VM Runtime Error: ${error || 'Unknown error'}`;
+                statusBar.textContent = `❌ VM Runtime Error: ${error || 'Unknown error'}${backtrace ? ` in ${backtrace}` : ''}`;
                 statusBar.className = "status-bar status-error";
                 pauseVM();
                 return;
             }
 
+            // Warn if the program has stopped producing output
+            if (vmResult.get('stuck')) {
+                const statusBar = document.getElementById('status-bar');
+                statusBar.textContent = "⚠️ Program appears stuck (no write, print or delay for a long time)";
+                statusBar.className = "status-bar status-error";
+            }
+
             // Get framebuffer data
             const framebuffer = get_vm_framebuffer(vm);
             
@@ -226,6 +299,9 @@ export function resizeVM(newWidth, newHeight) {
     
     // Create new VM
     vm = create_vm(vmWidth, vmHeight);
+    vm.set_watchdog?.(watchdogCycles);
+    vm.enable_history?.(historyInterval);
+    vm.set_storage?.(programStorage);
     
     // Resize canvas
     canvas.width = vmWidth * 10;
@@ -250,6 +326,9 @@ export function resizeVM(newWidth, newHeight) {
 
 export function resetVM() {
     vm = create_vm(vmWidth, vmHeight);
+    vm.set_watchdog?.(watchdogCycles);
+    vm.enable_history?.(historyInterval);
+    vm.set_storage?.(programStorage);
     ctx.clearRect(0, 0, canvas.width, canvas.height);
     ctx.imageSmoothingEnabled = false;    
     const statusBar = document.getElementById('status-bar');
@@ -291,35 +370,59 @@ export function stepVM() {
             }
 
             // Render single frame
-            const framebuffer = get_vm_framebuffer(vm);
-            const imageData = ctx.createImageData(vmWidth, vmHeight);
-            
-            for (let i = 0; i < framebuffer.length; i += 3) {
-                const pixelIndex = i / 3;
-                const x = pixelIndex % vmWidth;
-                const y = Math.floor(pixelIndex / vmWidth);
-                const flippedY = (vmHeight - 1) - y;
-                const flippedIndex = (flippedY * vmWidth + x) * 4;
-                
-                imageData.data[flippedIndex] = framebuffer[i];
-                imageData.data[flippedIndex + 1] = framebuffer[i + 1];
-                imageData.data[flippedIndex + 2] = framebuffer[i + 2];
-                imageData.data[flippedIndex + 3] = 255;
-            }
-            
-            const tempCanvas = document.createElement('canvas');
-            tempCanvas.width = vmWidth;
-            tempCanvas.height = vmHeight;
-            const tempCtx = tempCanvas.getContext('2d');
-            tempCtx.putImageData(imageData, 0, 0);
-            
-            ctx.drawImage(tempCanvas, 0, 0, vmWidth * 10, vmHeight * 10);
+            drawFramebuffer();
         } catch (error) {
             console.error("VM step error:", error);
         }
     }
 }
 
+// Rewind one instruction using the VM's checkpoint history
+export function stepBackVM() {
+    if (!isRunning) {
+        try {
+            const vmResult = vm.step_back(1);
+            const success = vmResult.get('success');
+            const error = vmResult.get('error');
+
+            if (!success) {
+                console.error("VM Step Back Error:", error);
+                return;
+            }
+
+            drawFramebuffer();
+        } catch (error) {
+            console.error("VM step back error:", error);
+        }
+    }
+}
+
+function drawFramebuffer() {
+    const framebuffer = get_vm_framebuffer(vm);
+    const imageData = ctx.createImageData(vmWidth, vmHeight);
+    
+    for (let i = 0; i < framebuffer.length; i += 3) {
+        const pixelIndex = i / 3;
+        const x = pixelIndex % vmWidth;
+        const y = Math.floor(pixelIndex / vmWidth);
+        const flippedY = (vmHeight - 1) - y;
+        const flippedIndex = (flippedY * vmWidth + x) * 4;
+        
+        imageData.data[flippedIndex] = framebuffer[i];
+        imageData.data[flippedIndex + 1] = framebuffer[i + 1];
+        imageData.data[flippedIndex + 2] = framebuffer[i + 2];
+        imageData.data[flippedIndex + 3] = 255;
+    }
+    
+    const tempCanvas = document.createElement('canvas');
+    tempCanvas.width = vmWidth;
+    tempCanvas.height = vmHeight;
+    const tempCtx = tempCanvas.getContext('2d');
+    tempCtx.putImageData(imageData, 0, 0);
+    
+    ctx.drawImage(tempCanvas, 0, 0, vmWidth * 10, vmHeight * 10);
+}
+
 export function setCyclesPerFrame(cycles) {
     cyclesPerFrame = Math.max(1, Math.min(5000, cycles));
     updateVMControls();
@@ -328,11 +431,13 @@ export function setCyclesPerFrame(cycles) {
 export function updateVMControls() {
     const pauseBtn = document.getElementById('pause-btn');
     const stepBtn = document.getElementById('step-btn');
+    const stepBackBtn = document.getElementById('step-back-btn');
     const cyclesSlider = document.getElementById('cycles-slider');
     const cyclesValue = document.getElementById('cycles-value');
     
     if (pauseBtn) pauseBtn.textContent = isRunning ? '⏸️ Pause' : '▶️ Resume';
     if (stepBtn) stepBtn.disabled = isRunning;
+    if (stepBackBtn) stepBackBtn.disabled = isRunning;
     if (cyclesSlider) cyclesSlider.value = cyclesPerFrame;
     if (cyclesValue) cyclesValue.textContent = cyclesPerFrame;
 }
@@ -343,6 +448,30 @@ export function updatePerformanceDisplay() {
     
     if (fpsDisplay) fpsDisplay.textContent = `FPS: ${performanceStats.fps}`;
     if (ipsDisplay) ipsDisplay.textContent = `IPS: ${performanceStats.ips.toLocaleString()}`;
+
+    // Most executed opcode and its share of all instructions
+    const hotDisplay = document.getElementById('hot-display');
+    const stats = vm?.get_stats?.();
+    if (hotDisplay && stats && stats.instructions > 0) {
+        const opcodes = stats.opcodes instanceof Map ? [...stats.opcodes] : Object.entries(stats.opcodes);
+        const [mnemonic, count] = opcodes.reduce((top, entry) => entry[1] > top[1] ? entry : top);
+        hotDisplay.textContent = `Top: ${mnemonic} ${Math.round(100 * Number(count) / Number(stats.instructions))}%`;
+    }
+
+    // Module memory, flagged once it's over half the most it can grow to
+    const memDisplay = document.getElementById('mem-display');
+    const memory = vm?.memory_stats?.();
+    if (memDisplay && memory) {
+        const field = (name) => memory instanceof Map ? memory.get(name) : memory[name];
+        const megabytes = (bytes) => (Number(bytes) / (1024 * 1024)).toFixed(1);
+        const nearLimit = Number(field('linear_memory')) > Number(field('linear_memory_limit')) / 2;
+
+        memDisplay.textContent = `Mem: ${megabytes(field('linear_memory'))} MB`;
+        memDisplay.classList.toggle('memory-warning', nearLimit);
+        memDisplay.title = `VM ${megabytes(field('vm_bytes'))} MB: frames ${megabytes(field('frame_bytes'))} MB, ` +
+            `print buffer ${megabytes(field('print_buffer_bytes'))} MB` +
+            (nearLimit ? ' (nearing the WebAssembly memory limit)' : '');
+    }
 }
 
 function updateConsoleOutput() {
@@ -371,6 +500,13 @@ function updateConsoleOutput() {
             // Auto-scroll to bottom
             consoleContent.scrollTop = consoleContent.scrollHeight;
         }
+
+        // Forward VM log output (if any categories are enabled) to the browser console
+        const log = vm.get_log_output?.();
+        if (log && log.length > 0) {
+            log.forEach(line => console.debug(line));
+            vm.clear_log_output();
+        }
     } catch (error) {
         console.error('Error updating console output:', error);
     }
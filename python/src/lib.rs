@@ -0,0 +1,194 @@
+// lib.rs
+//
+// Python bindings for the Pixardis compiler and virtual machine, e.g. for
+// driving the toolchain from Jupyter notebooks:
+//
+//     import pixardis
+//     vm = pixardis.PixardisVM(64, 48)
+//     vm.load(pixardis.compile(source))
+//     while vm.step() == "delayed": ...
+//
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+
+use shared::pixardis::pixardis_instruction_to_string;
+
+use vm::machine::architecture::VirtualMachineError;
+use vm::machine::observer::Observer;
+use vm::pixardis::input::{pixardis_input_from_string, pixardis_input_to_string};
+use vm::pixardis::pixardis::{PixardisLogLevel, PixardisVirtualMachine};
+
+use compiler::common::logger::Logger;
+
+//
+// Run the compiler pipeline, returning assembly text or the stage that failed
+//
+fn compile_with_logger<'a>(source: &'a str, logger: &Logger<'a>) -> Result<String, String> {
+    let assembly = compiler::compile_with_logger(source, logger, None)?
+        .into_iter()
+        .map(|(_, instruction)| format!("{}\n", pixardis_instruction_to_string(instruction)))
+        .collect();
+
+    Ok(assembly)
+}
+
+///
+/// Compile Pixardis source to assembly text; raises ValueError carrying the
+/// compiler's diagnostics on failure
+///
+#[pyfunction]
+fn compile(source: &str) -> PyResult<String> {
+    let logger = Logger::new_capturing(source);
+
+    compile_with_logger(source, &logger).map_err(|error| {
+        let messages = logger.captured_messages();
+        PyValueError::new_err(if messages.is_empty() { error } else { messages.join("\n") })
+    })
+}
+
+//
+// Collects program print output for Python
+//
+struct PrintCollector {
+    lines: Rc<RefCell<Vec<String>>>,
+}
+
+impl Observer for PrintCollector {
+    fn on_print(&mut self, text: &str) {
+        self.lines.borrow_mut().push(text.to_string());
+    }
+}
+
+///
+/// A Pixardis virtual machine with its own display
+///
+#[pyclass(unsendable)]
+struct PixardisVM {
+    machine: PixardisVirtualMachine,
+    prints: Rc<RefCell<Vec<String>>>,
+}
+
+#[pymethods]
+impl PixardisVM {
+    #[new]
+    #[pyo3(signature = (width = 64, height = 48))]
+    fn new(width: usize, height: usize) -> PyResult<Self> {
+        if width == 0 || height == 0 {
+            return Err(PyValueError::new_err("display dimensions must be positive"));
+        }
+
+        let mut machine = PixardisVirtualMachine::new(width, height);
+        machine.log_level_set(PixardisLogLevel::None);
+
+        // A runtime error must raise in Python rather than end the interpreter
+        machine.exit_on_error_set(false);
+
+        let prints = Rc::new(RefCell::new(Vec::new()));
+        machine.observer_add(Box::new(PrintCollector { lines: prints.clone() }));
+
+        Ok(PixardisVM { machine, prints })
+    }
+
+    /// Load assembly text (as returned by compile)
+    fn load(&mut self, assembly: &str) {
+        self.machine.load_program_from_source(assembly);
+    }
+
+    /// Compile Pixardis source and load the result
+    fn load_source(&mut self, source: &str) -> PyResult<()> {
+        let assembly = compile(source)?;
+        self.machine.load_program_from_source(&assembly);
+        Ok(())
+    }
+
+    /// Seed the random number generator, for reproducible runs
+    fn seed(&mut self, seed: u64) {
+        self.machine.random_seed_set(seed);
+    }
+
+    /// Run one program frame (up to and including the next delay, or at most
+    /// max_cycles instructions). Returns "delayed", "running" or "halted";
+    /// raises RuntimeError if the program fails.
+    #[pyo3(signature = (max_cycles = 100_000))]
+    fn step(&mut self, max_cycles: usize) -> PyResult<&'static str> {
+        match self.machine.run_frame(max_cycles) {
+            Ok(_) if self.machine.delay_requested().is_some() => Ok("delayed"),
+            Ok(_) => Ok("running"),
+            Err(VirtualMachineError::TrapHalt) => Ok("halted"),
+            Err(error) => Err(PyRuntimeError::new_err(format!("{:?}", error))),
+        }
+    }
+
+    /// Display width and height
+    fn size(&self) -> (usize, usize) {
+        let (width, height, _) = self.machine.framebuffer();
+        (width, height)
+    }
+
+    /// Framebuffer as row-major 0xRRGGBB integers
+    fn framebuffer(&self) -> Vec<u64> {
+        let (_, _, framebuffer) = self.machine.framebuffer();
+        framebuffer.clone()
+    }
+
+    /// Framebuffer as packed RGB bytes (e.g. for PIL.Image.frombytes("RGB", size, data))
+    fn framebuffer_rgb(&self) -> Cow<'static, [u8]> {
+        let (_, _, framebuffer) = self.machine.framebuffer();
+
+        Cow::Owned(framebuffer.iter()
+            .flat_map(|colour| [(colour >> 16) as u8, (colour >> 8) as u8, *colour as u8])
+            .collect())
+    }
+
+    /// Hold back pixels written mid-frame until the program's next delay
+    fn set_input_latch(&mut self, enabled: bool) {
+        self.machine.input_latch_set(enabled);
+    }
+
+    /// Write a pixel; programs read it back with __read
+    fn write_pixel(&mut self, x: usize, y: usize, colour: u64) -> PyResult<()> {
+        self.machine.framebuffer_write(x, y, colour)
+            .map_err(|_| PyValueError::new_err("pixel coordinates out of range"))
+    }
+
+    /// Record the pixels written with write_pixel and the cycle each was
+    /// written at; loading a program starts a new recording
+    fn record_input(&mut self, enabled: bool) {
+        self.machine.input_record_set(enabled);
+    }
+
+    /// Recorded input as "cycle x y #colour" lines, for replay_input or
+    /// chroma-run --replay-input
+    fn recorded_input(&self) -> String {
+        pixardis_input_to_string(self.machine.input_recording())
+    }
+
+    /// Replay recorded input, writing each pixel when the program reaches its
+    /// cycle; call after load
+    fn replay_input(&mut self, recording: &str) -> PyResult<()> {
+        let events = pixardis_input_from_string(recording).map_err(PyValueError::new_err)?;
+        self.machine.input_replay_set(events);
+        Ok(())
+    }
+
+    /// Lines printed by the program since the last clear_print_output
+    fn print_output(&self) -> Vec<String> {
+        self.prints.borrow().clone()
+    }
+
+    fn clear_print_output(&mut self) {
+        self.prints.borrow_mut().clear();
+    }
+}
+
+#[pymodule]
+fn pixardis(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(compile, module)?)?;
+    module.add_class::<PixardisVM>()?;
+    Ok(())
+}
@@ -0,0 +1,62 @@
+//
+// Proptest strategies for well-formed Pixardis instructions: ones whose
+// operands the assembler can read back, so that every generated program
+// passes roundtrip_check
+//
+use alloc::{string::String, vec::Vec};
+
+use proptest::collection;
+use proptest::prelude::*;
+use proptest::sample;
+
+use crate::pixardis::{
+    PixardisInstruction,
+    PIXARDIS_BINARY_MNEMONICS,
+    pixardis_mnemonic_to_instruction,
+};
+
+// Label or function name as the assembler accepts it
+pub fn label() -> impl Strategy<Value = String> {
+    "[a-zA-Z][a-zA-Z0-9_]{0,15}"
+}
+
+// Immediate operand: an integer or a real
+pub fn immediate() -> impl Strategy<Value = String> {
+    prop_oneof![
+        any::<i64>().prop_map(|value| value.to_string()),
+        "-?[0-9]{1,9}\\.[0-9]{1,6}",
+    ]
+}
+
+// Frame index or offset (the assembler reads them without a sign)
+fn index() -> impl Strategy<Value = i64> {
+    0..=i64::MAX
+}
+
+pub fn instruction() -> impl Strategy<Value = PixardisInstruction> {
+    prop_oneof![
+        sample::select(PIXARDIS_BINARY_MNEMONICS)
+            .prop_map(|mnemonic| pixardis_mnemonic_to_instruction(mnemonic).unwrap()),
+        label().prop_map(|label| PixardisInstruction::Label(label.into())),
+        ("[a-z][a-z0-9_]{0,11}", "([a-zA-Z0-9_]([a-zA-Z0-9_ ]{0,30}[a-zA-Z0-9_])?)?")
+            .prop_map(|(key, value)| PixardisInstruction::Metadata(key, value)),
+        (label(), 0..=u32::MAX as usize).prop_map(|(name, size)| PixardisInstruction::Signature(name.into(), size)),
+        immediate().prop_map(|value| PixardisInstruction::PushImmediate(value.into())),
+        (0..=0xffffffu32).prop_map(PixardisInstruction::PushColour),
+        label().prop_map(|label| PixardisInstruction::PushLabel(label.into())),
+        any::<i64>().prop_map(PixardisInstruction::PushOffset),
+        (index(), index()).prop_map(|(offset, frame)| PixardisInstruction::PushIndexed([offset, frame])),
+        (index(), index()).prop_map(|(offset, frame)| PixardisInstruction::PushIndexedOffset([offset, frame])),
+        (index(), index()).prop_map(|(offset, frame)| PixardisInstruction::PushArray([offset, frame])),
+        (index(), index(), any::<i64>())
+            .prop_map(|(offset, frame, count)| PixardisInstruction::PushArrayCount([offset, frame, count])),
+        label().prop_map(PixardisInstruction::Param),
+        label().prop_map(PixardisInstruction::LoadKV),
+        label().prop_map(PixardisInstruction::StoreKV),
+    ]
+}
+
+// Programs of up to max_length instructions
+pub fn program(max_length: usize) -> impl Strategy<Value = Vec<PixardisInstruction>> {
+    collection::vec(instruction(), 0..=max_length)
+}
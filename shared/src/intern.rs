@@ -0,0 +1,183 @@
+use alloc::{
+    collections::BTreeSet,
+    rc::Rc,
+    string::String,
+};
+
+use core::{
+    cell::RefCell,
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+    ops::Deref,
+};
+
+//
+// An interned identifier or label. Cloning one shares its text rather than
+// copying it, and atoms from the same interner compare by address; atoms
+// made separately still compare (and hash, and order) by their text, so
+// they mix freely with strings.
+//
+#[derive(Clone)]
+pub struct Atom(Rc<str>);
+
+impl Atom {
+    // An atom outside any interner (e.g. a label read from assembly)
+    pub fn new(text: &str) -> Self {
+        Atom(Rc::from(text))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    // True if both atoms share their text, as atoms interned together do
+    pub fn ptr_eq(&self, other: &Atom) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Deref for Atom {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Atom {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl core::borrow::Borrow<str> for Atom {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Atom {
+    fn eq(&self, other: &Atom) -> bool {
+        self.ptr_eq(other) || self.0 == other.0
+    }
+}
+
+impl Eq for Atom {}
+
+impl PartialEq<str> for Atom {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Atom {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl PartialEq<String> for Atom {
+    fn eq(&self, other: &String) -> bool {
+        *self.0 == **other
+    }
+}
+
+impl PartialOrd for Atom {
+    fn partial_cmp(&self, other: &Atom) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Atom {
+    fn cmp(&self, other: &Atom) -> Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+impl Hash for Atom {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
+impl fmt::Debug for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl fmt::Display for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&*self.0, f)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Atom {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl From<&str> for Atom {
+    fn from(text: &str) -> Self {
+        Atom::new(text)
+    }
+}
+
+impl From<String> for Atom {
+    fn from(text: String) -> Self {
+        Atom(Rc::from(text))
+    }
+}
+
+impl From<&String> for Atom {
+    fn from(text: &String) -> Self {
+        Atom::new(text)
+    }
+}
+
+//
+// Table of atoms: interning the same text twice returns the same atom, so a
+// name used throughout a program is allocated once. Clones of an interner
+// share its table, letting each stage of a pipeline add to the one the
+// stage before it filled.
+//
+#[derive(Clone, Default)]
+pub struct Interner {
+    atoms: Rc<RefCell<BTreeSet<Atom>>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&self, text: &str) -> Atom {
+        let mut atoms = self.atoms.borrow_mut();
+
+        if let Some(atom) = atoms.get(text) {
+            return atom.clone();
+        }
+
+        let atom = Atom::new(text);
+        atoms.insert(atom.clone());
+        atom
+    }
+
+    // Number of distinct atoms interned
+    pub fn len(&self) -> usize {
+        self.atoms.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.atoms.borrow().is_empty()
+    }
+}
+
+impl fmt::Debug for Interner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Interner").field("atoms", &self.len()).finish()
+    }
+}
@@ -1,5 +1,13 @@
 // lib.rs
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 
 // Declare and define the additional modules
+pub mod intern;
+#[cfg(feature = "std")]
 pub mod io;
-pub mod pixardis;
\ No newline at end of file
+pub mod pixardis;
+// Also built for the crate's own tests when they cover every instruction
+#[cfg(any(feature = "proptest", all(test, feature = "std", feature = "draw-ext", feature = "input-ext", feature = "audio-ext")))]
+pub mod arbitrary;
\ No newline at end of file
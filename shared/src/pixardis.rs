@@ -1,15 +1,32 @@
+use alloc::{borrow::Cow, format, string::{String, ToString}, vec::Vec};
+
+use crate::intern::{Atom, Interner};
+
+#[cfg(feature = "std")]
 use std::io::Write;
+#[cfg(feature = "std")]
 use regex::Regex;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum PixardisInstruction {
-    Label(String),
-    PushImmediate(String),
-    PushLabel(String),
+    Label(Atom),
+    // Program metadata directive (.meta key "value"); executes as a no-op
+    Metadata(String, String),
+    // Function signature directive (.sig name size): the number of argument
+    // values a call to the function passes; executes as a no-op
+    Signature(Atom, usize),
+    // Integer or real immediate, as written; small integers borrow their text
+    // from PIXARDIS_SMALL_INTEGERS rather than allocating it
+    PushImmediate(Cow<'static, str>),
+    // Colour immediate (0xrrggbb), written #rrggbb
+    PushColour(u32),
+    PushLabel(Atom),
     PushOffset(i64),
     PushIndexed([i64; 2]),
     PushIndexedOffset([i64; 2]),
     PushArray([i64; 2]),
+    // pusha with the element count as an operand rather than on the stack
+    PushArrayCount([i64; 3]),
     Store,
     StoreArray,
     Nop,
@@ -19,6 +36,11 @@ pub enum PixardisInstruction {
     Not,
     Add,
     Subtract,
+    // Per-channel saturating colour arithmetic
+    #[cfg(feature = "draw-ext")]
+    AddColour,
+    #[cfg(feature = "draw-ext")]
+    SubtractColour,
     Multiply,
     Divide,
     Modulo,
@@ -27,6 +49,12 @@ pub enum PixardisInstruction {
     Maximum,
     Minimum,
     RandomInt,
+    // Random real in [0, 1), and random value in [lo, hi) (lo on top)
+    RandomFloat,
+    RandomRange,
+    // Smooth gradient noise in [0, 1] at (x, y, t), x on top
+    #[cfg(feature = "draw-ext")]
+    Noise,
     LessThan,
     LessEqual,
     GreaterThan,
@@ -45,72 +73,257 @@ pub enum PixardisInstruction {
     Write,
     WriteBox,
     WriteLine,
+    // Box filled with a gradient or a dithered blend of two colours
+    #[cfg(feature = "draw-ext")]
+    WriteBoxGradient,
+    #[cfg(feature = "draw-ext")]
+    WriteBoxPattern,
+    // Filled triangle
+    #[cfg(feature = "draw-ext")]
+    WriteTriangle,
     Read,
+    // True if any pixel in a box matches a colour mask
+    #[cfg(feature = "input-ext")]
+    HitTest,
+    // Colour from hue (degrees), saturation and value (0-255), and back
+    #[cfg(feature = "draw-ext")]
+    HsvToRgb,
+    #[cfg(feature = "draw-ext")]
+    RgbToHsv,
     Clear,
+    // Display brightness (0-255) applied when the framebuffer is shown
+    #[cfg(feature = "draw-ext")]
+    SetBrightness,
     Width,
     Height,
     Print,
     PrintArray,
+    // Print with the number of decimal places taken from the stack
+    PrintFormatted,
+    // As Print and PrintFormatted, for a real lowered to a fixed-point
+    // integer: the number of fractional bits is below the value
+    PrintFixed,
+    PrintFormattedFixed,
+    // Bit set of the instruction set extensions the VM was built with (see
+    // PIXARDIS_CAPABILITY_*)
+    Capabilities,
+    // Stop with an error unless the condition on top of the stack is true
+    Assert,
+    // Host parameter (param name): replaces the default value on top of the
+    // stack with the value the host set for name, if any
+    #[cfg(feature = "input-ext")]
+    Param(String),
+    // Persistent storage (kvld key, kvst key): load replaces the default on
+    // top of the stack with the stored value, if any; store pops the value
+    #[cfg(feature = "input-ext")]
+    LoadKV(String),
+    #[cfg(feature = "input-ext")]
+    StoreKV(String),
+}
+
+// Instruction set extensions, as reported by the caps instruction; the core
+// instruction set is always present
+pub const PIXARDIS_CAPABILITY_DRAW: i64 = 1;
+pub const PIXARDIS_CAPABILITY_INPUT: i64 = 2;
+pub const PIXARDIS_CAPABILITY_AUDIO: i64 = 4;
+
+// Extensions this build of the instruction set includes, which decides the
+// instructions the assembler and compiler accept
+pub const PIXARDIS_CAPABILITIES: i64 =
+    if cfg!(feature = "draw-ext") { PIXARDIS_CAPABILITY_DRAW } else { 0 }
+    | if cfg!(feature = "input-ext") { PIXARDIS_CAPABILITY_INPUT } else { 0 }
+    | if cfg!(feature = "audio-ext") { PIXARDIS_CAPABILITY_AUDIO } else { 0 };
+
+// Label execution starts at unless the host picks another entry point
+pub const PIXARDIS_ENTRY_LABEL: &str = "main";
+
+// Deprecated mnemonics still accepted by the assembler, with the canonical
+// mnemonic each stands for
+pub const PIXARDIS_MNEMONIC_ALIASES: &[(&str, &str)] = &[
+    ("pop", "drop"),
+    ("cjmp2", "cjmp"),
+    ("pixel", "write"),
+    ("pixelr", "writebox"),
+    ("pixell", "writeline"),
+];
+
+//
+// Decode an instruction that takes no operands from its mnemonic (aliases
+// included)
+//
+//
+// Text of the integers most often pushed (frame sizes, offsets, counts, 0 and
+// 1); immediates equal to one of these share it instead of owning a copy
+//
+pub const PIXARDIS_SMALL_INTEGERS: &[&str] = &[
+    "0", "1", "2", "3", "4", "5", "6", "7", "8", "9", "10", "11", "12", "13", "14", "15",
+    "16", "17", "18", "19", "20", "21", "22", "23", "24", "25", "26", "27", "28", "29", "30", "31",
+    "32", "33", "34", "35", "36", "37", "38", "39", "40", "41", "42", "43", "44", "45", "46", "47",
+    "48", "49", "50", "51", "52", "53", "54", "55", "56", "57", "58", "59", "60", "61", "62", "63",
+];
+
+//
+// Immediate operand for an integer, borrowed from the pool when it is small
+//
+pub fn pixardis_immediate_integer(value: i64) -> Cow<'static, str> {
+    match usize::try_from(value).ok().and_then(|index| PIXARDIS_SMALL_INTEGERS.get(index)) {
+        Some(text) => Cow::Borrowed(text),
+        None => Cow::Owned(value.to_string()),
+    }
+}
+
+//
+// Immediate operand for a number as written; only text identical to a pooled
+// integer is borrowed, so "007" and "1.0" keep their spelling
+//
+pub fn pixardis_immediate(text: &str) -> Cow<'static, str> {
+    match PIXARDIS_SMALL_INTEGERS.iter().find(|pooled| **pooled == text) {
+        Some(pooled) => Cow::Borrowed(pooled),
+        None => Cow::Owned(text.to_string()),
+    }
+}
+
+//
+// Value (0xrrggbb) of a colour written #rgb, #rrggbb or #rrggbbaa; #rgb
+// doubles each digit (#f80 is #ff8800). Colours are opaque, so an alpha
+// must be ff; the error says what's wrong with the colour.
+//
+pub fn pixardis_colour_from_string(colour: &str) -> Result<u32, &'static str> {
+    let hex = colour.strip_prefix('#').ok_or("colours start with #")?;
+
+    if !hex.chars().all(|digit| digit.is_ascii_hexdigit()) {
+        return Err("colours are written in hex digits");
+    }
+
+    match hex.len() {
+        3 => {
+            let value = u32::from_str_radix(hex, 16).map_err(|_| "colours are written in hex digits")?;
+            Ok((0..3).fold(0, |rgb, channel| {
+                let digit = (value >> (8 - channel * 4)) & 0xF;
+                (rgb << 8) | (digit << 4) | digit
+            }))
+        },
+        6 => u32::from_str_radix(hex, 16).map_err(|_| "colours are written in hex digits"),
+        8 => match u32::from_str_radix(hex, 16) {
+            Ok(rgba) if rgba & 0xFF == 0xFF => Ok(rgba >> 8),
+            Ok(_) => Err("colours are opaque, so the alpha must be ff"),
+            Err(_) => Err("colours are written in hex digits"),
+        },
+        _ => Err("colours are written #rgb, #rrggbb or #rrggbbaa"),
+    }
 }
 
+pub fn pixardis_mnemonic_to_instruction(mnemonic: &str) -> Option<PixardisInstruction> {
+    match mnemonic {
+        "st" => Some(PixardisInstruction::Store),
+        "sta" => Some(PixardisInstruction::StoreArray),
+        "nop" => Some(PixardisInstruction::Nop),
+        "drop" | "pop" => Some(PixardisInstruction::Drop),
+        "dup" => Some(PixardisInstruction::Duplicate),
+        "dupa" => Some(PixardisInstruction::DuplicateArray),
+        "not" => Some(PixardisInstruction::Not),
+        "add" => Some(PixardisInstruction::Add),
+        "sub" => Some(PixardisInstruction::Subtract),
+        #[cfg(feature = "draw-ext")]
+        "addc" => Some(PixardisInstruction::AddColour),
+        #[cfg(feature = "draw-ext")]
+        "subc" => Some(PixardisInstruction::SubtractColour),
+        "mul" => Some(PixardisInstruction::Multiply),
+        "div" => Some(PixardisInstruction::Divide),
+        "mod" => Some(PixardisInstruction::Modulo),
+        "inc" => Some(PixardisInstruction::Increment),
+        "dec" => Some(PixardisInstruction::Decrement),
+        "max" => Some(PixardisInstruction::Maximum),
+        "min" => Some(PixardisInstruction::Minimum),
+        "irnd" => Some(PixardisInstruction::RandomInt),
+        "frnd" => Some(PixardisInstruction::RandomFloat),
+        "rrnd" => Some(PixardisInstruction::RandomRange),
+        #[cfg(feature = "draw-ext")]
+        "noise" => Some(PixardisInstruction::Noise),
+        "lt" => Some(PixardisInstruction::LessThan),
+        "le" => Some(PixardisInstruction::LessEqual),
+        "gt" => Some(PixardisInstruction::GreaterThan),
+        "ge" => Some(PixardisInstruction::GreaterEqual),
+        "eq" => Some(PixardisInstruction::Equal),
+        "jmp" => Some(PixardisInstruction::Jump),
+        "cjmp" | "cjmp2" => Some(PixardisInstruction::ConditionalJump),
+        "call" => Some(PixardisInstruction::Call),
+        "ret" => Some(PixardisInstruction::Return),
+        "reta" => Some(PixardisInstruction::ReturnArray),
+        "halt" => Some(PixardisInstruction::Halt),
+        "oframe" => Some(PixardisInstruction::FrameOpen),
+        "cframe" => Some(PixardisInstruction::FrameClose),
+        "alloc" => Some(PixardisInstruction::Allocate),
+        "delay" => Some(PixardisInstruction::Delay),
+        "write" | "pixel" => Some(PixardisInstruction::Write),
+        "writebox" | "pixelr" => Some(PixardisInstruction::WriteBox),
+        "writeline" | "pixell" => Some(PixardisInstruction::WriteLine),
+        #[cfg(feature = "draw-ext")]
+        "writeboxg" => Some(PixardisInstruction::WriteBoxGradient),
+        #[cfg(feature = "draw-ext")]
+        "writeboxp" => Some(PixardisInstruction::WriteBoxPattern),
+        #[cfg(feature = "draw-ext")]
+        "writetri" => Some(PixardisInstruction::WriteTriangle),
+        "read" => Some(PixardisInstruction::Read),
+        #[cfg(feature = "input-ext")]
+        "hit" => Some(PixardisInstruction::HitTest),
+        #[cfg(feature = "draw-ext")]
+        "hsvrgb" => Some(PixardisInstruction::HsvToRgb),
+        #[cfg(feature = "draw-ext")]
+        "rgbhsv" => Some(PixardisInstruction::RgbToHsv),
+        "clear" => Some(PixardisInstruction::Clear),
+        #[cfg(feature = "draw-ext")]
+        "bright" => Some(PixardisInstruction::SetBrightness),
+        "width" => Some(PixardisInstruction::Width),
+        "height" => Some(PixardisInstruction::Height),
+        "print" => Some(PixardisInstruction::Print),
+        "printa" => Some(PixardisInstruction::PrintArray),
+        "printf" => Some(PixardisInstruction::PrintFormatted),
+        "printx" => Some(PixardisInstruction::PrintFixed),
+        "printfx" => Some(PixardisInstruction::PrintFormattedFixed),
+        "caps" => Some(PixardisInstruction::Capabilities),
+        "assert" => Some(PixardisInstruction::Assert),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "std")]
 pub fn pixardis_instruction_from_string(instruction: String) -> PixardisInstruction {
     
+    // Metadata directives carry a free-form (quoted) value, so they're
+    // handled before the instruction is split on whitespace.
+    if let Some(directive) = strip_prefix_ignore_case(instruction.trim(), ".meta ") {
+        let (key, value) = directive.trim().split_once(char::is_whitespace).unwrap_or((directive.trim(), ""));
+        let value = value.trim();
+        let value = value.strip_prefix('"').and_then(|value| value.strip_suffix('"')).unwrap_or(value);
+
+        return PixardisInstruction::Metadata(key.to_string(), value.to_string());
+    }
+
     // Let's make some preliminary processing of the instruction string
     // to remove comments and trim whitespace.
-    let instruction_filtered:Vec<&str> = 
+    let mut instruction_filtered:Vec<&str> = 
         instruction.splitn(2, "//").next().unwrap().trim().split_whitespace().collect();
 
+    // Mnemonics are case-insensitive (labels and operands are not)
+    let mnemonic = instruction_filtered.first().map(|mnemonic| mnemonic.to_ascii_lowercase()).unwrap_or_default();
+    if let Some(first) = instruction_filtered.first_mut() {
+        if !first.starts_with('.') || first.eq_ignore_ascii_case(".sig") {
+            *first = &mnemonic;
+        }
+    }
+
     // Next we discriminate the instruction on the basis of the number of arguments.
     if instruction_filtered.len() == 1 
     {
-        match instruction_filtered[0] {
-            "st" => PixardisInstruction::Store,
-            "sta" => PixardisInstruction::StoreArray,
-            "nop" => PixardisInstruction::Nop,
-            "drop" | "pop" => PixardisInstruction::Drop,
-            "dup" => PixardisInstruction::Duplicate,
-            "dupa" => PixardisInstruction::DuplicateArray,
-            "not" => PixardisInstruction::Not,
-            "add" => PixardisInstruction::Add,
-            "sub" => PixardisInstruction::Subtract,
-            "mul" => PixardisInstruction::Multiply,
-            "div" => PixardisInstruction::Divide,
-            "mod" => PixardisInstruction::Modulo,
-            "inc" => PixardisInstruction::Increment,
-            "dec" => PixardisInstruction::Decrement,
-            "max" => PixardisInstruction::Maximum,
-            "min" => PixardisInstruction::Minimum,
-            "irnd" => PixardisInstruction::RandomInt,
-            "lt" => PixardisInstruction::LessThan,
-            "le" => PixardisInstruction::LessEqual,
-            "gt" => PixardisInstruction::GreaterThan,
-            "ge" => PixardisInstruction::GreaterEqual,
-            "eq" => PixardisInstruction::Equal,
-            "jmp" => PixardisInstruction::Jump,
-            "cjmp" | "cjmp2" => PixardisInstruction::ConditionalJump,
-            "call" => PixardisInstruction::Call,
-            "ret" => PixardisInstruction::Return,
-            "reta" => PixardisInstruction::ReturnArray,
-            "halt" => PixardisInstruction::Halt,
-            "oframe" => PixardisInstruction::FrameOpen,
-            "cframe" => PixardisInstruction::FrameClose,
-            "alloc" => PixardisInstruction::Allocate,
-            "delay" => PixardisInstruction::Delay,
-            "write" | "pixel" => PixardisInstruction::Write,
-            "writebox" | "pixelr" => PixardisInstruction::WriteBox,
-            "writeline" | "pixell" => PixardisInstruction::WriteLine,
-            "read" => PixardisInstruction::Read,
-            "clear" => PixardisInstruction::Clear,
-            "width" => PixardisInstruction::Width,
-            "height" => PixardisInstruction::Height,
-            "print" => PixardisInstruction::Print,
-            "printa" => PixardisInstruction::PrintArray,
-            value => {
+        match pixardis_mnemonic_to_instruction(instruction_filtered[0]) {
+            Some(instruction) => instruction,
+            None => {
                 let mut instruction = PixardisInstruction::Nop;
 
                 let pattern = Regex::new(r"^\.(?P<label>[a-zA-Z][a-zA-Z0-9_]*)$").unwrap();
-                if let Some(label) = pattern.captures(value) {
-                    instruction = PixardisInstruction::Label(label["label"].to_string());
+                if let Some(label) = pattern.captures(instruction_filtered[0]) {
+                    instruction = PixardisInstruction::Label(Atom::new(&label["label"]));
                 }
 
                 instruction
@@ -130,20 +343,23 @@ pub fn pixardis_instruction_from_string(instruction: String) -> PixardisInstruct
                 let mut instruction = PixardisInstruction::Nop;
                 
                 let pattern = Regex::new(
-                    r"^(?P<colour>#([0-9a-fA-F]{6}))|(?P<number>-?\d+(?:\.\d+)?)|\.(?P<label>[a-zA-Z][a-zA-Z0-9_]*)|(#PC(?P<offset>[+-]\d+))|(\[(?P<index>\d+):(?P<scope>\d+)\])|(\+\[(?P<offset_index>\d+):(?P<offset_scope>\d+)\])$"
+                    r"^(?:(?P<colour>#([0-9a-fA-F]{8}|[0-9a-fA-F]{6}|[0-9a-fA-F]{3}))|(?P<number>-?\d+(?:\.\d+)?)|\.(?P<label>[a-zA-Z][a-zA-Z0-9_]*)|(#PC(?P<offset>[+-]\d+))|(\[(?P<index>\d+):(?P<scope>\d+)\])|(\+\[(?P<offset_index>\d+):(?P<offset_scope>\d+)\]))$"
                 ).unwrap();
             
                 for captures in pattern.captures_iter((*value).trim()) {
                     // push number
                     if let Some(num) = captures.name("number") {
-                        instruction = PixardisInstruction::PushImmediate(num.as_str().to_string());
+                        instruction = PixardisInstruction::PushImmediate(pixardis_immediate(num.as_str()));
                     }
-                    if let Some(num) = captures.name("colour") {
-                        instruction = PixardisInstruction::PushImmediate(num.as_str().to_string());
+                    if let Some(colour) = captures.name("colour") {
+                        // A translucent colour doesn't parse (see pixardis_colour_from_string)
+                        if let Ok(colour) = pixardis_colour_from_string(colour.as_str()) {
+                            instruction = PixardisInstruction::PushColour(colour);
+                        }
                     }
                     // push .label
                     else if let Some(label) = captures.name("label") {
-                        instruction = PixardisInstruction::PushLabel(label.as_str().to_string());
+                        instruction = PixardisInstruction::PushLabel(Atom::new(label.as_str()));
                     } 
                     // push #PC±offset
                     else if let Some(offset) = captures.name("offset") {
@@ -166,10 +382,10 @@ pub fn pixardis_instruction_from_string(instruction: String) -> PixardisInstruct
 
                 instruction
             },
-            // Push array has a single variant so far; I'm considering adding a second
-            // variant that includes the count of elements to push.
+            // Push array has two variants:
             //
-            // pusha [i:s] - Push value array onto stack
+            // pusha [i:s]   - Push value array onto stack (count popped from stack)
+            // pusha [i:s] n - Push n-element value array onto stack
             ["pusha", value] => {
                 let mut instruction = PixardisInstruction::Nop;
                 
@@ -188,6 +404,35 @@ pub fn pixardis_instruction_from_string(instruction: String) -> PixardisInstruct
 
                 instruction
             },
+            ["pusha", value, count] => {
+                let pattern = Regex::new(r"^\[(?P<index>\d+):(?P<scope>\d+)\]$").unwrap();
+
+                match (pattern.captures((*value).trim()), count.parse::<i64>()) {
+                    (Some(captures), Ok(count)) => {
+                        let index_value = captures["index"].parse::<i64>().unwrap();
+                        let scope_value = captures["scope"].parse::<i64>().unwrap();
+                        PixardisInstruction::PushArrayCount([index_value, scope_value, count])
+                    },
+                    _ => PixardisInstruction::Nop,
+                }
+            },
+            #[cfg(feature = "input-ext")]
+            [mnemonic @ ("param" | "kvld" | "kvst"), name] => {
+                let pattern = Regex::new(r"^[a-zA-Z_][a-zA-Z0-9_]*$").unwrap();
+
+                match (pattern.is_match(name), *mnemonic) {
+                    (false, _) => PixardisInstruction::Nop,
+                    (true, "param") => PixardisInstruction::Param(name.to_string()),
+                    (true, "kvld") => PixardisInstruction::LoadKV(name.to_string()),
+                    (true, _) => PixardisInstruction::StoreKV(name.to_string()),
+                }
+            },
+            [".sig", name, size] => {
+                match size.parse::<usize>() {
+                    Ok(size) => PixardisInstruction::Signature(Atom::new(name), size),
+                    Err(_) => PixardisInstruction::Nop,
+                }
+            },
             _ => PixardisInstruction::Nop,
         }
     }
@@ -196,10 +441,19 @@ pub fn pixardis_instruction_from_string(instruction: String) -> PixardisInstruct
 pub fn pixardis_instruction_to_string(instruction: PixardisInstruction) -> String {
     match instruction {
         PixardisInstruction::Label(s) => format!(".{}", s),
+        PixardisInstruction::Metadata(key, value) => format!(".meta {} \"{}\"", key, value),
+        PixardisInstruction::Signature(name, size) => format!(".sig {} {}", name, size),
         PixardisInstruction::PushImmediate(s) => format!("push {}", s),
+        PixardisInstruction::PushColour(colour) => format!("push #{:06x}", colour),
+        #[cfg(feature = "input-ext")]
+        PixardisInstruction::Param(name) => format!("param {}", name),
+        #[cfg(feature = "input-ext")]
+        PixardisInstruction::LoadKV(key) => format!("kvld {}", key),
+        #[cfg(feature = "input-ext")]
+        PixardisInstruction::StoreKV(key) => format!("kvst {}", key),
         PixardisInstruction::PushLabel(s) => format!("push .{}", s),
         PixardisInstruction::PushOffset(n) => {
-            if n > 0 {
+            if n >= 0 {
                 format!("push #PC+{}", n)
             } else {
                 format!("push #PC{}", n)
@@ -208,6 +462,7 @@ pub fn pixardis_instruction_to_string(instruction: PixardisInstruction) -> Strin
         PixardisInstruction::PushIndexed([index, frame]) => format!("push [{}:{}]", index, frame),
         PixardisInstruction::PushIndexedOffset([index, frame]) => format!("push +[{}:{}]", index, frame),
         PixardisInstruction::PushArray([index, frame]) => format!("pusha [{}:{}]", index, frame),
+        PixardisInstruction::PushArrayCount([index, frame, count]) => format!("pusha [{}:{}] {}", index, frame, count),
         PixardisInstruction::Store => String::from("st"),
         PixardisInstruction::StoreArray => String::from("sta"),
         PixardisInstruction::Nop => String::from("nop"),
@@ -217,6 +472,10 @@ pub fn pixardis_instruction_to_string(instruction: PixardisInstruction) -> Strin
         PixardisInstruction::DuplicateArray => String::from("dupa"),
         PixardisInstruction::Add => String::from("add"),
         PixardisInstruction::Subtract => String::from("sub"),
+        #[cfg(feature = "draw-ext")]
+        PixardisInstruction::AddColour => String::from("addc"),
+        #[cfg(feature = "draw-ext")]
+        PixardisInstruction::SubtractColour => String::from("subc"),
         PixardisInstruction::Multiply => String::from("mul"),
         PixardisInstruction::Divide => String::from("div"),
         PixardisInstruction::Modulo => String::from("mod"),
@@ -225,6 +484,10 @@ pub fn pixardis_instruction_to_string(instruction: PixardisInstruction) -> Strin
         PixardisInstruction::Maximum => String::from("max"),
         PixardisInstruction::Minimum => String::from("min"),
         PixardisInstruction::RandomInt => String::from("irnd"),
+        PixardisInstruction::RandomFloat => String::from("frnd"),
+        PixardisInstruction::RandomRange => String::from("rrnd"),
+        #[cfg(feature = "draw-ext")]
+        PixardisInstruction::Noise => String::from("noise"),
         PixardisInstruction::LessThan => String::from("lt"),
         PixardisInstruction::LessEqual => String::from("le"),
         PixardisInstruction::GreaterThan => String::from("gt"),
@@ -243,15 +506,417 @@ pub fn pixardis_instruction_to_string(instruction: PixardisInstruction) -> Strin
         PixardisInstruction::Write => String::from("write"),
         PixardisInstruction::WriteBox => String::from("writebox"),
         PixardisInstruction::WriteLine => String::from("writeline"),
+        #[cfg(feature = "draw-ext")]
+        PixardisInstruction::WriteBoxGradient => String::from("writeboxg"),
+        #[cfg(feature = "draw-ext")]
+        PixardisInstruction::WriteBoxPattern => String::from("writeboxp"),
+        #[cfg(feature = "draw-ext")]
+        PixardisInstruction::WriteTriangle => String::from("writetri"),
         PixardisInstruction::Read => String::from("read"),
+        #[cfg(feature = "input-ext")]
+        PixardisInstruction::HitTest => String::from("hit"),
+        #[cfg(feature = "draw-ext")]
+        PixardisInstruction::HsvToRgb => String::from("hsvrgb"),
+        #[cfg(feature = "draw-ext")]
+        PixardisInstruction::RgbToHsv => String::from("rgbhsv"),
         PixardisInstruction::Clear => String::from("clear"),
+        #[cfg(feature = "draw-ext")]
+        PixardisInstruction::SetBrightness => String::from("bright"),
         PixardisInstruction::Width => String::from("width"),
         PixardisInstruction::Height => String::from("height"),
         PixardisInstruction::Print => String::from("print"),
         PixardisInstruction::PrintArray => String::from("printa"),
+        PixardisInstruction::PrintFormatted => String::from("printf"),
+        PixardisInstruction::PrintFixed => String::from("printx"),
+        PixardisInstruction::PrintFormattedFixed => String::from("printfx"),
+        PixardisInstruction::Capabilities => String::from("caps"),
+        PixardisInstruction::Assert => String::from("assert"),
+    }
+}
+
+#[cfg(feature = "std")]
+fn strip_prefix_ignore_case<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    match text.get(..prefix.len()) {
+        Some(start) if start.eq_ignore_ascii_case(prefix) => Some(&text[prefix.len()..]),
+        _ => None,
+    }
+}
+
+//
+// Strict-mode check of one line of assembly: a warning if it uses a
+// deprecated alias, a mnemonic not in lower case, or doesn't parse (and so
+// loads as a nop)
+//
+#[cfg(feature = "std")]
+pub fn pixardis_instruction_warning(instruction: &str) -> Option<String> {
+    let code = instruction.split("//").next().unwrap().trim();
+    let first = code.split_whitespace().next()?;
+    let mnemonic = first.to_ascii_lowercase();
+
+    if let Some((_, canonical)) = PIXARDIS_MNEMONIC_ALIASES.iter().find(|(alias, _)| *alias == mnemonic) {
+        return Some(format!("'{}' is a deprecated alias of '{}'", first, canonical));
+    }
+
+    let parsed = pixardis_instruction_from_string(code.to_string());
+
+    if parsed == PixardisInstruction::Nop && mnemonic != "nop" {
+        // Known mnemonics only fail to parse here if their extension was
+        // compiled out
+        let extension = PIXARDIS_BINARY_MNEMONICS.contains(&mnemonic.as_str())
+            || (!cfg!(feature = "input-ext") && matches!(mnemonic.as_str(), "param" | "kvld" | "kvst"));
+
+        if extension {
+            return Some(format!("'{}' needs an instruction set extension this build doesn't include (loads as nop)", first));
+        }
+
+        return Some(format!("unrecognised instruction '{}' (loads as nop)", code));
+    }
+
+    let is_label = matches!(parsed, PixardisInstruction::Label(_));
+    if !is_label && first != mnemonic {
+        return Some(format!("mnemonic '{}' should be written '{}'", first, mnemonic));
+    }
+
+    None
+}
+
+//
+// Error in one line of assembly that would otherwise load as a nop: a push
+// of a colour the VM can't represent, such as a translucent one
+//
+#[cfg(feature = "std")]
+pub fn pixardis_instruction_error(instruction: &str) -> Option<String> {
+    let code = instruction.split("//").next().unwrap().trim();
+
+    match code.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [mnemonic, value] if mnemonic.eq_ignore_ascii_case("push") && value.starts_with('#') && !value.starts_with("#PC") => {
+            pixardis_colour_from_string(value).err().map(|error| format!("can't push {}: {}", value, error))
+        },
+        _ => None,
+    }
+}
+
+//
+// Errors in a program, by (1-based) line number; a program with any
+// shouldn't be loaded
+//
+#[cfg(feature = "std")]
+pub fn pixardis_assembly_errors(source: &str) -> Vec<(usize, String)> {
+    source.split('\n')
+        .enumerate()
+        .filter_map(|(index, line)| pixardis_instruction_error(line).map(|error| (index + 1, error)))
+        .collect()
+}
+
+//
+// Strict-mode warnings for a program, by (1-based) line number
+//
+#[cfg(feature = "std")]
+pub fn pixardis_assembly_warnings(source: &str) -> Vec<(usize, String)> {
+    source.split('\n')
+        .enumerate()
+        .filter_map(|(index, line)| pixardis_instruction_warning(line).map(|warning| (index + 1, warning)))
+        .collect()
+}
+
+//
+// Rewrite a program in canonical form: one instruction per line, canonical
+// mnemonics, no comments. Lines map one to one (blank lines become nops), so
+// addresses are unchanged, and canonical assembly parses back to the same
+// instructions it was written from.
+//
+#[cfg(feature = "std")]
+pub fn pixardis_canonicalise_assembly(source: &str) -> String {
+    source.split('\n')
+        .map(|line| pixardis_instruction_to_string(pixardis_instruction_from_string(line.to_string())))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+//
+// An instruction that doesn't survive being written as text and parsed back
+//
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PixardisRoundTripError {
+    pub index: usize,
+    pub instruction: PixardisInstruction,
+    pub text: String,
+    pub parsed: PixardisInstruction,
+}
+
+//
+// Check that every instruction of a program parses back from its text form
+// unchanged; the error reports the first that doesn't (such as an immediate
+// that isn't a number, or a label the assembler can't read)
+//
+#[cfg(feature = "std")]
+pub fn roundtrip_check(program: &[PixardisInstruction]) -> Result<(), Box<PixardisRoundTripError>> {
+    for (index, instruction) in program.iter().enumerate() {
+        let text = pixardis_instruction_to_string(instruction.clone());
+        let parsed = pixardis_instruction_from_string(text.clone());
+
+        if parsed != *instruction {
+            return Err(Box::new(PixardisRoundTripError { index, instruction: instruction.clone(), text, parsed }));
+        }
+    }
+
+    Ok(())
+}
+
+//
+// The generators in arbitrary.rs must only produce programs that survive
+// both the text and the binary form; a bounded number of cases keeps this
+// quick enough for every test run
+//
+#[cfg(all(test, feature = "std", feature = "draw-ext", feature = "input-ext", feature = "audio-ext"))]
+mod roundtrip_tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::arbitrary;
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn generated_programs_roundtrip(program in arbitrary::program(32)) {
+            prop_assert_eq!(roundtrip_check(&program), Ok(()));
+            prop_assert_eq!(pixardis_decode_program(&pixardis_encode_program(&program)), Some(program));
+        }
     }
 }
 
+// Leading bytes of a binary program; the last byte is the format version
+pub const PIXARDIS_BINARY_MAGIC: &[u8] = b"PXB\x01";
+
+// Instructions without operands, by binary opcode (offset by
+// PIXARDIS_BINARY_OPCODE_BASE); opcodes below the base carry operands
+pub(crate) const PIXARDIS_BINARY_MNEMONICS: &[&str] = &[
+    "st", "sta", "nop", "drop", "dup", "dupa", "not", "add", "sub", "mul", "div", "mod",
+    "inc", "dec", "max", "min", "irnd", "lt", "le", "gt", "ge", "eq", "jmp", "cjmp",
+    "call", "ret", "reta", "halt", "oframe", "cframe", "alloc", "delay", "write",
+    "writebox", "writeline", "writeboxg", "writeboxp", "writetri", "read", "hit", "clear",
+    "width", "height", "print", "printa", "printf", "addc", "subc",
+    "hsvrgb", "rgbhsv", "bright", "frnd", "rrnd",
+    "noise", "caps", "assert", "printx", "printfx",
+];
+
+//
+// 64-bit FNV-1a hash of some bytes, as 16 hex digits; build manifests use it
+// to tie a program to its source
+//
+pub fn pixardis_hash(bytes: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    format!("{:016x}", hash)
+}
+
+//
+// Hash of a program: of each instruction's canonical text, so a program
+// hashes the same read from assembly (in any spelling) or binary. Trailing
+// nops are left out, as the blank line ending a file reads as one.
+//
+pub fn pixardis_program_hash(code: &[PixardisInstruction]) -> String {
+    let end = code.iter().rposition(|instruction| *instruction != PixardisInstruction::Nop).map_or(0, |last| last + 1);
+
+    let text: String = code[..end].iter()
+        .map(|instruction| pixardis_instruction_to_string(instruction.clone()) + "\n")
+        .collect();
+
+    pixardis_hash(text.as_bytes())
+}
+
+const PIXARDIS_BINARY_OPCODE_BASE: u8 = 16;
+
+//
+// Encode a program in the binary format: the magic bytes, the instruction
+// count, then an opcode per instruction followed by its operands (integers
+// little-endian, strings length-prefixed)
+//
+pub fn pixardis_encode_program(code: &[PixardisInstruction]) -> Vec<u8> {
+    fn write_u32(bytes: &mut Vec<u8>, value: usize) {
+        bytes.extend_from_slice(&(value as u32).to_le_bytes());
+    }
+
+    fn write_i64s(bytes: &mut Vec<u8>, values: &[i64]) {
+        for value in values {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    fn write_str(bytes: &mut Vec<u8>, value: &str) {
+        write_u32(bytes, value.len());
+        bytes.extend_from_slice(value.as_bytes());
+    }
+
+    let mut bytes = PIXARDIS_BINARY_MAGIC.to_vec();
+    write_u32(&mut bytes, code.len());
+
+    for instruction in code {
+        match instruction {
+            PixardisInstruction::Label(label) => { bytes.push(0); write_str(&mut bytes, label); },
+            PixardisInstruction::Metadata(key, value) => { bytes.push(1); write_str(&mut bytes, key); write_str(&mut bytes, value); },
+            PixardisInstruction::Signature(name, size) => { bytes.push(2); write_str(&mut bytes, name); write_i64s(&mut bytes, &[*size as i64]); },
+            PixardisInstruction::PushImmediate(value) => { bytes.push(3); write_str(&mut bytes, value); },
+            PixardisInstruction::PushLabel(label) => { bytes.push(4); write_str(&mut bytes, label); },
+            PixardisInstruction::PushOffset(offset) => { bytes.push(5); write_i64s(&mut bytes, &[*offset]); },
+            PixardisInstruction::PushIndexed(index) => { bytes.push(6); write_i64s(&mut bytes, index); },
+            PixardisInstruction::PushIndexedOffset(index) => { bytes.push(7); write_i64s(&mut bytes, index); },
+            PixardisInstruction::PushArray(index) => { bytes.push(8); write_i64s(&mut bytes, index); },
+            PixardisInstruction::PushArrayCount(index) => { bytes.push(9); write_i64s(&mut bytes, index); },
+            PixardisInstruction::PushColour(colour) => { bytes.push(10); write_i64s(&mut bytes, &[*colour as i64]); },
+            #[cfg(feature = "input-ext")]
+            PixardisInstruction::Param(name) => { bytes.push(11); write_str(&mut bytes, name); },
+            #[cfg(feature = "input-ext")]
+            PixardisInstruction::LoadKV(key) => { bytes.push(12); write_str(&mut bytes, key); },
+            #[cfg(feature = "input-ext")]
+            PixardisInstruction::StoreKV(key) => { bytes.push(13); write_str(&mut bytes, key); },
+            instruction => {
+                let mnemonic = pixardis_instruction_to_string(instruction.clone());
+                let opcode = PIXARDIS_BINARY_MNEMONICS.iter().position(|&entry| entry == mnemonic)
+                    .expect("instruction missing from the binary opcode table");
+
+                bytes.push(PIXARDIS_BINARY_OPCODE_BASE + opcode as u8);
+            },
+        }
+    }
+
+    bytes
+}
+
+//
+// Decode a program encoded by pixardis_encode_program; None if the bytes
+// aren't a well-formed binary program
+//
+pub fn pixardis_decode_program(bytes: &[u8]) -> Option<Vec<PixardisInstruction>> {
+    struct Reader<'a> {
+        bytes: &'a [u8],
+    }
+
+    impl<'a> Reader<'a> {
+        fn take(&mut self, count: usize) -> Option<&'a [u8]> {
+            if count > self.bytes.len() {
+                return None;
+            }
+
+            let (taken, rest) = self.bytes.split_at(count);
+            self.bytes = rest;
+            Some(taken)
+        }
+
+        fn u8(&mut self) -> Option<u8> {
+            Some(self.take(1)?[0])
+        }
+
+        fn u32(&mut self) -> Option<usize> {
+            Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?) as usize)
+        }
+
+        fn i64(&mut self) -> Option<i64> {
+            Some(i64::from_le_bytes(self.take(8)?.try_into().ok()?))
+        }
+
+        fn str(&mut self) -> Option<&'a str> {
+            let length = self.u32()?;
+            core::str::from_utf8(self.take(length)?).ok()
+        }
+
+        fn string(&mut self) -> Option<String> {
+            self.str().map(|value| value.to_string())
+        }
+    }
+
+    // A label is stored at each use; decoding shares one copy of its text
+    let interner = Interner::new();
+
+    let mut reader = Reader { bytes: bytes.strip_prefix(PIXARDIS_BINARY_MAGIC)? };
+
+    // Every instruction takes at least a byte, which bounds the allocation
+    let count = reader.u32()?;
+    if count > reader.bytes.len() {
+        return None;
+    }
+
+    let mut code = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        let instruction = match reader.u8()? {
+            0 => PixardisInstruction::Label(interner.intern(reader.str()?)),
+            1 => PixardisInstruction::Metadata(reader.string()?, reader.string()?),
+            2 => PixardisInstruction::Signature(interner.intern(reader.str()?), usize::try_from(reader.i64()?).ok()?),
+            3 => PixardisInstruction::PushImmediate(pixardis_immediate(reader.str()?)),
+            4 => PixardisInstruction::PushLabel(interner.intern(reader.str()?)),
+            5 => PixardisInstruction::PushOffset(reader.i64()?),
+            6 => PixardisInstruction::PushIndexed([reader.i64()?, reader.i64()?]),
+            7 => PixardisInstruction::PushIndexedOffset([reader.i64()?, reader.i64()?]),
+            8 => PixardisInstruction::PushArray([reader.i64()?, reader.i64()?]),
+            9 => PixardisInstruction::PushArrayCount([reader.i64()?, reader.i64()?, reader.i64()?]),
+            10 => PixardisInstruction::PushColour(u32::try_from(reader.i64()?).ok()?),
+            #[cfg(feature = "input-ext")]
+            11 => PixardisInstruction::Param(reader.string()?),
+            #[cfg(feature = "input-ext")]
+            12 => PixardisInstruction::LoadKV(reader.string()?),
+            #[cfg(feature = "input-ext")]
+            13 => PixardisInstruction::StoreKV(reader.string()?),
+            opcode => {
+                let mnemonic = PIXARDIS_BINARY_MNEMONICS.get(opcode.checked_sub(PIXARDIS_BINARY_OPCODE_BASE)? as usize)?;
+                pixardis_mnemonic_to_instruction(mnemonic)?
+            },
+        };
+
+        code.push(instruction);
+    }
+
+    if !reader.bytes.is_empty() {
+        return None;
+    }
+
+    Some(code)
+}
+
+//
+// Make the labels of a program (parsed line by line, so each with its own
+// copy of its text) share their text through the interner
+//
+pub fn pixardis_intern_labels(code: &mut [PixardisInstruction], interner: &Interner) {
+    for instruction in code.iter_mut() {
+        match instruction {
+            PixardisInstruction::Label(label) |
+            PixardisInstruction::Signature(label, _) |
+            PixardisInstruction::PushLabel(label) => *label = interner.intern(label),
+            _ => { },
+        }
+    }
+}
+
+//
+// Label for a function declared in the given scope; scoping the label keeps
+// same-named functions (and a user function called main) apart
+//
+pub fn pixardis_mangle_label(name: &str, scope: usize) -> String {
+    format!("{}__{}", name, scope)
+}
+
+//
+// Function name and declaring scope of a label made by pixardis_mangle_label;
+// None for any other label
+//
+pub fn pixardis_demangle_label(label: &str) -> Option<(&str, usize)> {
+    let (name, scope) = label.rsplit_once("__")?;
+
+    if name.is_empty() || scope.is_empty() || !scope.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    Some((name, scope.parse().ok()?))
+}
+
 pub fn pixardis_instruction_to_string_ex(instruction: PixardisInstruction, line: Option<usize>, scope: Option<usize>) -> String {
     let prefix;
         
@@ -268,6 +933,7 @@ pub fn pixardis_instruction_to_string_ex(instruction: PixardisInstruction, line:
     format!("{}{}", prefix, pixardis_instruction_to_string(instruction.clone()))
 }
 
+#[cfg(feature = "std")]
 pub fn pixardis_save_code(code: &Vec<(usize, PixardisInstruction)>, filename: &str, show_line_numbers: bool, show_scope: bool) -> std::io::Result<()> {
     let mut file = std::fs::File::create(filename)?;
     
@@ -294,6 +960,7 @@ pub fn pixardis_save_code(code: &Vec<(usize, PixardisInstruction)>, filename: &s
     Ok(())
 }
 
+#[cfg(feature = "std")]
 pub fn pixardis_print_code(code: &Vec<(usize, PixardisInstruction)>, show_line_numbers: bool, show_scope: bool) {
     for (index, instruction) in code.iter().enumerate() 
     {
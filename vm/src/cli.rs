@@ -0,0 +1,169 @@
+// cli.rs
+//
+// Command line options and VM setup shared by the front ends (chroma-vm,
+// chroma-run and chroma-term), so each flag parses and behaves the same in
+// all of them.
+//
+
+use std::io;
+
+use crate::machine::architecture::Operand;
+use crate::pixardis::pixardis::{PixardisVirtualMachine, PixardisLogLevel, PixardisMetadata, PixardisErrorPolicy, PixardisClipMode, param_from_string};
+use crate::pixardis::storage::PixardisFileStorage;
+
+// Display size used when neither the flags nor the program specify one
+pub const DEFAULT_WIDTH: usize = 64;
+pub const DEFAULT_HEIGHT: usize = 48;
+
+///
+/// Options every front end takes for the machine itself, as opposed to how
+/// it's run or shown
+///
+#[derive(clap::Args, Debug)]
+pub struct MachineArgs {
+    #[arg(short = 'x', long, help = "VM display width [default = program's #pragma width, else 64].")]
+    pub width: Option<usize>,
+
+    #[arg(short = 'y', long, help = "VM display height [default = program's #pragma height, else 48].")]
+    pub height: Option<usize>,
+
+    #[arg(short = 'L', help = "Log level [default = 0].", default_value = "0")]
+    pub log_level: Option<usize>,
+
+    #[arg(long, help = "Emulate a float-less target; real operands raise an error (see chroma --fixed-point).")]
+    pub fixed_point: bool,
+
+    #[arg(long, help = "Substitute zero for division by zero and off-screen reads instead of stopping.")]
+    pub continue_on_error: bool,
+
+    #[arg(long, help = "Initial display brightness (0-255); the program can change it with bright.", default_value = "255")]
+    pub brightness: u8,
+
+    #[arg(long, value_parser = parse_gamma, help = "Display gamma applied to each colour channel.", default_value = "1.0")]
+    pub gamma: f64,
+
+    #[arg(short = 'D', value_name = "NAME=VALUE", value_parser = parse_param, help = "Set a parameter read by __param (an integer or #rrggbb). May be repeated.")]
+    pub param: Vec<(String, Operand)>,
+
+    #[arg(long, value_name = "FILE", help = "Keep values the program stores (kvst) in FILE, so they persist between runs.")]
+    pub storage: Option<String>,
+}
+
+impl MachineArgs {
+    //
+    // Create a VM set up with these options; the display size comes from the
+    // flags, then the program's metadata, then the defaults
+    //
+    pub fn create(&self, metadata: &PixardisMetadata) -> Result<PixardisVirtualMachine, io::Error> {
+        let width = self.width.or(metadata.width).unwrap_or(DEFAULT_WIDTH);
+        let height = self.height.or(metadata.height).unwrap_or(DEFAULT_HEIGHT);
+
+        let mut vm = PixardisVirtualMachine::new(width, height);
+        vm.log_level_set(log_level_from(self.log_level));
+
+        // Emulate a float-less target for fixed-point programs
+        vm.float_support_set(!self.fixed_point);
+
+        // Keep running through division by zero and off-screen reads in art mode
+        if self.continue_on_error {
+            vm.error_policy_set(PixardisErrorPolicy::Continue);
+        }
+
+        // Brightness and gamma only change how the framebuffer is shown
+        vm.brightness_set(self.brightness);
+        vm.gamma_set(self.gamma);
+
+        for (name, value) in &self.param {
+            vm.param_set(name, value.clone());
+        }
+
+        // Values programs store (kvst) persist in the storage file between runs
+        if let Some(path) = &self.storage {
+            vm.storage_set(Some(Box::new(PixardisFileStorage::open(path)?)));
+        }
+
+        Ok(vm)
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum ClipArg {
+    // Ignore off-screen writes; off-screen reads are an error
+    Clip,
+    // Wrap coordinates around to the opposite edge
+    Wrap,
+    // Treat any off-screen access as a runtime error
+    Error,
+}
+
+impl From<ClipArg> for PixardisClipMode {
+    fn from(clip: ClipArg) -> PixardisClipMode {
+        match clip {
+            ClipArg::Clip => PixardisClipMode::Clip,
+            ClipArg::Wrap => PixardisClipMode::Wrap,
+            ClipArg::Error => PixardisClipMode::Error,
+        }
+    }
+}
+
+//
+// Parse a program parameter given as name=value
+//
+pub fn parse_param(param: &str) -> Result<(String, Operand), String>
+{
+    let (name, value) = param.split_once('=').ok_or("expected NAME=VALUE")?;
+    let name = name.trim();
+
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("invalid parameter name '{}'", name));
+    }
+
+    match param_from_string(value) {
+        Some(value) => Ok((name.to_string(), value)),
+        None => Err(format!("invalid value for parameter '{}' (expected an integer or #rrggbb)", name)),
+    }
+}
+
+//
+// Parse a frame rate, which must be positive
+//
+pub fn parse_fps(fps: &str) -> Result<f64, String>
+{
+    parse_positive(fps, "frame rate")
+}
+
+//
+// Parse a time scale, which must be positive
+//
+pub fn parse_time_scale(scale: &str) -> Result<f64, String>
+{
+    parse_positive(scale, "time scale")
+}
+
+//
+// Parse a display gamma, which must be positive
+//
+pub fn parse_gamma(gamma: &str) -> Result<f64, String>
+{
+    parse_positive(gamma, "gamma")
+}
+
+fn parse_positive(value: &str, what: &str) -> Result<f64, String>
+{
+    match value.trim().parse::<f64>() {
+        Ok(number) if number > 0.0 && number.is_finite() => Ok(number),
+        _ => Err(format!("invalid {} '{}' (expected a positive number)", what, value)),
+    }
+}
+
+//
+// Map a numeric log level from the command line onto a VM log level
+//
+pub fn log_level_from(level: Option<usize>) -> PixardisLogLevel
+{
+    match level {
+        Some(1) => PixardisLogLevel::Error,
+        Some(2) => PixardisLogLevel::Full,
+        _ => PixardisLogLevel::None,
+    }
+}
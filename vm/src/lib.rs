@@ -0,0 +1,12 @@
+// lib.rs
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+// Declare and define the VM core modules
+pub mod machine;
+pub mod pixardis;
+
+// Command line options and VM setup shared by the front ends
+#[cfg(feature = "cli")]
+pub mod cli;
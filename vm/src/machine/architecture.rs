@@ -1,5 +1,11 @@
-use shared::pixardis::PixardisInstruction;
-use std::collections::HashMap;
+use shared::pixardis::{PixardisInstruction, pixardis_encode_program, pixardis_decode_program};
+use super::snapshot::{SnapshotWriter, SnapshotReader};
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
 
 #[derive(Debug, Clone)]
 pub enum VirtualMachineError {
@@ -15,16 +21,38 @@ pub enum VirtualMachineError {
     InvalidCount,
     InvalidArgumentCount,
     InvalidDelay,
+    InvalidProgram,
+    // The program is over the VM's load limits (see PixardisLoadLimits)
+    ProgramTooLarge,
     DivisionByZero,
-    InstructionError,
+    ProgramCounterOutOfBounds,
     TrapHalt,
+    InvalidSnapshot,
+    MismatchedReturn,
+    HistoryUnavailable,
+    // An instruction set extension the VM was built without
+    UnsupportedInstruction,
+    // An assert instruction found its condition false; carries the
+    // assertion's source line when the host supplied debug information
+    AssertionFailed(Option<usize>),
 }
 
+//
+// Return addresses, each paired with the memory frame depth at the call so
+// that a return can be checked against the frames opened since
+//
+#[derive(Clone)]
 pub struct AddressStack {
-    stack: Vec<usize>,
+    stack: Vec<(usize, usize)>,
 }
 
 #[allow(dead_code)]
+impl Default for AddressStack {
+    fn default() -> AddressStack {
+        AddressStack::new()
+    }
+}
+
 impl AddressStack {
     pub fn new() -> AddressStack {
         AddressStack {
@@ -40,11 +68,11 @@ impl AddressStack {
         self.stack.len()
     }
 
-    pub fn push(&mut self, address: usize) {
-        self.stack.push(address);
+    pub fn push(&mut self, address: usize, frame_depth: usize) {
+        self.stack.push((address, frame_depth));
     }
 
-    pub fn pop(&mut self) -> Result<usize,VirtualMachineError> {
+    pub fn pop(&mut self) -> Result<(usize, usize),VirtualMachineError> {
         if self.stack.len() > 0 {
             return Ok(self.stack.pop().unwrap());
         }
@@ -52,18 +80,23 @@ impl AddressStack {
         Err(VirtualMachineError::StackUnderflow)
     }
 
-    pub fn peek(&self) -> Result<usize,VirtualMachineError> {
+    pub fn peek(&self) -> Result<(usize, usize),VirtualMachineError> {
         if self.stack.len() > 0 {
             return Ok(self.stack.last().unwrap().clone());
         }
 
         Err(VirtualMachineError::StackUnderflow)
     }
+
+    // Return addresses and frame depths, outermost call first
+    pub fn entries(&self) -> &[(usize, usize)] {
+        &self.stack
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum Operand {
-    Unsigned(u64),
+    Colour(u64),
     Integer(i64),
     Real(f64),
 }
@@ -74,6 +107,12 @@ pub struct OperandStack {
 }
 
 #[allow(dead_code)]
+impl Default for OperandStack {
+    fn default() -> OperandStack {
+        OperandStack::new()
+    }
+}
+
 impl OperandStack {
     pub fn new() -> OperandStack {
         OperandStack {
@@ -89,6 +128,11 @@ impl OperandStack {
         self.stack.len()
     }
 
+    // Operands, bottom of the stack first
+    pub fn entries(&self) -> &[Operand] {
+        &self.stack
+    }
+
     pub fn push(&mut self, operand: Operand) {
         self.stack.push(operand);
     }
@@ -118,7 +162,7 @@ impl OperandStack {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct StackFrame {
     stack: Vec<Operand>,
 }
@@ -166,10 +210,17 @@ impl StackFrame {
     }
 }
 
+#[derive(Clone)]
 pub struct Memory {
     stack: Vec<StackFrame>,
 }
 
+impl Default for Memory {
+    fn default() -> Memory {
+        Memory::new()
+    }
+}
+
 impl Memory {
     pub fn new() -> Memory {
         Memory {
@@ -178,13 +229,28 @@ impl Memory {
     }
 
     fn stack_frame_to_index(&self, frame: usize) -> Result<usize, VirtualMachineError> {
-        let frame_index = self.stack.len() - frame - 1;
+        frame.checked_add(1)
+            .and_then(|depth| self.stack.len().checked_sub(depth))
+            .ok_or(VirtualMachineError::InvalidStackFrame)
+    }
 
-        if frame_index >= self.stack.len() {
-            return Err(VirtualMachineError::InvalidStackFrame);
-        }
+    // Absolute index (0 = outermost) of a frame given relative to the current one
+    pub fn frame_index(&self, frame: usize) -> Result<usize, VirtualMachineError> {
+        self.stack_frame_to_index(frame)
+    }
+
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    // Cells in all open frames
+    pub fn cells(&self) -> usize {
+        self.stack.iter().map(|frame| frame.size()).sum()
+    }
 
-        Ok(frame_index)
+    pub fn frame_size(&self, frame: usize) -> Result<usize, VirtualMachineError> {
+        let frame_index = self.stack_frame_to_index(frame)?;
+        Ok(self.stack[frame_index].size())
     }
 
     pub fn frame_open(&mut self, allocation: usize) {
@@ -234,6 +300,20 @@ pub enum VirtualMachineState {
     Delayed(f64, f64),
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum VirtualMachineStepResult {
+    // All requested cycles were consumed (or the VM is ready to continue)
+    Ready,
+    // The VM is delayed; nothing will happen until the given VM time (in seconds)
+    WaitUntil(f64),
+    // A watched memory location was written; execution paused after the write
+    Watchpoint,
+    // The host asked the VM to stop (see PixardisVirtualMachine::request_stop);
+    // execution paused, and resumes with the next step
+    Stopped,
+}
+
+#[derive(Clone)]
 pub struct VirtualMachine {
     memory: Memory,
     address_stack: AddressStack,
@@ -241,15 +321,31 @@ pub struct VirtualMachine {
 
     program: Vec<PixardisInstruction>,
     program_counter: usize,
-    address_map: HashMap<String, usize>,
+    address_map: BTreeMap<String, usize>,
 
     state: VirtualMachineState,
+
+    random_number_generator: fastrand::Rng,
+    // Seed of the noise instruction, which follows the RNG seed
+    noise_seed: u64,
 }
 
 #[allow(dead_code)]
+impl Default for VirtualMachine {
+    fn default() -> VirtualMachine {
+        VirtualMachine::new()
+    }
+}
+
 impl VirtualMachine
 {
     pub fn new() -> VirtualMachine {
+        #[cfg(feature = "std")]
+        let random_number_generator = fastrand::Rng::new();
+        // No entropy source without std; hosts seed via random_seed_set
+        #[cfg(not(feature = "std"))]
+        let random_number_generator = fastrand::Rng::with_seed(0);
+
         VirtualMachine {
             memory: Memory::new(),
             address_stack: AddressStack::new(),
@@ -257,15 +353,18 @@ impl VirtualMachine
 
             program: Vec::new(),
             program_counter: 0,
-            address_map: HashMap::new(),
+            address_map: BTreeMap::new(),
 
             state: VirtualMachineState::Stopped,
+
+            noise_seed: random_number_generator.get_seed(),
+            random_number_generator,
         }
     }
 
-    // pub fn print_operand_stack(&self) {
-    //     println!("operand_stack: {:?}", self.operand_stack);
-    // }
+    pub fn operand_stack(&self) -> &[Operand] {
+        self.operand_stack.entries()
+    }
 
     pub fn state(&self) -> VirtualMachineState {
         self.state.clone()
@@ -279,8 +378,24 @@ impl VirtualMachine
      *
      */
     pub fn random_integer(&mut self, value: i64) -> i64 {
-        // self.random_number_generator.gen_range(0..value)
-        fastrand::i64(0..value)
+        self.random_number_generator.i64(0..value)
+    }
+
+    pub fn random_real(&mut self) -> f64 {
+        self.random_number_generator.f64()
+    }
+
+    pub fn random_integer_range(&mut self, low: i64, high: i64) -> i64 {
+        self.random_number_generator.i64(low..high)
+    }
+
+    pub fn random_seed_set(&mut self, seed: u64) {
+        self.random_number_generator.seed(seed);
+        self.noise_seed = seed;
+    }
+
+    pub fn noise_seed(&self) -> u64 {
+        self.noise_seed
     }
 
     /*
@@ -329,6 +444,22 @@ impl VirtualMachine
         Ok(self.memory.read(frame, offset)?)
     }
 
+    pub fn memory_frame_size(&self, frame: usize) -> Result<usize,VirtualMachineError> {
+        self.memory.frame_size(frame)
+    }
+
+    pub fn memory_frame_depth(&self) -> usize {
+        self.memory.depth()
+    }
+
+    pub fn memory_cells(&self) -> usize {
+        self.memory.cells()
+    }
+
+    pub fn memory_frame_index(&self, frame: usize) -> Result<usize,VirtualMachineError> {
+        self.memory.frame_index(frame)
+    }
+
     /*
      * Operand stack subsystem
      */
@@ -346,6 +477,10 @@ impl VirtualMachine
         Err(VirtualMachineError::InvalidLabel)
     }
 
+    pub fn operand_stack_size(&self) -> usize {
+        self.operand_stack.size()
+    }
+
     pub fn operand_pop(&mut self) -> Result<Operand,VirtualMachineError> {
         Ok(self.operand_stack.pop()?)
     }
@@ -358,12 +493,26 @@ impl VirtualMachine
      * Address stack 
      */
 
+    // Push a return address, recording the current memory frame depth
     pub fn address_push(&mut self, address: usize) {
-        self.address_stack.push(address);
+        self.address_stack.push(address, self.memory.depth());
     }
 
     pub fn address_pop(&mut self) -> Result<usize,VirtualMachineError> {
-        Ok(self.address_stack.pop()?)
+        Ok(self.address_stack.pop()?.0)
+    }
+
+    // Return address and memory frame depth of the innermost call
+    pub fn address_peek(&self) -> Result<(usize, usize),VirtualMachineError> {
+        self.address_stack.peek()
+    }
+
+    pub fn address_stack(&self) -> &[(usize, usize)] {
+        self.address_stack.entries()
+    }
+
+    pub fn address_label_get(&self, label: &str) -> Option<usize> {
+        self.address_map.get(label).copied()
     }
 
     pub fn address_label_set(&mut self, label: &str, address: usize) {
@@ -374,10 +523,25 @@ impl VirtualMachine
      * Program subsystem
      */
 
+    // Clears execution state and labels, ready for a new program to be loaded
+    pub fn reset(&mut self) {
+        self.memory = Memory::new();
+        self.address_stack = AddressStack::new();
+        self.operand_stack = OperandStack::new();
+        self.address_map.clear();
+        self.program_counter = 0;
+        self.state = VirtualMachineState::Stopped;
+    }
+
     pub fn program_load(&mut self, program: Vec<Instruction>) {
         self.program = program;
     }
 
+    #[allow(dead_code)]
+    pub fn program(&self) -> &[Instruction] {
+        &self.program
+    }
+
     pub fn program_set_entry_point(&mut self, entry_point: usize) {
         self.program_counter_set_absolute(entry_point);
     }
@@ -390,7 +554,108 @@ impl VirtualMachine
             return Ok(instruction.clone());
         }
 
-        Err(VirtualMachineError::InstructionError)
+        // Execution ran off the end of the program (e.g. a missing halt)
+        Err(VirtualMachineError::ProgramCounterOutOfBounds)
+    }
+}
+
+/*
+ * Snapshots: the complete execution state (program included) so a run can
+ * be checkpointed and resumed later
+ */
+#[allow(dead_code)]
+impl VirtualMachine
+{
+    pub fn snapshot_write(&self, writer: &mut SnapshotWriter) {
+        // The program in the binary format, which decodes without the assembler
+        let program = pixardis_encode_program(&self.program);
+        writer.write_usize(program.len());
+        writer.write_bytes(&program);
+
+        writer.write_usize(self.address_map.len());
+        for (label, address) in self.address_map.iter() {
+            writer.write_str(label);
+            writer.write_usize(*address);
+        }
+
+        writer.write_usize(self.program_counter);
+
+        match self.state {
+            VirtualMachineState::Running => writer.write_u8(0),
+            VirtualMachineState::Paused => writer.write_u8(1),
+            VirtualMachineState::Stopped => writer.write_u8(2),
+            VirtualMachineState::Delayed(time_stamp, cooldown) => {
+                writer.write_u8(3);
+                writer.write_f64(time_stamp);
+                writer.write_f64(cooldown);
+            },
+        }
+
+        writer.write_usize(self.operand_stack.stack.len());
+        for operand in self.operand_stack.stack.iter() {
+            writer.write_operand(operand);
+        }
+
+        writer.write_usize(self.address_stack.stack.len());
+        for (address, frame_depth) in self.address_stack.stack.iter() {
+            writer.write_usize(*address);
+            writer.write_usize(*frame_depth);
+        }
+
+        writer.write_usize(self.memory.stack.len());
+        for frame in self.memory.stack.iter() {
+            writer.write_usize(frame.stack.len());
+            for operand in frame.stack.iter() {
+                writer.write_operand(operand);
+            }
+        }
+
+        writer.write_u64(self.random_number_generator.get_seed());
+        writer.write_u64(self.noise_seed);
+    }
+
+    pub fn snapshot_read(reader: &mut SnapshotReader) -> Result<VirtualMachine, VirtualMachineError> {
+        let mut machine = VirtualMachine::new();
+
+        let length = reader.read_count(1)?;
+        machine.program = pixardis_decode_program(reader.read_bytes(length)?)
+            .ok_or(VirtualMachineError::InvalidSnapshot)?;
+
+        for _ in 0..reader.read_count(16)? {
+            let label = reader.read_string()?;
+            machine.address_map.insert(label, reader.read_usize()?);
+        }
+
+        machine.program_counter = reader.read_usize()?;
+
+        machine.state = match reader.read_u8()? {
+            0 => VirtualMachineState::Running,
+            1 => VirtualMachineState::Paused,
+            2 => VirtualMachineState::Stopped,
+            3 => VirtualMachineState::Delayed(reader.read_f64()?, reader.read_f64()?),
+            _ => Err(VirtualMachineError::InvalidSnapshot)?,
+        };
+
+        for _ in 0..reader.read_count(9)? {
+            machine.operand_stack.push(reader.read_operand()?);
+        }
+
+        for _ in 0..reader.read_count(16)? {
+            machine.address_stack.push(reader.read_usize()?, reader.read_usize()?);
+        }
+
+        for _ in 0..reader.read_count(8)? {
+            let mut frame = StackFrame::new(0);
+            for _ in 0..reader.read_count(9)? {
+                frame.stack.push(reader.read_operand()?);
+            }
+            machine.memory.stack.push(frame);
+        }
+
+        machine.random_number_generator = fastrand::Rng::with_seed(reader.read_u64()?);
+        machine.noise_seed = reader.read_u64()?;
+
+        Ok(machine)
     }
 }
 
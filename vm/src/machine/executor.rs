@@ -1,9 +1,9 @@
-use super::architecture::VirtualMachineError;
+use super::architecture::{VirtualMachineError, VirtualMachineStepResult};
 
 pub trait Executor
 {
     fn run(&mut self) -> Result<(), VirtualMachineError>;
-    fn step(&mut self, cycles: usize) -> Result<(), VirtualMachineError>;
+    fn step(&mut self, cycles: usize) -> Result<VirtualMachineStepResult, VirtualMachineError>;
     fn stop(&mut self) -> Result<(), VirtualMachineError>;
     fn reset(&mut self) -> Result<(), VirtualMachineError>;
     fn delay(&mut self, millis: u64) -> Result<(), VirtualMachineError>;
@@ -1,2 +1,4 @@
 pub mod architecture;
-pub mod executor;
\ No newline at end of file
+pub mod executor;
+pub mod observer;
+pub mod snapshot;
\ No newline at end of file
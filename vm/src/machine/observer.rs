@@ -0,0 +1,35 @@
+use shared::pixardis::PixardisInstruction;
+
+use super::architecture::{Operand, VirtualMachineError};
+
+///
+/// Hooks into VM execution; embedders (debuggers, profilers, tracers) implement
+/// the callbacks they are interested in and register the observer with the VM.
+/// Addresses are program counter values (instruction indices).
+///
+pub trait Observer
+{
+    // Called before an instruction is executed
+    fn on_instruction(&mut self, _address: usize, _instruction: &PixardisInstruction) { }
+
+    // Called after a call has transferred control to its target
+    fn on_call(&mut self, _address: usize, _target: usize) { }
+
+    // Called after a return has transferred control back to the caller
+    fn on_return(&mut self, _address: usize, _target: usize) { }
+
+    // Called for every pixel written to the display
+    fn on_pixel_write(&mut self, _x: usize, _y: usize, _colour: u64) { }
+
+    // Called when the display is cleared
+    fn on_clear(&mut self, _colour: u64) { }
+
+    // Called after each instruction with the operand stack (bottom first)
+    fn on_stack(&mut self, _address: usize, _stack: &[Operand]) { }
+
+    // Called with each line produced by a print instruction
+    fn on_print(&mut self, _text: &str) { }
+
+    // Called when an instruction raises an error
+    fn on_error(&mut self, _address: usize, _error: &VirtualMachineError) { }
+}
@@ -0,0 +1,154 @@
+use alloc::{string::String, vec::Vec};
+
+use super::architecture::{Operand, VirtualMachineError};
+
+//
+// Byte encoding used by VM snapshots (save/load state). Values are written
+// little-endian and sequences are prefixed with their length; the layout is
+// only meant to be read back by the same version of the VM.
+//
+pub struct SnapshotWriter {
+    bytes: Vec<u8>,
+}
+
+#[allow(dead_code)]
+impl SnapshotWriter {
+    pub fn new() -> SnapshotWriter {
+        SnapshotWriter {
+            bytes: Vec::new(),
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    pub fn write_usize(&mut self, value: usize) {
+        self.write_u64(value as u64);
+    }
+
+    pub fn write_f64(&mut self, value: f64) {
+        self.write_u64(value.to_bits());
+    }
+
+    pub fn write_str(&mut self, value: &str) {
+        self.write_usize(value.len());
+        self.write_bytes(value.as_bytes());
+    }
+
+    pub fn write_operand(&mut self, operand: &Operand) {
+        match operand {
+            Operand::Colour(value) => { self.write_u8(0); self.write_u64(*value); },
+            Operand::Integer(value) => { self.write_u8(1); self.write_u64(*value as u64); },
+            Operand::Real(value) => { self.write_u8(2); self.write_f64(*value); },
+        }
+    }
+}
+
+impl Default for SnapshotWriter {
+    fn default() -> SnapshotWriter {
+        SnapshotWriter::new()
+    }
+}
+
+//
+// Reads a snapshot back; any truncated or malformed field is an
+// InvalidSnapshot error
+//
+pub struct SnapshotReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+#[allow(dead_code)]
+impl<'a> SnapshotReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> SnapshotReader<'a> {
+        SnapshotReader {
+            bytes,
+            position: 0,
+        }
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+
+    pub fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], VirtualMachineError> {
+        let end = self.position.checked_add(count)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or(VirtualMachineError::InvalidSnapshot)?;
+
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, VirtualMachineError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, VirtualMachineError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(VirtualMachineError::InvalidSnapshot),
+        }
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, VirtualMachineError> {
+        let bytes = self.read_bytes(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn read_usize(&mut self) -> Result<usize, VirtualMachineError> {
+        usize::try_from(self.read_u64()?).map_err(|_| VirtualMachineError::InvalidSnapshot)
+    }
+
+    // A length prefix, checked against the bytes left so a corrupt snapshot
+    // can't trigger a huge allocation
+    pub fn read_count(&mut self, element_size: usize) -> Result<usize, VirtualMachineError> {
+        let count = self.read_usize()?;
+
+        match count.checked_mul(element_size) {
+            Some(size) if size <= self.bytes.len() - self.position => Ok(count),
+            _ => Err(VirtualMachineError::InvalidSnapshot),
+        }
+    }
+
+    pub fn read_f64(&mut self) -> Result<f64, VirtualMachineError> {
+        Ok(f64::from_bits(self.read_u64()?))
+    }
+
+    pub fn read_string(&mut self) -> Result<String, VirtualMachineError> {
+        let length = self.read_count(1)?;
+        let bytes = self.read_bytes(length)?;
+
+        String::from_utf8(bytes.to_vec()).map_err(|_| VirtualMachineError::InvalidSnapshot)
+    }
+
+    pub fn read_operand(&mut self) -> Result<Operand, VirtualMachineError> {
+        match self.read_u8()? {
+            0 => Ok(Operand::Colour(self.read_u64()?)),
+            1 => Ok(Operand::Integer(self.read_u64()? as i64)),
+            2 => Ok(Operand::Real(self.read_f64()?)),
+            _ => Err(VirtualMachineError::InvalidSnapshot),
+        }
+    }
+}
@@ -1,38 +1,75 @@
-mod pixardis;
-mod machine;
+use vm::{machine, pixardis};
 
 use std::io;
 
 use macroquad::prelude::*;
 
-#[macroquad::main("Chroma VM (Pixardis Emulator)")]
+//
+// Window configuration; uses the program's title when its metadata has one
+// (and only one program is running)
+//
+fn window_conf() -> Conf
+{
+    let context = process_cmd_args();
+
+    let title = match context.input.as_slice() {
+        [input] => shared::io::read_file_to_string(input).ok()
+            .and_then(|source| PixardisMetadata::from_source(&source).title),
+        _ => None,
+    };
+
+    Conf {
+        window_title: match title {
+            Some(title) => format!("{} - Chroma VM", title),
+            None => String::from("Chroma VM (Pixardis Emulator)"),
+        },
+        fullscreen: context.fullscreen,
+        ..Default::default()
+    }
+}
+
+#[macroquad::main(window_conf)]
 
 async fn main() -> Result<(), io::Error> 
 {
     // Parse command line arguments; place the results in a context object.
     let context = process_cmd_args();
 
-    // Initialise VM
-    let mut vm = PixardisVirtualMachine::new(context.width.unwrap(), context.height.unwrap());
+    // Programs fill the layout row by row; without one they sit side by side
+    let (columns, rows) = context.layout.unwrap_or((context.input.len(), 1));
 
-    // Get desired log level
-    let log_level = match context.log_level {
-        Some(1) => PixardisLogLevel::Error,
-        Some(2) => PixardisLogLevel::Full,
-        _ => PixardisLogLevel::None,
-    };
+    if context.coverage.is_some() && context.input.len() > 1 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "--coverage takes a single program"));
+    }
 
-    // Set log level
-    vm.log_level_set(log_level);
+    if columns * rows < context.input.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("layout {}x{} has room for {} programs, not {}", columns, rows, columns * rows, context.input.len())
+        ));
+    }
 
-    // Get the file path from the context object
-    let file_path = context.input.as_str();
-    
-    // Read source file
-    let source = shared::io::read_file_to_string(&file_path)?;
-    
-    // Load program from source (text)
-    vm.load_program_from_source(&source);
+    // Window state the keys toggle at run time
+    let mut fullscreen = context.fullscreen;
+    let mut scaling = context.scaling;
+    let mut grid = context.grid;
+    let mut coordinates = context.coordinates;
+    let background = Color::from_hex(context.background);
+
+    // One VM per program, each with its own framebuffer and cycle budget
+    let mut vms = Vec::with_capacity(context.input.len());
+
+    for (index, input) in context.input.iter().enumerate() {
+        // Name messages after the program when several are running
+        let name = if context.input.len() > 1 { format!("{}: ", input) } else { String::new() };
+
+        vms.push(PanelVm {
+            vm: create_vm(&context, input, &name)?,
+            name,
+            cycles: *context.cycles.get(index).or(context.cycles.last()).unwrap() as usize,
+            watchdog_reported: false,
+        });
+    }
     
     loop {
         // Start execution when S is pressed
@@ -54,6 +91,17 @@ async fn main() -> Result<(), io::Error>
             DARKGRAY,
         );
 
+        let keys = "[ ] speed, [f] fullscreen, [m] scaling, [g] grid, [c] coordinates, click to inspect";
+        let keys_size = measure_text(keys, None, 20, 1.0);
+
+        draw_text(
+            keys,
+            screen_width() / 2. - keys_size.width / 2.,
+            screen_height() / 2. + text_size.height * 2. + keys_size.height / 2.,
+            20.,
+            GRAY,
+        );
+
         next_frame().await
     }
 
@@ -63,38 +111,318 @@ async fn main() -> Result<(), io::Error>
             break;
         }
 
-        // Run for a given number of cycles
-        let _ = vm.step(context.cycles.unwrap() as usize);
-
-        // Draw the VM framebuffer
-        let (width, height, colours) = vm.framebuffer();
-
-        // Determine cell size (from screen size and framebuffer dimensions)
-        let cell_edge_size = (screen_width() / width as f32).min(screen_height() / height as f32);
-        
-        for y in 0..height {
-            for x in 0..width {
-                let colour = colours[y * width + x];
-
-                draw_rectangle(
-                    x as f32 * cell_edge_size,
-                    (height - y - 1) as f32 * cell_edge_size,
-                    cell_edge_size,
-                    cell_edge_size,
-                    Color::from_hex(colour as u32),
-                );
+        // ] and [ double and halve the programs' speed; 0 restores it
+        let current_scale = vms[0].vm.time_scale();
+
+        let time_scale = if is_key_pressed(KeyCode::RightBracket) {
+            Some((current_scale * 2.0).min(MAX_TIME_SCALE))
+        } else if is_key_pressed(KeyCode::LeftBracket) {
+            Some((current_scale / 2.0).max(1.0 / MAX_TIME_SCALE))
+        } else if is_key_pressed(KeyCode::Key0) {
+            Some(1.0)
+        } else {
+            None
+        };
+
+        if let Some(time_scale) = time_scale {
+            for panel in vms.iter_mut() {
+                panel.vm.time_scale_set(time_scale);
+            }
+
+            eprintln!("Speed: {}x", time_scale);
+        }
+
+        // F toggles fullscreen and M cycles through the scaling modes
+        if is_key_pressed(KeyCode::F) {
+            fullscreen = !fullscreen;
+            set_fullscreen(fullscreen);
+        }
+
+        if is_key_pressed(KeyCode::M) {
+            scaling = scaling.next();
+            eprintln!("Scaling: {:?}", scaling);
+        }
+
+        // G toggles the pixel grid and C the coordinates under the cursor
+        if is_key_pressed(KeyCode::G) {
+            grid = !grid;
+        }
+
+        if is_key_pressed(KeyCode::C) {
+            coordinates = !coordinates;
+        }
+
+        // Framebuffer cell under the cursor, drawn once every panel is
+        let (mouse_x, mouse_y) = mouse_position();
+        let mut tooltip = None;
+
+        clear_background(background);
+
+        // Each program gets an equal cell of the window
+        let cell_width = screen_width() / columns as f32;
+        let cell_height = screen_height() / rows as f32;
+
+        for (index, panel) in vms.iter_mut().enumerate() {
+            let vm = &mut panel.vm;
+
+            // Run for a given number of cycles, or one display frame of virtual time
+            let stopped = match context.fps {
+                Some(fps) => vm.advance_time(1.0 / fps, panel.cycles).is_err(),
+                None => vm.step(panel.cycles).is_err(),
+            };
+
+            // When recording coverage the VM returns errors (and halt) rather
+            // than exiting, so the report can be written first
+            if stopped && context.coverage.is_some() {
+                write_coverage(&context, vm)?;
+                std::process::exit(1);
+            }
+
+            // Warn (once per stall) if the program has stopped producing output
+            if vm.watchdog_tripped() != panel.watchdog_reported {
+                panel.watchdog_reported = vm.watchdog_tripped();
+
+                if panel.watchdog_reported {
+                    eprintln!("Warning: {}program appears stuck (no write, print or delay in {} million cycles).", panel.name, context.watchdog.unwrap());
+                }
             }
+
+            // Draw the VM framebuffer (as shown, after brightness and gamma)
+            let (width, height, colours) = vm.framebuffer_present();
+
+            // Determine pixel size (from the panel size, framebuffer dimensions
+            // and scaling mode) and centre the framebuffer in its panel
+            let (pixel_width, pixel_height) = scaling.pixel_size(cell_width / width as f32, cell_height / height as f32);
+            let left = (index % columns) as f32 * cell_width + (cell_width - width as f32 * pixel_width) / 2.;
+            let top = (index / columns) as f32 * cell_height + (cell_height - height as f32 * pixel_height) / 2.;
+
+            for y in 0..height {
+                for x in 0..width {
+                    let colour = colours[y * width + x];
+
+                    draw_rectangle(
+                        left + x as f32 * pixel_width,
+                        top + (height - y - 1) as f32 * pixel_height,
+                        pixel_width,
+                        pixel_height,
+                        Color::from_hex(colour as u32),
+                    );
+                }
+            }
+
+            // Lines between display pixels, once they're big enough to see
+            if grid && pixel_width >= GRID_MIN_PIXEL_SIZE && pixel_height >= GRID_MIN_PIXEL_SIZE {
+                for x in 0..=width {
+                    let line_x = left + x as f32 * pixel_width;
+                    draw_line(line_x, top, line_x, top + height as f32 * pixel_height, 1., GRID_COLOUR);
+                }
+
+                for y in 0..=height {
+                    let line_y = top + y as f32 * pixel_height;
+                    draw_line(left, line_y, left + width as f32 * pixel_width, line_y, 1., GRID_COLOUR);
+                }
+            }
+
+            // Map the cursor back to a framebuffer cell (rows are drawn bottom-up)
+            let cell_x = ((mouse_x - left) / pixel_width).floor();
+            let cell_y = ((mouse_y - top) / pixel_height).floor();
+
+            if cell_x >= 0. && cell_y >= 0. && (cell_x as usize) < width && (cell_y as usize) < height {
+                let (x, y) = (cell_x as usize, height - cell_y as usize - 1);
+
+                if coordinates {
+                    let (_, _, framebuffer) = vm.framebuffer();
+                    tooltip = Some(format!("{}({}, {}) #{:06x}", panel.name, x, y, framebuffer[y * width + x]));
+                }
+
+                // Clicking a pixel reports its colour and what last wrote it
+                if is_mouse_button_pressed(MouseButton::Left) {
+                    if let Some(info) = vm.pixel_inspect(x, y) {
+                        eprintln!("{}", describe_pixel(&panel.name, &info));
+                    }
+                }
+            }
+        }
+
+        if let Some(text) = tooltip {
+            let font_size = 20.;
+            let text_size = measure_text(&text, None, font_size as _, 1.0);
+
+            // Keep the tooltip inside the window
+            let x = (mouse_x + 12.).min(screen_width() - text_size.width - 8.).max(0.);
+            let y = (mouse_y + 12.).min(screen_height() - text_size.height - 8.).max(0.);
+
+            draw_rectangle(x, y, text_size.width + 8., text_size.height + 8., Color::new(0., 0., 0., 0.75));
+            draw_text(&text, x + 4., y + 4. + text_size.offset_y, font_size, WHITE);
         }
 
         next_frame().await
     }
+
+    if context.coverage.is_some() {
+        write_coverage(&context, &vms[0].vm)?;
+    }
     
     Ok(())
 }
 
+//
+// Write the coverage report requested with --coverage
+//
+fn write_coverage(context: &Args, vm: &PixardisVirtualMachine) -> Result<(), io::Error>
+{
+    match &context.coverage {
+        Some(path) => std::fs::write(path, pixardis_coverage_to_json(&vm.line_coverage())),
+        None => Ok(()),
+    }
+}
+
+//
+// Pixel inspector report, e.g. "(3, 4) #ff0000 written by [27] write in draw"
+//
+fn describe_pixel(name: &str, info: &PixardisPixelInfo) -> String
+{
+    let location = format!("{}({}, {}) #{:06x}", name, info.x, info.y, info.colour);
+
+    match &info.writer {
+        Some(writer) => format!("{} written by [{}] {} in {}",
+            location,
+            writer.address,
+            pixardis_instruction_to_string(writer.instruction.clone()),
+            writer.function.as_deref().unwrap_or("?")),
+        None => format!("{} (not written since the program started)", location),
+    }
+}
+
+//
+// A program running in its own panel of the window
+//
+struct PanelVm {
+    vm: PixardisVirtualMachine,
+    // Prefix for messages about this program (empty when it runs alone)
+    name: String,
+    cycles: usize,
+    watchdog_reported: bool,
+}
+
+//
+// Create a VM for a program with the options from the command line; name
+// prefixes its warnings
+//
+fn create_vm(context: &Args, input: &str, name: &str) -> Result<PixardisVirtualMachine, io::Error>
+{
+    // Read source file
+    let source = shared::io::read_file_to_string(input)?;
+
+    // Warn when the program, its source or its debug information has moved
+    // on from the build the manifest describes
+    if let Some(path) = &context.manifest {
+        let program: Vec<PixardisInstruction> = source.split('\n')
+            .map(|line| shared::pixardis::pixardis_instruction_from_string(line.to_string()))
+            .collect();
+
+        for warning in pixardis::manifest::pixardis_read_manifest(path)?.stale_warnings(&program, context.debug_info.as_deref()) {
+            eprintln!("Warning: {}{}.", name, warning);
+        }
+    }
+
+    if context.strict {
+        for (line, warning) in shared::pixardis::pixardis_assembly_warnings(&source) {
+            eprintln!("Warning: {}line {}: {}.", name, line, warning);
+        }
+    }
+
+    // Initialise VM from the shared machine options, then apply any
+    // per-category log level overrides
+    let mut vm = context.machine.create(&PixardisMetadata::from_source(&source))?;
+
+    if context.log_exec.is_some() {
+        vm.log_level_set_category(PixardisLogCategory::Exec, log_level_from(context.log_exec));
+    }
+
+    if context.log_memory.is_some() {
+        vm.log_level_set_category(PixardisLogCategory::Memory, log_level_from(context.log_memory));
+    }
+
+    if context.log_display.is_some() {
+        vm.log_level_set_category(PixardisLogCategory::Display, log_level_from(context.log_display));
+    }
+
+    vm.clip_mode_set(context.clip.into());
+
+    if context.print_raw {
+        vm.print_format_set(PixardisPrintFormat::Raw);
+    }
+
+    // Pace delays by displayed frames rather than wall time
+    vm.virtual_clock_set(context.fps.is_some());
+
+    // Enable watchdog if requested (specified in millions of cycles)
+    vm.watchdog_set(context.watchdog.map(|millions| millions * 1_000_000));
+
+    // Remember which instruction writes each pixel, for the pixel inspector
+    vm.pixel_tracking_set(true);
+
+    // Print the top of the operand stack after every instruction
+    if let Some(depth) = context.trace_stack {
+        vm.observer_add(Box::new(StackTracer { depth, instruction: None }));
+    }
+
+    vm.storage_quota_set(context.storage_quota);
+
+    vm.load_limits_set(PixardisLoadLimits { max_instructions: context.max_instructions, max_labels: context.max_labels });
+
+    // Load program from source (text), starting at .main unless told otherwise
+    match &context.entry {
+        Some(entry) => vm.load_program_from_source_with_entry(&source, entry).or_else(|error| match error {
+            VirtualMachineError::InvalidLabel => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}entry label {} is not defined", name, entry)
+            )),
+            _ => Ok(()),
+        })?,
+        None => vm.load_program_from_source(&source),
+    }
+
+    if let Some(rejection) = &vm.load_report().rejection {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{}program not loaded: {}", name, rejection)));
+    }
+
+    for warning in vm.load_report().warnings.iter() {
+        eprintln!("Warning: {}{}.", name, warning);
+    }
+
+    if let Some(path) = &context.debug_info {
+        vm.source_lines_set(pixardis::debug_info::pixardis_read_line_table(path)?);
+    }
+
+    // Replayed input counts cycles from the start of the loaded program
+    if let Some(path) = &context.replay_input {
+        vm.input_replay_set(pixardis::input::pixardis_read_input(path)?);
+    }
+
+    // Record executed instructions for the coverage report
+    if context.coverage.is_some() {
+        vm.coverage_set(true);
+        vm.exit_on_error_set(false);
+    }
+
+    Ok(vm)
+}
+
 use clap::Parser as ClapParser;
-use machine::executor::Executor;
-use pixardis::pixardis::{PixardisVirtualMachine, PixardisLogLevel};
+use machine::{architecture::{Operand, VirtualMachineError}, executor::Executor, observer::Observer};
+use pixardis::coverage::pixardis_coverage_to_json;
+use shared::pixardis::{PixardisInstruction, pixardis_instruction_to_string};
+use pixardis::pixardis::{PixardisVirtualMachine, PixardisLogCategory, PixardisMetadata, PixardisPrintFormat, PixardisPixelInfo, PixardisLoadLimits};
+use vm::cli::{MachineArgs, ClipArg, parse_fps, log_level_from};
+
+// Grid overlay colour, and the smallest display pixel (in window pixels) it's drawn over
+const GRID_COLOUR: Color = Color::new(0.5, 0.5, 0.5, 0.5);
+const GRID_MIN_PIXEL_SIZE: f32 = 4.;
+
+// Fastest (and, inverted, slowest) speed the [ and ] keys reach
+const MAX_TIME_SCALE: f64 = 16.0;
 
 #[derive(clap::Parser, Debug)]
 #[command(name = "chroma-vm")]
@@ -113,20 +441,83 @@ use pixardis::pixardis::{PixardisVirtualMachine, PixardisLogLevel};
                       Virtual Machine
 ------------------------------------------------------------")]
 struct Args {
-    #[arg(short, long, value_name = "FILE")]
-    input: String,
+    #[arg(short, long, value_name = "FILE", required = true, help = "Program to run. May be repeated to run several programs side by side.")]
+    input: Vec<String>,
+
+    #[arg(short, long, help = "VM instruction cycles per frame [default = 250]. May be repeated to give each program its own budget (the last applies to the rest).", default_value = "250")]
+    cycles: Vec<u32>,
+
+    #[arg(long, value_name = "COLSxROWS", value_parser = parse_layout, help = "Arrange the programs in a grid [default = one row].")]
+    layout: Option<(usize, usize)>,
+
+    #[arg(long, help = "Start in fullscreen (toggle with f).")]
+    fullscreen: bool,
+
+    #[arg(long, value_enum, help = "How the display is scaled to the window (cycle with m).", default_value = "fit")]
+    scaling: ScalingArg,
+
+    #[arg(long, help = "Draw a grid between display pixels (toggle with g).")]
+    grid: bool,
+
+    #[arg(long, help = "Show the display coordinates and colour under the mouse cursor (toggle with c).")]
+    coordinates: bool,
+
+    #[arg(long, value_name = "#RRGGBB", value_parser = parse_background, help = "Colour of the window area the display doesn't cover.", default_value = "#000000")]
+    background: u32,
+
+    #[command(flatten)]
+    machine: MachineArgs,
+
+    #[arg(long, help = "Log level for instruction execution (overrides -L).")]
+    log_exec: Option<usize>,
+
+    #[arg(long, help = "Log level for memory operations (overrides -L).")]
+    log_memory: Option<usize>,
+
+    #[arg(long, help = "Log level for display operations (overrides -L).")]
+    log_display: Option<usize>,
 
-    #[arg(short, long, help = "VM instruction cycles per frame [default = 250].", default_value = "250")]
-    cycles: Option<u32>,
+    #[arg(short, long, help = "Warn if no write, print or delay occurs within N million cycles.")]
+    watchdog: Option<usize>,
 
-    #[arg(short = 'x', long, help = "VM display width [default = 64].", default_value = "64")]
-    width: Option<usize>,
+    #[arg(long, value_name = "N", help = "Refuse to load programs of more than N instructions.")]
+    max_instructions: Option<usize>,
 
-    #[arg(short = 'y', long, help = "VM display height [default = 48].", default_value = "48")]
-    height: Option<usize>,
+    #[arg(long, value_name = "N", help = "Refuse to load programs defining more than N labels.")]
+    max_labels: Option<usize>,
 
-    #[arg(short = 'L', help = "Log level [default = 0].", default_value = "0")]
-    log_level: Option<usize>,
+    #[arg(long, value_enum, help = "Handling of pixel coordinates outside the display.", default_value = "clip")]
+    clip: ClipArg,
+
+    #[arg(long, help = "Print bare values, without the \"int ::\"/\"real ::\" type prefix.")]
+    print_raw: bool,
+
+    #[arg(long, value_parser = parse_fps, help = "Time delays against a virtual clock advanced 1/FPS seconds per displayed frame (cycles per frame still apply).")]
+    fps: Option<f64>,
+
+    #[arg(long, value_name = "N", help = "Print the top N operand stack entries after each instruction.")]
+    trace_stack: Option<usize>,
+
+    #[arg(long, value_name = "KEYS", help = "Most keys the program may store.", default_value = "64")]
+    storage_quota: usize,
+
+    #[arg(long, value_name = "FILE", help = "Replay host input recorded in FILE, one \"cycle x y #colour\" line per pixel written, each when the program reaches its cycle.")]
+    replay_input: Option<String>,
+
+    #[arg(long, value_name = "LABEL", help = "Start execution at this label instead of .main.")]
+    entry: Option<String>,
+
+    #[arg(long, value_name = "FILE", help = "Read source lines from FILE (chroma --emit dbg:FILE) so runtime errors, such as failed assertions, name them.")]
+    debug_info: Option<String>,
+
+    #[arg(long, value_name = "FILE", help = "Check the program against its build manifest (chroma --emit manifest:FILE), warning if it, its source or its debug information is stale.")]
+    manifest: Option<String>,
+
+    #[arg(long, value_name = "FILE", requires = "debug_info", help = "Write the source lines that executed to FILE (JSON) when the program stops or the window is closed.")]
+    coverage: Option<String>,
+
+    #[arg(long, help = "Warn about deprecated mnemonic aliases, upper-case mnemonics and lines that don't parse.")]
+    strict: bool,
 
     //#[arg(short, long, help = "Run VM in debug mode.")]
     //debug: Option<bool>,
@@ -135,6 +526,97 @@ struct Args {
     //pure: Option<bool>,
 }
 
+//
+// Parse a panel layout given as columns x rows, e.g. 2x1
+//
+fn parse_layout(layout: &str) -> Result<(usize, usize), String>
+{
+    let size = layout.trim().split_once(['x', 'X'])
+        .and_then(|(columns, rows)| Some((columns.trim().parse::<usize>().ok()?, rows.trim().parse::<usize>().ok()?)));
+
+    match size {
+        Some((columns, rows)) if columns > 0 && rows > 0 => Ok((columns, rows)),
+        _ => Err(format!("invalid layout '{}' (expected COLSxROWS, e.g. 2x1)", layout)),
+    }
+}
+
+//
+// Parse a background colour given as #rrggbb
+//
+fn parse_background(colour: &str) -> Result<u32, String>
+{
+    match colour.trim().strip_prefix('#') {
+        Some(hex) if hex.len() == 6 => u32::from_str_radix(hex, 16).map_err(|_| format!("invalid colour '{}' (expected #rrggbb)", colour)),
+        _ => Err(format!("invalid colour '{}' (expected #rrggbb)", colour)),
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum ScalingArg {
+    // Largest size that keeps the aspect ratio (letterboxed)
+    Fit,
+    // Largest whole multiple of the display size, for crisp pixels
+    Integer,
+    // Fill the window, ignoring the aspect ratio
+    Stretch,
+}
+
+impl ScalingArg {
+    fn next(self) -> ScalingArg {
+        match self {
+            ScalingArg::Fit => ScalingArg::Integer,
+            ScalingArg::Integer => ScalingArg::Stretch,
+            ScalingArg::Stretch => ScalingArg::Fit,
+        }
+    }
+
+    // Size of a display pixel given the sizes that would fill the panel
+    // horizontally and vertically
+    fn pixel_size(self, fill_width: f32, fill_height: f32) -> (f32, f32) {
+        match self {
+            ScalingArg::Fit => (fill_width.min(fill_height), fill_width.min(fill_height)),
+            ScalingArg::Integer => {
+                let size = fill_width.min(fill_height).floor().max(1.);
+                (size, size)
+            },
+            ScalingArg::Stretch => (fill_width, fill_height),
+        }
+    }
+}
+
+//
+// Traces each instruction with the top of the operand stack, top entry
+// first, e.g. "[   14] add              | 10 3 (+2)"
+//
+struct StackTracer {
+    depth: usize,
+    instruction: Option<PixardisInstruction>,
+}
+
+impl Observer for StackTracer {
+    fn on_instruction(&mut self, _address: usize, instruction: &PixardisInstruction) {
+        self.instruction = Some(instruction.clone());
+    }
+
+    fn on_stack(&mut self, address: usize, stack: &[Operand]) {
+        let instruction = self.instruction.take().map(pixardis_instruction_to_string).unwrap_or_default();
+
+        let mut entries: Vec<String> = stack.iter().rev().take(self.depth)
+            .map(|operand| match operand {
+                Operand::Colour(value) => format!("#{:06x}", value),
+                Operand::Integer(value) => value.to_string(),
+                Operand::Real(value) => format!("{:?}", value),
+            })
+            .collect();
+
+        if stack.len() > self.depth {
+            entries.push(format!("(+{})", stack.len() - self.depth));
+        }
+
+        eprintln!("[{:>5}] {:<16} | {}", address, instruction, entries.join(" "));
+    }
+}
+
 //
 // Process compiler command line arguments
 //
@@ -143,4 +625,4 @@ fn process_cmd_args() -> Args
     let args = Args::parse();
 
     args
-}
\ No newline at end of file
+}
@@ -0,0 +1,84 @@
+use alloc::{
+    format,
+    string::String,
+    vec::Vec,
+};
+
+//
+// How much of one source line's code ran: the number of instructions the
+// compiler generated for the line and how many of them executed
+//
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PixardisLineCoverage {
+    pub line: usize,
+    pub instructions: usize,
+    pub executed: usize,
+}
+
+//
+// Per-line coverage, in line order, from an executed-instruction bitmap and
+// the compiler's line table (both indexed by address); instructions of
+// unknown line (0) are left out
+//
+pub fn pixardis_line_coverage(executed: &[bool], lines: &[usize]) -> Vec<PixardisLineCoverage> {
+    let mut coverage: Vec<PixardisLineCoverage> = Vec::new();
+
+    let mut instructions: Vec<(usize, bool)> = lines.iter().enumerate()
+        .filter(|(_, &line)| line > 0)
+        .map(|(address, &line)| (line, executed.get(address).copied().unwrap_or(false)))
+        .collect();
+
+    instructions.sort_by_key(|&(line, _)| line);
+
+    for (line, ran) in instructions {
+        match coverage.last_mut() {
+            Some(last) if last.line == line => {
+                last.instructions += 1;
+                last.executed += ran as usize;
+            },
+            _ => coverage.push(PixardisLineCoverage { line, instructions: 1, executed: ran as usize }),
+        }
+    }
+
+    coverage
+}
+
+//
+// A coverage report as JSON: each line's instruction counts, and how many of
+// the lines ran at all
+//
+pub fn pixardis_coverage_to_json(coverage: &[PixardisLineCoverage]) -> String {
+    let lines: Vec<String> = coverage.iter()
+        .map(|line| format!("    {{ \"line\": {}, \"instructions\": {}, \"executed\": {} }}",
+            line.line, line.instructions, line.executed))
+        .collect();
+
+    let covered = coverage.iter().filter(|line| line.executed > 0).count();
+
+    format!("{{\n  \"lines\": [\n{}\n  ],\n  \"covered\": {},\n  \"total\": {}\n}}\n",
+        lines.join(",\n"), covered, coverage.len())
+}
+
+//
+// Lines none of whose code ran, with consecutive lines collapsed into
+// ranges, e.g. "4, 9-11"
+//
+pub fn pixardis_uncovered_lines(coverage: &[PixardisLineCoverage]) -> String {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+
+    for line in coverage.iter().filter(|line| line.executed == 0).map(|line| line.line) {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == line => *end = line,
+            _ => ranges.push((line, line)),
+        }
+    }
+
+    let ranges: Vec<String> = ranges.iter()
+        .map(|&(start, end)| match start == end {
+            true => format!("{}", start),
+            false => format!("{}-{}", start, end),
+        })
+        .collect();
+
+    ranges.join(", ")
+}
@@ -0,0 +1,34 @@
+use std::fs;
+use std::io;
+
+//
+// Read the line table from a compiler debug information file (chroma --emit
+// dbg:FILE): the source line of each instruction, indexed by address, with 0
+// where the line is unknown. Hosts pass it to source_lines_set so runtime
+// errors can name source lines.
+//
+pub fn pixardis_read_line_table(path: &str) -> Result<Vec<usize>, io::Error> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, message));
+
+    let contents = fs::read_to_string(path)?;
+    let debug_info: serde_json::Value = serde_json::from_str(&contents)
+        .map_err(|error| invalid(&error.to_string()))?;
+
+    let instructions = debug_info.get("instructions")
+        .and_then(|instructions| instructions.as_array())
+        .ok_or_else(|| invalid("no instructions array"))?;
+
+    let mut lines = vec![0; instructions.len()];
+
+    for instruction in instructions {
+        let address = instruction.get("address").and_then(|address| address.as_u64());
+        let line = instruction.get("line").and_then(|line| line.as_u64()).unwrap_or(0);
+
+        match address {
+            Some(address) if (address as usize) < lines.len() => lines[address as usize] = line as usize,
+            _ => return Err(invalid("instruction without a valid address")),
+        }
+    }
+
+    Ok(lines)
+}
@@ -0,0 +1,185 @@
+use alloc::{
+    boxed::Box,
+    format,
+    rc::Rc,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use core::cell::RefCell;
+
+use shared::pixardis::PixardisInstruction;
+
+use crate::machine::{
+    architecture::VirtualMachineError,
+    observer::Observer,
+};
+
+use super::pixardis::PixardisVirtualMachine;
+
+//
+// Something a running program did that can be seen from outside: the
+// effects of two programs that should behave the same are compared in order
+//
+#[derive(Debug, Clone, PartialEq)]
+pub enum PixardisEffect {
+    Pixel(usize, usize, u64),
+    Clear(u64),
+    Print(String),
+    // The program halted or raised a runtime error (as its Debug text)
+    Stop(String),
+}
+
+//
+// An effect and the instruction that caused it
+//
+#[derive(Debug, Clone)]
+pub struct PixardisTracedEffect {
+    pub address: usize,
+    pub instruction: PixardisInstruction,
+    pub effect: PixardisEffect,
+}
+
+//
+// The first effect on which the two programs disagree, and how many they
+// agreed on before it
+//
+#[derive(Debug, Clone)]
+pub struct PixardisDivergence {
+    pub index: usize,
+    pub reference: PixardisTracedEffect,
+    pub candidate: PixardisTracedEffect,
+}
+
+//
+// Result of a differential run. Each program runs for at most the given
+// number of cycles, so one that does the same work in fewer instructions
+// simply gets further; only effects both programs reached are compared.
+//
+#[derive(Debug, Clone)]
+pub struct PixardisDifferentialReport {
+    // Effects compared (the fewer of the two programs' counts)
+    pub compared: usize,
+    pub reference_effects: usize,
+    pub candidate_effects: usize,
+    pub divergence: Option<PixardisDivergence>,
+}
+
+impl PixardisDifferentialReport {
+    pub fn is_equivalent(&self) -> bool {
+        self.divergence.is_none()
+    }
+}
+
+struct EffectRecorder {
+    effects: Rc<RefCell<Vec<PixardisTracedEffect>>>,
+    current: Option<(usize, PixardisInstruction)>,
+}
+
+impl EffectRecorder {
+    fn record(&mut self, effect: PixardisEffect) {
+        let (address, instruction) = self.current.clone().unwrap_or((0, PixardisInstruction::Nop));
+        self.effects.borrow_mut().push(PixardisTracedEffect { address, instruction, effect });
+    }
+}
+
+impl Observer for EffectRecorder {
+    fn on_instruction(&mut self, address: usize, instruction: &PixardisInstruction) {
+        self.current = Some((address, instruction.clone()));
+    }
+
+    fn on_pixel_write(&mut self, x: usize, y: usize, colour: u64) {
+        self.record(PixardisEffect::Pixel(x, y, colour));
+    }
+
+    fn on_clear(&mut self, colour: u64) {
+        self.record(PixardisEffect::Clear(colour));
+    }
+
+    fn on_print(&mut self, text: &str) {
+        self.record(PixardisEffect::Print(text.to_string()));
+    }
+
+    // Errors fetching an instruction (e.g. running off the end of the
+    // program) come without one
+    fn on_error(&mut self, address: usize, error: &VirtualMachineError) {
+        let instruction = match self.current.take() {
+            Some((current, instruction)) if current == address => instruction,
+            _ => PixardisInstruction::Nop,
+        };
+
+        self.current = Some((address, instruction));
+        self.record(PixardisEffect::Stop(format!("{:?}", error)));
+    }
+}
+
+//
+// Run a reference program (e.g. the code generator's output) and a candidate
+// (e.g. the optimiser's) on two VMs of the given size, each set up by
+// configure (seed, parameters, input) and run for at most cycles
+// instructions, and compare what they do. Delays don't wait, and time stands
+// still, so runs are repeatable.
+//
+pub fn pixardis_differential_run<F>(reference: Vec<PixardisInstruction>, candidate: Vec<PixardisInstruction>, width: usize, height: usize, cycles: usize, configure: F) -> PixardisDifferentialReport
+where
+    F: Fn(&mut PixardisVirtualMachine),
+{
+    let reference = traced_run(reference, width, height, cycles, &configure);
+    let candidate = traced_run(candidate, width, height, cycles, &configure);
+
+    let divergence = reference.iter().zip(candidate.iter()).enumerate()
+        .find(|(_, (reference, candidate))| reference.effect != candidate.effect)
+        .map(|(index, (reference, candidate))| PixardisDivergence {
+            index,
+            reference: reference.clone(),
+            candidate: candidate.clone(),
+        });
+
+    PixardisDifferentialReport {
+        compared: reference.len().min(candidate.len()),
+        reference_effects: reference.len(),
+        candidate_effects: candidate.len(),
+        divergence,
+    }
+}
+
+fn traced_run<F>(program: Vec<PixardisInstruction>, width: usize, height: usize, cycles: usize, configure: &F) -> Vec<PixardisTracedEffect>
+where
+    F: Fn(&mut PixardisVirtualMachine),
+{
+    let mut vm = PixardisVirtualMachine::new(width, height);
+
+    // Errors end the run (and are an effect) rather than the process, and
+    // output is only wanted for comparison
+    #[cfg(all(feature = "std", any(not(target_arch = "wasm32"), target_os = "wasi")))]
+    {
+        vm.exit_on_error_set(false);
+        vm.console_print_set(false);
+    }
+
+    #[cfg(feature = "std")]
+    vm.virtual_clock_set(true);
+
+    vm.random_seed_set(0);
+    configure(&mut vm);
+
+    let effects = Rc::new(RefCell::new(Vec::new()));
+    vm.observer_add(Box::new(EffectRecorder { effects: effects.clone(), current: None }));
+    vm.load_program(program);
+
+    let mut executed = 0;
+
+    while executed < cycles {
+        match vm.run_frame(cycles - executed) {
+            Ok(count) => {
+                executed += count;
+                if vm.delay_requested().is_none() {
+                    break;
+                }
+            },
+            Err(_) => break,
+        }
+    }
+
+    effects.take()
+}
@@ -0,0 +1,70 @@
+use alloc::{
+    format,
+    string::String,
+    vec::Vec,
+};
+
+//
+// A pixel the host wrote into the framebuffer (the VM's input channel), and
+// the number of instructions the program had executed when it did
+//
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PixardisInputEvent {
+    pub cycle: u64,
+    pub x: usize,
+    pub y: usize,
+    pub colour: u64,
+}
+
+//
+// Input events as text, one "cycle x y #colour" line per event, e.g. for a
+// bug report to be replayed with chroma-run --replay-input
+//
+pub fn pixardis_input_to_string(events: &[PixardisInputEvent]) -> String {
+    events.iter()
+        .map(|event| format!("{} {} {} #{:06x}\n", event.cycle, event.x, event.y, event.colour))
+        .collect()
+}
+
+//
+// Input events from text written by pixardis_input_to_string; blank lines
+// are skipped, and the first malformed line is an error naming it
+//
+pub fn pixardis_input_from_string(text: &str) -> Result<Vec<PixardisInputEvent>, String> {
+    let mut events: Vec<PixardisInputEvent> = Vec::new();
+
+    for (index, line) in text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        let event = input_event_from_string(line)
+            .ok_or_else(|| format!("line {}: expected \"cycle x y #colour\", found \"{}\"", index + 1, line.trim()))?;
+
+        if events.last().is_some_and(|last| last.cycle > event.cycle) {
+            return Err(format!("line {}: cycle {} is earlier than the event before it", index + 1, event.cycle));
+        }
+
+        events.push(event);
+    }
+
+    Ok(events)
+}
+
+fn input_event_from_string(line: &str) -> Option<PixardisInputEvent> {
+    let mut fields = line.split_whitespace();
+
+    let cycle = fields.next()?.parse().ok()?;
+    let x = fields.next()?.parse().ok()?;
+    let y = fields.next()?.parse().ok()?;
+    let colour = u64::from_str_radix(fields.next()?.strip_prefix('#')?, 16).ok()?;
+
+    fields.next().is_none().then_some(PixardisInputEvent { cycle, x, y, colour })
+}
+
+//
+// Read input events from a file written with pixardis_input_to_string
+//
+#[cfg(feature = "std")]
+pub fn pixardis_read_input(path: &str) -> Result<Vec<PixardisInputEvent>, std::io::Error> {
+    let text = std::fs::read_to_string(path)?;
+
+    pixardis_input_from_string(&text)
+        .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path, error)))
+}
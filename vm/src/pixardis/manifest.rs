@@ -0,0 +1,76 @@
+use std::fs;
+use std::io;
+
+use shared::pixardis::{PixardisInstruction, pixardis_hash, pixardis_program_hash};
+
+//
+// What a build manifest (chroma --emit manifest:FILE) says a program was
+// built from and alongside; fields the manifest lacks are None
+//
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PixardisManifest {
+    pub source_path: Option<String>,
+    pub source_hash: Option<String>,
+    pub program_hash: Option<String>,
+    pub debug_info: Option<String>,
+}
+
+pub fn pixardis_read_manifest(path: &str) -> Result<PixardisManifest, io::Error> {
+    let contents = fs::read_to_string(path)?;
+    let manifest: serde_json::Value = serde_json::from_str(&contents)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, error)))?;
+
+    let field = |section: &str, name: &str| manifest.get(section)
+        .and_then(|section| section.get(name))
+        .and_then(|value| value.as_str())
+        .map(String::from);
+
+    Ok(PixardisManifest {
+        source_path: field("source", "path"),
+        source_hash: field("source", "hash"),
+        program_hash: field("program", "hash"),
+        debug_info: manifest.get("debug_info").and_then(|value| value.as_str()).map(String::from),
+    })
+}
+
+impl PixardisManifest {
+    //
+    // Ways the loaded program and debug information don't match the build
+    // the manifest describes: the program differs from the one built, the
+    // source has changed since (if it can still be read), or the debug
+    // information wasn't written by the same build
+    //
+    pub fn stale_warnings(&self, program: &[PixardisInstruction], debug_info: Option<&str>) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        if let Some(hash) = &self.program_hash {
+            if *hash != pixardis_program_hash(program) {
+                warnings.push(String::from("the program differs from the one the manifest describes"));
+            }
+        }
+
+        if let (Some(path), Some(hash)) = (&self.source_path, &self.source_hash) {
+            if let Ok(source) = fs::read(path) {
+                if *hash != pixardis_hash(&source) {
+                    warnings.push(format!("{} has changed since the program was compiled", path));
+                }
+            }
+        }
+
+        match (debug_info, &self.debug_info) {
+            (Some(path), None) => warnings.push(format!("{} wasn't written with the program", path)),
+            (Some(path), Some(built)) if !same_file(path, built) =>
+                warnings.push(format!("{} isn't the debug information written with the program ({})", path, built)),
+            _ => (),
+        }
+
+        warnings
+    }
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
@@ -1 +1,13 @@
-pub mod pixardis;
\ No newline at end of file
+pub mod coverage;
+#[cfg(feature = "cli")]
+pub mod debug_info;
+pub mod differential;
+pub mod input;
+#[cfg(feature = "cli")]
+pub mod manifest;
+// The machine itself shares the module's name; the path
+// vm::pixardis::pixardis is what the frontends import
+#[allow(clippy::module_inception)]
+pub mod pixardis;
+pub mod storage;
+pub mod terminal;
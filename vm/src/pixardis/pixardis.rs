@@ -2,29 +2,430 @@ use crate::machine::{
     architecture::{
         Operand,
         VirtualMachine, 
-        VirtualMachineError, VirtualMachineState,
+        VirtualMachineError, VirtualMachineState, VirtualMachineStepResult,
     }, 
-    executor::Executor
+    executor::Executor,
+    observer::Observer,
+    snapshot::SnapshotWriter,
 };
 
+use crate::machine::snapshot::SnapshotReader;
+
 // use macroquad::time::get_time;
-use shared::pixardis::PixardisInstruction;
+use shared::intern::{Atom, Interner};
+// The entry label is shared with the compiler, which defines it
+pub use shared::pixardis::PIXARDIS_ENTRY_LABEL;
+
+use shared::pixardis::{
+    PixardisInstruction,
+    PIXARDIS_CAPABILITY_AUDIO,
+    PIXARDIS_CAPABILITY_DRAW,
+    PIXARDIS_CAPABILITY_INPUT,
+    pixardis_colour_from_string,
+    pixardis_demangle_label,
+    pixardis_intern_labels,
+};
+
+use super::coverage::{PixardisLineCoverage, pixardis_line_coverage};
+use super::input::PixardisInputEvent;
+use super::storage::PixardisStorage;
+
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
 
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(feature = "std")]
 use instant::Instant;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
 pub enum PixardisLogLevel {
     None,
     Error,
     Full,
 }
 
+//
+// What the VM does when a recoverable runtime error (division by zero, or
+// reading or drawing a pixel off-screen) occurs: stop, or substitute a default value,
+// record a diagnostic and keep running (useful for glitchy "art mode" programs)
+//
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PixardisErrorPolicy {
+    Halt,
+    Continue,
+}
+
+//
+// How pixel coordinates outside the display are treated: ignored (clip),
+// wrapped around to the opposite edge (wrap) or raised as an error
+//
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PixardisClipMode {
+    Clip,
+    Wrap,
+    Error,
+}
+
+//
+// How print instructions format values: prefixed with their type
+// ("int :: 3") or as the bare value ("3"), for clean numeric output
+//
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PixardisPrintFormat {
+    Typed,
+    Raw,
+}
+
+//
+// Memory the VM holds, in bytes (estimated from element sizes and string
+// lengths, not allocator overhead): its memory frames, the operand stack,
+// the framebuffer, and the print and log buffers kept when output has no
+// console to go to
+//
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PixardisMemoryUsage {
+    pub frames: usize,
+    pub frame_bytes: usize,
+    pub operand_stack_bytes: usize,
+    pub framebuffer_bytes: usize,
+    pub print_buffer_bytes: usize,
+    pub log_buffer_bytes: usize,
+}
+
+impl PixardisMemoryUsage {
+    pub fn total(&self) -> usize {
+        self.frame_bytes + self.operand_stack_bytes + self.framebuffer_bytes + self.print_buffer_bytes + self.log_buffer_bytes
+    }
+}
+
+//
+// A write to a watched memory location. The frame is absolute (0 is the
+// outermost frame), so a watch keeps referring to the same variable across
+// calls; address is that of the writing instruction.
+//
+#[derive(Debug, Clone)]
+pub struct PixardisWatchHit {
+    pub frame: usize,
+    pub offset: usize,
+    pub old: Operand,
+    pub new: Operand,
+    pub address: usize,
+    pub instruction: PixardisInstruction,
+}
+
+//
+// Asks a VM to stop from outside the step that's running it (another
+// thread, or a host callback); see PixardisVirtualMachine::stop_handle
+//
+#[derive(Debug, Clone)]
+pub struct PixardisStopHandle {
+    requested: Arc<AtomicBool>,
+}
+
+impl PixardisStopHandle {
+    pub fn request_stop(&self) {
+        self.requested.store(true, Ordering::Relaxed);
+    }
+}
+
+//
+// Most instructions and labels a program may have to be loaded (None for no
+// limit), e.g. to guard a host against pathological generated programs
+//
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PixardisLoadLimits {
+    pub max_instructions: Option<usize>,
+    pub max_labels: Option<usize>,
+}
+
+//
+// What loading the last program found: why it was rejected, if it was (the
+// VM then keeps the program it had), and structural problems with it that
+// don't stop it running
+//
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PixardisLoadReport {
+    pub rejection: Option<String>,
+    pub warnings: Vec<String>,
+}
+
+//
+// One level of the call chain reported with a runtime error: the address
+// executing in that function (the faulting instruction for the innermost
+// frame, the call site for the others)
+//
+#[derive(Debug, Clone)]
+pub struct PixardisCallFrame {
+    pub address: usize,
+    pub function: Option<String>,
+}
+
+//
+// A display pixel as pixel_inspect reports it: the colour the program wrote
+// and, if pixel tracking was on at the time, the instruction that wrote it
+//
+#[derive(Debug, Clone)]
+pub struct PixardisPixelInfo {
+    pub x: usize,
+    pub y: usize,
+    pub colour: u64,
+    pub writer: Option<PixardisPixelWriter>,
+}
+
+//
+// The instruction behind a pixel's last write, and the function it's in
+// (when the program declares function signatures, or it's in main)
+//
+#[derive(Debug, Clone)]
+pub struct PixardisPixelWriter {
+    pub address: usize,
+    pub instruction: PixardisInstruction,
+    pub function: Option<String>,
+}
+
+// Leading bytes of saved state; the last byte is the format version
+const PIXARDIS_SNAPSHOT_MAGIC: &[u8] = b"PXVM\x05";
+
+// Colours are 24-bit (0xrrggbb); arithmetic on colour operands wraps
+// within these bits rather than spilling over
+const PIXARDIS_COLOUR_MASK: u64 = 0xFFFFFF;
+
+//
+// Add or subtract two colours channel by channel, clamping each channel to
+// 0..=255 so that one channel can't bleed into the next
+//
+#[cfg(feature = "draw-ext")]
+fn colour_saturating(a: u64, b: u64, subtract: bool) -> u64 {
+    (0..3).fold(0, |colour, channel| {
+        let shift = channel * 8;
+        let (x, y) = ((a >> shift) as u8, (b >> shift) as u8);
+        let value = if subtract { x.saturating_sub(y) } else { x.saturating_add(y) };
+
+        colour | (value as u64) << shift
+    })
+}
+
+//
+// Colour for a hue in degrees (taken modulo 360) and a saturation and value
+// in 0..=255 (clamped)
+//
+#[cfg(feature = "draw-ext")]
+fn hsv_to_rgb(hue: i64, saturation: i64, value: i64) -> u64 {
+    let hue = hue.rem_euclid(360);
+    let (s, v) = (saturation.clamp(0, 255), value.clamp(0, 255));
+
+    let remainder = (hue % 60) * 255 / 60;
+    let p = v * (255 - s) / 255;
+    let q = v * (255 - s * remainder / 255) / 255;
+    let t = v * (255 - s * (255 - remainder) / 255) / 255;
+
+    let (r, g, b) = match hue / 60 {
+        0 => (v, t, p),
+        1 => (q, v, p),
+        2 => (p, v, t),
+        3 => (p, q, v),
+        4 => (t, p, v),
+        _ => (v, p, q),
+    };
+
+    ((r as u64) << 16) | ((g as u64) << 8) | b as u64
+}
+
+//
+// Hue (0..360), saturation and value (0..=255) of a colour
+//
+#[cfg(feature = "draw-ext")]
+fn rgb_to_hsv(colour: u64) -> (i64, i64, i64) {
+    let (r, g, b) = (((colour >> 16) & 0xFF) as i64, ((colour >> 8) & 0xFF) as i64, (colour & 0xFF) as i64);
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+
+    let saturation = if max == 0 { 0 } else { delta * 255 / max };
+
+    let hue = if delta == 0 {
+        0
+    } else if max == r {
+        60 * (g - b) / delta
+    } else if max == g {
+        120 + 60 * (b - r) / delta
+    } else {
+        240 + 60 * (r - g) / delta
+    };
+
+    (hue.rem_euclid(360), saturation, max)
+}
+
+//
+// Gradient (Perlin) noise at a point, in [0, 1]: smooth in each coordinate,
+// with features about one unit apart, and the same for the same seed
+//
+#[cfg(feature = "draw-ext")]
+fn noise(seed: u64, x: f64, y: f64, z: f64) -> f64 {
+    // Lattice cell and position within it (floor needs std, hence by hand)
+    let cell = |value: f64| {
+        let whole = value as i64;
+        if (whole as f64) > value { whole - 1 } else { whole }
+    };
+
+    let (ix, iy, iz) = (cell(x), cell(y), cell(z));
+    let (fx, fy, fz) = (x - ix as f64, y - iy as f64, z - iz as f64);
+
+    // Gradient at a lattice point, dotted with the offset to it
+    let gradient = |dx: i64, dy: i64, dz: i64| {
+        let mut hash = seed
+            ^ ((ix + dx) as u64).wrapping_mul(0x9E3779B97F4A7C15)
+            ^ ((iy + dy) as u64).wrapping_mul(0xC2B2AE3D27D4EB4F)
+            ^ ((iz + dz) as u64).wrapping_mul(0x165667B19E3779F9);
+        hash = (hash ^ (hash >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        hash = (hash ^ (hash >> 27)).wrapping_mul(0x94D049BB133111EB);
+        hash ^= hash >> 31;
+
+        let (px, py, pz) = (fx - dx as f64, fy - dy as f64, fz - dz as f64);
+        let h = hash & 15;
+        let u = if h < 8 { px } else { py };
+        let v = if h < 4 { py } else if h == 12 || h == 14 { px } else { pz };
+
+        (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+    };
+
+    let fade = |t: f64| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+    let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+    let (u, v, w) = (fade(fx), fade(fy), fade(fz));
+
+    let value = lerp(
+        lerp(lerp(gradient(0, 0, 0), gradient(1, 0, 0), u), lerp(gradient(0, 1, 0), gradient(1, 1, 0), u), v),
+        lerp(lerp(gradient(0, 0, 1), gradient(1, 0, 1), u), lerp(gradient(0, 1, 1), gradient(1, 1, 1), u), v),
+        w);
+
+    ((value + 1.0) / 2.0).clamp(0.0, 1.0)
+}
+
+//
+// A host-provided value (parameter or stored value) converted to the kind
+// of the program's default, which it replaces; the default if there's none
+//
+#[cfg(feature = "input-ext")]
+fn operand_like(value: Option<&Operand>, default: Operand) -> Operand {
+    match (value, default) {
+        (Some(Operand::Integer(value)), Operand::Colour(_)) => Operand::Colour(*value as u64 & PIXARDIS_COLOUR_MASK),
+        (Some(Operand::Colour(value)), Operand::Integer(_)) => Operand::Integer(*value as i64),
+        (Some(Operand::Real(value)), Operand::Integer(_)) => Operand::Integer(*value as i64),
+        (Some(Operand::Real(value)), Operand::Colour(_)) => Operand::Colour(*value as u64 & PIXARDIS_COLOUR_MASK),
+        (Some(Operand::Integer(value)), Operand::Real(_)) => Operand::Real(*value as f64),
+        (Some(value), _) => value.clone(),
+        (None, default) => default,
+    }
+}
+
+//
+// Host parameter value as given on a command line or by a page: a colour
+// (#rgb, #rrggbb or opaque #rrggbbaa) or an integer
+//
+#[allow(dead_code)]
+pub fn param_from_string(value: &str) -> Option<Operand> {
+    let value = value.trim();
+
+    if value.starts_with('#') {
+        return pixardis_colour_from_string(value).ok().map(|colour| Operand::Colour(colour as u64));
+    }
+
+    value.parse::<i64>().ok().map(Operand::Integer)
+}
+
+// Diagnostics retained under the continue policy
+const PIXARDIS_DIAGNOSTICS_MAX: usize = 100;
+
+// Most decimal places a formatted print can request
+const PIXARDIS_PRINT_DECIMALS_MAX: usize = 16;
+
+// Keys a program may store by default (see storage_quota_set)
+const PIXARDIS_STORAGE_QUOTA: usize = 64;
+
+// Checkpoints kept for step_back; older ones are discarded
+const PIXARDIS_HISTORY_CHECKPOINTS_MAX: usize = 64;
+
+// Instructions between polls of the host's stop signal (see stop_signal_set)
+const PIXARDIS_STOP_POLL_INTERVAL: usize = 1024;
+
+//
+// Log output is grouped by category so that each can be traced independently
+//
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PixardisLogCategory {
+    Exec,
+    Memory,
+    Display,
+}
+
+impl PixardisLogCategory {
+    fn index(self) -> usize {
+        match self {
+            PixardisLogCategory::Exec => 0,
+            PixardisLogCategory::Memory => 1,
+            PixardisLogCategory::Display => 2,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            PixardisLogCategory::Exec => "exec",
+            PixardisLogCategory::Memory => "memory",
+            PixardisLogCategory::Display => "display",
+        }
+    }
+
+    //
+    // Category under which an executed instruction is traced
+    //
+    fn of(instruction: &PixardisInstruction) -> PixardisLogCategory {
+        match instruction {
+            PixardisInstruction::Store |
+            PixardisInstruction::StoreArray |
+            PixardisInstruction::FrameOpen |
+            PixardisInstruction::FrameClose |
+            PixardisInstruction::Allocate => PixardisLogCategory::Memory,
+            #[cfg(feature = "input-ext")]
+            PixardisInstruction::StoreKV(_) |
+            PixardisInstruction::LoadKV(_) => PixardisLogCategory::Memory,
+            PixardisInstruction::Write |
+            PixardisInstruction::WriteBox |
+            PixardisInstruction::WriteLine |
+            PixardisInstruction::Read |
+            PixardisInstruction::Clear => PixardisLogCategory::Display,
+            #[cfg(feature = "draw-ext")]
+            PixardisInstruction::WriteBoxGradient |
+            PixardisInstruction::WriteBoxPattern |
+            PixardisInstruction::WriteTriangle => PixardisLogCategory::Display,
+            #[cfg(feature = "input-ext")]
+            PixardisInstruction::HitTest => PixardisLogCategory::Display,
+            _ => PixardisLogCategory::Exec,
+        }
+    }
+}
+
 pub struct PixardisDisplay
 {
     width: usize,
     height: usize,
 
     display_buffer: Vec<u64>,
+    journal: Option<Vec<(usize, usize, u64)>>,
+    clip_mode: PixardisClipMode,
+
+    // Brightness (255 = full) and gamma applied to each channel when the
+    // framebuffer is shown, precomputed as a lookup table
+    brightness: u8,
+    gamma: f64,
+    tone: [u8; 256],
 }
 
 #[allow(dead_code)]
@@ -35,6 +436,102 @@ impl PixardisDisplay {
             height: height,
 
             display_buffer: vec![0; width * height],
+            journal: None,
+            clip_mode: PixardisClipMode::Clip,
+
+            brightness: 255,
+            gamma: 1.0,
+            tone: core::array::from_fn(|channel| channel as u8),
+        }
+    }
+
+    pub fn brightness_set(&mut self, brightness: u8) {
+        self.brightness = brightness;
+        self.tone_update();
+    }
+
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    // Gamma needs floating-point powers, which only std provides
+    #[cfg(feature = "std")]
+    pub fn gamma_set(&mut self, gamma: f64) {
+        self.gamma = gamma;
+        self.tone_update();
+    }
+
+    pub fn gamma(&self) -> f64 {
+        self.gamma
+    }
+
+    // Each channel c is shown as 255 * (c / 255)^gamma * brightness / 255
+    fn tone_update(&mut self) {
+        let brightness = self.brightness as f64 / 255.0;
+
+        for channel in 0..256 {
+            let level = channel as f64 / 255.0;
+
+            #[cfg(feature = "std")]
+            let level = level.powf(self.gamma);
+
+            self.tone[channel] = (level * brightness * 255.0 + 0.5) as u8;
+        }
+    }
+
+    // Colour as shown, after brightness and gamma
+    pub fn present(&self, colour: u64) -> u64 {
+        let tone = |shift: u32| (self.tone[((colour >> shift) & 0xFF) as usize] as u64) << shift;
+
+        tone(16) | tone(8) | tone(0)
+    }
+
+    // Set how coordinates outside the display are treated
+    pub fn clip_mode_set(&mut self, mode: PixardisClipMode) {
+        self.clip_mode = mode;
+    }
+
+    pub fn clip_mode(&self) -> PixardisClipMode {
+        self.clip_mode
+    }
+
+    // Map coordinates onto the display, wrapping them around in wrap mode.
+    // Coordinates come from signed operands, so negative values arrive as
+    // their two's complement and are reinterpreted here.
+    fn locate(&self, x: usize, y: usize) -> Option<usize> {
+        if x < self.width && y < self.height {
+            return Some(y * self.width + x);
+        }
+
+        match self.clip_mode {
+            PixardisClipMode::Wrap => {
+                let x = (x as isize).rem_euclid(self.width as isize) as usize;
+                let y = (y as isize).rem_euclid(self.height as isize) as usize;
+                Some(y * self.width + x)
+            },
+            _ => None,
+        }
+    }
+
+    // Store a pixel at a located index
+    fn plot(&mut self, index: usize, value: u64) {
+        self.display_buffer[index] = value;
+
+        if let Some(journal) = self.journal.as_mut() {
+            journal.push((index % self.width, index / self.width, value));
+        }
+    }
+
+    // Enable or disable recording of pixel writes
+    pub fn journal_enable(&mut self, enable: bool) {
+        self.journal = if enable { Some(Vec::new()) } else { None };
+    }
+
+    // Take the pixel writes recorded since the last call
+    pub fn journal_drain(&mut self) -> Vec<(usize, usize, u64)> {
+        match self.journal.as_mut() {
+            Some(journal) => core::mem::take(journal),
+            None => Vec::new(),
         }
     }
 
@@ -62,36 +559,179 @@ impl PixardisDisplay {
 
     // Read pixel from framebuffer
     pub fn read_pixel(&self, x: usize, y: usize) -> Result<u64, VirtualMachineError> {
-        if x < self.width && y < self.height {
-            let index = y * self.width + x;
-            return Ok(self.display_buffer[index]);
+        match self.locate(x, y) {
+            Some(index) => Ok(self.display_buffer[index]),
+            None => Err(VirtualMachineError::InvalidMemoryAccess),
+        }
+    }
+
+    // True if any pixel in the box has a colour sharing bits with the mask
+    // (e.g. #ffffff matches anything but black). Off-screen pixels never
+    // match, except in error mode where the box must fit entirely.
+    pub fn hit_test(&self, x: usize, y: usize, width: usize, height: usize, mask: u64) -> Result<bool, VirtualMachineError> {
+        let fits = x.checked_add(width).is_some_and(|right| right <= self.width)
+            && y.checked_add(height).is_some_and(|top| top <= self.height);
+
+        if self.clip_mode == PixardisClipMode::Error && !fits {
+            return Err(VirtualMachineError::InvalidMemoryAccess);
         }
 
-        Err(VirtualMachineError::InvalidMemoryAccess)
+        let (columns, rows) = self.box_span(x, y, width, height);
+
+        for y_offset in rows {
+            for x_offset in columns.clone() {
+                if let Some(index) = self.locate(x.wrapping_add(x_offset), y.wrapping_add(y_offset)) {
+                    if self.display_buffer[index] & mask != 0 {
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+
+        Ok(false)
     }
 
-    // Write pixel to framebuffer
+    // Write pixel to framebuffer; fails if the pixel isn't drawn
     pub fn write_pixel(&mut self, x: usize, y: usize, value: u64) -> Result<(), VirtualMachineError> {
-        if x < self.width && y < self.height {
-            let index = y * self.width + x;
-            self.display_buffer[index] = value;
-            
-            return Ok(());
+        match self.locate(x, y) {
+            Some(index) => {
+                self.plot(index, value);
+                Ok(())
+            },
+            None => Err(VirtualMachineError::InvalidMemoryAccess),
         }
-
-        Err(VirtualMachineError::InvalidMemoryAccess)
     }
 
-    // Draw a box on framebuffer
+    // Draw a box on framebuffer; in error mode the box must fit entirely
     pub fn write_box(&mut self, x: usize, y: usize, width: usize, height: usize, value: u64) -> Result<(), VirtualMachineError> {
-        for y_offset in 0..height {
-            for x_offset in 0..width {
-                let x_index = x + x_offset;
-                let y_index = y + y_offset;
-
-                if x_index < self.width && y_index < self.height {
-                    let index = y_index * self.width + x_index;
-                    self.display_buffer[index] = value;
+        self.fill_box(x, y, width, height, |_, _| value)
+    }
+
+    // Draw a box shaded from one colour to the other, left to right
+    // (horizontal) or bottom to top (vertical)
+    pub fn write_box_gradient(&mut self, x: usize, y: usize, width: usize, height: usize, (from, to): (u64, u64), vertical: bool) -> Result<(), VirtualMachineError> {
+        let span = if vertical { height } else { width };
+        let steps = span.saturating_sub(1).max(1) as u64;
+
+        // Interpolate each channel separately, rounding to the nearest level
+        let blend = move |offset: usize| -> u64 {
+            let offset = (offset as u64).min(steps);
+
+            (0..3).fold(0, |colour, channel| {
+                let shift = channel * 8;
+                let a = (from >> shift) & 0xFF;
+                let b = (to >> shift) & 0xFF;
+                let level = (a * (steps - offset) + b * offset + steps / 2) / steps;
+
+                colour | (level << shift)
+            })
+        };
+
+        self.fill_box(x, y, width, height, |x_offset, y_offset| {
+            blend(if vertical { y_offset } else { x_offset })
+        })
+    }
+
+    // Draw a box in an ordered (4x4 Bayer) dither of two colours; level runs
+    // from 0 (all background) to 16 (all foreground), 8 being a checkerboard
+    pub fn write_box_pattern(&mut self, x: usize, y: usize, width: usize, height: usize, (background, foreground): (u64, u64), level: usize) -> Result<(), VirtualMachineError> {
+        const BAYER: [[usize; 4]; 4] = [
+            [ 0,  8,  2, 10],
+            [12,  4, 14,  6],
+            [ 3, 11,  1,  9],
+            [15,  7, 13,  5],
+        ];
+
+        // The pattern is anchored to the display, so adjacent boxes tile seamlessly
+        self.fill_box(x, y, width, height, |x_offset, y_offset| {
+            let threshold = BAYER[y.wrapping_add(y_offset) % 4][x.wrapping_add(x_offset) % 4];
+            if threshold < level { foreground } else { background }
+        })
+    }
+
+    // Draw a filled triangle (either winding). Pixels are sampled at their
+    // integer coordinates, so shared edges are drawn by both triangles. In
+    // error mode all three vertices must be on the display.
+    pub fn write_triangle(&mut self, vertices: [(usize, usize); 3], value: u64) -> Result<(), VirtualMachineError> {
+        if self.clip_mode == PixardisClipMode::Error && vertices.iter().any(|&(x, y)| self.locate(x, y).is_none()) {
+            return Err(VirtualMachineError::InvalidMemoryAccess);
+        }
+
+        // Coordinates arrive as two's complement; work with them signed, and
+        // widened so far-apart vertices can't overflow the edge functions
+        let [a, b, c] = vertices.map(|(x, y)| (x as isize as i128, y as isize as i128));
+
+        let edge = |(x0, y0): (i128, i128), (x1, y1): (i128, i128), (x, y): (i128, i128)| {
+            (x1 - x0).saturating_mul(y - y0).saturating_sub((y1 - y0).saturating_mul(x - x0))
+        };
+
+        let (columns, rows) = self.triangle_span(vertices);
+
+        for y in rows.map(i128::from) {
+            for x in columns.clone().map(i128::from) {
+                let weights = [edge(b, c, (x, y)), edge(c, a, (x, y)), edge(a, b, (x, y))];
+
+                let inside = weights.iter().all(|&weight| weight >= 0)
+                    || weights.iter().all(|&weight| weight <= 0);
+
+                if inside {
+                    if let Some(index) = self.locate(x as usize, y as usize) {
+                        self.plot(index, value);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Offsets (columns and rows) within a box of the pixels drawing it visits:
+    // those on the display, or in wrap mode at most a display's worth, as
+    // more would only draw the same pixels again
+    pub fn box_span(&self, x: usize, y: usize, width: usize, height: usize) -> (Range<usize>, Range<usize>) {
+        match self.clip_mode {
+            PixardisClipMode::Wrap => (0..width.min(self.width), 0..height.min(self.height)),
+            _ => (visible_span(x, width, self.width), visible_span(y, height, self.height)),
+        }
+    }
+
+    // Columns and rows a triangle's rasteriser visits: its bounding box clipped
+    // to the display, or in wrap mode at most a display's worth from its top
+    // left corner, as for boxes
+    pub fn triangle_span(&self, vertices: [(usize, usize); 3]) -> (Range<i64>, Range<i64>) {
+        let span = |values: [usize; 3], size: usize| {
+            let values = values.map(|value| value as isize as i64);
+            let (min, max) = (*values.iter().min().unwrap(), *values.iter().max().unwrap());
+            let size = size as i64;
+
+            match self.clip_mode {
+                PixardisClipMode::Wrap => min..max.min(min.saturating_add(size - 1)).saturating_add(1),
+                _ => min.max(0)..(max.min(size - 1) + 1).max(min.max(0)),
+            }
+        };
+
+        (span(vertices.map(|(x, _)| x), self.width), span(vertices.map(|(_, y)| y), self.height))
+    }
+
+    // Fill a box with colours given by offset within it; clipped or wrapped
+    // according to the clip mode, and in error mode the box must fit entirely
+    fn fill_box(&mut self, x: usize, y: usize, width: usize, height: usize, colour: impl Fn(usize, usize) -> u64) -> Result<(), VirtualMachineError> {
+        let fits = x.checked_add(width).is_some_and(|right| right <= self.width)
+            && y.checked_add(height).is_some_and(|top| top <= self.height);
+
+        if self.clip_mode == PixardisClipMode::Error && !fits {
+            return Err(VirtualMachineError::InvalidMemoryAccess);
+        }
+
+        let (columns, rows) = self.box_span(x, y, width, height);
+
+        for y_offset in rows {
+            for x_offset in columns.clone() {
+                let x_index = x.wrapping_add(x_offset);
+                let y_index = y.wrapping_add(y_offset);
+
+                if let Some(index) = self.locate(x_index, y_index) {
+                    self.plot(index, colour(x_offset, y_offset));
                 }
             }
         }
@@ -99,7 +739,12 @@ impl PixardisDisplay {
         Ok(())
     }
 
+    // Draw a line on framebuffer; in error mode both ends must be on the display
     pub fn write_line(&mut self, x0: usize, y0: usize, x1: usize, y1: usize, value: u64) -> Result<(), VirtualMachineError> {
+        if self.clip_mode == PixardisClipMode::Error && (self.locate(x0, y0).is_none() || self.locate(x1, y1).is_none()) {
+            return Err(VirtualMachineError::InvalidMemoryAccess);
+        }
+
         let dx = x1 as isize - x0 as isize;
         let dy = y1 as isize - y0 as isize;
 
@@ -123,7 +768,8 @@ impl PixardisDisplay {
         let mut error = dx - dy;
 
         loop {
-            self.write_pixel(x as usize, y as usize, value)?;
+            // Off-screen pixels are skipped so the visible part is still drawn
+            let _ = self.write_pixel(x as usize, y as usize, value);
 
             if x == x1 as isize && y == y1 as isize {
                 break;
@@ -146,14 +792,183 @@ impl PixardisDisplay {
     }
 }
 
+//
+// Offsets within a run of length pixels starting at start (two's complement,
+// so it may be left of the display) that land on a display of size pixels
+//
+fn visible_span(start: usize, length: usize, size: usize) -> Range<usize> {
+    let start = start as isize as i128;
+    let first = (-start).clamp(0, length as i128);
+    let last = (size as i128 - start).clamp(first, length as i128);
+
+    first as usize..last as usize
+}
+
+///
+/// Program information declared with metadata directives (.meta key "value")
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PixardisMetadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    // Preferred display size
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+}
+
+impl PixardisMetadata {
+    //
+    // Collect metadata from a program's directives; unknown keys and
+    // malformed sizes are ignored
+    //
+    pub fn from_instructions(instructions: &[PixardisInstruction]) -> PixardisMetadata {
+        let mut metadata = PixardisMetadata::default();
+
+        for instruction in instructions {
+            if let PixardisInstruction::Metadata(key, value) = instruction {
+                match key.as_str() {
+                    "title" => metadata.title = Some(value.clone()),
+                    "author" => metadata.author = Some(value.clone()),
+                    "width" => metadata.width = value.parse().ok().filter(|&size| size > 0),
+                    "height" => metadata.height = value.parse().ok().filter(|&size| size > 0),
+                    _ => { },
+                }
+            }
+        }
+
+        metadata
+    }
+
+    //
+    // Collect metadata from assembly text without loading the program
+    //
+    #[cfg(feature = "std")]
+    #[allow(dead_code)]
+    pub fn from_source(source: &str) -> PixardisMetadata {
+        let instructions: Vec<PixardisInstruction> = source.lines()
+            .filter(|line| line.trim_start().starts_with(".meta"))
+            .map(|line| shared::pixardis::pixardis_instruction_from_string(line.to_string()))
+            .collect();
+
+        Self::from_instructions(&instructions)
+    }
+}
+
+pub struct PixardisProgram
+{
+    instructions: Vec<PixardisInstruction>,
+    labels: BTreeMap<Atom, usize>,
+    entry_point: usize,
+    metadata: PixardisMetadata,
+    // Argument size declared for each function, by entry address
+    signatures: BTreeMap<usize, (Atom, usize)>,
+    // Labels defined more than once; the last definition is the one used
+    duplicate_labels: Vec<String>,
+    // Lines of assembly text the program was parsed from that don't say
+    // anything the VM can run (see pixardis_assembly_errors)
+    errors: Vec<String>,
+}
+
+//
+// Execution state captured every few instructions so that step_back can
+// restore it and re-execute forward
+//
+struct PixardisCheckpoint {
+    executed: u64,
+    machine: VirtualMachine,
+    display_buffer: Vec<u64>,
+    brightness: u8,
+    frame_in_progress: bool,
+}
+
+struct PixardisHistory {
+    interval: u64,
+    checkpoints: VecDeque<PixardisCheckpoint>,
+}
+
 pub struct PixardisVirtualMachine
 {
     virtual_machine: VirtualMachine,
     display: PixardisDisplay,
-    log_level: PixardisLogLevel,
+    programs: BTreeMap<String, PixardisProgram>,
+    metadata: PixardisMetadata,
+    signatures: BTreeMap<usize, (Atom, usize)>,
+    duplicate_labels: Vec<String>,
+    // Whether the loaded program defines .main
+    entry_label: bool,
+    // Source line of each instruction (0 if unknown), from debug information
+    source_lines: Vec<usize>,
+    // Addresses executed since the program was loaded, when recording coverage
+    coverage: Option<Vec<bool>>,
+    // Values the host set for the program's param instructions
+    params: BTreeMap<String, Operand>,
+    // Address of the instruction that last wrote each pixel, when tracked
+    pixel_writers: Option<Vec<Option<usize>>>,
+    // Persistent key-value storage and the most keys a program may store
+    storage: Option<Box<dyn PixardisStorage>>,
+    storage_enabled: bool,
+    storage_quota: usize,
+    // Registered observers by handle, and the handle the next one gets;
+    // handles aren't reused, so removing one leaves the others valid
+    observers: Vec<(usize, Box<dyn Observer>)>,
+    observer_next: usize,
+    log_levels: [PixardisLogLevel; 3],
+    #[cfg(feature = "std")]
     start_time: Instant,
-    #[cfg(target_arch = "wasm32")]
+    // Host-advanced clock (see advance_time); without std there is no wall
+    // clock, so this is the only time source
+    clock: f64,
+    #[cfg(feature = "std")]
+    virtual_clock: bool,
+    // Program speed: delays last 1/time_scale as long as requested
+    time_scale: f64,
+    watchdog_limit: Option<usize>,
+    watchdog_counter: usize,
+    watchdog_tripped: bool,
+    instruction_cost: usize,
+    float_support: bool,
+    error_policy: PixardisErrorPolicy,
+    print_format: PixardisPrintFormat,
+    diagnostics: Vec<String>,
+    // Host pixel writes held back until the current frame ends
+    input_latch: bool,
+    input_pending: Vec<(usize, usize, u64)>,
+    // Host writes with the cycle each was made at, while recording, and
+    // recorded writes still to be replayed
+    input_recording: Option<Vec<PixardisInputEvent>>,
+    input_replay: VecDeque<PixardisInputEvent>,
+    // Set once a frame has started executing, cleared at its delay
+    frame_in_progress: bool,
+    // Instructions executed since the program was loaded
+    executed: u64,
+    history: Option<PixardisHistory>,
+    // Set while step_back re-executes; output and observers are suppressed
+    replaying: bool,
+    // Watched (absolute frame, offset) locations and the latest write to one
+    watches: Vec<(usize, usize)>,
+    watch_hit: Option<PixardisWatchHit>,
+    // Call chain at the last runtime error, innermost first
+    backtrace: Vec<PixardisCallFrame>,
+    load_limits: PixardisLoadLimits,
+    load_report: PixardisLoadReport,
+    // Operand of each push immediate in the loaded program, by address, read
+    // from its text once when the program is loaded
+    immediates: Vec<Option<Operand>>,
+    // Stop requests, the host's stop signal and the instructions left until
+    // it's next polled, and whether the last step ended for a stop
+    stop_requested: Arc<AtomicBool>,
+    stop_signal: Option<Box<dyn FnMut() -> bool>>,
+    stop_countdown: usize,
+    stopped: bool,
+    #[cfg(all(feature = "std", any(not(target_arch = "wasm32"), target_os = "wasi")))]
+    exit_on_error: bool,
+    // Whether program output goes to stdout (observers see it regardless)
+    #[cfg(all(feature = "std", any(not(target_arch = "wasm32"), target_os = "wasi")))]
+    console_print: bool,
+    #[cfg(any(all(target_arch = "wasm32", not(target_os = "wasi")), not(feature = "std")))]
     print_buffer: Vec<String>,
+    #[cfg(any(all(target_arch = "wasm32", not(target_os = "wasi")), not(feature = "std")))]
+    log_buffer: Vec<String>,
 }
 
 impl PixardisVirtualMachine {
@@ -161,106 +976,787 @@ impl PixardisVirtualMachine {
         PixardisVirtualMachine {
             virtual_machine: VirtualMachine::new(),
             display: PixardisDisplay::new(width, height),
-            log_level: PixardisLogLevel::None,
+            programs: BTreeMap::new(),
+            metadata: PixardisMetadata::default(),
+            signatures: BTreeMap::new(),
+            duplicate_labels: Vec::new(),
+            entry_label: false,
+            source_lines: Vec::new(),
+            coverage: None,
+            params: BTreeMap::new(),
+            pixel_writers: None,
+            storage: None,
+            storage_enabled: true,
+            storage_quota: PIXARDIS_STORAGE_QUOTA,
+            observers: Vec::new(),
+            observer_next: 0,
+            log_levels: [PixardisLogLevel::None; 3],
+            #[cfg(feature = "std")]
             start_time: Instant::now(),
-            #[cfg(target_arch = "wasm32")]
+            clock: 0.0,
+            #[cfg(feature = "std")]
+            virtual_clock: false,
+            time_scale: 1.0,
+            watchdog_limit: None,
+            watchdog_counter: 0,
+            watchdog_tripped: false,
+            instruction_cost: 1,
+            float_support: true,
+            error_policy: PixardisErrorPolicy::Halt,
+            print_format: PixardisPrintFormat::Typed,
+            diagnostics: Vec::new(),
+            input_latch: false,
+            input_pending: Vec::new(),
+            input_recording: None,
+            input_replay: VecDeque::new(),
+            frame_in_progress: false,
+            executed: 0,
+            history: None,
+            replaying: false,
+            watches: Vec::new(),
+            watch_hit: None,
+            backtrace: Vec::new(),
+            load_limits: PixardisLoadLimits::default(),
+            load_report: PixardisLoadReport::default(),
+            immediates: Vec::new(),
+            stop_requested: Arc::new(AtomicBool::new(false)),
+            stop_signal: None,
+            stop_countdown: PIXARDIS_STOP_POLL_INTERVAL,
+            stopped: false,
+            #[cfg(all(feature = "std", any(not(target_arch = "wasm32"), target_os = "wasi")))]
+            exit_on_error: true,
+            #[cfg(all(feature = "std", any(not(target_arch = "wasm32"), target_os = "wasi")))]
+            console_print: true,
+            #[cfg(any(all(target_arch = "wasm32", not(target_os = "wasi")), not(feature = "std")))]
             print_buffer: Vec::new(),
+            #[cfg(any(all(target_arch = "wasm32", not(target_os = "wasi")), not(feature = "std")))]
+            log_buffer: Vec::new(),
         }
     }
 
-    #[cfg(target_arch = "wasm32")]
+    #[cfg(any(all(target_arch = "wasm32", not(target_os = "wasi")), not(feature = "std")))]
     // Add methods to manage the print buffer
     pub fn get_print_output(&self) -> &Vec<String> {
         &self.print_buffer
     }
 
-    #[cfg(target_arch = "wasm32")]
+    #[cfg(any(all(target_arch = "wasm32", not(target_os = "wasi")), not(feature = "std")))]
     pub fn clear_print_output(&mut self) {
         self.print_buffer.clear();
     }
 
-    #[cfg(target_arch = "wasm32")]
+    #[cfg(any(all(target_arch = "wasm32", not(target_os = "wasi")), not(feature = "std")))]
     fn add_print_output(&mut self, text: String) {
         self.print_buffer.push(text);
     }        
 
+    #[cfg(any(all(target_arch = "wasm32", not(target_os = "wasi")), not(feature = "std")))]
+    pub fn get_log_output(&self) -> &Vec<String> {
+        &self.log_buffer
+    }
+
+    #[cfg(any(all(target_arch = "wasm32", not(target_os = "wasi")), not(feature = "std")))]
+    pub fn clear_log_output(&mut self) {
+        self.log_buffer.clear();
+    }
+
+    //
+    // Emit a log message if the category is enabled at the given level.
+    // Log output goes to stderr on console targets (native and WASI, keeping
+    // stdout for program prints) and is buffered in the browser.
+    //
+    fn log(&mut self, category: PixardisLogCategory, level: PixardisLogLevel, message: String) {
+        if level == PixardisLogLevel::None || self.log_levels[category.index()] < level {
+            return;
+        }
+
+        let line = format!("[{}] {}", category.name(), message);
+
+        #[cfg(any(all(target_arch = "wasm32", not(target_os = "wasi")), not(feature = "std")))]
+        self.log_buffer.push(line);
+
+        #[cfg(all(feature = "std", any(not(target_arch = "wasm32"), target_os = "wasi")))]
+        eprintln!("{}", line);
+    }
+
     // Add the get_time function
+    #[cfg(feature = "std")]
     fn get_time(&self) -> f64 {
+        if self.virtual_clock {
+            return self.clock;
+        }
+
         self.start_time.elapsed().as_secs_f64()
     }    
 
+    #[cfg(not(feature = "std"))]
+    fn get_time(&self) -> f64 {
+        self.clock
+    }
+
     //
-    // Convert a string to an operand
+    // VM time in seconds: wall time since creation with std, else the
+    // host-advanced clock
     //
-    fn operand_from_string(&self, operand: &str) -> Operand {
-        // Operand is a real number
-        if operand.contains(".") {
-            Operand::Real(operand.parse::<f64>().unwrap())
-        } else {
-            // Operand is a hex colour
-            if operand.starts_with("#") && operand.len() == 7 {
-                let hex_digits = &operand[1..]; // Remove the '#' character
-                let rgb_value = u64::from_str_radix(hex_digits, 16);
-            
-                match rgb_value {
-                    Ok(value) => Operand::Unsigned(value),
-                    Err(_) => Operand::Unsigned(0xFF00FF)        // Push false colour (error)
-                }
-            } else {
-                // Operand is an integer
-                Operand::Integer(operand.parse::<i64>().unwrap())
-            }
-        }
+    #[allow(dead_code)]
+    pub fn time(&self) -> f64 {
+        self.get_time()
     }
 
     //
-    // Load program from source (text)
+    // Time delays against the host-advanced clock instead of wall time, so
+    // the host decides how fast program time passes (see advance_time).
+    // Without std the clock is always virtual. Switching keeps the current
+    // time, so a pending delay still has the same time left to run.
     //
-    pub fn load_program_from_source(&mut self, source: &str) {
-        // Split the string using newlines (\n)
-        let source_lines: Vec<&str> = source.split('\n').collect();
+    #[cfg(feature = "std")]
+    #[allow(dead_code)]
+    pub fn virtual_clock_set(&mut self, enabled: bool) {
+        if enabled == self.virtual_clock {
+            return;
+        }
+
+        if enabled {
+            self.clock = self.get_time();
+        } else if let VirtualMachineState::Delayed(time_stamp, cooldown) = self.virtual_machine.state() {
+            let wall_time = self.start_time.elapsed().as_secs_f64();
+            self.virtual_machine.state_set(VirtualMachineState::Delayed(time_stamp - self.clock + wall_time, cooldown));
+        }
 
-        let mut pixardis_program = Vec::<PixardisInstruction>::new();
+        self.virtual_clock = enabled;
+    }
 
-        for line in source_lines {
-            let instruction = shared::pixardis::pixardis_instruction_from_string(line.to_string());
-            match instruction.clone() {
-                PixardisInstruction::Label(label) => {
-                    let current_instruction_index = pixardis_program.len();
+    #[cfg(feature = "std")]
+    #[allow(dead_code)]
+    pub fn virtual_clock(&self) -> bool {
+        self.virtual_clock
+    }
 
-                    self.virtual_machine.address_label_set(&label, current_instruction_index);
-                    
-                    if label == ".main" {
-                        self.virtual_machine.program_set_entry_point(current_instruction_index);
-                    }
-                },
-                _ => { },
-            }
+    #[cfg(not(feature = "std"))]
+    #[allow(dead_code)]
+    pub fn virtual_clock(&self) -> bool {
+        true
+    }
+
+    //
+    // Advance the virtual clock (in seconds) without running the program.
+    // Has no effect on delays while the VM follows wall time.
+    //
+    #[allow(dead_code)]
+    pub fn clock_advance(&mut self, seconds: f64) {
+        self.clock += seconds.max(0.0);
+    }
 
-            pixardis_program.push(instruction);
+    //
+    // Speed programs up (scale above 1) or slow them down (below 1) by
+    // scaling delays; the next delay the program issues uses the new scale.
+    // Scales that aren't positive and finite are ignored.
+    //
+    #[allow(dead_code)]
+    pub fn time_scale_set(&mut self, scale: f64) {
+        if scale > 0.0 && scale.is_finite() {
+            self.time_scale = scale;
         }
+    }
 
-        // Load program into virtual machine
-        self.virtual_machine.program_load(pixardis_program);
+    #[allow(dead_code)]
+    pub fn time_scale(&self) -> f64 {
+        self.time_scale
     }
-    
+
     //
-    // Execute a single instruction
+    // Advance the virtual clock by the given number of seconds, running the
+    // program through every delay that expires on the way. The clock stops
+    // at each delay's expiry before the program resumes, so a program paced
+    // by delays behaves the same whatever step size the host advances by
+    // (one display frame, a fast-forward burst or a headless run). Returns
+    // the number of instructions executed; stops early after max_cycles or
+    // a watched write, with the clock still advanced by the full amount.
     //
-    pub fn execute_instruction(&mut self, instruction: PixardisInstruction) -> Result<(), VirtualMachineError> {                
-        match instruction.clone() {
-            PixardisInstruction::Label(_) => { },
+    #[allow(dead_code)]
+    pub fn advance_time(&mut self, seconds: f64, max_cycles: usize) -> Result<usize, VirtualMachineError> {
+        let target = self.clock + seconds.max(0.0);
+        let mut cycles = 0;
 
-            PixardisInstruction::PushImmediate(value) => { 
-                let operand = self.operand_from_string(value.as_str());
-                self.virtual_machine.operand_push(operand); 
-            },
+        while cycles < max_cycles {
+            if let VirtualMachineState::Delayed(time_stamp, cooldown) = self.virtual_machine.state() {
+                if time_stamp + cooldown > target || !self.virtual_clock() {
+                    break;
+                }
 
-            PixardisInstruction::PushLabel(label) => {
-                self.virtual_machine.operand_push_label(label.as_str())?;            
-            },
+                // Resume exactly at the expiry; the delay itself is over
+                self.clock = self.clock.max(time_stamp + cooldown);
+                self.virtual_machine.state_set(VirtualMachineState::Paused);
+            }
 
-            PixardisInstruction::PushOffset(offset) => {
+            cycles += 1;
+
+            match self.step(1)? {
+                VirtualMachineStepResult::Watchpoint => break,
+                // Nothing ran
+                VirtualMachineStepResult::Stopped => {
+                    cycles -= 1;
+                    break;
+                },
+                VirtualMachineStepResult::WaitUntil(_) |
+                VirtualMachineStepResult::Ready => { },
+            }
+        }
+
+        self.clock = self.clock.max(target);
+
+        Ok(cycles)
+    }
+
+    //
+    // Execute until the program issues a delay (the natural frame boundary
+    // for animation programs) or max_cycles instructions have run. Returns
+    // the number of instructions executed; a pending, unexpired delay is
+    // honoured and executes nothing.
+    //
+    #[allow(dead_code)]
+    pub fn run_until_delay(&mut self, max_cycles: usize) -> Result<usize, VirtualMachineError> {
+        for cycles in 0..max_cycles {
+            match self.step(1)? {
+                VirtualMachineStepResult::WaitUntil(_) |
+                VirtualMachineStepResult::Stopped => return Ok(cycles),
+                VirtualMachineStepResult::Watchpoint => return Ok(cycles + 1),
+                VirtualMachineStepResult::Ready => { },
+            }
+
+            if let VirtualMachineState::Delayed(_, _) = self.virtual_machine.state() {
+                return Ok(cycles + 1);
+            }
+        }
+
+        Ok(max_cycles)
+    }
+
+    //
+    // Execute exactly one program frame: any pending delay is skipped and
+    // the VM runs up to (and including) the next delay instruction. Unlike
+    // run_until_delay this doesn't depend on wall-clock time, so frontends
+    // can render one program frame per display frame.
+    //
+    #[allow(dead_code)]
+    pub fn run_frame(&mut self, max_cycles: usize) -> Result<usize, VirtualMachineError> {
+        if let VirtualMachineState::Delayed(_, _) = self.virtual_machine.state() {
+            self.virtual_machine.state_set(VirtualMachineState::Paused);
+        }
+
+        self.run_until_delay(max_cycles)
+    }
+
+    //
+    // Delay (in milliseconds) requested by the last delay instruction, if
+    // the VM is currently delayed (after time scaling)
+    //
+    #[allow(dead_code)]
+    pub fn delay_requested(&self) -> Option<f64> {
+        match self.virtual_machine.state() {
+            VirtualMachineState::Delayed(_, cooldown) => Some(cooldown * 1000.0),
+            _ => None,
+        }
+    }
+
+    //
+    // Time remaining (in seconds) until a pending delay expires
+    //
+    #[allow(dead_code)]
+    pub fn delay_remaining(&self) -> Option<f64> {
+        match self.virtual_machine.state() {
+            VirtualMachineState::Delayed(time_stamp, cooldown) => {
+                Some((time_stamp + cooldown - self.get_time()).max(0.0))
+            },
+            _ => None,
+        }
+    }
+
+    //
+    // Convert a string to an operand, or None if it isn't a number (or a
+    // #rrggbb colour, in programs assembled before PushColour)
+    //
+    fn operand_from_string(operand: &str) -> Option<Operand> {
+        if operand.contains(".") {
+            operand.parse::<f64>().ok().map(Operand::Real)
+        } else if operand.starts_with("#") && operand.len() == 7 {
+            u64::from_str_radix(&operand[1..], 16).ok().map(Operand::Colour)
+        } else {
+            operand.parse::<i64>().ok().map(Operand::Integer)
+        }
+    }
+
+    //
+    // Read the operand of every push immediate in a program, so executing
+    // one doesn't parse its text; the error names the first that can't be
+    // read
+    //
+    fn immediate_operands(program: &PixardisProgram) -> Result<Vec<Option<Operand>>, String> {
+        program.instructions.iter().enumerate()
+            .map(|(address, instruction)| match instruction {
+                PixardisInstruction::PushImmediate(text) => match Self::operand_from_string(text) {
+                    Some(operand) => Ok(Some(operand)),
+                    None => Err(format!("push {} at address {} isn't a number it can represent", text, address)),
+                },
+                _ => Ok(None),
+            })
+            .collect()
+    }
+
+    //
+    // Parse a program from source (text) without loading it
+    //
+    #[cfg(feature = "std")]
+    fn parse_program(source: &str) -> PixardisProgram {
+        // Split the string using newlines (\n)
+        let instructions = source.split('\n')
+            .map(|line| shared::pixardis::pixardis_instruction_from_string(line.to_string()))
+            .collect();
+
+        let mut program = Self::build_program(instructions);
+        program.errors = shared::pixardis::pixardis_assembly_errors(source).into_iter()
+            .map(|(line, error)| format!("line {}: {}", line, error))
+            .collect();
+
+        program
+    }
+
+    //
+    // Build a program from already decoded instructions, resolving labels;
+    // each label's definition and uses share one copy of its text
+    //
+    fn build_program(mut instructions: Vec<PixardisInstruction>) -> PixardisProgram {
+        pixardis_intern_labels(&mut instructions, &Interner::new());
+
+        let mut program = PixardisProgram {
+            instructions: Vec::new(),
+            labels: BTreeMap::new(),
+            entry_point: 0,
+            metadata: PixardisMetadata::from_instructions(&instructions),
+            signatures: BTreeMap::new(),
+            duplicate_labels: Vec::new(),
+            errors: Vec::new(),
+        };
+
+        let mut signatures = Vec::new();
+
+        for instruction in instructions {
+            match instruction.clone() {
+                PixardisInstruction::Label(label) => {
+                    let current_instruction_index = program.instructions.len();
+
+                    if program.labels.insert(label.clone(), current_instruction_index).is_some()
+                        && !program.duplicate_labels.iter().any(|duplicate| label == *duplicate) {
+                        program.duplicate_labels.push(label.to_string());
+                    }
+
+                    if label == PIXARDIS_ENTRY_LABEL {
+                        program.entry_point = current_instruction_index;
+                    }
+                },
+                PixardisInstruction::Signature(name, size) => signatures.push((name, size)),
+                _ => { },
+            }
+
+            program.instructions.push(instruction);
+        }
+
+        // Signatures of functions the program doesn't define are ignored
+        for (name, size) in signatures {
+            if let Some(&address) = program.labels.get(&name) {
+                program.signatures.insert(address, (name, size));
+            }
+        }
+
+        program
+    }
+
+    //
+    // Check a parsed program against the load limits and for structural
+    // problems, and install it into the virtual machine unless it's over a
+    // limit, has a line of assembly that doesn't parse into what it says or
+    // an immediate that isn't a number; either way, load_report says what
+    // was found
+    //
+    fn install_program(&mut self, program: &PixardisProgram) -> Result<(), VirtualMachineError> {
+        self.load_report = self.load_check(program);
+
+        if let Some(rejection) = self.load_report.rejection.clone() {
+            return self.reject_program(rejection, VirtualMachineError::ProgramTooLarge);
+        }
+
+        if let Some(error) = program.errors.first() {
+            return self.reject_program(error.clone(), VirtualMachineError::InvalidProgram);
+        }
+
+        let immediates = match Self::immediate_operands(program) {
+            Ok(immediates) => immediates,
+            Err(rejection) => return self.reject_program(rejection, VirtualMachineError::InvalidProgram),
+        };
+
+        for (label, address) in program.labels.iter() {
+            self.virtual_machine.address_label_set(label, *address);
+        }
+
+        self.virtual_machine.program_set_entry_point(program.entry_point);
+        self.virtual_machine.program_load(program.instructions.clone());
+        self.immediates = immediates;
+
+        self.metadata = program.metadata.clone();
+        self.signatures = program.signatures.clone();
+        self.duplicate_labels = program.duplicate_labels.clone();
+        self.entry_label = program.labels.contains_key(PIXARDIS_ENTRY_LABEL);
+        self.source_lines.clear();
+        self.history_reset();
+        self.watch_hit = None;
+
+        // Recorded cycles count from the start of the program
+        if let Some(recording) = self.input_recording.as_mut() {
+            recording.clear();
+        }
+        self.backtrace.clear();
+
+        // Recorded writers are addresses in the previous program
+        if let Some(writers) = self.pixel_writers.as_mut() {
+            writers.fill(None);
+        }
+
+        if let Some(executed) = self.coverage.as_mut() {
+            *executed = vec![false; program.instructions.len()];
+        }
+
+        for label in program.duplicate_labels.iter() {
+            let message = format!("Label {} is defined more than once; calls use its last definition", label);
+            self.log(PixardisLogCategory::Exec, PixardisLogLevel::Error, message);
+        }
+
+        Ok(())
+    }
+
+    //
+    // Refuse a program, leaving the loaded one in place
+    //
+    fn reject_program(&mut self, rejection: String, error: VirtualMachineError) -> Result<(), VirtualMachineError> {
+        self.log(PixardisLogCategory::Exec, PixardisLogLevel::Error, format!("Program not loaded: {}", rejection));
+        self.load_report.rejection = Some(rejection);
+
+        Err(error)
+    }
+
+    fn load_check(&self, program: &PixardisProgram) -> PixardisLoadReport {
+        let mut report = PixardisLoadReport::default();
+        let limits = self.load_limits;
+
+        if let Some(limit) = limits.max_instructions.filter(|limit| program.instructions.len() > *limit) {
+            report.rejection = Some(format!("it has {} instructions, more than the limit of {}", program.instructions.len(), limit));
+        } else if let Some(limit) = limits.max_labels.filter(|limit| program.labels.len() > *limit) {
+            report.rejection = Some(format!("it defines {} labels, more than the limit of {}", program.labels.len(), limit));
+        }
+
+        if report.rejection.is_some() {
+            return report;
+        }
+
+        for label in program.duplicate_labels.iter() {
+            report.warnings.push(format!("label {} is defined more than once", label));
+        }
+
+        for (address, instruction) in program.instructions.iter().enumerate() {
+            if let PixardisInstruction::PushLabel(label) = instruction {
+                if !program.labels.contains_key(label.as_str()) {
+                    report.warnings.push(format!("push .{} at address {} names a label the program doesn't define", label, address));
+                }
+            }
+        }
+
+        if !program.instructions.is_empty() && !Self::halt_reachable(program) {
+            report.warnings.push(String::from("no halt is reachable from the entry point; the program runs until it's stopped"));
+        }
+
+        report
+    }
+
+    //
+    // Whether a halt may be reached from the program's entry point, following
+    // jumps and calls to the addresses pushed just before them (and assuming
+    // calls return and conditional jumps go either way); a jump to a computed
+    // address may go anywhere, so counts as reaching one
+    //
+    fn halt_reachable(program: &PixardisProgram) -> bool {
+        let instructions = &program.instructions;
+
+        // Where the jump or call at an address goes, if that can be told
+        let target = |address: usize| match address.checked_sub(1).map(|push| (push, &instructions[push])) {
+            Some((push, PixardisInstruction::PushOffset(offset))) => usize::try_from(push as i64 + offset).ok(),
+            Some((_, PixardisInstruction::PushImmediate(address))) => address.parse().ok(),
+            Some((_, PixardisInstruction::PushLabel(label))) => program.labels.get(label.as_str()).copied(),
+            _ => None,
+        };
+
+        let mut visited = vec![false; instructions.len()];
+        let mut pending = vec![program.entry_point];
+
+        while let Some(address) = pending.pop() {
+            if address >= instructions.len() || visited[address] {
+                continue;
+            }
+
+            visited[address] = true;
+
+            match &instructions[address] {
+                PixardisInstruction::Halt => return true,
+                PixardisInstruction::Return | PixardisInstruction::ReturnArray => { },
+                PixardisInstruction::Jump => match target(address) {
+                    Some(target) => pending.push(target),
+                    None => return true,
+                },
+                PixardisInstruction::ConditionalJump | PixardisInstruction::Call => match target(address) {
+                    Some(target) => pending.extend([target, address + 1]),
+                    None => return true,
+                },
+                _ => pending.push(address + 1),
+            }
+        }
+
+        false
+    }
+
+    //
+    // Limits on the size of programs loaded from now on
+    //
+    #[allow(dead_code)]
+    pub fn load_limits_set(&mut self, limits: PixardisLoadLimits) {
+        self.load_limits = limits;
+    }
+
+    //
+    // What loading the last program found (see PixardisLoadReport)
+    //
+    #[allow(dead_code)]
+    pub fn load_report(&self) -> &PixardisLoadReport {
+        &self.load_report
+    }
+
+    //
+    // Labels the loaded program defines more than once
+    //
+    #[allow(dead_code)]
+    pub fn duplicate_labels(&self) -> &[String] {
+        &self.duplicate_labels
+    }
+
+    //
+    // Metadata of the loaded program
+    //
+    #[allow(dead_code)]
+    pub fn metadata(&self) -> &PixardisMetadata {
+        &self.metadata
+    }
+
+    //
+    // Load program from decoded instructions
+    //
+    #[allow(dead_code)]
+    pub fn load_program(&mut self, instructions: Vec<PixardisInstruction>) {
+        let program = Self::build_program(instructions);
+
+        if self.install_program(&program).is_ok() {
+            self.entry_check(&program);
+        }
+    }
+
+    //
+    // Load program from source (text)
+    //
+    #[cfg(feature = "std")]
+    pub fn load_program_from_source(&mut self, source: &str) {
+        let program = Self::parse_program(source);
+
+        // Load program into virtual machine
+        if self.install_program(&program).is_ok() {
+            self.entry_check(&program);
+        }
+    }
+
+    //
+    // Load a program that starts at the given label (without the leading
+    // dot) instead of .main, e.g. to run one of several entry points; the
+    // VM is left as it was if the program doesn't define the label
+    //
+    #[allow(dead_code)]
+    pub fn load_program_with_entry(&mut self, instructions: Vec<PixardisInstruction>, entry: &str) -> Result<(), VirtualMachineError> {
+        let program = Self::build_program(instructions);
+        self.install_program_at(program, entry)
+    }
+
+    #[cfg(feature = "std")]
+    #[allow(dead_code)]
+    pub fn load_program_from_source_with_entry(&mut self, source: &str, entry: &str) -> Result<(), VirtualMachineError> {
+        let program = Self::parse_program(source);
+        self.install_program_at(program, entry)
+    }
+
+    fn install_program_at(&mut self, mut program: PixardisProgram, entry: &str) -> Result<(), VirtualMachineError> {
+        let entry = entry.strip_prefix('.').unwrap_or(entry);
+
+        match program.labels.get(entry) {
+            Some(&address) => program.entry_point = address,
+            None => {
+                let message = format!("Entry label .{} is not defined by the program", entry);
+                self.log(PixardisLogCategory::Exec, PixardisLogLevel::Error, message);
+
+                return Err(VirtualMachineError::InvalidLabel);
+            },
+        }
+
+        self.install_program(&program)
+    }
+
+    //
+    // False if the loaded program has no .main label, and so runs from its
+    // first instruction
+    //
+    #[allow(dead_code)]
+    pub fn has_entry_label(&self) -> bool {
+        self.entry_label
+    }
+
+    //
+    // Attach the compiler's line table (the source line of each instruction,
+    // 0 if unknown) to the loaded program, so runtime errors can name source
+    // lines; loading a program clears it, so set it after loading
+    //
+    #[allow(dead_code)]
+    pub fn source_lines_set(&mut self, lines: Vec<usize>) {
+        self.source_lines = lines;
+    }
+
+    //
+    // Source line of the instruction at an address, if known
+    //
+    #[allow(dead_code)]
+    pub fn source_line(&self, address: usize) -> Option<usize> {
+        self.source_lines.get(address).copied().filter(|&line| line > 0)
+    }
+
+    //
+    // Programs without a .main label run from their first instruction, which
+    // is rarely what was meant
+    //
+    fn entry_check(&mut self, program: &PixardisProgram) {
+        if !program.instructions.is_empty() && !self.entry_label {
+            let message = format!("No .{} label; execution starts at address 0", PIXARDIS_ENTRY_LABEL);
+            self.log(PixardisLogCategory::Exec, PixardisLogLevel::Error, message);
+            self.load_report.warnings.insert(0, format!("no .{} label; execution starts at address 0", PIXARDIS_ENTRY_LABEL));
+        }
+    }
+
+    //
+    // Load a named program (scene) without running it; scenes can be
+    // switched between using run_program
+    //
+    #[cfg(feature = "std")]
+    #[allow(dead_code)]
+    pub fn load_program_named(&mut self, name: &str, source: &str) {
+        let program = Self::parse_program(source);
+        self.programs.insert(name.to_string(), program);
+    }
+
+    //
+    // Switch execution to a previously loaded named program. Execution state
+    // (memory, stacks) is reset, but the display is preserved.
+    //
+    #[allow(dead_code)]
+    pub fn run_program(&mut self, name: &str) -> Result<(), VirtualMachineError> {
+        let program = self.programs.remove(name).ok_or(VirtualMachineError::InvalidProgram)?;
+
+        self.virtual_machine.reset();
+        let result = self.install_program(&program);
+
+        self.programs.insert(name.to_string(), program);
+
+        result
+    }
+
+    //
+    // Names of the programs loaded using load_program_named
+    //
+    #[allow(dead_code)]
+    pub fn program_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.programs.keys().cloned().collect();
+        names.sort();
+        names
+    }
+    
+    //
+    // Execute a single instruction
+    //
+    pub fn execute_instruction(&mut self, instruction: &PixardisInstruction) -> Result<(), VirtualMachineError> {                
+        match instruction {
+            PixardisInstruction::Label(_) | PixardisInstruction::Metadata(_, _) | PixardisInstruction::Signature(_, _) => { },
+
+            PixardisInstruction::PushImmediate(value) => { 
+                // Read when the program was loaded, unless this isn't the
+                // loaded instruction the program counter just moved past
+                let address = self.virtual_machine.program_counter().wrapping_sub(1);
+                let operand = match (self.virtual_machine.program().get(address), self.immediates.get(address)) {
+                    (Some(loaded), Some(Some(operand))) if loaded == instruction => operand.clone(),
+                    _ => Self::operand_from_string(value).ok_or(VirtualMachineError::InvalidOperand)?,
+                };
+
+                // Float-less targets can't represent real operands
+                if !self.float_support && matches!(operand, Operand::Real(_)) {
+                    Err(VirtualMachineError::InvalidOperand)?
+                }
+
+                self.virtual_machine.operand_push(operand); 
+            },
+
+            PixardisInstruction::PushColour(colour) => {
+                self.virtual_machine.operand_push(Operand::Colour(*colour as u64));
+            },
+
+            PixardisInstruction::PushLabel(label) => {
+                self.virtual_machine.operand_push_label(label.as_str())?;            
+            },
+
+            #[cfg(feature = "input-ext")]
+            PixardisInstruction::Param(name) => {
+                let default = self.virtual_machine.operand_pop()?;
+                let value = operand_like(self.params.get(name), default);
+
+                self.virtual_machine.operand_push(value);
+            },
+
+            // Without storage (or with it disabled) loads give the default
+            // and stores are dropped
+            #[cfg(feature = "input-ext")]
+            PixardisInstruction::LoadKV(key) => {
+                let default = self.virtual_machine.operand_pop()?;
+
+                let value = match (self.storage_enabled, self.storage.as_mut()) {
+                    (true, Some(storage)) => storage.load(key),
+                    _ => None,
+                };
+
+                self.virtual_machine.operand_push(operand_like(value.as_ref(), default));
+            },
+
+            #[cfg(feature = "input-ext")]
+            PixardisInstruction::StoreKV(key) => {
+                let value = self.virtual_machine.operand_pop()?;
+
+                if let (true, false, Some(storage)) = (self.storage_enabled, self.replaying, self.storage.as_mut()) {
+                    if storage.contains(key) || storage.count() < self.storage_quota {
+                        storage.store(key, &value);
+                    } else {
+                        let message = format!("Storage quota ({} keys) reached; '{}' not stored.", self.storage_quota, key);
+                        self.log(PixardisLogCategory::Memory, PixardisLogLevel::Error, message);
+                    }
+                }
+            },
+
+            PixardisInstruction::PushOffset(offset) => {
                 let address = self.virtual_machine.program_counter() as i64 + offset - 1;
                 self.virtual_machine.operand_push(Operand::Integer(address));
             },
@@ -322,10 +1818,16 @@ impl PixardisVirtualMachine {
                     _ => { Err(VirtualMachineError::InvalidOperand)? },
                 };
 
-                for offset in (0..count).rev() {
-                    let value = self.virtual_machine.memory_read(index[1] as usize, index[0] as usize + offset)?;
-                    self.virtual_machine.operand_push(value);
+                self.push_array(index[1] as usize, index[0] as usize, count)?;
+            },
+
+            // As PushArray, with the count given by the instruction
+            PixardisInstruction::PushArrayCount([offset, frame, count]) => {
+                if *count <= 0 {
+                    Err(VirtualMachineError::InvalidOperand)?
                 }
+
+                self.push_array(*frame as usize, *offset as usize, *count as usize)?;
             },
 
             PixardisInstruction::Store => {
@@ -347,7 +1849,7 @@ impl PixardisVirtualMachine {
 
                 let value = self.virtual_machine.operand_pop()?;
 
-                self.virtual_machine.memory_write(frame, offset, value)?;
+                self.memory_store(frame, offset, value, instruction)?;
             },
 
             /*
@@ -386,15 +1888,22 @@ impl PixardisVirtualMachine {
 
                 let operand_count = self.virtual_machine.operand_pop()?;
                 let count = match operand_count {
-                    Operand::Integer(count) => {
+                    Operand::Integer(count) if count >= 0 => {
                         count as usize
                     },
                     _ => { Err(VirtualMachineError::InvalidCount)? },
                 };
 
+                // Check the whole array up front so a bad store writes nothing
+                self.array_bounds_check("StoreArray", frame, offset, count)?;
+
+                if self.virtual_machine.operand_stack_size() < count {
+                    Err(VirtualMachineError::StackUnderflow)?
+                }
+
                 for index in 0..count {
                     let value = self.virtual_machine.operand_pop()?;
-                    self.virtual_machine.memory_write(frame, offset + index, value)?;
+                    self.memory_store(frame, offset + index, value, instruction)?;
                 }
             },
 
@@ -425,8 +1934,8 @@ impl PixardisVirtualMachine {
             PixardisInstruction::Not => {
                 let operand = self.virtual_machine.operand_pop()?;
                 let result = match operand {
-                    Operand::Unsigned(value) => {
-                        Operand::Unsigned(!value)
+                    Operand::Colour(value) => {
+                        Operand::Colour(!value & PIXARDIS_COLOUR_MASK)
                     },
                     Operand::Integer(value) => {
                         Operand::Integer(!value)
@@ -442,8 +1951,8 @@ impl PixardisVirtualMachine {
                 let operand_b = self.virtual_machine.operand_pop()?;
 
                 let result = match (operand_a.clone(), operand_b.clone()) {
-                    (Operand::Unsigned(a), Operand::Unsigned(b)) => {
-                        Operand::Unsigned(a + b)
+                    (Operand::Colour(a), Operand::Colour(b)) => {
+                        Operand::Colour(a.wrapping_add(b) & PIXARDIS_COLOUR_MASK)
                     },                    
                     (Operand::Integer(a), Operand::Integer(b)) => {
                         Operand::Integer(a + b)
@@ -468,8 +1977,8 @@ impl PixardisVirtualMachine {
                 let operand_b = self.virtual_machine.operand_pop()?;
 
                 let result = match (operand_a, operand_b) {
-                    (Operand::Unsigned(a), Operand::Unsigned(b)) => {
-                        Operand::Unsigned(a - b)
+                    (Operand::Colour(a), Operand::Colour(b)) => {
+                        Operand::Colour(a.wrapping_sub(b) & PIXARDIS_COLOUR_MASK)
                     },
                     (Operand::Integer(a), Operand::Integer(b)) => {
                         Operand::Integer(a - b)
@@ -489,13 +1998,22 @@ impl PixardisVirtualMachine {
                 self.virtual_machine.operand_push(result);
             },
 
+            #[cfg(feature = "draw-ext")]
+            PixardisInstruction::AddColour | PixardisInstruction::SubtractColour => {
+                let a = self.operand_pop_colour()?;
+                let b = self.operand_pop_colour()?;
+
+                let subtract = *instruction == PixardisInstruction::SubtractColour;
+                self.virtual_machine.operand_push(Operand::Colour(colour_saturating(a, b, subtract)));
+            },
+
             PixardisInstruction::Multiply => {
                 let operand_a = self.virtual_machine.operand_pop()?;
                 let operand_b = self.virtual_machine.operand_pop()?;
 
                 let result = match (operand_a, operand_b) {
-                    (Operand::Unsigned(a), Operand::Unsigned(b)) => {
-                        Operand::Unsigned(a * b)
+                    (Operand::Colour(a), Operand::Colour(b)) => {
+                        Operand::Colour(a.wrapping_mul(b) & PIXARDIS_COLOUR_MASK)
                     },
                     (Operand::Integer(a), Operand::Integer(b)) => {
                         Operand::Integer(a * b)
@@ -520,12 +2038,12 @@ impl PixardisVirtualMachine {
                 let operand_b = self.virtual_machine.operand_pop()?;
 
                 let result = match (operand_a, operand_b) {
-                    (Operand::Unsigned(a), Operand::Unsigned(b)) => {
+                    (Operand::Colour(a), Operand::Colour(b)) => {
                         if b == 0 {
                             Err(VirtualMachineError::DivisionByZero)?
                         }
 
-                        Operand::Unsigned(a / b)
+                        Operand::Colour(a / b)
                     },
                     (Operand::Integer(a), Operand::Integer(b)) => {
                         if b == 0 {
@@ -566,12 +2084,12 @@ impl PixardisVirtualMachine {
                 let operand_b = self.virtual_machine.operand_pop()?;
 
                 let result = match (operand_a, operand_b) {
-                    (Operand::Unsigned(a), Operand::Unsigned(b)) => {
+                    (Operand::Colour(a), Operand::Colour(b)) => {
                         if b == 0 {
                             Err(VirtualMachineError::DivisionByZero)?
                         }
 
-                        Operand::Unsigned(a % b)
+                        Operand::Colour(a % b)
                     },
                     (Operand::Integer(a), Operand::Integer(b)) => {
                         if b == 0 {
@@ -589,8 +2107,8 @@ impl PixardisVirtualMachine {
             PixardisInstruction::Increment => {
                 let operand = self.virtual_machine.operand_pop()?;
                 let result = match operand {
-                    Operand::Unsigned(value) => {
-                        Operand::Unsigned(value + 1)
+                    Operand::Colour(value) => {
+                        Operand::Colour(value.wrapping_add(1) & PIXARDIS_COLOUR_MASK)
                     },
                     Operand::Integer(value) => {
                         Operand::Integer(value + 1)
@@ -606,8 +2124,8 @@ impl PixardisVirtualMachine {
             PixardisInstruction::Decrement => {
                 let operand = self.virtual_machine.operand_pop()?;
                 let result = match operand {
-                    Operand::Unsigned(value) => {
-                        Operand::Unsigned(value - 1)
+                    Operand::Colour(value) => {
+                        Operand::Colour(value.wrapping_sub(1) & PIXARDIS_COLOUR_MASK)
                     },
                     Operand::Integer(value) => {
                         Operand::Integer(value - 1)
@@ -625,8 +2143,8 @@ impl PixardisVirtualMachine {
                 let operand_b = self.virtual_machine.operand_pop()?;
 
                 let result = match (operand_a, operand_b) {
-                    (Operand::Unsigned(a), Operand::Unsigned(b)) => {
-                        Operand::Unsigned(a.max(b))
+                    (Operand::Colour(a), Operand::Colour(b)) => {
+                        Operand::Colour(a.max(b))
                     },
                     (Operand::Integer(a), Operand::Integer(b)) => {
                         Operand::Integer(a.max(b))
@@ -651,8 +2169,8 @@ impl PixardisVirtualMachine {
                 let operand_b = self.virtual_machine.operand_pop()?;
 
                 let result = match (operand_a, operand_b) {
-                    (Operand::Unsigned(a), Operand::Unsigned(b)) => {
-                        Operand::Unsigned(a.min(b))
+                    (Operand::Colour(a), Operand::Colour(b)) => {
+                        Operand::Colour(a.min(b))
                     },
                     (Operand::Integer(a), Operand::Integer(b)) => {
                         Operand::Integer(a.min(b))
@@ -687,17 +2205,68 @@ impl PixardisVirtualMachine {
                 self.virtual_machine.operand_push(result);
             },
 
-            PixardisInstruction::LessThan => {
-                let operand_a = self.virtual_machine.operand_pop()?;
-                let operand_b = self.virtual_machine.operand_pop()?;
+            PixardisInstruction::RandomFloat => {
+                // Float-less targets can't represent real operands
+                if !self.float_support {
+                    Err(VirtualMachineError::InvalidOperand)?
+                }
 
-                let result = match (operand_a, operand_b) {
-                    (Operand::Unsigned(a), Operand::Unsigned(b)) => {
-                        Operand::Unsigned(if a < b { 1 } else { 0 })
-                    },
-                    (Operand::Integer(a), Operand::Integer(b)) => {
-                        Operand::Integer(if a < b { 1 } else { 0 })
-                    },
+                let value = self.virtual_machine.random_real();
+                self.virtual_machine.operand_push(Operand::Real(value));
+            },
+
+            #[cfg(feature = "draw-ext")]
+            PixardisInstruction::Noise => {
+                // Float-less targets can't represent the (real) result
+                if !self.float_support {
+                    Err(VirtualMachineError::InvalidOperand)?
+                }
+
+                let x = self.operand_pop_real()?;
+                let y = self.operand_pop_real()?;
+                let t = self.operand_pop_real()?;
+
+                let value = noise(self.virtual_machine.noise_seed(), x, y, t);
+                self.virtual_machine.operand_push(Operand::Real(value));
+            },
+
+            // Integer bounds give an integer, otherwise a real; an empty
+            // range yields its lower bound
+            PixardisInstruction::RandomRange => {
+                let low = self.virtual_machine.operand_pop()?;
+                let high = self.virtual_machine.operand_pop()?;
+
+                let real = |operand: &Operand| match operand {
+                    Operand::Integer(value) => Some(*value as f64),
+                    Operand::Real(value) => Some(*value),
+                    Operand::Colour(_) => None,
+                };
+
+                let result = match (&low, &high) {
+                    (Operand::Integer(low), Operand::Integer(high)) if low < high => {
+                        Operand::Integer(self.virtual_machine.random_integer_range(*low, *high))
+                    },
+                    (Operand::Integer(low), Operand::Integer(_)) => Operand::Integer(*low),
+                    _ => match (real(&low), real(&high)) {
+                        (Some(low), Some(high)) => Operand::Real(low + self.virtual_machine.random_real() * (high - low).max(0.0)),
+                        _ => { Err(VirtualMachineError::InvalidOperand)? },
+                    },
+                };
+
+                self.virtual_machine.operand_push(result);
+            },
+
+            PixardisInstruction::LessThan => {
+                let operand_a = self.virtual_machine.operand_pop()?;
+                let operand_b = self.virtual_machine.operand_pop()?;
+
+                let result = match (operand_a, operand_b) {
+                    (Operand::Colour(a), Operand::Colour(b)) => {
+                        Operand::Colour(if a < b { 1 } else { 0 })
+                    },
+                    (Operand::Integer(a), Operand::Integer(b)) => {
+                        Operand::Integer(if a < b { 1 } else { 0 })
+                    },
                     (Operand::Real(a), Operand::Real(b)) => {
                         Operand::Integer(if a < b { 1 } else { 0 })
                     },
@@ -718,8 +2287,8 @@ impl PixardisVirtualMachine {
                 let operand_b = self.virtual_machine.operand_pop()?;
 
                 let result = match (operand_a, operand_b) {
-                    (Operand::Unsigned(a), Operand::Unsigned(b)) => {
-                        Operand::Unsigned(if a <= b { 1 } else { 0 })
+                    (Operand::Colour(a), Operand::Colour(b)) => {
+                        Operand::Colour(if a <= b { 1 } else { 0 })
                     },
                     (Operand::Integer(a), Operand::Integer(b)) => {
                         Operand::Integer(if a <= b { 1 } else { 0 })
@@ -744,8 +2313,8 @@ impl PixardisVirtualMachine {
                 let operand_b = self.virtual_machine.operand_pop()?;
 
                 let result = match (operand_a, operand_b) {
-                    (Operand::Unsigned(a), Operand::Unsigned(b)) => {
-                        Operand::Unsigned(if a > b { 1 } else { 0 })
+                    (Operand::Colour(a), Operand::Colour(b)) => {
+                        Operand::Colour(if a > b { 1 } else { 0 })
                     },
                     (Operand::Integer(a), Operand::Integer(b)) => {
                         Operand::Integer(if a > b { 1 } else { 0 })
@@ -770,8 +2339,8 @@ impl PixardisVirtualMachine {
                 let operand_b = self.virtual_machine.operand_pop()?;
 
                 let result = match (operand_a, operand_b) {
-                    (Operand::Unsigned(a), Operand::Unsigned(b)) => {
-                        Operand::Unsigned(if a >= b { 1 } else { 0 })
+                    (Operand::Colour(a), Operand::Colour(b)) => {
+                        Operand::Colour(if a >= b { 1 } else { 0 })
                     },
                     (Operand::Integer(a), Operand::Integer(b)) => {
                         Operand::Integer(if a >= b { 1 } else { 0 })
@@ -796,8 +2365,8 @@ impl PixardisVirtualMachine {
                 let operand_b = self.virtual_machine.operand_pop()?;
 
                 let result = match (operand_a, operand_b) {
-                    (Operand::Unsigned(a), Operand::Unsigned(b)) => {
-                        Operand::Unsigned(if a == b { 1 } else { 0 })
+                    (Operand::Colour(a), Operand::Colour(b)) => {
+                        Operand::Colour(if a == b { 1 } else { 0 })
                     },
                     (Operand::Integer(a), Operand::Integer(b)) => {
                         Operand::Integer(if a == b { 1 } else { 0 })
@@ -840,7 +2409,7 @@ impl PixardisVirtualMachine {
 
                 let operand = self.virtual_machine.operand_pop()?;
                 let condition = match operand {
-                    Operand::Unsigned(condition) => {
+                    Operand::Colour(condition) => {
                         condition as i64
                     },
                     Operand::Integer(condition) => {
@@ -873,6 +2442,16 @@ impl PixardisVirtualMachine {
                     _ => { Err(VirtualMachineError::InvalidArgumentCount)? },
                 };
 
+                // Check the count against the callee's signature, if the program declares one
+                if let Some((name, size)) = self.signatures.get(&address) {
+                    if *size != param_count {
+                        let message = format!("Call to {} with {} argument value(s); it expects {}", Self::function_name(name), param_count, size);
+                        self.log(PixardisLogCategory::Exec, PixardisLogLevel::Error, message);
+
+                        return Err(VirtualMachineError::InvalidArgumentCount);
+                    }
+                }
+
                 // Read arguments from operand stack
                 let mut param_copy = param_count.clone();
                 let mut param_buffer = Vec::<Operand>::new();
@@ -888,7 +2467,7 @@ impl PixardisVirtualMachine {
 
                 // Copy arguments
                 for (index, operand) in param_buffer.iter().enumerate() {
-                    self.virtual_machine.memory_write(0, index, operand.clone())?;
+                    self.memory_store(0, index, operand.clone(), instruction)?;
                 }
 
                 // Push return address onto address stack
@@ -899,6 +2478,8 @@ impl PixardisVirtualMachine {
             },
 
             PixardisInstruction::Return => {
+                self.return_check()?;
+
                 // Read return value
                 let operand = self.virtual_machine.operand_pop()?;
 
@@ -936,6 +2517,8 @@ impl PixardisVirtualMachine {
             */
 
             PixardisInstruction::ReturnArray => {
+                self.return_check()?;
+
                 // Read number of elements to return
                 let operand = self.virtual_machine.operand_pop()?;
                 
@@ -1012,6 +2595,10 @@ impl PixardisVirtualMachine {
                 };
 
                 self.delay(_delay)?;
+
+                // The frame is over; host input written during it becomes visible
+                self.frame_in_progress = false;
+                self.input_apply();
             },
 
             PixardisInstruction::Write => {
@@ -1021,7 +2608,7 @@ impl PixardisVirtualMachine {
                         x as usize
                     },
                     Operand::Real(x) => {
-                        x as usize
+                        x as i64 as usize
                     },
                     _ => { Err(VirtualMachineError::InvalidOperand)? },
                 };
@@ -1032,14 +2619,14 @@ impl PixardisVirtualMachine {
                         y as usize
                     },
                     Operand::Real(y) => {
-                        y as usize
+                        y as i64 as usize
                     },
                     _ => { Err(VirtualMachineError::InvalidOperand)? },
                 };
 
                 let operand = self.virtual_machine.operand_pop()?;
                 let c = match operand {
-                    Operand::Unsigned(c) => {
+                    Operand::Colour(c) => {
                         c as u64
                     },
                     Operand::Integer(c) => {
@@ -1048,7 +2635,8 @@ impl PixardisVirtualMachine {
                     _ => { Err(VirtualMachineError::InvalidOperand)? },
                 };
 
-                let _ = self.display.write_pixel(x, y, c);
+                let result = self.display.write_pixel(x, y, c);
+                self.clip_result(result)?;
             },
 
             PixardisInstruction::WriteBox => {
@@ -1058,7 +2646,7 @@ impl PixardisVirtualMachine {
                         x as usize
                     },
                     Operand::Real(x) => {
-                        x as usize
+                        x as i64 as usize
                     },
                     _ => { Err(VirtualMachineError::InvalidOperand)? },
                 };
@@ -1069,7 +2657,7 @@ impl PixardisVirtualMachine {
                         y as usize
                     },
                     Operand::Real(y) => {
-                        y as usize
+                        y as i64 as usize
                     },
                     _ => { Err(VirtualMachineError::InvalidOperand)? },
                 };
@@ -1098,7 +2686,7 @@ impl PixardisVirtualMachine {
 
                 let operand = self.virtual_machine.operand_pop()?;
                 let c = match operand {
-                    Operand::Unsigned(c) => {
+                    Operand::Colour(c) => {
                         c as u64
                     },
                     Operand::Integer(c) => {
@@ -1107,7 +2695,12 @@ impl PixardisVirtualMachine {
                     _ => { Err(VirtualMachineError::InvalidOperand)? },
                 };
 
-                let _ = self.display.write_box(x, y, w, h, c);
+                // Boxes cost one cycle per (visible) pixel
+                let (columns, rows) = self.display.box_span(x, y, w, h);
+                self.instruction_cost = columns.len() * rows.len();
+
+                let result = self.display.write_box(x, y, w, h, c);
+                self.clip_result(result)?;
             },
 
             PixardisInstruction::WriteLine => {
@@ -1117,7 +2710,7 @@ impl PixardisVirtualMachine {
                         x0 as usize
                     },
                     Operand::Real(x0) => {
-                        x0 as usize
+                        x0 as i64 as usize
                     },
                     _ => { Err(VirtualMachineError::InvalidOperand)? },
                 };
@@ -1128,7 +2721,7 @@ impl PixardisVirtualMachine {
                         y0 as usize
                     },
                     Operand::Real(y0) => {
-                        y0 as usize
+                        y0 as i64 as usize
                     },
                     _ => { Err(VirtualMachineError::InvalidOperand)? },
                 };
@@ -1139,7 +2732,7 @@ impl PixardisVirtualMachine {
                         x1 as usize
                     },
                     Operand::Real(x1) => {
-                        x1 as usize
+                        x1 as i64 as usize
                     },
                     _ => { Err(VirtualMachineError::InvalidOperand)? },
                 };
@@ -1150,14 +2743,14 @@ impl PixardisVirtualMachine {
                         y1 as usize
                     },
                     Operand::Real(y1) => {
-                        y1 as usize
+                        y1 as i64 as usize
                     },
                     _ => { Err(VirtualMachineError::InvalidOperand)? },
                 };
 
                 let operand = self.virtual_machine.operand_pop()?;
                 let c = match operand {
-                    Operand::Unsigned(c) => {
+                    Operand::Colour(c) => {
                         c as u64
                     },
                     Operand::Integer(c) => {
@@ -1166,7 +2759,53 @@ impl PixardisVirtualMachine {
                     _ => { Err(VirtualMachineError::InvalidOperand)? },
                 };
 
-                let _ = self.display.write_line(x0, y0, x1, y1, c);
+                // Lines cost one cycle per pixel along their length
+                self.instruction_cost = (x1 as isize - x0 as isize).unsigned_abs().max((y1 as isize - y0 as isize).unsigned_abs()) + 1;
+
+                let result = self.display.write_line(x0, y0, x1, y1, c);
+                self.clip_result(result)?;
+            },
+
+            #[cfg(feature = "draw-ext")]
+            PixardisInstruction::WriteBoxGradient |
+            PixardisInstruction::WriteBoxPattern => {
+                let x = self.operand_pop_coordinate()?;
+                let y = self.operand_pop_coordinate()?;
+                let w = self.operand_pop_size()?;
+                let h = self.operand_pop_size()?;
+                let c0 = self.operand_pop_colour()?;
+                let c1 = self.operand_pop_colour()?;
+                let mode = self.operand_pop_size()?;
+
+                // Filled boxes cost one cycle per (visible) pixel, as with WriteBox
+                let (columns, rows) = self.display.box_span(x, y, w, h);
+                self.instruction_cost = columns.len() * rows.len();
+
+                let result = match instruction {
+                    PixardisInstruction::WriteBoxGradient => match mode {
+                        0 | 1 => self.display.write_box_gradient(x, y, w, h, (c0, c1), mode == 1),
+                        _ => Err(VirtualMachineError::InvalidOperand)?,
+                    },
+                    _ => self.display.write_box_pattern(x, y, w, h, (c0, c1), mode.min(16)),
+                };
+                self.clip_result(result)?;
+            },
+
+            #[cfg(feature = "draw-ext")]
+            PixardisInstruction::WriteTriangle => {
+                let mut vertices = [(0, 0); 3];
+                for vertex in vertices.iter_mut() {
+                    *vertex = (self.operand_pop_coordinate()?, self.operand_pop_coordinate()?);
+                }
+
+                let c = self.operand_pop_colour()?;
+
+                // Triangles cost one cycle per pixel of the bounding box they rasterise
+                let (columns, rows) = self.display.triangle_span(vertices);
+                self.instruction_cost = (columns.end - columns.start) as usize * (rows.end - rows.start) as usize;
+
+                let result = self.display.write_triangle(vertices, c);
+                self.clip_result(result)?;
             },
 
             PixardisInstruction::Read => {
@@ -1176,7 +2815,7 @@ impl PixardisVirtualMachine {
                         x as usize
                     },
                     Operand::Real(x) => {
-                        x as usize
+                        x as i64 as usize
                     },
                     _ => { Err(VirtualMachineError::InvalidOperand)? },
                 };
@@ -1187,19 +2826,60 @@ impl PixardisVirtualMachine {
                         y as usize
                     },
                     Operand::Real(y) => {
-                        y as usize
+                        y as i64 as usize
                     },
                     _ => { Err(VirtualMachineError::InvalidOperand)? },
                 };
 
                 let value = self.display.read_pixel(x, y)?;
-                self.virtual_machine.operand_push(Operand::Unsigned(value));
+                self.virtual_machine.operand_push(Operand::Colour(value));
+            },
+
+            #[cfg(feature = "input-ext")]
+            PixardisInstruction::HitTest => {
+                let x = self.operand_pop_coordinate()?;
+                let y = self.operand_pop_coordinate()?;
+                let w = self.operand_pop_size()?;
+                let h = self.operand_pop_size()?;
+                let mask = self.operand_pop_colour()?;
+
+                // Tests cost one cycle per (visible) pixel, as reading them would
+                let (columns, rows) = self.display.box_span(x, y, w, h);
+                self.instruction_cost = columns.len() * rows.len();
+
+                let hit = self.display.hit_test(x, y, w, h, mask)?;
+                self.virtual_machine.operand_push(Operand::Integer(hit as i64));
+            },
+
+            #[cfg(feature = "draw-ext")]
+            PixardisInstruction::HsvToRgb => {
+                let hue = self.operand_pop_integer()?;
+                let saturation = self.operand_pop_integer()?;
+                let value = self.operand_pop_integer()?;
+
+                self.virtual_machine.operand_push(Operand::Colour(hsv_to_rgb(hue, saturation, value)));
+            },
+
+            // Leaves the hue on top, then saturation and value
+            #[cfg(feature = "draw-ext")]
+            PixardisInstruction::RgbToHsv => {
+                let (hue, saturation, value) = rgb_to_hsv(self.operand_pop_colour()?);
+
+                self.virtual_machine.operand_push(Operand::Integer(value));
+                self.virtual_machine.operand_push(Operand::Integer(saturation));
+                self.virtual_machine.operand_push(Operand::Integer(hue));
+            },
+
+            #[cfg(feature = "draw-ext")]
+            PixardisInstruction::SetBrightness => {
+                let brightness = self.operand_pop_integer()?;
+                self.display.brightness_set(brightness.clamp(0, 255) as u8);
             },
 
             PixardisInstruction::Clear => {
                 let operand = self.virtual_machine.operand_pop()?;
                 let value = match operand {
-                    Operand::Unsigned(value) => {
+                    Operand::Colour(value) => {
                         value
                     },
                     Operand::Integer(value) => {
@@ -1208,6 +2888,8 @@ impl PixardisVirtualMachine {
                     _ => { Err(VirtualMachineError::InvalidOperand)? },
                 };
 
+                self.instruction_cost = self.display.width() * self.display.height();
+
                 self.display.clear(value);
             },
 
@@ -1221,28 +2903,9 @@ impl PixardisVirtualMachine {
             
             PixardisInstruction::Print => {
                 let operand = self.virtual_machine.operand_pop()?;
-                let value = match operand {
-                    Operand::Unsigned(value) => {
-                        format!("unsigned :: {}", value.to_string())
-                    },
-                    Operand::Integer(value) => {
-                        format!("int :: {}", value.to_string())
-                    },
-                    Operand::Real(value) => {
-                        format!("real :: {}", value.to_string())
-                    },
-                };
+                let value = self.format_operand(&operand, None);
 
-                // For web targets, store in buffer; for native, print to console
-                #[cfg(target_arch = "wasm32")]
-                {
-                    self.add_print_output(value);
-                }
-                
-                #[cfg(not(target_arch = "wasm32"))]
-                {
-                    println!("{}", value);
-                }
+                self.print(value);
             },
 
             /*
@@ -1254,7 +2917,7 @@ impl PixardisVirtualMachine {
                 3. Initialize an empty vector to collect the string representations of the array elements.
                 4. Iterate over the number of elements specified by the array size:
                 a. Pop each element from the operand stack.
-                b. Match the type of each operand (Unsigned, Integer, Real) and format it accordingly.
+                b. Match the type of each operand (Colour, Integer, Real) and format it accordingly.
                 c. Push the formatted string representation of each element into the vector.
                 5. Join the collected string representations with a comma separator and enclose them in brackets.
                 6. Print the resulting string to the console.
@@ -1279,150 +2942,1374 @@ impl PixardisVirtualMachine {
                 for _ in 0..array_size {
                     let operand = self.virtual_machine.operand_pop()?;
                 
-                    let value = match operand {
-                        Operand::Unsigned(value) => format!("unsigned :: {}", value),
-                        Operand::Integer(value) => format!("int :: {}", value),
-                        Operand::Real(value) => format!("real :: {}", value),
-                    };
-                
-                    values.push(value);
+                    values.push(self.format_operand(&operand, None));
                 }
                 
                 // Print in stack order
                 let output = format!("[{}]", values.join(", "));
 
-                // For web targets, store in buffer; for native, print to console
-                #[cfg(target_arch = "wasm32")]
-                {
-                    self.add_print_output(output);
+                self.print(output);
+            },
+
+            // As Print, with reals rounded to the number of decimal places
+            // below the value (at most PIXARDIS_PRINT_DECIMALS_MAX)
+            PixardisInstruction::PrintFormatted => {
+                let operand = self.virtual_machine.operand_pop()?;
+
+                let decimals = match self.virtual_machine.operand_pop()? {
+                    Operand::Integer(decimals) if (0..=PIXARDIS_PRINT_DECIMALS_MAX as i64).contains(&decimals) => {
+                        decimals as usize
+                    },
+                    _ => { Err(VirtualMachineError::InvalidOperand)? },
+                };
+
+                let value = self.format_operand(&operand, Some(decimals));
+
+                self.print(value);
+            },
+
+            // As Print and PrintFormatted, for a fixed-point value with the
+            // number of fractional bits below it (chroma --fixed-point)
+            PixardisInstruction::PrintFixed => {
+                let operand = self.fixed_point_pop()?;
+                let value = self.format_operand(&operand, None);
+
+                self.print(value);
+            },
+
+            PixardisInstruction::PrintFormattedFixed => {
+                let operand = self.fixed_point_pop()?;
+
+                let decimals = match self.virtual_machine.operand_pop()? {
+                    Operand::Integer(decimals) if (0..=PIXARDIS_PRINT_DECIMALS_MAX as i64).contains(&decimals) => {
+                        decimals as usize
+                    },
+                    _ => { Err(VirtualMachineError::InvalidOperand)? },
+                };
+
+                let value = self.format_operand(&operand, Some(decimals));
+
+                self.print(value);
+            },
+
+            PixardisInstruction::Capabilities => {
+                self.virtual_machine.operand_push(Operand::Integer(self.capabilities()));
+            },
+
+            // Conditions are true when non-zero, as for cjmp
+            PixardisInstruction::Assert => {
+                match self.virtual_machine.operand_pop()? {
+                    Operand::Integer(0) | Operand::Colour(0) => {
+                        let address = self.virtual_machine.program_counter().saturating_sub(1);
+                        Err(VirtualMachineError::AssertionFailed(self.source_line(address)))?
+                    },
+                    Operand::Integer(_) | Operand::Colour(_) => { },
+                    _ => { Err(VirtualMachineError::InvalidOperand)? },
                 }
-                
-                #[cfg(not(target_arch = "wasm32"))]
-                {
-                    println!("{}", output);
-                }              
             },
 
-            // Just in case we get an instruction we don't recognise
-            // _ => { },
+            // Extension instructions the assembler knows (through another
+            // crate's features) but this VM was built without
+            #[allow(unreachable_patterns)]
+            _ => { Err(VirtualMachineError::UnsupportedInstruction)? },
         }
 
         Ok(())
     }
 
     //
-    // Returns the display framebuffer
+    // Report a runtime error and stop the VM
     //
-    pub fn framebuffer(&self) -> (usize, usize, &Vec<u64>) {
-        (self.display.width(), self.display.height(), self.display.framebuffer())
+    fn raise_error(&mut self, error: VirtualMachineError, address: usize, instruction: Option<PixardisInstruction>) -> Result<VirtualMachineStepResult, VirtualMachineError> {
+        for (_, observer) in self.observers.iter_mut() {
+            observer.on_error(address, &error);
+        }
+
+        let message = match error {
+            VirtualMachineError::AssertionFailed(Some(line)) => format!("Error: assertion failed at line {}", line),
+            VirtualMachineError::AssertionFailed(None) => String::from("Error: assertion failed"),
+            _ => format!("Error: {:?}", error),
+        };
+
+        self.log(PixardisLogCategory::Exec, PixardisLogLevel::Error, message);
+        match instruction {
+            Some(instruction) => self.log(PixardisLogCategory::Exec, PixardisLogLevel::Error, format!("@ ==> [{}] : {:?}", address, instruction)),
+            None => self.log(PixardisLogCategory::Exec, PixardisLogLevel::Error, format!("@ ==> [{}]", address)),
+        }
+
+        self.backtrace = self.call_stack(address);
+        for frame in self.backtrace.clone() {
+            self.log(PixardisLogCategory::Exec, PixardisLogLevel::Error, format!("    in {} @ [{}]", frame.function.as_deref().unwrap_or("?"), frame.address));
+        }
+        
+        self.virtual_machine.state_set(VirtualMachineState::Stopped);
+
+        // No frame is running any more, so latched input can be applied
+        self.frame_in_progress = false;
+        self.input_apply();
+
+        // For browser targets, return the error
+        #[cfg(any(all(target_arch = "wasm32", not(target_os = "wasi")), not(feature = "std")))]
+        {
+            Err(error)
+        }
+
+        // For console targets, exit the process unless the host embeds the VM
+        #[cfg(all(feature = "std", any(not(target_arch = "wasm32"), target_os = "wasi")))]
+        {
+            if self.exit_on_error {
+                std::process::exit(1);
+            }
+
+            Err(error)
+        }
     }
 
     //
-    // Set VM log level
+    // The call chain leading to an address, innermost first. Functions are
+    // named after the label pushed just before each call instruction (which
+    // is how the compiler emits calls); the outermost frame is the program's
+    // main, since its code may sit after other functions' bodies.
     //
-    pub fn log_level_set(&mut self, log_level: PixardisLogLevel) {
-        self.log_level = log_level;
+    pub fn call_stack(&self, address: usize) -> Vec<PixardisCallFrame> {
+        let program = self.virtual_machine.program();
+
+        // The callee of the call that returns to the given address
+        let callee = |return_address: usize| match return_address.checked_sub(2).and_then(|index| program.get(index)) {
+            Some(PixardisInstruction::PushLabel(label)) => Some(Self::function_name(label)),
+            _ => None,
+        };
+
+        let mut frames = Vec::new();
+        let mut address = address;
+
+        for &(return_address, _) in self.virtual_machine.address_stack().iter().rev() {
+            frames.push(PixardisCallFrame { address, function: callee(return_address) });
+            address = return_address.saturating_sub(1);
+        }
+
+        let main = self.virtual_machine.address_label_get("main").map(|_| String::from("main"));
+        frames.push(PixardisCallFrame { address, function: main });
+
+        frames
     }
 
-    pub fn log_level(&self) -> PixardisLogLevel {
-        self.log_level.clone()
+    //
+    // Source name of the function a label enters (compiled function labels
+    // are scoped; see pixardis_mangle_label)
+    //
+    fn function_name(label: &str) -> String {
+        match pixardis_demangle_label(label) {
+            Some((name, _)) => name.to_string(),
+            None => label.to_string(),
+        }
     }
-}
 
-///
-/// Implements the Executor trait for the Pixardis virtual machine
-///
-impl Executor for PixardisVirtualMachine {    
-    
-    fn run(&mut self) -> Result<(), VirtualMachineError> { 
-        while self.step(1).is_ok() { };
+    //
+    // Record which instruction writes each pixel from now on, for
+    // pixel_inspect (off by default, since it slows every write down)
+    //
+    #[allow(dead_code)]
+    pub fn pixel_tracking_set(&mut self, enabled: bool) {
+        self.pixel_writers = match enabled {
+            true => Some(vec![None; self.display.width() * self.display.height()]),
+            false => None,
+        };
+
+        self.display.journal_enable(enabled || !self.observers.is_empty());
+    }
+
+    //
+    // Record which instructions execute from now on, for coverage reports
+    // (off by default); loading a program starts a fresh record
+    //
+    #[allow(dead_code)]
+    pub fn coverage_set(&mut self, enabled: bool) {
+        self.coverage = match enabled {
+            true => Some(vec![false; self.virtual_machine.program().len()]),
+            false => None,
+        };
+    }
+
+    //
+    // Whether each instruction (by address) has executed, if recording
+    //
+    #[allow(dead_code)]
+    pub fn coverage(&self) -> Option<&[bool]> {
+        self.coverage.as_deref()
+    }
+
+    //
+    // Recorded coverage by source line (see source_lines_set); empty unless
+    // both coverage and the line table are available
+    //
+    #[allow(dead_code)]
+    pub fn line_coverage(&self) -> Vec<PixardisLineCoverage> {
+        match &self.coverage {
+            Some(executed) => pixardis_line_coverage(executed, &self.source_lines),
+            None => Vec::new(),
+        }
+    }
+
+    //
+    // The colour at a display pixel (as written, before brightness and
+    // gamma) and the instruction that last wrote it, if tracked
+    //
+    #[allow(dead_code)]
+    pub fn pixel_inspect(&self, x: usize, y: usize) -> Option<PixardisPixelInfo> {
+        let (width, height) = (self.display.width(), self.display.height());
+
+        if x >= width || y >= height {
+            return None;
+        }
+
+        let writer = self.pixel_writers.as_ref()
+            .and_then(|writers| writers[y * width + x])
+            .map(|address| PixardisPixelWriter {
+                address,
+                instruction: self.virtual_machine.program().get(address).cloned().unwrap_or(PixardisInstruction::Nop),
+                function: self.function_at(address),
+            });
+
+        Some(PixardisPixelInfo { x, y, colour: self.display.framebuffer()[y * width + x], writer })
+    }
+
+    //
+    // Source name of the function whose code holds an address: the closest
+    // function entry (or main) at or before it
+    //
+    fn function_at(&self, address: usize) -> Option<String> {
+        let function = self.signatures.range(..=address).next_back()
+            .map(|(&entry, (label, _))| (entry, Self::function_name(label)));
+
+        let main = self.virtual_machine.address_label_get("main")
+            .filter(|&entry| entry <= address)
+            .map(|entry| (entry, String::from("main")));
+
+        match (function, main) {
+            (Some(function), Some(main)) => Some(if main.0 > function.0 { main.1 } else { function.1 }),
+            (function, main) => function.or(main).map(|(_, name)| name),
+        }
+    }
+
+    //
+    // Call chain at the last runtime error, innermost first
+    //
+    #[allow(dead_code)]
+    pub fn backtrace(&self) -> &[PixardisCallFrame] {
+        &self.backtrace
+    }
+
+    //
+    // Push count values from memory so that the first element ends up on top
+    // of the stack; StoreArray pops them back in the same order
+    //
+    fn push_array(&mut self, frame: usize, offset: usize, count: usize) -> Result<(), VirtualMachineError> {
+        self.array_bounds_check("PushArray", frame, offset, count)?;
+
+        for index in (0..count).rev() {
+            let value = self.virtual_machine.memory_read(frame, offset + index)?;
+            self.virtual_machine.operand_push(value);
+        }
 
         Ok(())
     }
 
-    fn step(&mut self, cycles: usize) -> Result<(), VirtualMachineError> {
-        // Don't change state to running when delayed
-        match self.virtual_machine.state() {
-            VirtualMachineState::Delayed(_, _) => { }, // if delayed, don't change state
-            _ => {
-                self.virtual_machine.state_set(VirtualMachineState::Running);
-            }
-        } 
+    //
+    // An array access must lie within its frame
+    //
+    fn array_bounds_check(&mut self, name: &str, frame: usize, offset: usize, count: usize) -> Result<(), VirtualMachineError> {
+        let size = self.virtual_machine.memory_frame_size(frame)?;
 
-        for _ in 0..cycles {
-            // If VM is delayed, check if delay has expired
-            if let VirtualMachineState::Delayed(time_stamp, cooldown) = self.virtual_machine.state() {
-                let elapsed = self.get_time() - time_stamp;
-                
-                if elapsed < cooldown {
-                    continue;
-                } 
-            
-                self.virtual_machine.state_set(VirtualMachineState::Running);
-            }
-                        
-            // Return current instruction
-            let instruction = self.virtual_machine.instruction_get_current()?;
+        if offset.checked_add(count).is_some_and(|end| end <= size) {
+            return Ok(());
+        }
 
-            // Increment program counter
-            self.virtual_machine.program_counter_increment();
+        let message = format!("{} of {} element(s) at [{}:{}] overruns the frame ({} element(s))", name, count, offset, frame, size);
+        self.log(PixardisLogCategory::Memory, PixardisLogLevel::Error, message);
 
-            // Execute instruction
-            let result = self.execute_instruction(instruction.clone());
-            
-            // Report an error if an exception is thrown
-            if result.is_err() {
-                let error = result.err().unwrap();
-
-                match self.log_level() {
-                    PixardisLogLevel::None => { },
-                    _ => {  
-                        println!("Error: {:?}", error);
-                        println!("@ ==> [{}] : {:?}", self.virtual_machine.program_counter(), instruction.clone());
-                    }
-                }
-                
-                self.virtual_machine.state_set(VirtualMachineState::Stopped);
+        Err(VirtualMachineError::InvalidMemoryAccess)
+    }
 
-                // For WASM targets, return the error
-                #[cfg(target_arch = "wasm32")]
-                {                    
-                    return Err(error);
-                }
+    //
+    // A return must match a call, and leave only the frame that call opened
+    //
+    fn return_check(&mut self) -> Result<(), VirtualMachineError> {
+        let depth = self.virtual_machine.memory_frame_depth();
 
-                // For non-WASM targets, exit the process
-                #[cfg(not(target_arch = "wasm32"))]
-                {
-                    std::process::exit(1);
-                }
-            } 
+        let message = match self.virtual_machine.address_peek() {
+            Ok((_, frame_depth)) if frame_depth == depth => return Ok(()),
+            Ok((_, frame_depth)) => format!("Return with {} frame(s) open, but its call left {}", depth, frame_depth),
+            Err(_) => String::from("Return without a matching call"),
+        };
 
-            // Provide output if log level is set to full
-            match self.log_level() {
-                PixardisLogLevel::Full => {println!("[{}] : {:?}", self.virtual_machine.program_counter(), instruction.clone())},
-                _ => { },
-            }
+        self.log(PixardisLogCategory::Exec, PixardisLogLevel::Error, message);
+
+        Err(VirtualMachineError::MismatchedReturn)
+    }
 
-            // self.virtual_machine.print_operand_stack();
+    //
+    // Write to memory, recording a watch hit if the location is watched
+    //
+    fn memory_store(&mut self, frame: usize, offset: usize, value: Operand, instruction: &PixardisInstruction) -> Result<(), VirtualMachineError> {
+        if self.watches.is_empty() || self.replaying {
+            return self.virtual_machine.memory_write(frame, offset, value);
         }
 
-        // Don't change state to paused when delayed
-        match self.virtual_machine.state() {
-            VirtualMachineState::Delayed(_, _) => { }, // if delayed, don't change state
-            _ => {
-                self.virtual_machine.state_set(VirtualMachineState::Paused);
-            }
-        } 
+        let old = self.virtual_machine.memory_read(frame, offset)?;
+        self.virtual_machine.memory_write(frame, offset, value.clone())?;
+
+        let frame = self.virtual_machine.memory_frame_index(frame)?;
+        if self.watches.contains(&(frame, offset)) {
+            self.log(PixardisLogCategory::Memory, PixardisLogLevel::Full, format!("Watch [{}:{}] : {:?} -> {:?}", frame, offset, old, value));
+
+            self.watch_hit = Some(PixardisWatchHit {
+                frame,
+                offset,
+                old,
+                new: value,
+                // The program counter has already moved past the writing instruction
+                address: self.virtual_machine.program_counter().saturating_sub(1),
+                instruction: instruction.clone(),
+            });
+        }
 
         Ok(())
     }
 
+    //
+    // Under the continue policy, stand in for a recoverable error by pushing the
+    // value the failed instruction would have produced (zero), or skipping it,
+    // and recording a diagnostic; returns false if the error must stop the VM
+    //
+    fn recover_error(&mut self, error: &VirtualMachineError, instruction: &PixardisInstruction, address: usize) -> bool {
+        if self.error_policy != PixardisErrorPolicy::Continue {
+            return false;
+        }
+
+        let substitute = match (instruction, error) {
+            (PixardisInstruction::Divide | PixardisInstruction::Modulo, VirtualMachineError::DivisionByZero) => Some(Operand::Integer(0)),
+            (PixardisInstruction::Read, VirtualMachineError::InvalidMemoryAccess) => Some(Operand::Colour(0)),
+            #[cfg(feature = "input-ext")]
+            (PixardisInstruction::HitTest, VirtualMachineError::InvalidMemoryAccess) => Some(Operand::Integer(0)),
+            // Off-screen drawing (in the error clip mode) produces nothing to substitute
+            (PixardisInstruction::Write | PixardisInstruction::WriteBox |
+             PixardisInstruction::WriteLine, VirtualMachineError::InvalidMemoryAccess) => None,
+            #[cfg(feature = "draw-ext")]
+            (PixardisInstruction::WriteBoxGradient | PixardisInstruction::WriteBoxPattern |
+             PixardisInstruction::WriteTriangle, VirtualMachineError::InvalidMemoryAccess) => None,
+            _ => return false,
+        };
+
+        if let Some(substitute) = substitute {
+            self.virtual_machine.operand_push(substitute);
+        }
+
+        if self.replaying {
+            return true;
+        }
+
+        let diagnostic = format!("[{}] {:?}: {:?} (continued)", address, instruction, error);
+        self.log(PixardisLogCategory::Exec, PixardisLogLevel::Error, diagnostic.clone());
+
+        // Keep only the most recent diagnostics; an animating program may fail every frame
+        if self.diagnostics.len() >= PIXARDIS_DIAGNOSTICS_MAX {
+            self.diagnostics.remove(0);
+        }
+        self.diagnostics.push(diagnostic);
+
+        true
+    }
+
+    //
+    // Format a printed value according to the print format; reals are
+    // rounded to the given number of decimal places, if any
+    //
+    fn format_operand(&self, operand: &Operand, decimals: Option<usize>) -> String {
+        let (type_name, value) = match (operand, decimals) {
+            (Operand::Colour(value), _) => ("colour", format!("#{:06x}", value)),
+            (Operand::Integer(value), _) => ("int", value.to_string()),
+            (Operand::Real(value), Some(decimals)) => ("real", format!("{:.*}", decimals, value)),
+            (Operand::Real(value), None) => ("real", value.to_string()),
+        };
+
+        match self.print_format {
+            PixardisPrintFormat::Typed => format!("{} :: {}", type_name, value),
+            PixardisPrintFormat::Raw => value,
+        }
+    }
+
+    //
+    // Pop a fixed-point value and then its number of fractional bits, as the
+    // real it stands for; only printed, so it's made even without float
+    // support
+    //
+    fn fixed_point_pop(&mut self) -> Result<Operand, VirtualMachineError> {
+        let value = match self.virtual_machine.operand_pop()? {
+            Operand::Integer(value) => value,
+            _ => { Err(VirtualMachineError::InvalidOperand)? },
+        };
+
+        match self.virtual_machine.operand_pop()? {
+            Operand::Integer(bits) if (0..63).contains(&bits) => Ok(Operand::Real(value as f64 / (1i64 << bits) as f64)),
+            _ => Err(VirtualMachineError::InvalidOperand),
+        }
+    }
+
+    //
+    // Send a line of program output to observers and the console (or, in
+    // the browser, the print buffer)
+    //
+    fn print(&mut self, text: String) {
+        // Output was already produced the first time round
+        if self.replaying {
+            return;
+        }
+
+        for (_, observer) in self.observers.iter_mut() {
+            observer.on_print(&text);
+        }
+
+        // For browser targets, store in buffer; for console targets (native, WASI), print
+        #[cfg(any(all(target_arch = "wasm32", not(target_os = "wasi")), not(feature = "std")))]
+        {
+            self.add_print_output(text);
+        }
+
+        #[cfg(all(feature = "std", any(not(target_arch = "wasm32"), target_os = "wasi")))]
+        if self.console_print {
+            println!("{}", text);
+        }
+    }
+
+    //
+    // Pop a display coordinate; negative values keep their two's complement
+    // so the display can clip or wrap them
+    //
+    #[allow(dead_code)]
+    fn operand_pop_coordinate(&mut self) -> Result<usize, VirtualMachineError> {
+        match self.virtual_machine.operand_pop()? {
+            Operand::Integer(value) => Ok(value as usize),
+            Operand::Real(value) => Ok(value as i64 as usize),
+            _ => Err(VirtualMachineError::InvalidOperand),
+        }
+    }
+
+    //
+    // Pop a size or count; negative values are treated as zero
+    //
+    #[allow(dead_code)]
+    fn operand_pop_size(&mut self) -> Result<usize, VirtualMachineError> {
+        match self.virtual_machine.operand_pop()? {
+            Operand::Integer(value) => Ok(value.max(0) as usize),
+            Operand::Real(value) => Ok(value as usize),
+            _ => Err(VirtualMachineError::InvalidOperand),
+        }
+    }
+
+    #[allow(dead_code)]
+    fn operand_pop_integer(&mut self) -> Result<i64, VirtualMachineError> {
+        match self.virtual_machine.operand_pop()? {
+            Operand::Integer(value) => Ok(value),
+            Operand::Real(value) => Ok(value as i64),
+            _ => Err(VirtualMachineError::InvalidOperand),
+        }
+    }
+
+    #[allow(dead_code)]
+    fn operand_pop_real(&mut self) -> Result<f64, VirtualMachineError> {
+        match self.virtual_machine.operand_pop()? {
+            Operand::Real(value) => Ok(value),
+            Operand::Integer(value) => Ok(value as f64),
+            _ => Err(VirtualMachineError::InvalidOperand),
+        }
+    }
+
+    #[allow(dead_code)]
+    fn operand_pop_colour(&mut self) -> Result<u64, VirtualMachineError> {
+        match self.virtual_machine.operand_pop()? {
+            Operand::Colour(value) => Ok(value),
+            Operand::Integer(value) => Ok(value as u64),
+            _ => Err(VirtualMachineError::InvalidOperand),
+        }
+    }
+
+    //
+    // Off-screen writes only fail the instruction in the error clip mode
+    //
+    fn clip_result(&self, result: Result<(), VirtualMachineError>) -> Result<(), VirtualMachineError> {
+        match self.display.clip_mode() {
+            PixardisClipMode::Error => result,
+            _ => Ok(()),
+        }
+    }
+
+    //
+    // Set how drawing and reading outside the display is handled (clip by default)
+    //
+    #[allow(dead_code)]
+    pub fn clip_mode_set(&mut self, mode: PixardisClipMode) {
+        self.display.clip_mode_set(mode);
+    }
+
+    //
+    // Set a host parameter, read by the program's param instructions (the
+    // __param builtin); parameters persist across program loads
+    //
+    #[allow(dead_code)]
+    pub fn param_set(&mut self, name: &str, value: Operand) {
+        self.params.insert(name.to_string(), value);
+    }
+
+    #[allow(dead_code)]
+    pub fn param_remove(&mut self, name: &str) -> bool {
+        self.params.remove(name).is_some()
+    }
+
+    #[allow(dead_code)]
+    pub fn params(&self) -> &BTreeMap<String, Operand> {
+        &self.params
+    }
+
+    //
+    // Provide persistent storage for kvst and kvld (None removes it, after
+    // which loads give the program's default and stores are dropped)
+    //
+    #[allow(dead_code)]
+    pub fn storage_set(&mut self, storage: Option<Box<dyn PixardisStorage>>) {
+        self.storage = storage;
+    }
+
+    //
+    // Turn storage off (and on again) without removing it, e.g. when the
+    // user hasn't allowed programs to save data
+    //
+    #[allow(dead_code)]
+    pub fn storage_enable(&mut self, enabled: bool) {
+        self.storage_enabled = enabled;
+    }
+
+    #[allow(dead_code)]
+    pub fn storage_enabled(&self) -> bool {
+        self.storage_enabled && self.storage.is_some()
+    }
+
+    //
+    // Most keys a program may store; stores of further new keys are dropped
+    // (and logged), while existing keys can still be updated
+    //
+    #[allow(dead_code)]
+    pub fn storage_quota_set(&mut self, keys: usize) {
+        self.storage_quota = keys;
+    }
+
+    //
+    // Instruction set extensions this VM executes, as the bit set the caps
+    // instruction pushes (PIXARDIS_CAPABILITY_*)
+    //
+    #[allow(dead_code)]
+    pub fn capabilities(&self) -> i64 {
+        let mut capabilities = 0;
+
+        if cfg!(feature = "draw-ext") {
+            capabilities |= PIXARDIS_CAPABILITY_DRAW;
+        }
+
+        if cfg!(feature = "input-ext") {
+            capabilities |= PIXARDIS_CAPABILITY_INPUT;
+        }
+
+        if cfg!(feature = "audio-ext") {
+            capabilities |= PIXARDIS_CAPABILITY_AUDIO;
+        }
+
+        capabilities
+    }
+
+    //
+    // Display brightness (0-255, full by default); programs can also set it
+    // with the bright instruction. Like gamma, it only changes how the
+    // framebuffer is shown (see framebuffer_present), not what __read sees.
+    //
+    #[allow(dead_code)]
+    pub fn brightness_set(&mut self, brightness: u8) {
+        self.display.brightness_set(brightness);
+    }
+
+    #[allow(dead_code)]
+    pub fn brightness(&self) -> u8 {
+        self.display.brightness()
+    }
+
+    //
+    // Display gamma (1.0 by default); values above one darken mid-tones, as
+    // LED panels need to match a screen
+    //
+    #[cfg(feature = "std")]
+    #[allow(dead_code)]
+    pub fn gamma_set(&mut self, gamma: f64) {
+        self.display.gamma_set(gamma);
+    }
+
+    #[allow(dead_code)]
+    pub fn gamma(&self) -> f64 {
+        self.display.gamma()
+    }
+
+    //
+    // The framebuffer as shown, with brightness and gamma applied
+    //
+    #[allow(dead_code)]
+    pub fn framebuffer_present(&self) -> (usize, usize, Vec<u64>) {
+        let colours = self.display.framebuffer().iter().map(|&colour| self.display.present(colour)).collect();
+
+        (self.display.width(), self.display.height(), colours)
+    }
+
+    //
+    // Set the policy for recoverable runtime errors (halt by default)
+    //
+    #[allow(dead_code)]
+    pub fn error_policy_set(&mut self, policy: PixardisErrorPolicy) {
+        self.error_policy = policy;
+    }
+
+    #[allow(dead_code)]
+    pub fn error_policy(&self) -> PixardisErrorPolicy {
+        self.error_policy
+    }
+
+    //
+    // Set how print instructions format values (typed by default)
+    //
+    #[allow(dead_code)]
+    pub fn print_format_set(&mut self, format: PixardisPrintFormat) {
+        self.print_format = format;
+    }
+
+    #[allow(dead_code)]
+    pub fn print_format(&self) -> PixardisPrintFormat {
+        self.print_format
+    }
+
+    //
+    // Errors recovered from under the continue policy, oldest first
+    //
+    #[allow(dead_code)]
+    pub fn diagnostics(&self) -> &Vec<String> {
+        &self.diagnostics
+    }
+
+    #[allow(dead_code)]
+    pub fn clear_diagnostics(&mut self) {
+        self.diagnostics.clear();
+    }
+
+    //
+    // Choose whether runtime errors (including halt) exit the process or are
+    // returned to the caller; embedding hosts should disable exiting
+    //
+    #[cfg(all(feature = "std", any(not(target_arch = "wasm32"), target_os = "wasi")))]
+    #[allow(dead_code)]
+    pub fn exit_on_error_set(&mut self, exit: bool) {
+        self.exit_on_error = exit;
+    }
+
+    //
+    // Choose whether program output is printed to stdout; hosts that collect
+    // it through an observer can turn this off
+    //
+    #[cfg(all(feature = "std", any(not(target_arch = "wasm32"), target_os = "wasi")))]
+    #[allow(dead_code)]
+    pub fn console_print_set(&mut self, enabled: bool) {
+        self.console_print = enabled;
+    }
+
+    //
+    // Register an observer; returns a handle that can be used to remove it
+    //
+    #[allow(dead_code)]
+    pub fn observer_add(&mut self, observer: Box<dyn Observer>) -> usize {
+        let handle = self.observer_next;
+
+        self.observer_next += 1;
+        self.observers.push((handle, observer));
+        self.display.journal_enable(true);
+
+        handle
+    }
+
+    //
+    // Remove a previously registered observer; None if the handle isn't one
+    // of a registered observer (e.g. it was already removed)
+    //
+    #[allow(dead_code)]
+    pub fn observer_remove(&mut self, handle: usize) -> Option<Box<dyn Observer>> {
+        let index = self.observers.iter().position(|(registered, _)| *registered == handle)?;
+
+        let (_, observer) = self.observers.remove(index);
+        self.display.journal_enable(!self.observers.is_empty() || self.pixel_writers.is_some());
+
+        Some(observer)
+    }
+
+    //
+    // Emulate a target without floating point support (e.g. when running code
+    // compiled with fixed-point lowering); real operands raise an error
+    //
+    pub fn float_support_set(&mut self, enabled: bool) {
+        self.float_support = enabled;
+    }
+
+    //
+    // Seed this VM's random number generator and noise (each VM has its own,
+    // so runs are reproducible and independent of other VMs in the process)
+    //
+    #[allow(dead_code)]
+    pub fn random_seed_set(&mut self, seed: u64) {
+        self.virtual_machine.random_seed_set(seed);
+    }
+
+    //
+    // Set the watchdog limit, i.e. the number of cycles without a write, print
+    // or delay after which the program is considered stuck (None disables it)
+    //
+    pub fn watchdog_set(&mut self, cycles: Option<usize>) {
+        self.watchdog_limit = cycles;
+        self.watchdog_counter = 0;
+        self.watchdog_tripped = false;
+    }
+
+    //
+    // True if the watchdog limit has been exceeded since the last observable instruction
+    //
+    pub fn watchdog_tripped(&self) -> bool {
+        self.watchdog_tripped
+    }
+
+    //
+    // Stop the running (or next) step before its next instruction, leaving
+    // the VM paused; it reports VirtualMachineStepResult::Stopped
+    //
+    #[allow(dead_code)]
+    pub fn request_stop(&self) {
+        self.stop_requested.store(true, Ordering::Relaxed);
+    }
+
+    //
+    // A handle that requests a stop as request_stop does, for hosts that
+    // can't reach the VM while it runs
+    //
+    #[allow(dead_code)]
+    pub fn stop_handle(&self) -> PixardisStopHandle {
+        PixardisStopHandle { requested: self.stop_requested.clone() }
+    }
+
+    //
+    // A host function polled while stepping (every few instructions, as it
+    // may be slow to ask); steps stop while it returns true. None removes it.
+    //
+    #[allow(dead_code)]
+    pub fn stop_signal_set(&mut self, signal: Option<Box<dyn FnMut() -> bool>>) {
+        self.stop_signal = signal;
+        self.stop_countdown = PIXARDIS_STOP_POLL_INTERVAL;
+    }
+
+    //
+    // True if the last step (or frame) ended early for a stop request
+    //
+    #[allow(dead_code)]
+    pub fn stopped(&self) -> bool {
+        self.stopped
+    }
+
+    fn stop_pending(&mut self) -> bool {
+        if self.stop_requested.swap(false, Ordering::Relaxed) {
+            return true;
+        }
+
+        let Some(signal) = self.stop_signal.as_mut() else {
+            return false;
+        };
+
+        self.stop_countdown = self.stop_countdown.saturating_sub(1);
+
+        if self.stop_countdown > 0 {
+            return false;
+        }
+
+        self.stop_countdown = PIXARDIS_STOP_POLL_INTERVAL;
+        signal()
+    }
+
+    //
+    // Notify observers of the side effects of an executed instruction
+    //
+    fn notify_observers(&mut self, address: usize, instruction: &PixardisInstruction) {
+        let target = self.virtual_machine.program_counter();
+
+        for (_, observer) in self.observers.iter_mut() {
+            match instruction {
+                PixardisInstruction::Call => observer.on_call(address, target),
+                PixardisInstruction::Return | 
+                PixardisInstruction::ReturnArray => observer.on_return(address, target),
+                PixardisInstruction::Clear => observer.on_clear(self.display.framebuffer().first().copied().unwrap_or(0)),
+                _ => { },
+            }
+        }
+
+        let width = self.display.width();
+
+        // A clear overwrites every pixel without going through the journal
+        if let (PixardisInstruction::Clear, Some(writers)) = (instruction, self.pixel_writers.as_mut()) {
+            writers.fill(Some(address));
+        }
+
+        for (x, y, colour) in self.display.journal_drain() {
+            if let Some(writer) = self.pixel_writers.as_mut().and_then(|writers| writers.get_mut(y * width + x)) {
+                *writer = Some(address);
+            }
+
+            for (_, observer) in self.observers.iter_mut() {
+                observer.on_pixel_write(x, y, colour);
+            }
+        }
+
+        let stack = self.virtual_machine.operand_stack();
+        for (_, observer) in self.observers.iter_mut() {
+            observer.on_stack(address, stack);
+        }
+    }
+
+    fn watchdog_update(&mut self, instruction: &PixardisInstruction) {
+        match instruction {
+            PixardisInstruction::Write | 
+            PixardisInstruction::WriteBox | 
+            PixardisInstruction::WriteLine | 
+            PixardisInstruction::Clear | 
+            PixardisInstruction::Print | 
+            PixardisInstruction::PrintArray | 
+            PixardisInstruction::PrintFormatted | 
+            PixardisInstruction::PrintFixed | 
+            PixardisInstruction::PrintFormattedFixed | 
+            PixardisInstruction::Delay => {
+                self.watchdog_counter = 0;
+                self.watchdog_tripped = false;
+            },
+            #[cfg(feature = "draw-ext")]
+            PixardisInstruction::WriteBoxGradient | 
+            PixardisInstruction::WriteBoxPattern | 
+            PixardisInstruction::WriteTriangle => {
+                self.watchdog_counter = 0;
+                self.watchdog_tripped = false;
+            },
+            _ => {
+                self.watchdog_counter += 1;
+
+                if let Some(limit) = self.watchdog_limit {
+                    if self.watchdog_counter >= limit {
+                        self.watchdog_tripped = true;
+                    }
+                }
+            }
+        }
+    }
+
+    //
+    // Returns the operand stack, bottom first
+    //
+    #[allow(dead_code)]
+    pub fn operand_stack(&self) -> &[Operand] {
+        self.virtual_machine.operand_stack()
+    }
+
+    //
+    // Returns how much memory the VM's frames, stacks and buffers take
+    //
+    #[allow(dead_code)]
+    pub fn memory_usage(&self) -> PixardisMemoryUsage {
+        #[cfg(any(all(target_arch = "wasm32", not(target_os = "wasi")), not(feature = "std")))]
+        let (print_buffer_bytes, log_buffer_bytes) = {
+            let text = |buffer: &[String]| buffer.iter().map(|line| line.len() + core::mem::size_of::<String>()).sum();
+            (text(&self.print_buffer), text(&self.log_buffer))
+        };
+        // Output goes to the console, so nothing is buffered
+        #[cfg(all(feature = "std", any(not(target_arch = "wasm32"), target_os = "wasi")))]
+        let (print_buffer_bytes, log_buffer_bytes) = (0, 0);
+
+        PixardisMemoryUsage {
+            frames: self.virtual_machine.memory_frame_depth(),
+            frame_bytes: self.virtual_machine.memory_cells() * core::mem::size_of::<Operand>(),
+            operand_stack_bytes: core::mem::size_of_val(self.virtual_machine.operand_stack()),
+            framebuffer_bytes: core::mem::size_of_val(self.display.framebuffer().as_slice()),
+            print_buffer_bytes,
+            log_buffer_bytes,
+        }
+    }
+
+    //
+    // Returns the display framebuffer
+    //
+    pub fn framebuffer(&self) -> (usize, usize, &Vec<u64>) {
+        (self.display.width(), self.display.height(), self.display.framebuffer())
+    }
+
+    //
+    // Save the execution state (program, stacks, memory, display, RNG) so the
+    // run can be resumed later with load_state. Host settings such as log
+    // levels, policies and observers aren't part of the state.
+    //
+    #[allow(dead_code)]
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = SnapshotWriter::new();
+        writer.write_bytes(PIXARDIS_SNAPSHOT_MAGIC);
+
+        self.virtual_machine.snapshot_write(&mut writer);
+
+        writer.write_usize(self.display.width);
+        writer.write_usize(self.display.height);
+        for colour in self.display.display_buffer.iter() {
+            writer.write_u64(*colour);
+        }
+
+        writer.write_bool(self.frame_in_progress);
+        writer.write_u8(self.display.brightness);
+
+        writer.into_bytes()
+    }
+
+    //
+    // Restore state saved by save_state. The display must be the size it was
+    // saved at; on error the VM is left unchanged.
+    //
+    #[allow(dead_code)]
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), VirtualMachineError> {
+        let mut reader = SnapshotReader::new(state);
+
+        if reader.read_bytes(PIXARDIS_SNAPSHOT_MAGIC.len())? != PIXARDIS_SNAPSHOT_MAGIC {
+            return Err(VirtualMachineError::InvalidSnapshot);
+        }
+
+        let machine = VirtualMachine::snapshot_read(&mut reader)?;
+
+        if (reader.read_usize()?, reader.read_usize()?) != (self.display.width, self.display.height) {
+            return Err(VirtualMachineError::InvalidSnapshot);
+        }
+
+        // The buffer's length follows from the (already checked) dimensions
+        let mut display_buffer = Vec::with_capacity(self.display.display_buffer.len());
+        for _ in 0..self.display.display_buffer.len() {
+            display_buffer.push(reader.read_u64()?);
+        }
+
+        let frame_in_progress = reader.read_bool()?;
+        let brightness = reader.read_u8()?;
+
+        if !reader.is_at_end() {
+            return Err(VirtualMachineError::InvalidSnapshot);
+        }
+
+        let program = Self::build_program(machine.program().to_vec());
+        self.metadata = program.metadata;
+        self.signatures = program.signatures;
+        self.duplicate_labels = program.duplicate_labels;
+        self.virtual_machine = machine;
+        self.display.display_buffer = display_buffer;
+        self.display.brightness_set(brightness);
+        self.frame_in_progress = frame_in_progress;
+        self.input_pending.clear();
+        self.history_reset();
+        self.backtrace.clear();
+
+        Ok(())
+    }
+
+    //
+    // Pause execution whenever the given memory location is written. The
+    // frame is absolute: 0 is the outermost (global) frame, and each call
+    // opens the next one.
+    //
+    #[allow(dead_code)]
+    pub fn watch_add(&mut self, frame: usize, offset: usize) {
+        if !self.watches.contains(&(frame, offset)) {
+            self.watches.push((frame, offset));
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn watch_remove(&mut self, frame: usize, offset: usize) -> bool {
+        let count = self.watches.len();
+        self.watches.retain(|&watch| watch != (frame, offset));
+        self.watches.len() != count
+    }
+
+    #[allow(dead_code)]
+    pub fn watch_clear(&mut self) {
+        self.watches.clear();
+    }
+
+    #[allow(dead_code)]
+    pub fn watches(&self) -> &[(usize, usize)] {
+        &self.watches
+    }
+
+    //
+    // The watched write that paused the last step, if any
+    //
+    #[allow(dead_code)]
+    pub fn watch_hit(&self) -> Option<&PixardisWatchHit> {
+        self.watch_hit.as_ref()
+    }
+
+    //
+    // Record execution history for step_back, checkpointing every interval
+    // instructions (None disables it). Smaller intervals use more memory but
+    // make stepping back cheaper.
+    //
+    #[allow(dead_code)]
+    pub fn history_enable(&mut self, interval: Option<usize>) {
+        self.history = interval.map(|interval| PixardisHistory {
+            interval: interval.max(1) as u64,
+            checkpoints: VecDeque::new(),
+        });
+    }
+
+    //
+    // Number of instructions executed since the program was loaded
+    //
+    #[allow(dead_code)]
+    pub fn instructions_executed(&self) -> u64 {
+        self.executed
+    }
+
+    //
+    // Rewind by the given number of instructions: the nearest earlier
+    // checkpoint is restored and execution replays forward to the target,
+    // without repeating output or notifying observers. Host framebuffer
+    // writes made since the checkpoint are not replayed. Returns the number
+    // of instructions executed at the new position.
+    //
+    #[allow(dead_code)]
+    pub fn step_back(&mut self, instructions: u64) -> Result<u64, VirtualMachineError> {
+        let target = self.executed.saturating_sub(instructions);
+
+        let history = self.history.as_mut().ok_or(VirtualMachineError::HistoryUnavailable)?;
+
+        // Checkpoints past the target describe a future that is about to be replayed
+        while history.checkpoints.back().is_some_and(|checkpoint| checkpoint.executed > target) {
+            history.checkpoints.pop_back();
+        }
+
+        let checkpoint = history.checkpoints.back().ok_or(VirtualMachineError::HistoryUnavailable)?;
+
+        self.virtual_machine = checkpoint.machine.clone();
+        self.display.display_buffer = checkpoint.display_buffer.clone();
+        self.display.brightness_set(checkpoint.brightness);
+        self.frame_in_progress = checkpoint.frame_in_progress;
+        self.executed = checkpoint.executed;
+        self.input_pending.clear();
+
+        // Checkpoints are taken mid-step, while the machine is running
+        if let VirtualMachineState::Running = self.virtual_machine.state() {
+            self.virtual_machine.state_set(VirtualMachineState::Paused);
+        }
+
+        self.replaying = true;
+
+        let mut result = Ok(());
+        while self.executed < target && result.is_ok() {
+            // Delays are skipped, as in run_frame
+            if let VirtualMachineState::Delayed(_, _) = self.virtual_machine.state() {
+                self.virtual_machine.state_set(VirtualMachineState::Paused);
+            }
+
+            result = self.step(1).map(|_| ());
+        }
+
+        self.replaying = false;
+
+        // Replayed pixel writes were never reported, so drop them
+        self.display.journal_drain();
+
+        result.map(|_| self.executed)
+    }
+
+    fn history_reset(&mut self) {
+        self.executed = 0;
+
+        if let Some(history) = self.history.as_mut() {
+            history.checkpoints.clear();
+        }
+    }
+
+    fn history_checkpoint(&mut self) {
+        let Some(history) = self.history.as_mut() else {
+            return;
+        };
+
+        if !self.executed.is_multiple_of(history.interval) {
+            return;
+        }
+
+        // A step that returned early (waiting on a delay) resumes at the same position
+        if history.checkpoints.back().is_some_and(|checkpoint| checkpoint.executed == self.executed) {
+            return;
+        }
+
+        if history.checkpoints.len() >= PIXARDIS_HISTORY_CHECKPOINTS_MAX {
+            history.checkpoints.pop_front();
+        }
+
+        history.checkpoints.push_back(PixardisCheckpoint {
+            executed: self.executed,
+            machine: self.virtual_machine.clone(),
+            display_buffer: self.display.display_buffer.clone(),
+            brightness: self.display.brightness,
+            frame_in_progress: self.frame_in_progress,
+        });
+    }
+
+    //
+    // Write a pixel from the host side; programs observe it through __read,
+    // which makes the framebuffer the VM's input channel
+    //
+    #[allow(dead_code)]
+    pub fn framebuffer_write(&mut self, x: usize, y: usize, colour: u64) -> Result<(), VirtualMachineError> {
+        // With input latching, a write made while a frame is in progress is
+        // held back so the program sees the same input throughout the frame
+        if self.input_latch && self.frame_in_progress {
+            self.display.read_pixel(x, y)?;
+            self.input_pending.push((x, y, colour));
+        } else {
+            self.display.write_pixel(x, y, colour)?;
+        }
+
+        if let Some(recording) = self.input_recording.as_mut() {
+            recording.push(PixardisInputEvent { cycle: self.executed, x, y, colour });
+        }
+
+        Ok(())
+    }
+
+    //
+    // Latch host input per frame: framebuffer writes made mid-frame take
+    // effect at the program's next delay instead of immediately
+    //
+    #[allow(dead_code)]
+    pub fn input_latch_set(&mut self, enabled: bool) {
+        self.input_latch = enabled;
+
+        if !enabled {
+            self.input_apply();
+        }
+    }
+
+    //
+    // Record host framebuffer writes with the cycle each was made at, so a run
+    // can be reproduced with input_replay_set; enabling starts an empty
+    // recording, as does loading a program
+    //
+    #[allow(dead_code)]
+    pub fn input_record_set(&mut self, enabled: bool) {
+        self.input_recording = enabled.then(Vec::new);
+    }
+
+    //
+    // Host writes recorded so far (empty unless recording)
+    //
+    #[allow(dead_code)]
+    pub fn input_recording(&self) -> &[PixardisInputEvent] {
+        self.input_recording.as_deref().unwrap_or_default()
+    }
+
+    //
+    // Replay recorded host writes: each is written, through the input latch
+    // as the original was, before the program executes past its cycle. Set
+    // it after loading the program, which restarts the cycle count.
+    //
+    #[allow(dead_code)]
+    pub fn input_replay_set(&mut self, events: Vec<PixardisInputEvent>) {
+        self.input_replay = events.into();
+    }
+
+    fn input_replay_due(&mut self) {
+        while let Some(event) = self.input_replay.front().copied().filter(|event| event.cycle <= self.executed) {
+            self.input_replay.pop_front();
+            let _ = self.framebuffer_write(event.x, event.y, event.colour);
+        }
+    }
+
+    fn input_apply(&mut self) {
+        for (x, y, colour) in core::mem::take(&mut self.input_pending) {
+            let _ = self.display.write_pixel(x, y, colour);
+        }
+    }
+
+    //
+    // Set VM log level (applies to all categories)
+    //
+    pub fn log_level_set(&mut self, log_level: PixardisLogLevel) {
+        self.log_levels = [log_level; 3];
+    }
+
+    //
+    // Set log level for a single category
+    //
+    pub fn log_level_set_category(&mut self, category: PixardisLogCategory, log_level: PixardisLogLevel) {
+        self.log_levels[category.index()] = log_level;
+    }
+
+    #[allow(dead_code)]
+    pub fn log_level(&self, category: PixardisLogCategory) -> PixardisLogLevel {
+        self.log_levels[category.index()]
+    }
+}
+
+///
+/// Implements the Executor trait for the Pixardis virtual machine
+///
+impl Executor for PixardisVirtualMachine {    
+    
+    fn run(&mut self) -> Result<(), VirtualMachineError> { 
+        while let Ok(result) = self.step(1) {
+            if result == VirtualMachineStepResult::Stopped {
+                break;
+            }
+
+            // Sleep through delays instead of spinning on the time check
+            #[cfg(all(feature = "std", any(not(target_arch = "wasm32"), target_os = "wasi")))]
+            if let (VirtualMachineStepResult::WaitUntil(_), Some(remaining)) = (result, self.delay_remaining()) {
+                std::thread::sleep(std::time::Duration::from_secs_f64(remaining));
+            }
+
+            #[cfg(any(all(target_arch = "wasm32", not(target_os = "wasi")), not(feature = "std")))]
+            let _ = result;
+        };
+
+        Ok(())
+    }
+
+    fn step(&mut self, cycles: usize) -> Result<VirtualMachineStepResult, VirtualMachineError> {
+        // Don't change state to running when delayed
+        match self.virtual_machine.state() {
+            VirtualMachineState::Delayed(_, _) => { }, // if delayed, don't change state
+            _ => {
+                self.virtual_machine.state_set(VirtualMachineState::Running);
+            }
+        } 
+
+        // Cycles are consumed by instruction cost rather than instruction count,
+        // so drawing with boxes or lines is as expensive as drawing pixel by pixel
+        let mut budget = cycles;
+
+        // Only report writes made during this call
+        self.watch_hit = None;
+        self.stopped = false;
+
+        while budget > 0 {
+            // Replay host input recorded at this point of the run
+            if !self.input_replay.is_empty() {
+                self.input_replay_due();
+            }
+
+            // Stop before the next instruction if the host asked to
+            if self.stop_pending() {
+                self.stopped = true;
+                break;
+            }
+
+            // If VM is delayed, check if delay has expired
+            if let VirtualMachineState::Delayed(time_stamp, cooldown) = self.virtual_machine.state() {
+                let elapsed = self.get_time() - time_stamp;
+                
+                // Return early with a hint rather than burning the remaining cycles
+                if elapsed < cooldown {
+                    return Ok(VirtualMachineStepResult::WaitUntil(time_stamp + cooldown));
+                } 
+            
+                self.virtual_machine.state_set(VirtualMachineState::Running);
+            }
+                        
+            self.history_checkpoint();
+
+            // Return current instruction
+            let instruction = match self.virtual_machine.instruction_get_current() {
+                Ok(instruction) => instruction,
+                Err(error) => return self.raise_error(error, self.virtual_machine.program_counter(), None),
+            };
+
+            let address = self.virtual_machine.program_counter();
+
+            if let Some(executed) = self.coverage.as_mut().and_then(|executed| executed.get_mut(address)) {
+                *executed = true;
+            }
+
+            if !self.replaying {
+                for (_, observer) in self.observers.iter_mut() {
+                    observer.on_instruction(address, &instruction);
+                }
+            }
+
+            // Increment program counter
+            self.virtual_machine.program_counter_increment();
+
+            // Execute instruction (most instructions cost a single cycle)
+            self.instruction_cost = 1;
+            self.frame_in_progress = true;
+            let result = self.execute_instruction(&instruction);
+            budget = budget.saturating_sub(self.instruction_cost.max(1));
+            self.executed += 1;
+            
+            // Report an error if an exception is thrown
+            if let Err(error) = result {
+                if !self.recover_error(&error, &instruction, address) {
+                    return self.raise_error(error, address, Some(instruction));
+                }
+            } 
+
+            if (!self.observers.is_empty() || self.pixel_writers.is_some()) && !self.replaying {
+                self.notify_observers(address, &instruction);
+            }
+
+            // Keep track of how long it has been since the program did anything observable
+            self.watchdog_update(&instruction);
+
+            // Trace instruction if its category is set to full
+            if self.log_levels[PixardisLogCategory::of(&instruction).index()] == PixardisLogLevel::Full {
+                self.log(PixardisLogCategory::of(&instruction), PixardisLogLevel::Full, format!("[{}] : {:?}", address, instruction));
+            }
+
+            // Pause right after a write to a watched location
+            if self.watch_hit.is_some() {
+                break;
+            }
+        }
+
+        // Don't change state to paused when delayed
+        match self.virtual_machine.state() {
+            VirtualMachineState::Delayed(_, _) => { }, // if delayed, don't change state
+            _ => {
+                self.virtual_machine.state_set(VirtualMachineState::Paused);
+            }
+        } 
+
+        if self.watch_hit.is_some() {
+            return Ok(VirtualMachineStepResult::Watchpoint);
+        }
+
+        if self.stopped {
+            return Ok(VirtualMachineStepResult::Stopped);
+        }
+
+        Ok(VirtualMachineStepResult::Ready)
+    }
+
     fn stop(&mut self) -> Result<(), VirtualMachineError> { Ok(() )}
 
     fn reset(&mut self) -> Result<(), VirtualMachineError> { Ok(()) }
 
     fn delay(&mut self, millis: u64) -> Result<(), VirtualMachineError> {
-        let sleep_time = millis as f64 / 1000.0;
+        let sleep_time = millis as f64 / 1000.0 / self.time_scale;
         let time_stamp = self.get_time();
 
         self.virtual_machine.state_set(VirtualMachineState::Delayed(time_stamp, sleep_time));
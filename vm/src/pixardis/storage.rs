@@ -0,0 +1,142 @@
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+};
+
+use crate::machine::architecture::Operand;
+
+///
+/// Persistent key-value storage behind the kvst and kvld instructions (e.g.
+/// for high scores). Hosts provide it: a file on native targets, the page's
+/// localStorage (through JS callbacks) on the web.
+///
+pub trait PixardisStorage
+{
+    // Value stored under key, if any
+    fn load(&mut self, key: &str) -> Option<Operand>;
+
+    // Store a value under key, replacing any previous value
+    fn store(&mut self, key: &str, value: &Operand);
+
+    // Number of keys held (checked against the VM's storage quota)
+    fn count(&mut self) -> usize;
+
+    fn contains(&mut self, key: &str) -> bool {
+        self.load(key).is_some()
+    }
+}
+
+//
+// Stored values as text: integers, reals and colours (#rrggbb)
+//
+pub fn storage_value_to_string(value: &Operand) -> String {
+    match value {
+        Operand::Integer(value) => value.to_string(),
+        Operand::Real(value) => format!("{:?}", value),
+        Operand::Colour(value) => format!("#{:06x}", value),
+    }
+}
+
+pub fn storage_value_from_string(value: &str) -> Option<Operand> {
+    let value = value.trim();
+
+    if let Some(hex) = value.strip_prefix('#') {
+        return match hex.len() {
+            6 => u64::from_str_radix(hex, 16).ok().map(Operand::Colour),
+            _ => None,
+        };
+    }
+
+    match value.parse::<i64>() {
+        Ok(value) => Some(Operand::Integer(value)),
+        Err(_) => value.parse::<f64>().ok().map(Operand::Real),
+    }
+}
+
+///
+/// Storage that lasts as long as the VM (or until the host drops it)
+///
+#[derive(Default)]
+pub struct PixardisMemoryStorage {
+    entries: BTreeMap<String, Operand>,
+}
+
+impl PixardisStorage for PixardisMemoryStorage {
+    fn load(&mut self, key: &str) -> Option<Operand> {
+        self.entries.get(key).cloned()
+    }
+
+    fn store(&mut self, key: &str, value: &Operand) {
+        self.entries.insert(key.to_string(), value.clone());
+    }
+
+    fn count(&mut self) -> usize {
+        self.entries.len()
+    }
+}
+
+///
+/// Storage kept in a text file, one "key value" entry per line; the file is
+/// read when the storage is opened and rewritten on every store
+///
+#[cfg(feature = "std")]
+#[allow(dead_code)]
+pub struct PixardisFileStorage {
+    path: std::path::PathBuf,
+    entries: BTreeMap<String, Operand>,
+}
+
+#[cfg(feature = "std")]
+#[allow(dead_code)]
+impl PixardisFileStorage {
+    // A missing file is an empty store; it's created on the first store
+    pub fn open(path: impl Into<std::path::PathBuf>) -> Result<PixardisFileStorage, std::io::Error> {
+        let path = path.into();
+
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error),
+        };
+
+        let entries = text.lines()
+            .filter_map(|line| line.trim().split_once(char::is_whitespace))
+            .filter_map(|(key, value)| Some((key.to_string(), storage_value_from_string(value)?)))
+            .collect();
+
+        Ok(PixardisFileStorage { path, entries })
+    }
+
+    fn save(&self) -> Result<(), std::io::Error> {
+        let text: String = self.entries.iter()
+            .map(|(key, value)| format!("{} {}\n", key, storage_value_to_string(value)))
+            .collect();
+
+        std::fs::write(&self.path, text)
+    }
+}
+
+#[cfg(feature = "std")]
+impl PixardisStorage for PixardisFileStorage {
+    fn load(&mut self, key: &str) -> Option<Operand> {
+        self.entries.get(key).cloned()
+    }
+
+    fn store(&mut self, key: &str, value: &Operand) {
+        self.entries.insert(key.to_string(), value.clone());
+
+        // A program can't act on a failed write, so it's reported here
+        if let Err(error) = self.save() {
+            #[cfg(any(not(target_arch = "wasm32"), target_os = "wasi"))]
+            eprintln!("Warning: couldn't write storage file {}: {}", self.path.display(), error);
+
+            #[cfg(all(target_arch = "wasm32", not(target_os = "wasi")))]
+            let _ = error;
+        }
+    }
+
+    fn count(&mut self) -> usize {
+        self.entries.len()
+    }
+}
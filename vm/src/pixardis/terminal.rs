@@ -0,0 +1,36 @@
+use alloc::{format, string::String};
+
+//
+// The framebuffer as text for a terminal with 24-bit ANSI colours: each
+// character is an upper half block coloured with two display rows (the
+// foreground above, the background below), so pixels stay roughly square.
+// Lines end with a colour reset and a newline.
+//
+#[allow(dead_code)]
+pub fn framebuffer_to_ansi(width: usize, height: usize, colours: &[u64]) -> String {
+    let rgb = |colour: u64| ((colour >> 16) & 0xff, (colour >> 8) & 0xff, colour & 0xff);
+    let mut text = String::new();
+
+    // Display row 0 is the bottom row, so rows are drawn from the top down
+    for pair in 0..height.div_ceil(2) {
+        let upper = height - 1 - 2 * pair;
+
+        for x in 0..width {
+            let (r, g, b) = rgb(colours[upper * width + x]);
+            text.push_str(&format!("\x1b[38;2;{};{};{}m", r, g, b));
+
+            // An odd row out at the bottom leaves the terminal's background
+            match upper.checked_sub(1) {
+                Some(lower) => {
+                    let (r, g, b) = rgb(colours[lower * width + x]);
+                    text.push_str(&format!("\x1b[48;2;{};{};{}m\u{2580}", r, g, b));
+                },
+                None => text.push_str("\x1b[49m\u{2580}"),
+            }
+        }
+
+        text.push_str("\x1b[0m\n");
+    }
+
+    text
+}
@@ -0,0 +1,359 @@
+use vm::pixardis;
+
+use std::io::{self, IsTerminal, Write};
+use std::process::ExitCode;
+
+use clap::Parser as ClapParser;
+use pixardis::pixardis::{PixardisVirtualMachine, PixardisMetadata, PixardisPrintFormat, PixardisLoadLimits};
+use vm::cli::{MachineArgs, ClipArg, parse_fps, parse_time_scale};
+use vm::machine::architecture::VirtualMachineError;
+use pixardis::coverage::pixardis_coverage_to_json;
+use pixardis::debug_info::pixardis_read_line_table;
+use pixardis::input::pixardis_read_input;
+use pixardis::manifest::pixardis_read_manifest;
+use pixardis::terminal::framebuffer_to_ansi;
+use shared::pixardis::{PixardisInstruction, PIXARDIS_BINARY_MAGIC, pixardis_assembly_errors, pixardis_assembly_warnings, pixardis_decode_program, pixardis_instruction_from_string};
+
+//
+// Headless VM runner: executes a program without a display window, printing
+// program output to stdout and optionally dumping the final framebuffer.
+// Only needs std (no windowing), so it also builds for wasm32-wasi.
+//
+fn main() -> ExitCode
+{
+    let context = process_cmd_args();
+
+    match run(&context) {
+        Ok(code) => code,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(context: &Args) -> Result<ExitCode, io::Error>
+{
+    let program = read_program(&context.input, context.strict)?;
+
+    // Warn when the program, its source or its debug information has moved
+    // on from the build the manifest describes
+    if let Some(path) = &context.manifest {
+        for warning in pixardis_read_manifest(path)?.stale_warnings(&program, context.debug_info.as_deref()) {
+            eprintln!("Warning: {}.", warning);
+        }
+    }
+
+    // Flags override the display size the program asks for
+    let metadata = PixardisMetadata::from_instructions(&program);
+    let mut vm = context.machine.create(&metadata)?;
+
+    vm.clip_mode_set(context.clip.into());
+
+    if context.print_raw {
+        vm.print_format_set(PixardisPrintFormat::Raw);
+    }
+
+    // Report errors (and halt) here rather than exiting from inside the VM
+    vm.exit_on_error_set(false);
+
+    if let Some(seed) = context.seed {
+        vm.random_seed_set(seed);
+    }
+
+    for &(frame, offset) in &context.watch {
+        vm.watch_add(frame, offset);
+    }
+
+    vm.storage_quota_set(context.storage_quota);
+
+    vm.load_limits_set(PixardisLoadLimits { max_instructions: context.max_instructions, max_labels: context.max_labels });
+
+    match &context.entry {
+        Some(entry) => vm.load_program_with_entry(program, entry).or_else(|error| match error {
+            VirtualMachineError::InvalidLabel => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("entry label {} is not defined", entry)
+            )),
+            _ => Ok(()),
+        })?,
+        None => vm.load_program(program),
+    }
+
+    if let Some(rejection) = &vm.load_report().rejection {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("program not loaded: {}", rejection)));
+    }
+
+    for warning in vm.load_report().warnings.iter() {
+        eprintln!("Warning: {}.", warning);
+    }
+
+    if let Some(path) = &context.debug_info {
+        vm.source_lines_set(pixardis_read_line_table(path)?);
+    }
+
+    // Replayed input counts cycles from the start of the loaded program
+    if let Some(path) = &context.replay_input {
+        vm.input_replay_set(pixardis_read_input(path)?);
+    }
+
+    vm.coverage_set(context.coverage.is_some());
+
+    // With a frame rate (or duration), delays are timed against a virtual
+    // clock advanced one display frame at a time, rather than skipped
+    let frame_time = match (context.fps, context.duration) {
+        (None, None) => None,
+        (fps, _) => Some(1.0 / fps.unwrap_or(60.0)),
+    };
+
+    vm.virtual_clock_set(frame_time.is_some());
+    vm.time_scale_set(context.time_scale);
+
+    // On a terminal, frames are redrawn in place as the program runs; otherwise
+    // (e.g. in CI logs) only the final frame is rendered
+    let animate = context.render.is_some() && io::stdout().is_terminal();
+
+    if animate {
+        print!("\x1b[2J");
+    }
+
+    // Run frame by frame until the program halts or the cycle budget runs out
+    let mut cycles = 0;
+    let code = loop {
+        if cycles >= context.cycles {
+            eprintln!("Cycle limit ({}) reached.", context.cycles);
+            break ExitCode::from(2);
+        }
+
+        if context.duration.is_some_and(|duration| vm.time() >= duration) {
+            break ExitCode::SUCCESS;
+        }
+
+        let result = match frame_time {
+            Some(frame_time) => vm.advance_time(frame_time, context.cycles - cycles),
+            None => vm.run_frame(context.cycles - cycles),
+        };
+
+        match result {
+            Ok(executed) => {
+                cycles += executed;
+
+                // Report the write and carry on; there's no one to resume a paused run
+                if let Some(hit) = vm.watch_hit() {
+                    eprintln!("Watch [{}:{}] {:?} -> {:?} @ [{}] : {}", hit.frame, hit.offset, hit.old, hit.new, hit.address,
+                        shared::pixardis::pixardis_instruction_to_string(hit.instruction.clone()));
+                }
+
+                if animate {
+                    print!("\x1b[H");
+                    render_framebuffer(&vm, context.render.unwrap())?;
+                }
+
+                if let (true, Some(frame_time)) = (context.realtime, frame_time) {
+                    std::thread::sleep(std::time::Duration::from_secs_f64(frame_time));
+                } else if let (true, Some(delay)) = (context.realtime, vm.delay_requested()) {
+                    std::thread::sleep(std::time::Duration::from_secs_f64(delay / 1000.0));
+                }
+            },
+            Err(VirtualMachineError::TrapHalt) => break ExitCode::SUCCESS,
+            Err(error) => {
+                match error {
+                    VirtualMachineError::AssertionFailed(Some(line)) => eprintln!("Runtime error: assertion failed at line {}", line),
+                    VirtualMachineError::AssertionFailed(None) => eprintln!("Runtime error: assertion failed"),
+                    error => eprintln!("Runtime error: {:?}", error),
+                }
+
+                for frame in vm.backtrace() {
+                    eprintln!("    in {} @ [{}]", frame.function.as_deref().unwrap_or("?"), frame.address);
+                }
+
+                break ExitCode::FAILURE;
+            }
+        }
+    };
+
+    for diagnostic in vm.diagnostics() {
+        eprintln!("Recovered: {}", diagnostic);
+    }
+
+    if let Some(path) = context.output.as_deref() {
+        write_framebuffer(&vm, path)?;
+    }
+
+    if let Some(path) = context.coverage.as_deref() {
+        std::fs::write(path, pixardis_coverage_to_json(&vm.line_coverage()))?;
+    }
+
+    if let Some(render) = context.render {
+        if animate {
+            print!("\x1b[H");
+        }
+
+        render_framebuffer(&vm, render)?;
+    }
+
+    Ok(code)
+}
+
+//
+// Read a program from assembly text or a binary program (chroma --emit bin)
+//
+fn read_program(path: &str, strict: bool) -> Result<Vec<PixardisInstruction>, io::Error>
+{
+    let bytes = std::fs::read(path)?;
+
+    if bytes.starts_with(PIXARDIS_BINARY_MAGIC) {
+        return pixardis_decode_program(&bytes)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed binary program"));
+    }
+
+    let source = String::from_utf8(bytes)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+    if strict {
+        for (line, warning) in pixardis_assembly_warnings(&source) {
+            eprintln!("Warning: line {}: {}.", line, warning);
+        }
+    }
+
+    if let Some((line, error)) = pixardis_assembly_errors(&source).into_iter().next() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("program not loaded: line {}: {}", line, error)));
+    }
+
+    Ok(source.split('\n')
+        .map(|line| pixardis_instruction_from_string(line.to_string()))
+        .collect())
+}
+
+//
+// Write the framebuffer as a binary PPM image (row 0 of the display is the
+// bottom row, as in chroma-vm)
+//
+fn write_framebuffer(vm: &PixardisVirtualMachine, path: &str) -> Result<(), io::Error>
+{
+    let (width, height, colours) = vm.framebuffer_present();
+
+    let mut image = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+
+    for y in (0..height).rev() {
+        for colour in &colours[y * width..(y + 1) * width] {
+            image.extend_from_slice(&[(colour >> 16) as u8, (colour >> 8) as u8, *colour as u8]);
+        }
+    }
+
+    std::fs::write(path, image)
+}
+
+//
+// Draw the framebuffer on the terminal (see framebuffer_to_ansi)
+//
+fn render_framebuffer(vm: &PixardisVirtualMachine, render: RenderArg) -> Result<(), io::Error>
+{
+    let RenderArg::Ansi = render;
+    let (width, height, colours) = vm.framebuffer_present();
+
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(framebuffer_to_ansi(width, height, &colours).as_bytes())?;
+    stdout.flush()
+}
+
+#[derive(clap::Parser, Debug)]
+#[command(name = "chroma-run")]
+#[command(author = "Keith <bugeja.keith@gmail.com>")]
+#[command(version = "0.1")]
+#[command(about = "Headless runner for the Pixardis (Pixel Art Display) VM.")]
+struct Args {
+    #[arg(short, long, value_name = "FILE")]
+    input: String,
+
+    #[arg(short, long, help = "Maximum number of VM instruction cycles to execute.", default_value = "10000000")]
+    cycles: usize,
+
+    #[command(flatten)]
+    machine: MachineArgs,
+
+    #[arg(short, long, help = "Seed for the VM's random number generator.")]
+    seed: Option<u64>,
+
+    #[arg(short, long, value_name = "FILE", help = "Write the final framebuffer to FILE (PPM).")]
+    output: Option<String>,
+
+    #[arg(long, value_enum, help = "Render the framebuffer on the terminal: redrawn every frame on a terminal, else once at the end.")]
+    render: Option<RenderArg>,
+
+    #[arg(long, help = "Honour delay instructions in wall-clock time instead of skipping them.")]
+    realtime: bool,
+
+    #[arg(long, value_parser = parse_fps, help = "Time delays against a virtual clock advanced at FPS frames per second [default with --duration = 60].")]
+    fps: Option<f64>,
+
+    #[arg(long, value_name = "SECONDS", help = "Stop after SECONDS of virtual time (see --fps).")]
+    duration: Option<f64>,
+
+    #[arg(long, value_name = "SCALE", value_parser = parse_time_scale, help = "Run delays SCALE times faster (below 1 for slow motion).", default_value = "1.0")]
+    time_scale: f64,
+
+    #[arg(long, value_name = "N", help = "Refuse to load programs of more than N instructions.")]
+    max_instructions: Option<usize>,
+
+    #[arg(long, value_name = "N", help = "Refuse to load programs defining more than N labels.")]
+    max_labels: Option<usize>,
+
+    #[arg(long, value_enum, help = "Handling of pixel coordinates outside the display.", default_value = "clip")]
+    clip: ClipArg,
+
+    #[arg(long, help = "Print bare values, without the \"int ::\"/\"real ::\" type prefix.")]
+    print_raw: bool,
+
+    #[arg(long, value_name = "KEYS", help = "Most keys the program may store.", default_value = "64")]
+    storage_quota: usize,
+
+    #[arg(long, value_name = "FILE", help = "Replay host input recorded in FILE, one \"cycle x y #colour\" line per pixel written, each when the program reaches its cycle. Together with --seed, this reproduces a run of a host that writes pixels.")]
+    replay_input: Option<String>,
+
+    #[arg(long, value_name = "LABEL", help = "Start execution at this label instead of .main.")]
+    entry: Option<String>,
+
+    #[arg(long, value_name = "FILE", help = "Read source lines from FILE (chroma --emit dbg:FILE) so runtime errors, such as failed assertions, name them.")]
+    debug_info: Option<String>,
+
+    #[arg(long, value_name = "FILE", help = "Check the program against its build manifest (chroma --emit manifest:FILE), warning if it, its source or its debug information is stale.")]
+    manifest: Option<String>,
+
+    #[arg(long, value_name = "FILE", requires = "debug_info", help = "Write the source lines that executed to FILE (JSON).")]
+    coverage: Option<String>,
+
+    #[arg(long, help = "Warn about deprecated mnemonic aliases, upper-case mnemonics and lines that don't parse.")]
+    strict: bool,
+
+    #[arg(long, value_name = "FRAME:OFFSET", value_parser = parse_watch, help = "Report every write to a memory location (frame 0 is the outermost). May be repeated.")]
+    watch: Vec<(usize, usize)>,
+}
+
+//
+// Parse a watch location given as frame:offset
+//
+fn parse_watch(location: &str) -> Result<(usize, usize), String>
+{
+    let (frame, offset) = location.split_once(':').ok_or("expected FRAME:OFFSET")?;
+
+    match (frame.trim().parse(), offset.trim().parse()) {
+        (Ok(frame), Ok(offset)) => Ok((frame, offset)),
+        _ => Err(format!("invalid watch location '{}'", location)),
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum RenderArg {
+    // Half-block characters in 24-bit ANSI colour
+    Ansi,
+}
+
+//
+// Process runner command line arguments
+//
+fn process_cmd_args() -> Args
+{
+    Args::parse()
+}
+
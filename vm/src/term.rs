@@ -0,0 +1,174 @@
+use vm::pixardis;
+
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use clap::Parser as ClapParser;
+use vm::machine::executor::Executor;
+use vm::cli::{MachineArgs, parse_fps};
+use pixardis::pixardis::PixardisMetadata;
+use pixardis::terminal::framebuffer_to_ansi;
+
+// Fastest (and, inverted, slowest) speed the [ and ] keys reach
+const MAX_TIME_SCALE: f64 = 16.0;
+
+// Frames drawn per second (chroma-vm draws one per display refresh)
+const FRAME_RATE: f64 = 60.0;
+
+//
+// Terminal frontend: runs a program like chroma-vm, with the same keys, but
+// draws the display with ANSI colours (see framebuffer_to_ansi) and reads
+// keys from the terminal, so it needs no graphics stack.
+//
+fn main() -> Result<(), io::Error>
+{
+    let context = process_cmd_args();
+
+    let source = shared::io::read_file_to_string(&context.input)?;
+
+    let mut vm = context.machine.create(&PixardisMetadata::from_source(&source))?;
+    vm.virtual_clock_set(context.fps.is_some());
+
+    vm.load_program_from_source(&source);
+
+    for label in vm.duplicate_labels() {
+        eprintln!("Warning: label {} is defined more than once.", label);
+    }
+
+    // Keys arrive as they're pressed, without echo, until the guard is dropped
+    let _terminal = RawTerminal::enable()?;
+    let mut stdout = io::stdout();
+
+    writeln!(stdout, "\x1b[2J\x1b[H\x1b[?25lHit [s] to execute loaded program.\n[ ] speed, [0] normal speed, [q] quit")?;
+    stdout.flush()?;
+
+    // Start execution when S is pressed
+    loop {
+        match read_keys()?.iter().rev().find(|&&key| matches!(key, b's' | b'S' | b'q' | b'Q')) {
+            Some(b's' | b'S') => break,
+            Some(_) => return Ok(()),
+            None => std::thread::sleep(Duration::from_secs_f64(1.0 / FRAME_RATE)),
+        }
+    }
+
+    write!(stdout, "\x1b[2J")?;
+
+    loop {
+        let frame_started = Instant::now();
+
+        // Q quits; ] and [ double and halve the program's speed and 0 restores it
+        for key in read_keys()? {
+            let time_scale = match key {
+                b'q' | b'Q' => return Ok(()),
+                b']' => (vm.time_scale() * 2.0).min(MAX_TIME_SCALE),
+                b'[' => (vm.time_scale() / 2.0).max(1.0 / MAX_TIME_SCALE),
+                b'0' => 1.0,
+                _ => continue,
+            };
+
+            vm.time_scale_set(time_scale);
+        }
+
+        // Run for a given number of cycles, or one display frame of virtual time
+        match context.fps {
+            Some(fps) => { let _ = vm.advance_time(1.0 / fps, context.cycles); },
+            None => { let _ = vm.step(context.cycles); },
+        }
+
+        // Redraw in place, with the keys and speed underneath
+        let (width, height, colours) = vm.framebuffer_present();
+        let display = framebuffer_to_ansi(width, height, &colours);
+
+        writeln!(stdout, "\x1b[H{}[ ] speed: {}x, [q] quit\x1b[K", display, vm.time_scale())?;
+        stdout.flush()?;
+
+        let frame_time = Duration::from_secs_f64(1.0 / FRAME_RATE);
+        std::thread::sleep(frame_time.saturating_sub(frame_started.elapsed()));
+    }
+}
+
+//
+// Bytes typed since the last call (empty if none); never blocks
+//
+fn read_keys() -> Result<Vec<u8>, io::Error>
+{
+    let mut keys = [0u8; 64];
+    let count = io::stdin().read(&mut keys)?;
+
+    Ok(keys[..count].to_vec())
+}
+
+//
+// Puts the terminal in raw, non-blocking mode and hides the cursor,
+// restoring both when dropped
+//
+struct RawTerminal {
+    #[cfg(unix)]
+    original: libc::termios,
+}
+
+impl RawTerminal {
+    #[cfg(unix)]
+    fn enable() -> Result<RawTerminal, io::Error> {
+        // SAFETY: termios is plain data, filled in by tcgetattr before use
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // No line buffering or echo; reads return at once, with whatever's there
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_cc[libc::VMIN] = 0;
+        raw.c_cc[libc::VTIME] = 0;
+
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(RawTerminal { original })
+    }
+
+    #[cfg(not(unix))]
+    fn enable() -> Result<RawTerminal, io::Error> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "chroma-term needs a Unix terminal"))
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original); }
+
+        println!("\x1b[0m\x1b[?25h");
+        let _ = io::stdout().flush();
+    }
+}
+
+#[derive(clap::Parser, Debug)]
+#[command(name = "chroma-term")]
+#[command(author = "Keith <bugeja.keith@gmail.com>")]
+#[command(version = "0.1")]
+#[command(about = "A terminal emulator frontend for the Pixardis (Pixel Art Display) VM.")]
+struct Args {
+    #[arg(short, long, value_name = "FILE")]
+    input: String,
+
+    #[arg(short, long, help = "VM instruction cycles per frame.", default_value = "250")]
+    cycles: usize,
+
+    #[command(flatten)]
+    machine: MachineArgs,
+
+    #[arg(long, value_parser = parse_fps, help = "Time delays against a virtual clock advanced 1/FPS seconds per displayed frame (cycles per frame still apply).")]
+    fps: Option<f64>,
+}
+
+//
+// Process command line arguments
+//
+fn process_cmd_args() -> Args
+{
+    Args::parse()
+}
@@ -8,208 +8,252 @@ use serde_wasm_bindgen;
 use wasm_bindgen::prelude::*;
 
 #[cfg(target_arch = "wasm32")]
-use shared::pixardis::{PixardisInstruction, pixardis_print_code};
+use std::{cell::RefCell, rc::Rc};
 
-// Import VM modules from the vm crate
 #[cfg(target_arch = "wasm32")]
-#[path = "../../vm/src/pixardis/mod.rs"]
-mod pixardis;
+use stats::{StatsCollector, StatsCounters};
 
 #[cfg(target_arch = "wasm32")]
-#[path = "../../vm/src/machine/mod.rs"] 
-mod machine;
+use shared::pixardis::{
+    PixardisInstruction,
+    PIXARDIS_CAPABILITY_AUDIO,
+    PIXARDIS_CAPABILITY_DRAW,
+    PIXARDIS_CAPABILITY_INPUT,
+    pixardis_instruction_to_string,
+};
 
-// Import compiler modules directly
+// The VM and compiler crates, under the module names the code below uses
 #[cfg(target_arch = "wasm32")]
-#[path = "../../compiler/src/common/mod.rs"]
-mod common;
+use vm::{machine, pixardis};
 
 #[cfg(target_arch = "wasm32")]
-#[path = "../../compiler/src/lexer/mod.rs"]
-mod lexer;
+use compiler::common;
 
-#[cfg(target_arch = "wasm32")]
-#[path = "../../compiler/src/parser/mod.rs"]
-mod parser;
+// Headless evaluation (automated grading), which the compiler crate provides
+// so native hosts and tests can run it too
+pub use compiler::eval;
 
+// Execution statistics for the playground
 #[cfg(target_arch = "wasm32")]
-#[path = "../../compiler/src/analysis/mod.rs"]
-mod analysis;
-
-#[cfg(target_arch = "wasm32")]
-#[path = "../../compiler/src/codegen/mod.rs"]
-mod codegen;
-
-#[cfg(target_arch = "wasm32")]
-use common::logger::{get_captured_errors, clear_captured_errors};
-
-// Provide fallback implementations for non-WASM targets
-#[cfg(not(target_arch = "wasm32"))]
-fn get_captured_errors() -> Vec<String> {
-    Vec::new()
-}
-
-#[cfg(not(target_arch = "wasm32"))]
-fn clear_captured_errors() {
-    // No-op
-}
+mod stats;
 
 // Use compiler modules
 #[cfg(target_arch = "wasm32")]
-use common::{
-    logger::{Logger, LoggerMessage}, 
-    status::CompilationResult
-};
+use common::{cancel::CancellationToken, logger::{Diagnostic, DiagnosticSeverity, Logger}};
 
 #[cfg(target_arch = "wasm32")] 
-use lexer::lexer::Lexer;
+use compiler::compile_cancellable;
 
+// VM modules
 #[cfg(target_arch = "wasm32")] 
-use parser::{parser::Parser, ast::ProgramNode};
-
+use machine::executor::Executor;
 #[cfg(target_arch = "wasm32")] 
-use analysis::{symbol::ScopeManager, semantic::SemanticAnalyser};
-
+use machine::architecture::{Operand, VirtualMachineStepResult};
 #[cfg(target_arch = "wasm32")] 
-use codegen::generator::CodeGenerator;
-
+use pixardis::pixardis::{PixardisVirtualMachine, PixardisLogLevel, PixardisLogCategory, PixardisMetadata, PixardisErrorPolicy, PixardisClipMode, PixardisPrintFormat, PixardisLoadLimits, param_from_string};
 #[cfg(target_arch = "wasm32")] 
-use codegen::optimiser::*;
+use pixardis::storage::{PixardisStorage, storage_value_from_string, storage_value_to_string};
 
-// VM modules
+// Compile a source; the result has success, assembly, lines (on success),
+// errors (as the compiler prints them) and warnings, which come whether or
+// not the compile succeeds, as an array of { severity, line, message } (line
+// 1-based, or null)
 #[cfg(target_arch = "wasm32")] 
-use machine::executor::Executor;
+#[wasm_bindgen]
+pub fn compile_pixardis_source_with_errors(source: &str) -> JsValue {
+    compile_pixardis_source_result(source, &CancellationToken::new())
+}
+
+// As compile_pixardis_source_with_errors, giving up (with the error
+// "Compilation cancelled") once signal.cancelled() is true; see CancelSignal
 #[cfg(target_arch = "wasm32")] 
-use pixardis::pixardis::{PixardisVirtualMachine, PixardisLogLevel};
+#[wasm_bindgen]
+pub fn compile_pixardis_source_cancellable(source: &str, signal: CancelSignal) -> JsValue {
+    compile_pixardis_source_result(source, &CancellationToken::with_poll(move || signal.cancelled()))
+}
 
-// Copy the compilation functions from compiler/main.rs
 #[cfg(target_arch = "wasm32")] 
-pub fn lexical_analysis<'a>(source: &'a str, logger: &'a mut Logger<'a>) -> Result<(Lexer<'a>, CompilationResult), ()> {
-    let mut lexer = Lexer::new(source, logger);
-    lexer.scan();
-    let status = lexer.status().clone();
-    Ok((lexer, status))
+fn compile_pixardis_source_result(source: &str, cancel: &CancellationToken) -> JsValue {
+    // Errors are collected by this compilation's own logger
+    let logger = Logger::new_capturing(source);
+
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        compile_pixardis_source_with_logger(source, &logger, cancel)
+    }));
+    
+    // Get the captured error messages; warnings are reported on their own
+    let captured_errors: Vec<String> = logger.captured_messages().into_iter()
+        .filter(|message| !message.starts_with("Warning: "))
+        .collect();
+    let warnings: Vec<Diagnostic> = logger.captured_diagnostics().into_iter()
+        .filter(|diagnostic| diagnostic.severity == DiagnosticSeverity::Warning)
+        .collect();
+    
+    match result {
+        Ok(Ok((assembly, lines))) => {
+            serde_wasm_bindgen::to_value(&serde_json::json!({
+                "success": true,
+                "assembly": assembly,
+                "lines": lines,
+                "errors": captured_errors,
+                "warnings": warnings
+            })).unwrap()
+        },
+        Ok(Err(error)) => {
+            serde_wasm_bindgen::to_value(&serde_json::json!({
+                "success": false,
+                "assembly": "",
+                "errors": if captured_errors.is_empty() { vec![error] } else { captured_errors },
+                "warnings": warnings
+            })).unwrap()
+        },
+        Err(_) => {
+            serde_wasm_bindgen::to_value(&serde_json::json!({
+                "success": false,
+                "assembly": "",
+                "errors": if captured_errors.is_empty() { vec!["Internal compiler error".to_string()] } else { captured_errors },
+                "warnings": warnings
+            })).unwrap()
+        }
+    }
 }
 
 #[cfg(target_arch = "wasm32")] 
-pub fn parse<'a>(lexer: Lexer<'a>, logger: &'a mut Logger<'a>) -> Result<(Parser<'a>, CompilationResult), ()> {
-    let mut parser = Parser::new(lexer, logger);
-    parser.parse();
-    let status = parser.status().clone();
-    Ok((parser, status))
+#[wasm_bindgen]
+pub fn compile_pixardis_source(source: &str) -> Result<String, String> {
+    compile_pixardis_source_with_logger(source, &Logger::new_capturing(source), &CancellationToken::new())
+        .map(|(assembly, _)| assembly)
 }
 
+// Assembly for a source and its line table (the source line of each
+// instruction, 0 if unknown; see WebVM::set_source_lines)
 #[cfg(target_arch = "wasm32")] 
-pub fn semantic_analysis<'a>(syntax_tree: &'a mut ProgramNode, scope_manager: &'a mut ScopeManager, logger: &'a mut Logger<'a>) -> Result<CompilationResult, ()> {
-    let mut semantic_analyser = SemanticAnalyser::new(syntax_tree, scope_manager, logger);
-    semantic_analyser.analyse();
-    Ok(semantic_analyser.status())
+fn compile_pixardis_source_with_logger<'a>(source: &'a str, logger: &Logger<'a>, cancel: &CancellationToken) -> Result<(String, Vec<usize>), String> {
+    let (program, lines) = compile_cancellable(source, logger, None, cancel)?;
+
+    Ok((instructions_to_assembly_string(&program), lines))
 }
 
+// Compile and run a program headless; config is an (optional) object with
+// width, height, cycles, seed and print_raw fields
 #[cfg(target_arch = "wasm32")] 
-pub fn code_generation<'a>(syntax_tree: &'a mut ProgramNode, scope_manager: &'a mut ScopeManager, logger: &'a mut Logger<'a>) -> Result<(Vec<(usize, PixardisInstruction)>, CompilationResult), ()> {
-    let mut code_generator = CodeGenerator::new(syntax_tree, scope_manager, logger);
-    code_generator.generate();
-    Ok((code_generator.program_code(), code_generator.status()))
+#[wasm_bindgen]
+pub fn evaluate_pixardis_source(source: &str, config: JsValue) -> JsValue {
+    let config: eval::EvalConfig = if config.is_undefined() || config.is_null() {
+        eval::EvalConfig::default()
+    } else {
+        serde_wasm_bindgen::from_value(config).unwrap_or_default()
+    };
+
+    serde_wasm_bindgen::to_value(&eval::evaluate(source, &config)).unwrap()
 }
 
+// Strict-mode warnings for assembly, as an array of { line, message }
 #[cfg(target_arch = "wasm32")] 
-pub fn code_optimisation(code: &mut Vec<(usize, PixardisInstruction)>) -> Result<(Vec<(usize, PixardisInstruction)>, CompilationResult), ()> {
-    Ok((optimise_code_pixardis(code), CompilationResult::Success))
+#[wasm_bindgen]
+pub fn check_pixardis_assembly(assembly: &str) -> JsValue {
+    let warnings: Vec<_> = shared::pixardis::pixardis_assembly_warnings(assembly)
+        .into_iter()
+        .map(|(line, message)| serde_json::json!({ "line": line, "message": message }))
+        .collect();
+
+    serde_wasm_bindgen::to_value(&warnings).unwrap()
 }
 
+// Syntax tree of a source, for the playground's AST explorer: ast is the
+// tree as JSON text (nodes carry 0-based source lines; see
+// compiler::parse_to_json)
 #[cfg(target_arch = "wasm32")] 
 #[wasm_bindgen]
-pub fn compile_pixardis_source_with_errors(source: &str) -> JsValue {
-    // Clear any previous errors
-    clear_captured_errors();
-    
+pub fn parse_to_json(source: &str) -> JsValue {
+    let logger = Logger::new_capturing(source);
+
     let result = std::panic::catch_unwind(|| {
-        compile_pixardis_source(source)
+        compiler::parse_to_json(source, &logger)
     });
-    
-    // Get the captured error messages
-    let captured_errors = get_captured_errors();
-    
+
+    let captured_errors = logger.captured_messages();
+
     match result {
-        Ok(Ok(assembly)) => {
+        Ok(Ok(ast)) => {
             serde_wasm_bindgen::to_value(&serde_json::json!({
                 "success": true,
-                "assembly": assembly,
+                "ast": ast.to_string(),
                 "errors": captured_errors
             })).unwrap()
         },
         Ok(Err(error)) => {
             serde_wasm_bindgen::to_value(&serde_json::json!({
                 "success": false,
-                "assembly": "",
+                "ast": "",
                 "errors": if captured_errors.is_empty() { vec![error] } else { captured_errors }
             })).unwrap()
         },
         Err(_) => {
             serde_wasm_bindgen::to_value(&serde_json::json!({
                 "success": false,
-                "assembly": "",
+                "ast": "",
                 "errors": if captured_errors.is_empty() { vec!["Internal compiler error".to_string()] } else { captured_errors }
             })).unwrap()
         }
     }
 }
 
+// Run the compiler up to a stage (lex, parse, analyse, generate or
+// optimise) and return what each stage on the way produced: tokens, ast and
+// symbols as JSON text, unoptimised and optimised assembly; a stage that
+// didn't run leaves its field null (see compiler::stages)
 #[cfg(target_arch = "wasm32")] 
 #[wasm_bindgen]
-pub fn compile_pixardis_source(source: &str) -> Result<String, String> {
-    let mut logger = Logger::new(source);
-    let mut scope_manager = ScopeManager::new();
+pub fn compile_pixardis_stage(source: &str, stage: &str) -> JsValue {
+    use compiler::stages::{compile_to_stage, PipelineStage};
 
-    // Lexical analysis
-    let mut lexer_logger = logger.clone();
-    let (lexer, status) = lexical_analysis(source, &mut lexer_logger)
-        .map_err(|_| "Lexical analysis failed")?;
-    
-    if matches!(status, CompilationResult::Failure) {
-        return Err("Lexical analysis failed".to_string());
-    }
+    let Some(stage) = PipelineStage::from_string(stage) else {
+        return serde_wasm_bindgen::to_value(&serde_json::json!({
+            "success": false,
+            "errors": [format!("Unknown stage '{}' (expected lex, parse, analyse, generate or optimise)", stage)]
+        })).unwrap();
+    };
 
-    // Parsing
-    let mut parser_logger = logger.clone();
-    let (parser, status) = parse(lexer, &mut parser_logger)
-        .map_err(|_| "Parsing failed")?;
-        
-    if matches!(status, CompilationResult::Failure) {
-        return Err("Parsing failed".to_string());
-    }
+    let logger = Logger::new_capturing(source);
 
-    // Semantic analysis
-    let mut analysis_logger = logger.clone();
-    let mut analysis_syntax_tree = parser.get_syntax_tree()
-        .ok_or("Failed to get syntax tree")?; // Changed from map_err to ok_or
-    let status = semantic_analysis(&mut analysis_syntax_tree, &mut scope_manager, &mut analysis_logger)
-        .map_err(|_| "Semantic analysis failed")?;
-        
-    if matches!(status, CompilationResult::Failure) {
-        return Err("Semantic analysis failed".to_string());
-    }
+    let result = std::panic::catch_unwind(|| {
+        compile_to_stage(source, &logger, stage)
+    });
 
-    // Code generation
-    let mut codegen_logger = logger.clone();
-    let mut codegen_syntax_tree = parser.get_syntax_tree()
-        .ok_or("Failed to get syntax tree for codegen")?; // Changed from map_err to ok_or
-    let (program, status) = code_generation(&mut codegen_syntax_tree, &mut scope_manager, &mut codegen_logger)
-        .map_err(|_| "Code generation failed")?;
-        
-    if matches!(status, CompilationResult::Failure) {
-        return Err("Code generation failed".to_string());
-    }
+    let captured_errors = logger.captured_messages();
 
-    // Code optimization
-    let (optimised_program, _status) = code_optimisation(&mut program.clone())
-        .map_err(|_| "Code optimization failed")?;
+    let artifacts = match result {
+        Ok(artifacts) => artifacts,
+        Err(_) => {
+            return serde_wasm_bindgen::to_value(&serde_json::json!({
+                "success": false,
+                "errors": if captured_errors.is_empty() { vec!["Internal compiler error".to_string()] } else { captured_errors }
+            })).unwrap();
+        },
+    };
+
+    let errors = match &artifacts.failed {
+        Some(error) if captured_errors.is_empty() => vec![error.clone()],
+        _ => captured_errors,
+    };
+
+    serde_wasm_bindgen::to_value(&serde_json::json!({
+        "success": artifacts.failed.is_none(),
+        "tokens": artifacts.tokens.map(|tokens| tokens.to_string()),
+        "ast": artifacts.ast.map(|ast| ast.to_string()),
+        "symbols": artifacts.symbols.map(|symbols| symbols.to_string()),
+        "unoptimised": artifacts.unoptimised.map(|lines| lines.join("\n")),
+        "optimised": artifacts.optimised.map(|lines| lines.join("\n")),
+        "errors": errors
+    })).unwrap()
+}
 
-    // Convert to assembly string
-    let assembly = instructions_to_assembly_string(&optimised_program);
-    
-    Ok(assembly)
+// Assembly rewritten with canonical mnemonics and without comments
+#[cfg(target_arch = "wasm32")] 
+#[wasm_bindgen]
+pub fn canonicalise_pixardis_assembly(assembly: &str) -> String {
+    shared::pixardis::pixardis_canonicalise_assembly(assembly)
 }
 
 // Helper function to convert instructions to assembly string
@@ -227,10 +271,77 @@ fn instructions_to_assembly_string(instructions: &[(usize, PixardisInstruction)]
     assembly
 }
 
+// Page-provided storage for kvst/kvld, e.g. on top of localStorage: an
+// object with load(key) (a string, or undefined), store(key, value) and
+// count(). Values cross as text: integers, reals and #rrggbb colours.
+#[cfg(target_arch = "wasm32")] 
+#[wasm_bindgen]
+extern "C" {
+    pub type StorageProvider;
+
+    #[wasm_bindgen(method)]
+    fn load(this: &StorageProvider, key: &str) -> Option<String>;
+
+    #[wasm_bindgen(method)]
+    fn store(this: &StorageProvider, key: &str, value: &str);
+
+    #[wasm_bindgen(method)]
+    fn count(this: &StorageProvider) -> usize;
+}
+
+// Page-provided stop request for long compiles and runs: an object whose
+// cancelled() is true once the user asks to stop, e.g. by reading a flag
+// the page sets in a SharedArrayBuffer with Atomics, as a worker busy
+// compiling or running doesn't see messages posted to it
+#[cfg(target_arch = "wasm32")] 
+#[wasm_bindgen]
+extern "C" {
+    pub type CancelSignal;
+
+    #[wasm_bindgen(method)]
+    fn cancelled(this: &CancelSignal) -> bool;
+}
+
+#[cfg(target_arch = "wasm32")] 
+struct WebStorage {
+    provider: StorageProvider,
+}
+
+#[cfg(target_arch = "wasm32")] 
+impl PixardisStorage for WebStorage {
+    fn load(&mut self, key: &str) -> Option<Operand> {
+        self.provider.load(key).and_then(|value| storage_value_from_string(&value))
+    }
+
+    fn store(&mut self, key: &str, value: &Operand) {
+        self.provider.store(key, &storage_value_to_string(value));
+    }
+
+    fn count(&mut self) -> usize {
+        self.provider.count()
+    }
+}
+
+// Load limits of a new VM, well beyond what the compiler produces for
+// playground programs
+#[cfg(target_arch = "wasm32")] 
+const WEB_MAX_PROGRAM_INSTRUCTIONS: usize = 1_000_000;
+#[cfg(target_arch = "wasm32")] 
+const WEB_MAX_PROGRAM_LABELS: usize = 100_000;
+
+// Linear memory grows a 64 KiB page at a time, to at most 4 GiB on wasm32
+#[cfg(target_arch = "wasm32")] 
+const WASM_PAGE_SIZE: usize = 65536;
+#[cfg(target_arch = "wasm32")] 
+const WASM_LINEAR_MEMORY_LIMIT: u64 = 1 << 32;
+
 #[cfg(target_arch = "wasm32")] 
 #[wasm_bindgen]
 pub struct WebVM {
     vm: PixardisVirtualMachine,
+    stats: Rc<RefCell<StatsCounters>>,
+    // VM time when the statistics were last reset
+    stats_started: f64,
 }
 
 #[cfg(target_arch = "wasm32")] 
@@ -240,19 +351,285 @@ impl WebVM {
     pub fn new(width: usize, height: usize) -> WebVM {
         let mut vm = PixardisVirtualMachine::new(width, height);
         vm.log_level_set(PixardisLogLevel::None);
-        WebVM { vm }
+        vm.load_limits_set(PixardisLoadLimits { max_instructions: Some(WEB_MAX_PROGRAM_INSTRUCTIONS), max_labels: Some(WEB_MAX_PROGRAM_LABELS) });
+
+        let stats = Rc::new(RefCell::new(StatsCounters::default()));
+        vm.observer_add(Box::new(StatsCollector { counters: stats.clone() }));
+
+        let stats_started = vm.time();
+        WebVM { vm, stats, stats_started }
     }
     
-    pub fn load_program(&mut self, assembly: &str) {
+    // Create a VM sized for the program (its .meta width/height, else 64x48)
+    // with the program loaded
+    pub fn new_from_program(assembly: &str) -> WebVM {
+        let metadata = PixardisMetadata::from_source(assembly);
+
+        let mut web_vm = WebVM::new(metadata.width.unwrap_or(64), metadata.height.unwrap_or(48));
+        web_vm.load_program(assembly);
+        web_vm
+    }
+
+    // False (with the current program left loaded) if the program is over
+    // the load limits; see get_load_report
+    pub fn load_program(&mut self, assembly: &str) -> bool {
         self.vm.load_program_from_source(assembly);
+        self.reset_stats();
+        self.vm.load_report().rejection.is_none()
+    }
+
+    // Most instructions and labels a program may have to be loaded
+    // (undefined for no limit); by default 1000000 and 100000
+    pub fn set_load_limits(&mut self, max_instructions: Option<usize>, max_labels: Option<usize>) {
+        self.vm.load_limits_set(PixardisLoadLimits { max_instructions, max_labels });
+    }
+
+    // What loading the last program found: { rejection (why it wasn't
+    // loaded, or null), warnings }
+    pub fn get_load_report(&self) -> JsValue {
+        let report = self.vm.load_report();
+
+        serde_wasm_bindgen::to_value(&serde_json::json!({
+            "rejection": report.rejection,
+            "warnings": report.warnings
+        })).unwrap()
+    }
+
+    // False if the loaded program has no .main label (it then runs from its
+    // first instruction)
+    pub fn has_entry_label(&self) -> bool {
+        self.vm.has_entry_label()
+    }
+
+    // Load a program that starts at the given label instead of .main; false
+    // (with the current program left loaded) if the label isn't defined
+    pub fn load_program_with_entry(&mut self, assembly: &str, entry: &str) -> bool {
+        let result = self.vm.load_program_from_source_with_entry(assembly, entry).is_ok();
+        if result {
+            self.reset_stats();
+        }
+        result
+    }
+
+    // Attach the line table from compile_pixardis_source_with_errors to the
+    // loaded program, so failed assertions report their source line; call
+    // after loading
+    pub fn set_source_lines(&mut self, lines: Vec<usize>) {
+        self.vm.source_lines_set(lines);
+    }
+
+    pub fn load_program_named(&mut self, name: &str, assembly: &str) {
+        self.vm.load_program_named(name, assembly);
+    }
+
+    pub fn run_program(&mut self, name: &str) -> bool {
+        let result = self.vm.run_program(name).is_ok();
+        if result {
+            self.reset_stats();
+        }
+        result
+    }
+
+    pub fn get_program_names(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.vm.program_names()).unwrap()
+    }
+
+    // Metadata (title, author, preferred size) of the loaded program
+    pub fn get_metadata(&self) -> JsValue {
+        let metadata = self.vm.metadata();
+
+        serde_wasm_bindgen::to_value(&serde_json::json!({
+            "title": metadata.title,
+            "author": metadata.author,
+            "width": metadata.width,
+            "height": metadata.height
+        })).unwrap()
     }
     
+    // Labels the loaded program defines more than once
+    pub fn get_duplicate_labels(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(self.vm.duplicate_labels()).unwrap()
+    }
+
     pub fn step(&mut self, steps: usize) -> JsValue {
         match self.vm.step(steps) {
-            Ok(()) => {
+            Ok(result) => {
+                // Let the driver know how long it can sleep for if the VM is delayed
+                let wait_ms = match result {
+                    VirtualMachineStepResult::WaitUntil(_) => self.vm.delay_remaining().map(|t| t * 1000.0),
+                    VirtualMachineStepResult::Ready |
+                    VirtualMachineStepResult::Watchpoint |
+                    VirtualMachineStepResult::Stopped => None,
+                };
+
                 serde_wasm_bindgen::to_value(&serde_json::json!({
                     "success": true,
-                    "error": null
+                    "error": null,
+                    "wait_ms": wait_ms,
+                    "stuck": self.vm.watchdog_tripped(),
+                    "stopped": self.vm.stopped(),
+                    "watch": self.watch_hit()
+                })).unwrap()
+            },
+            Err(error) => {
+                serde_wasm_bindgen::to_value(&serde_json::json!({
+                    "success": false,
+                    "error": format!("{:?}", error),
+                    "backtrace": self.backtrace()
+                })).unwrap()
+            }
+        }
+    }
+
+    // Run one program frame (up to the next delay instruction), ignoring
+    // wall-clock time; delay_ms reports the delay the program asked for.
+    pub fn run_frame(&mut self, max_cycles: usize) -> JsValue {
+        match self.vm.run_frame(max_cycles) {
+            Ok(cycles) => {
+                serde_wasm_bindgen::to_value(&serde_json::json!({
+                    "success": true,
+                    "error": null,
+                    "cycles": cycles,
+                    "delay_ms": self.vm.delay_requested(),
+                    "stuck": self.vm.watchdog_tripped(),
+                    "stopped": self.vm.stopped(),
+                    "watch": self.watch_hit()
+                })).unwrap()
+            },
+            Err(error) => {
+                serde_wasm_bindgen::to_value(&serde_json::json!({
+                    "success": false,
+                    "error": format!("{:?}", error),
+                    "backtrace": self.backtrace()
+                })).unwrap()
+            }
+        }
+    }
+
+    // Time delays against a clock advanced by advance_time instead of wall
+    // time, so the page decides how fast the program runs
+    #[cfg(feature = "std")]
+    pub fn set_virtual_clock(&mut self, enabled: bool) {
+        self.vm.virtual_clock_set(enabled);
+    }
+
+    // Fast-forward (scale above 1) or slow motion (below 1); delays last
+    // 1/scale as long. Returns false if the scale isn't positive.
+    pub fn set_time_scale(&mut self, scale: f64) -> bool {
+        if !(scale > 0.0 && scale.is_finite()) {
+            return false;
+        }
+
+        self.vm.time_scale_set(scale);
+        true
+    }
+
+    pub fn get_time_scale(&self) -> f64 {
+        self.vm.time_scale()
+    }
+
+    // Advance the virtual clock by dt_ms, running the program through every
+    // delay that expires in that time; call once per animation frame with the
+    // frame time (scaled for fast-forward or slow motion)
+    pub fn advance_time(&mut self, dt_ms: f64, max_cycles: usize) -> JsValue {
+        match self.vm.advance_time(dt_ms / 1000.0, max_cycles) {
+            Ok(cycles) => {
+                serde_wasm_bindgen::to_value(&serde_json::json!({
+                    "success": true,
+                    "error": null,
+                    "cycles": cycles,
+                    "time_ms": self.vm.time() * 1000.0,
+                    "stuck": self.vm.watchdog_tripped(),
+                    "stopped": self.vm.stopped(),
+                    "watch": self.watch_hit()
+                })).unwrap()
+            },
+            Err(error) => {
+                serde_wasm_bindgen::to_value(&serde_json::json!({
+                    "success": false,
+                    "error": format!("{:?}", error),
+                    "backtrace": self.backtrace()
+                })).unwrap()
+            }
+        }
+    }
+
+    // Checkpoint the running program (its code, memory, display and RNG)
+    pub fn save_state(&self) -> Vec<u8> {
+        self.vm.save_state()
+    }
+
+    // Resume from a checkpoint made by save_state; returns false (leaving the
+    // VM unchanged) if the state is invalid or the display size differs
+    pub fn load_state(&mut self, state: &[u8]) -> bool {
+        let result = self.vm.load_state(state).is_ok();
+        if result {
+            self.reset_stats();
+        }
+        result
+    }
+
+    // Pause whenever memory at frame:offset is written; frames are absolute,
+    // with 0 the outermost (global) frame
+    pub fn add_watch(&mut self, frame: usize, offset: usize) {
+        self.vm.watch_add(frame, offset);
+    }
+
+    pub fn remove_watch(&mut self, frame: usize, offset: usize) -> bool {
+        self.vm.watch_remove(frame, offset)
+    }
+
+    pub fn clear_watches(&mut self) {
+        self.vm.watch_clear();
+    }
+
+    // Instructions executed, per-opcode counts, pixels written and VM time
+    // elapsed since the program was loaded (instructions undone by step_back
+    // remain counted)
+    pub fn get_stats(&self) -> JsValue {
+        let elapsed = self.vm.time() - self.stats_started;
+        serde_wasm_bindgen::to_value(&self.stats.borrow().report(elapsed)).unwrap()
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats.borrow_mut().reset();
+        self.stats_started = self.vm.time();
+    }
+
+    // Memory in use, in bytes: the module's linear memory (which only grows)
+    // and the most it can grow to, and what the VM holds of it in memory
+    // frames, operand stack, framebuffer and print and log buffers
+    pub fn memory_stats(&self) -> JsValue {
+        let usage = self.vm.memory_usage();
+
+        serde_wasm_bindgen::to_value(&serde_json::json!({
+            "linear_memory": core::arch::wasm32::memory_size(0) * WASM_PAGE_SIZE,
+            "linear_memory_limit": WASM_LINEAR_MEMORY_LIMIT,
+            "frames": usage.frames,
+            "frame_bytes": usage.frame_bytes,
+            "operand_stack_bytes": usage.operand_stack_bytes,
+            "framebuffer_bytes": usage.framebuffer_bytes,
+            "print_buffer_bytes": usage.print_buffer_bytes,
+            "log_buffer_bytes": usage.log_buffer_bytes,
+            "vm_bytes": usage.total()
+        })).unwrap()
+    }
+
+    // Record checkpoints every interval instructions so step_back can rewind
+    // (None turns history off)
+    pub fn enable_history(&mut self, interval: Option<usize>) {
+        self.vm.history_enable(interval);
+    }
+
+    // Rewind by the given number of instructions; executed is the position
+    // reached, counted in instructions since the program was loaded
+    pub fn step_back(&mut self, instructions: usize) -> JsValue {
+        match self.vm.step_back(instructions as u64) {
+            Ok(executed) => {
+                serde_wasm_bindgen::to_value(&serde_json::json!({
+                    "success": true,
+                    "error": null,
+                    "executed": executed
                 })).unwrap()
             },
             Err(error) => {
@@ -264,8 +641,159 @@ impl WebVM {
         }
     }
 
+    pub fn set_float_support(&mut self, enabled: bool) {
+        self.vm.float_support_set(enabled);
+    }
+
+    pub fn set_watchdog(&mut self, cycles: Option<usize>) {
+        self.vm.watchdog_set(cycles);
+    }
+
+    // In continue mode, division by zero and off-screen reads yield zero and
+    // are recorded as diagnostics instead of stopping the program
+    pub fn set_continue_on_error(&mut self, enabled: bool) {
+        self.vm.error_policy_set(if enabled { PixardisErrorPolicy::Continue } else { PixardisErrorPolicy::Halt });
+    }
+
+    // Clip mode is one of "clip", "wrap" or "error"; returns false if unknown
+    pub fn set_clip_mode(&mut self, mode: &str) -> bool {
+        let mode = match mode {
+            "clip" => PixardisClipMode::Clip,
+            "wrap" => PixardisClipMode::Wrap,
+            "error" => PixardisClipMode::Error,
+            _ => return false,
+        };
+
+        self.vm.clip_mode_set(mode);
+        true
+    }
+
+    // Set a parameter read by the program's __param builtin: a number, or a
+    // colour string (#rrggbb); returns false if the value is neither.
+    // Parameters persist across program loads.
+    pub fn set_param(&mut self, name: &str, value: JsValue) -> bool {
+        let value = match (value.as_f64(), value.as_string()) {
+            (Some(number), _) => Some(Operand::Integer(number as i64)),
+            (_, Some(text)) => param_from_string(&text),
+            _ => None,
+        };
+
+        match value {
+            Some(value) => { self.vm.param_set(name, value); true },
+            None => false,
+        }
+    }
+
+    pub fn remove_param(&mut self, name: &str) -> bool {
+        self.vm.param_remove(name)
+    }
+
+    // Stop the running step, frame or advance_time before its next
+    // instruction (it reports stopped: true); the program resumes with the
+    // next call
+    pub fn request_stop(&self) {
+        self.vm.request_stop();
+    }
+
+    // Polled while running (every 1024 instructions) to stop as
+    // request_stop does (undefined removes it); see CancelSignal
+    pub fn set_stop_signal(&mut self, signal: Option<CancelSignal>) {
+        self.vm.stop_signal_set(signal.map(|signal| Box::new(move || signal.cancelled()) as Box<dyn FnMut() -> bool>));
+    }
+
+    // Storage for the program's kvst/kvld (undefined removes it); see StorageProvider
+    pub fn set_storage(&mut self, provider: Option<StorageProvider>) {
+        self.vm.storage_set(provider.map(|provider| Box::new(WebStorage { provider }) as Box<dyn PixardisStorage>));
+    }
+
+    // Disabled storage reads as empty and ignores stores (e.g. until the user opts in)
+    pub fn set_storage_enabled(&mut self, enabled: bool) {
+        self.vm.storage_enable(enabled);
+    }
+
+    // Most keys a program may store
+    pub fn set_storage_quota(&mut self, keys: usize) {
+        self.vm.storage_quota_set(keys);
+    }
+
+    // Instruction set extensions the VM executes, as the caps instruction
+    // reports them: { "bits", "draw", "input", "audio" }
+    pub fn capabilities(&self) -> JsValue {
+        let bits = self.vm.capabilities();
+
+        let capabilities = serde_json::json!({
+            "bits": bits,
+            "draw": bits & PIXARDIS_CAPABILITY_DRAW != 0,
+            "input": bits & PIXARDIS_CAPABILITY_INPUT != 0,
+            "audio": bits & PIXARDIS_CAPABILITY_AUDIO != 0
+        });
+
+        serde_wasm_bindgen::to_value(&capabilities).unwrap()
+    }
+
+    // Display brightness (0-255) and gamma only affect get_framebuffer; the
+    // program still reads back the colours it wrote
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.vm.brightness_set(brightness);
+    }
+
+    // Gamma must be positive; returns false (and keeps the current gamma) otherwise
+    #[cfg(feature = "std")]
+    pub fn set_gamma(&mut self, gamma: f64) -> bool {
+        if !(gamma > 0.0 && gamma.is_finite()) {
+            return false;
+        }
+
+        self.vm.gamma_set(gamma);
+        true
+    }
+
+    // Print bare values rather than prefixing them with their type
+    pub fn set_print_raw(&mut self, enabled: bool) {
+        self.vm.print_format_set(if enabled { PixardisPrintFormat::Raw } else { PixardisPrintFormat::Typed });
+    }
+
+    // The top depth entries of the operand stack, top first
+    pub fn get_operand_stack(&self, depth: usize) -> JsValue {
+        let entries: Vec<String> = self.vm.operand_stack().iter().rev().take(depth)
+            .map(|operand| format!("{:?}", operand))
+            .collect();
+
+        serde_wasm_bindgen::to_value(&entries).unwrap()
+    }
+
+    // Record which instruction writes each pixel, for inspect_pixel
+    pub fn set_pixel_tracking(&mut self, enabled: bool) {
+        self.vm.pixel_tracking_set(enabled);
+    }
+
+    // Colour at a display pixel and, with pixel tracking on, the instruction
+    // (and function) that last wrote it; null outside the display
+    pub fn inspect_pixel(&self, x: usize, y: usize) -> JsValue {
+        let info = self.vm.pixel_inspect(x, y).map(|info| serde_json::json!({
+            "x": info.x,
+            "y": info.y,
+            "colour": format!("#{:06x}", info.colour),
+            "writer": info.writer.map(|writer| serde_json::json!({
+                "address": writer.address,
+                "instruction": pixardis_instruction_to_string(writer.instruction),
+                "function": writer.function
+            }))
+        }));
+
+        serde_wasm_bindgen::to_value(&info.unwrap_or(serde_json::Value::Null)).unwrap()
+    }
+
+    pub fn get_diagnostics(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(self.vm.diagnostics()).unwrap()
+    }
+
+    pub fn clear_diagnostics(&mut self) {
+        self.vm.clear_diagnostics();
+    }
+
     pub fn get_framebuffer(&self) -> Vec<u8> {
-        let (width, height, colors) = self.vm.framebuffer();
+        let (width, height, colors) = self.vm.framebuffer_present();
         let mut rgb_data = Vec::with_capacity(width * height * 3);
         
         for color in colors {
@@ -296,6 +824,59 @@ impl WebVM {
             self.vm.clear_print_output();
         }
     }
+
+    // Log levels are 0 (none), 1 (errors) or 2 (full trace); category is
+    // one of "exec", "memory" or "display", or empty for all categories.
+    pub fn set_log_level(&mut self, category: &str, level: usize) {
+        let level = match level {
+            1 => PixardisLogLevel::Error,
+            2 => PixardisLogLevel::Full,
+            _ => PixardisLogLevel::None,
+        };
+
+        match category {
+            "exec" => self.vm.log_level_set_category(PixardisLogCategory::Exec, level),
+            "memory" => self.vm.log_level_set_category(PixardisLogCategory::Memory, level),
+            "display" => self.vm.log_level_set_category(PixardisLogCategory::Display, level),
+            _ => self.vm.log_level_set(level),
+        }
+    }
+
+    pub fn get_log_output(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(self.vm.get_log_output()).unwrap()
+    }
+
+    pub fn clear_log_output(&mut self) {
+        self.vm.clear_log_output();
+    }
+}
+
+#[cfg(target_arch = "wasm32")] 
+impl WebVM {
+    // The watched write that paused the last step or frame, or null
+    fn watch_hit(&self) -> serde_json::Value {
+        match self.vm.watch_hit() {
+            Some(hit) => serde_json::json!({
+                "frame": hit.frame,
+                "offset": hit.offset,
+                "old": format!("{:?}", hit.old),
+                "new": format!("{:?}", hit.new),
+                "address": hit.address,
+                "instruction": pixardis_instruction_to_string(hit.instruction.clone())
+            }),
+            None => serde_json::Value::Null,
+        }
+    }
+
+    // Call chain at the last runtime error, innermost first
+    fn backtrace(&self) -> serde_json::Value {
+        self.vm.backtrace().iter()
+            .map(|frame| serde_json::json!({
+                "address": frame.address,
+                "function": frame.function
+            }))
+            .collect()
+    }
 }
 
 // Convenience functions for JS
@@ -311,6 +892,30 @@ pub fn step_vm(vm: &mut WebVM, steps: usize) -> JsValue {
     vm.step(steps)
 }
 
+#[cfg(target_arch = "wasm32")] 
+#[wasm_bindgen]
+pub fn run_vm_frame(vm: &mut WebVM, max_cycles: usize) -> JsValue {
+    vm.run_frame(max_cycles)
+}
+
+#[cfg(target_arch = "wasm32")] 
+#[wasm_bindgen]
+pub fn advance_vm_time(vm: &mut WebVM, dt_ms: f64, max_cycles: usize) -> JsValue {
+    vm.advance_time(dt_ms, max_cycles)
+}
+
+#[cfg(target_arch = "wasm32")] 
+#[wasm_bindgen]
+pub fn wasm_memory_stats(vm: &WebVM) -> JsValue {
+    vm.memory_stats()
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub fn set_vm_watchdog(vm: &mut WebVM, cycles: Option<usize>) {
+    vm.set_watchdog(cycles);
+}
+
 #[cfg(target_arch = "wasm32")] 
 #[wasm_bindgen]
 pub fn get_vm_framebuffer(vm: &WebVM) -> Vec<u8> {
@@ -331,6 +936,18 @@ pub fn clear_vm_print_output(vm: &mut WebVM) {
 
 #[cfg(target_arch = "wasm32")] 
 #[wasm_bindgen]
-pub fn load_vm_program(vm: &mut WebVM, assembly: &str) {
-    vm.load_program(assembly);
+pub fn get_vm_log_output(vm: &WebVM) -> JsValue {
+    vm.get_log_output()
+}
+
+#[cfg(target_arch = "wasm32")] 
+#[wasm_bindgen]
+pub fn clear_vm_log_output(vm: &mut WebVM) {
+    vm.clear_log_output();
+}
+
+#[cfg(target_arch = "wasm32")] 
+#[wasm_bindgen]
+pub fn load_vm_program(vm: &mut WebVM, assembly: &str) -> bool {
+    vm.load_program(assembly)
 }
@@ -0,0 +1,81 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use serde::Serialize;
+use shared::pixardis::{PixardisInstruction, pixardis_instruction_to_string};
+
+use super::machine::observer::Observer;
+
+///
+/// Execution statistics since the program was loaded
+///
+#[derive(Debug, Clone, Serialize)]
+pub struct ExecutionStats {
+    pub instructions: u64,
+    // Executed instruction counts, keyed by mnemonic
+    pub opcodes: BTreeMap<String, u64>,
+    pub pixels_written: u64,
+    // VM time elapsed, in seconds
+    pub elapsed: f64,
+}
+
+//
+// Counts are kept per address and only grouped by mnemonic when read, so
+// the per-instruction cost stays a single increment
+//
+#[derive(Default)]
+pub struct StatsCounters {
+    executed: Vec<u64>,
+    mnemonics: Vec<Option<String>>,
+    pixels_written: u64,
+}
+
+impl StatsCounters {
+    pub fn reset(&mut self) {
+        *self = StatsCounters::default();
+    }
+
+    pub fn report(&self, elapsed: f64) -> ExecutionStats {
+        let mut opcodes = BTreeMap::new();
+
+        for (count, mnemonic) in self.executed.iter().zip(&self.mnemonics) {
+            if let Some(mnemonic) = mnemonic {
+                *opcodes.entry(mnemonic.clone()).or_insert(0) += count;
+            }
+        }
+
+        ExecutionStats {
+            instructions: self.executed.iter().sum(),
+            opcodes,
+            pixels_written: self.pixels_written,
+            elapsed,
+        }
+    }
+}
+
+pub struct StatsCollector {
+    pub counters: Rc<RefCell<StatsCounters>>,
+}
+
+impl Observer for StatsCollector {
+    fn on_instruction(&mut self, address: usize, instruction: &PixardisInstruction) {
+        let mut counters = self.counters.borrow_mut();
+
+        if address >= counters.executed.len() {
+            counters.executed.resize(address + 1, 0);
+            counters.mnemonics.resize(address + 1, None);
+        }
+
+        if counters.mnemonics[address].is_none() {
+            let text = pixardis_instruction_to_string(instruction.clone());
+            counters.mnemonics[address] = Some(text.split_whitespace().next().unwrap_or_default().to_string());
+        }
+
+        counters.executed[address] += 1;
+    }
+
+    fn on_pixel_write(&mut self, _x: usize, _y: usize, _colour: u64) {
+        self.counters.borrow_mut().pixels_written += 1;
+    }
+}